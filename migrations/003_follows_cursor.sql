@@ -0,0 +1 @@
+ALTER TABLE active_users ADD COLUMN last_follows_cursor TEXT;
@@ -0,0 +1 @@
+ALTER TABLE posts ADD COLUMN reply_parent_uri TEXT;
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS user_preferences (
+    did TEXT PRIMARY KEY,
+    post_retention_hours INTEGER,
+    updated_at TEXT NOT NULL
+);
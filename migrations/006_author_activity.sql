@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS author_activity (
+    did TEXT PRIMARY KEY,
+    migration_observed_at TEXT
+);
@@ -0,0 +1,2 @@
+ALTER TABLE posts ADD COLUMN quoted_uri TEXT;
+ALTER TABLE posts ADD COLUMN is_link_only INTEGER NOT NULL DEFAULT 0;
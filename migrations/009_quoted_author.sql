@@ -0,0 +1,6 @@
+ALTER TABLE posts ADD COLUMN quoted_author_did TEXT;
+ALTER TABLE user_preferences ADD COLUMN hide_stranger_quotes INTEGER NOT NULL DEFAULT 0;
+
+UPDATE posts
+SET quoted_author_did = substr(quoted_uri, 6, instr(substr(quoted_uri, 6), '/') - 1)
+WHERE quoted_uri LIKE 'at://%/%';
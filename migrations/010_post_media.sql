@@ -0,0 +1,2 @@
+ALTER TABLE posts ADD COLUMN has_media INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE posts ADD COLUMN embed_type TEXT;
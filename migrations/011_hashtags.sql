@@ -0,0 +1,7 @@
+ALTER TABLE posts ADD COLUMN hashtags TEXT;
+
+CREATE TABLE IF NOT EXISTS hashtag_blocklist (
+    owner_did TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (owner_did, tag)
+);
@@ -0,0 +1 @@
+ALTER TABLE user_preferences ADD COLUMN min_post_length INTEGER;
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS pinned_posts (
+    uri TEXT PRIMARY KEY,
+    pinned_at TEXT NOT NULL
+);
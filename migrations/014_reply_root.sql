@@ -0,0 +1 @@
+ALTER TABLE posts ADD COLUMN reply_root_uri TEXT;
@@ -0,0 +1,71 @@
+-- Post and follow timestamps become INTEGER microseconds since the Unix epoch,
+-- so they compare numerically whatever offset the original string carried.
+-- SQLite's date functions only keep milliseconds, so whole seconds come from
+-- unixepoch() and the fraction is read off the string ('.' at position 20,
+-- then digits up to a 'Z' or '+HH:MM' suffix), padded or cut to six digits.
+
+CREATE TABLE posts_new (
+    uri TEXT PRIMARY KEY,
+    cid TEXT NOT NULL,
+    author_did TEXT NOT NULL,
+    text TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    indexed_at INTEGER NOT NULL,
+    reply_parent_uri TEXT,
+    quoted_uri TEXT,
+    is_link_only INTEGER NOT NULL DEFAULT 0,
+    quoted_author_did TEXT,
+    has_media INTEGER NOT NULL DEFAULT 0,
+    embed_type TEXT,
+    hashtags TEXT,
+    reply_root_uri TEXT
+);
+
+INSERT INTO posts_new
+SELECT uri, cid, author_did, text,
+       unixepoch(created_at) * 1000000 + CASE WHEN substr(created_at, 20, 1) = '.'
+           THEN CAST(substr(substr(created_at, 21, length(created_at) - 20
+               - CASE WHEN created_at LIKE '%Z' THEN 1 ELSE 6 END) || '000000', 1, 6) AS INTEGER)
+           ELSE 0 END,
+       unixepoch(indexed_at) * 1000000 + CASE WHEN substr(indexed_at, 20, 1) = '.'
+           THEN CAST(substr(substr(indexed_at, 21, length(indexed_at) - 20
+               - CASE WHEN indexed_at LIKE '%Z' THEN 1 ELSE 6 END) || '000000', 1, 6) AS INTEGER)
+           ELSE 0 END,
+       reply_parent_uri, quoted_uri, is_link_only, quoted_author_did, has_media, embed_type,
+       hashtags, reply_root_uri
+FROM posts;
+
+DROP TABLE posts;
+ALTER TABLE posts_new RENAME TO posts;
+
+-- Feeds join on the author and page by creation time; cleanup scans indexed_at
+CREATE INDEX idx_posts_author_created ON posts(author_did, created_at DESC);
+CREATE INDEX idx_posts_created ON posts(created_at DESC);
+CREATE INDEX idx_posts_indexed ON posts(indexed_at);
+
+CREATE TABLE follows_new (
+    uri TEXT PRIMARY KEY,
+    follower_did TEXT NOT NULL,
+    target_did TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    indexed_at INTEGER NOT NULL
+);
+
+INSERT INTO follows_new
+SELECT uri, follower_did, target_did,
+       unixepoch(created_at) * 1000000 + CASE WHEN substr(created_at, 20, 1) = '.'
+           THEN CAST(substr(substr(created_at, 21, length(created_at) - 20
+               - CASE WHEN created_at LIKE '%Z' THEN 1 ELSE 6 END) || '000000', 1, 6) AS INTEGER)
+           ELSE 0 END,
+       unixepoch(indexed_at) * 1000000 + CASE WHEN substr(indexed_at, 20, 1) = '.'
+           THEN CAST(substr(substr(indexed_at, 21, length(indexed_at) - 20
+               - CASE WHEN indexed_at LIKE '%Z' THEN 1 ELSE 6 END) || '000000', 1, 6) AS INTEGER)
+           ELSE 0 END
+FROM follows;
+
+DROP TABLE follows;
+ALTER TABLE follows_new RENAME TO follows;
+
+CREATE INDEX idx_follows_follower ON follows(follower_did);
+CREATE INDEX idx_follows_target ON follows(target_did);
+CREATE UNIQUE INDEX idx_follows_unique ON follows(follower_did, target_did);
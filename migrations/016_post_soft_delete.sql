@@ -0,0 +1,3 @@
+-- Deleted posts are kept for a while, marked with the deletion time in epoch
+-- microseconds, before cleanup removes them for good
+ALTER TABLE posts ADD COLUMN deleted_at INTEGER;
@@ -0,0 +1,6 @@
+-- Where the Jetstream consumer got to, so a restart resumes from there.
+-- A single row, keyed by the consumer's name.
+CREATE TABLE IF NOT EXISTS consumer_state (
+    name TEXT PRIMARY KEY,
+    cursor_us INTEGER NOT NULL
+);
@@ -0,0 +1,27 @@
+-- Full-text index over post text for admin search. The index reads its
+-- content from posts (matched by rowid), and triggers keep it in step. Post
+-- inserts upsert rather than REPLACE, which would skip the delete trigger.
+CREATE VIRTUAL TABLE IF NOT EXISTS posts_fts USING fts5(
+    uri UNINDEXED,
+    text,
+    content = 'posts',
+    content_rowid = 'rowid',
+    tokenize = 'porter unicode61'
+);
+
+INSERT INTO posts_fts(posts_fts) VALUES ('rebuild');
+
+CREATE TRIGGER posts_fts_insert AFTER INSERT ON posts BEGIN
+    INSERT INTO posts_fts(rowid, uri, text) VALUES (new.rowid, new.uri, new.text);
+END;
+
+CREATE TRIGGER posts_fts_delete AFTER DELETE ON posts BEGIN
+    INSERT INTO posts_fts(posts_fts, rowid, uri, text)
+    VALUES ('delete', old.rowid, old.uri, old.text);
+END;
+
+CREATE TRIGGER posts_fts_update AFTER UPDATE OF uri, text ON posts BEGIN
+    INSERT INTO posts_fts(posts_fts, rowid, uri, text)
+    VALUES ('delete', old.rowid, old.uri, old.text);
+    INSERT INTO posts_fts(rowid, uri, text) VALUES (new.rowid, new.uri, new.text);
+END;
@@ -0,0 +1 @@
+ALTER TABLE user_preferences ADD COLUMN max_posts_per_author INTEGER;
@@ -0,0 +1,5 @@
+ALTER TABLE active_users ADD COLUMN request_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE active_users ADD COLUMN first_seen TEXT;
+
+-- Existing users have been seen at least once, by their last request
+UPDATE active_users SET request_count = 1, first_seen = last_feed_request;
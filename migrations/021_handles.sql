@@ -0,0 +1,6 @@
+-- The handle each tracked DID last announced, for readable admin output and logs
+CREATE TABLE IF NOT EXISTS handles (
+    did TEXT PRIMARY KEY,
+    handle TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
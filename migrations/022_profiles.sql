@@ -0,0 +1,7 @@
+-- Display names from tracked accounts' profile records, for the admin console
+CREATE TABLE IF NOT EXISTS profiles (
+    did TEXT PRIMARY KEY,
+    handle TEXT,
+    display_name TEXT,
+    updated_at TEXT NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Accounts deactivated or taken down, and when their posts were hidden in
+-- epoch microseconds, so reactivating brings back only those posts
+CREATE TABLE IF NOT EXISTS inactive_authors (
+    did TEXT PRIMARY KEY,
+    deactivated_at INTEGER NOT NULL
+);
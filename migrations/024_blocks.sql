@@ -0,0 +1,10 @@
+-- Accounts feed users have blocked, from the block records in their repos.
+-- Times are epoch microseconds, as in follows.
+CREATE TABLE IF NOT EXISTS blocks (
+    uri TEXT PRIMARY KEY,
+    blocker_did TEXT NOT NULL,
+    target_did TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_blocks_blocker_target ON blocks(blocker_did, target_did);
@@ -0,0 +1,3 @@
+-- When a user's follows were last fetched in full, so follows removed while
+-- Jetstream was disconnected are eventually dropped
+ALTER TABLE active_users ADD COLUMN last_full_follow_sync TEXT;
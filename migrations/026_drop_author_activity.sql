@@ -0,0 +1,3 @@
+-- Observed identity changes were recorded here but never read; the identity
+-- cache invalidation on those events is all that acts on them
+DROP TABLE IF EXISTS author_activity;
@@ -0,0 +1,317 @@
+use anyhow::Result;
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::{info, warn, Instrument};
+
+use crate::{
+    admin_socket::secret_matches,
+    api_budget::ApiBudget,
+    backfill::{self, BackfillLimits},
+    database::Database,
+    identity::IdentityCache,
+    jetstream_consumer::IngestQueue,
+};
+
+/// Admin operations over HTTP, for deployments that can't reach the Unix
+/// socket. Every route requires `Authorization: Bearer <admin secret>`.
+#[derive(Clone)]
+pub struct AdminHttpState {
+    pub db: Arc<Database>,
+    pub budget: Arc<ApiBudget>,
+    pub identity: Arc<IdentityCache>,
+    pub ingest: Option<IngestQueue>,
+    pub backfill: BackfillLimits,
+    pub secret: String,
+}
+
+pub fn router(state: AdminHttpState) -> Router {
+    Router::new()
+        .route("/admin/stats", get(stats))
+        .route("/admin/backfill", post(start_backfill))
+        .route("/admin/user/{did}", delete(purge_user))
+        .route("/admin/users", get(users))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_bearer,
+        ))
+        .with_state(state)
+}
+
+/// Serves the admin router on `127.0.0.1:<port>`
+pub async fn start(state: AdminHttpState, port: u16) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    info!(
+        "Admin HTTP endpoint listening on {}",
+        listener.local_addr()?
+    );
+    axum::serve(listener, router(state)).await?;
+    Ok(())
+}
+
+async fn require_bearer(State(state): State<AdminHttpState>, req: Request, next: Next) -> Response {
+    let authorized = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| secret_matches(token, &state.secret));
+
+    if !authorized {
+        return error_response(StatusCode::UNAUTHORIZED, "Missing or invalid bearer token");
+    }
+    next.run(req).await
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+async fn stats(State(state): State<AdminHttpState>) -> Response {
+    let database = match state.db.get_stats().await {
+        Ok(stats) => stats,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let api_budget: Vec<_> = state
+        .budget
+        .stats()
+        .into_iter()
+        .map(|class| {
+            json!({
+                "priority": class.priority.name(),
+                "acquired": class.acquired,
+                "deferred": class.rejected,
+                "wait_ms": class.total_wait_ms,
+            })
+        })
+        .collect();
+
+    Json(json!({
+        "database": database,
+        "api_budget": api_budget,
+        "ingest": state.ingest.as_ref().map(IngestQueue::stats),
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct BackfillRequest {
+    did: String,
+}
+
+/// Backfills run in the background, as they can take minutes
+async fn start_backfill(
+    State(state): State<AdminHttpState>,
+    Json(request): Json<BackfillRequest>,
+) -> Response {
+    if !request.did.starts_with("did:") {
+        return error_response(StatusCode::BAD_REQUEST, "Expected a DID");
+    }
+
+    let did = request.did.clone();
+    tokio::spawn(
+        async move {
+            if let Err(e) = backfill::backfill_follows(
+                Arc::clone(&state.db),
+                Arc::clone(&state.budget),
+                Arc::clone(&state.identity),
+                &did,
+                state.backfill,
+                None,
+            )
+            .await
+            {
+                warn!("Follow backfill failed for {}: {}", did, e);
+                return;
+            }
+            if let Err(e) = backfill::backfill_posts_for_follows(
+                state.db,
+                state.budget,
+                state.identity,
+                &did,
+                state.backfill,
+                None,
+            )
+            .await
+            {
+                warn!("Post backfill failed for {}: {}", did, e);
+            }
+        }
+        .in_current_span(),
+    );
+
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({ "status": "started", "did": request.did })),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct PurgeParams {
+    /// Also delete posts by authors only this user follows
+    #[serde(default)]
+    cascade_posts: bool,
+}
+
+async fn purge_user(
+    State(state): State<AdminHttpState>,
+    Path(did): Path<String>,
+    Query(params): Query<PurgeParams>,
+) -> Response {
+    match state
+        .db
+        .delete_all_user_data(&did, params.cascade_posts)
+        .await
+    {
+        Ok(rows_deleted) => {
+            info!("Purged {} via admin HTTP ({} rows)", did, rows_deleted);
+            Json(json!({ "did": did, "rows_deleted": rows_deleted })).into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+async fn users(State(state): State<AdminHttpState>) -> Response {
+    match state.db.get_active_user_summaries().await {
+        Ok(users) => Json(json!({ "users": users })).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Follow, Post};
+    use chrono::Utc;
+
+    async fn spawn_admin() -> Result<(String, Arc<Database>)> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let state = AdminHttpState {
+            db: Arc::clone(&db),
+            budget: Arc::new(ApiBudget::default()),
+            identity: Arc::new(IdentityCache::default()),
+            ingest: None,
+            backfill: BackfillLimits::default(),
+            secret: "s3cret".to_string(),
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let base = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, router(state)).await });
+        Ok((base, db))
+    }
+
+    #[tokio::test]
+    async fn test_requires_bearer_token() -> Result<()> {
+        let (base, _db) = spawn_admin().await?;
+        let client = reqwest::Client::new();
+        let url = format!("{}/admin/stats", base);
+
+        let response = client.get(&url).send().await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = client.get(&url).bearer_auth("wrong").send().await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = client
+            .delete(format!("{}/admin/user/did:example:alice", base))
+            .header(header::AUTHORIZATION, "s3cret")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = client.get(&url).bearer_auth("s3cret").send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_endpoints() -> Result<()> {
+        let (base, db) = spawn_admin().await?;
+        let client = reqwest::Client::new();
+
+        db.record_feed_request("did:example:alice").await?;
+        for target in ["did:example:bob", "did:example:carol"] {
+            db.insert_follow(&Follow {
+                uri: format!("at://did:example:alice/app.bsky.graph.follow/{}", target),
+                follower_did: "did:example:alice".to_string(),
+                target_did: target.to_string(),
+                created_at: Utc::now(),
+                indexed_at: Utc::now(),
+            })
+            .await?;
+        }
+
+        db.insert_post(&Post {
+            uri: "at://did:example:bob/app.bsky.feed.post/1".to_string(),
+            cid: "cid".to_string(),
+            author_did: "did:example:bob".to_string(),
+            text: "hello".to_string(),
+            reply_parent_uri: None,
+            reply_root_uri: None,
+            quoted_uri: None,
+            is_link_only: false,
+            has_media: false,
+            embed_type: None,
+            hashtags: Vec::new(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await?;
+
+        let stats: serde_json::Value = client
+            .get(format!("{}/admin/stats", base))
+            .bearer_auth("s3cret")
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(stats["database"]["follows"], 2);
+        assert_eq!(stats["database"]["users"], 1);
+        assert_eq!(stats["api_budget"][0]["priority"], "interactive");
+        assert!(stats["ingest"].is_null());
+
+        let users: serde_json::Value = client
+            .get(format!("{}/admin/users", base))
+            .bearer_auth("s3cret")
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(users["users"][0]["did"], "did:example:alice");
+        assert_eq!(users["users"][0]["follows"], 2);
+
+        let response = client
+            .post(format!("{}/admin/backfill", base))
+            .bearer_auth("s3cret")
+            .json(&json!({ "did": "not-a-did" }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let purged: serde_json::Value = client
+            .delete(format!("{}/admin/user/did:example:alice", base))
+            .bearer_auth("s3cret")
+            .send()
+            .await?
+            .json()
+            .await?;
+        // Two follows and the activity row; posts stay without cascade_posts
+        assert_eq!(purged["rows_deleted"], 3);
+        assert_eq!(db.get_stats().await?.follows, 0);
+        assert_eq!(db.get_stats().await?.posts, 1);
+        assert!(db.get_active_user_summaries().await?.is_empty());
+
+        Ok(())
+    }
+}
@@ -1,22 +1,155 @@
 use anyhow::Result;
-use sqlx::Row;
+use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::{UnixListener, UnixStream};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::mpsc;
 use tracing::{error, info, warn};
 
-use crate::{backfill, database::Database};
+use crate::{
+    api_budget::ApiBudget,
+    backfill::{self, BackfillLimits},
+    cleanup,
+    database::{Database, ProfileInfo, RuleCheck},
+    feed_algorithm::{FeedSettings, FollowingNoRepostsFeed},
+    identity::IdentityCache,
+    jetstream_consumer::{IngestQueue, IngestStats},
+    stats::RequestStats,
+    types::{FeedFilter, FeedSkeletonResponse, Post, MAX_POSTS_PER_AUTHOR},
+};
+
+/// Most posts `search-posts` lists
+const SEARCH_RESULTS: usize = 50;
+
+/// How a session writes its replies: readable text, or after `mode json`,
+/// one `{"command", "status", "data"}` object per line for scripts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResponseFormatter {
+    Text,
+    Json,
+}
+
+impl ResponseFormatter {
+    /// Commands with a JSON reply
+    const JSON_COMMANDS: [&'static str; 5] = ["mode", "backfill", "stats", "quit", "exit"];
+
+    /// The prompt written after each reply; JSON mode has none
+    fn prompt(self) -> &'static [u8] {
+        match self {
+            ResponseFormatter::Text => b"> ",
+            ResponseFormatter::Json => b"",
+        }
+    }
+
+    /// A command's reply as one JSON line; errors carry their message as
+    /// `data.message`
+    fn json(command: &str, result: std::result::Result<serde_json::Value, String>) -> String {
+        let (status, data) = match result {
+            Ok(data) => ("ok", data),
+            Err(message) => ("error", serde_json::json!({ "message": message })),
+        };
+        let reply = serde_json::json!({ "command": command, "status": status, "data": data });
+        format!("{}\n", reply)
+    }
+
+    /// An error reply: the message as is in text mode
+    fn error(self, command: &str, message: &str) -> String {
+        match self {
+            ResponseFormatter::Text => format!("{}\n", message),
+            ResponseFormatter::Json => Self::json(command, Err(message.to_string())),
+        }
+    }
+}
 
 pub struct AdminSocket {
     db: Arc<Database>,
+    budget: Arc<ApiBudget>,
+    identity: Arc<IdentityCache>,
     socket_path: String,
+    secret: Option<String>,
+    ingest: Option<IngestQueue>,
+    info: ServerInfo,
+}
+
+/// How the server runs, for `version`, `feed` and `request-stats`
+#[derive(Debug, Clone)]
+struct ServerInfo {
+    started_at: Instant,
+    service_did: Option<String>,
+    jetstream_hostname: Option<String>,
+    feed_settings: FeedSettings,
+    backfill: BackfillLimits,
+    requests: Option<Arc<RequestStats>>,
 }
 
 impl AdminSocket {
-    pub fn new(db: Arc<Database>, socket_path: String) -> Self {
-        Self { db, socket_path }
+    pub fn new(
+        db: Arc<Database>,
+        budget: Arc<ApiBudget>,
+        identity: Arc<IdentityCache>,
+        socket_path: String,
+    ) -> Self {
+        Self {
+            db,
+            budget,
+            identity,
+            socket_path,
+            secret: None,
+            ingest: None,
+            info: ServerInfo {
+                started_at: Instant::now(),
+                service_did: None,
+                jetstream_hostname: None,
+                feed_settings: FeedSettings::default(),
+                backfill: BackfillLimits::default(),
+                requests: None,
+            },
+        }
     }
 
+    /// Report the HTTP endpoints' counts and latencies in `request-stats`
+    pub fn with_request_stats(mut self, requests: Arc<RequestStats>) -> Self {
+        self.info.requests = Some(requests);
+        self
+    }
+
+    /// How much `backfill` fetches when not told otherwise
+    pub fn with_backfill_limits(mut self, backfill: BackfillLimits) -> Self {
+        self.info.backfill = backfill;
+        self
+    }
+
+    /// Generate `feed` previews the way the HTTP endpoint does, and clean up
+    /// posts with the server's retention
+    pub fn with_feed_settings(mut self, feed_settings: FeedSettings) -> Self {
+        self.info.feed_settings = feed_settings;
+        self
+    }
+
+    /// Report the feed's service DID and Jetstream host in `version`
+    pub fn with_service(mut self, service_did: String, jetstream_hostname: String) -> Self {
+        self.info.service_did = Some(service_did);
+        self.info.jetstream_hostname = Some(jetstream_hostname);
+        self
+    }
+
+    /// Require clients to send this secret before any command
+    pub fn with_secret(mut self, secret: Option<String>) -> Self {
+        self.secret = secret;
+        self
+    }
+
+    /// Report the Jetstream ingest queue in `stats`
+    pub fn with_ingest_queue(mut self, ingest: IngestQueue) -> Self {
+        self.ingest = Some(ingest);
+        self
+    }
+
+    #[cfg(unix)]
     pub async fn start(&self) -> Result<()> {
         // Remove old socket if it exists
         let _ = std::fs::remove_file(&self.socket_path);
@@ -25,43 +158,397 @@ impl AdminSocket {
         info!("Admin socket listening on {}", self.socket_path);
 
         // Set socket permissions so anyone can connect
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let mut perms = std::fs::metadata(&self.socket_path)?.permissions();
-            perms.set_mode(0o666);
-            std::fs::set_permissions(&self.socket_path, perms)?;
-        }
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = std::fs::metadata(&self.socket_path)?.permissions();
+        perms.set_mode(0o666);
+        std::fs::set_permissions(&self.socket_path, perms)?;
 
         loop {
             match listener.accept().await {
-                Ok((stream, _)) => {
-                    let db = Arc::clone(&self.db);
-                    tokio::spawn(async move {
-                        if let Err(e) = handle_connection(stream, db).await {
-                            error!("Error handling admin connection: {}", e);
-                        }
-                    });
+                Ok((stream, _)) => self.spawn_connection(stream),
+                Err(e) => {
+                    warn!("Failed to accept admin connection: {}", e);
                 }
+            }
+        }
+    }
+
+    /// Serves the admin console on `127.0.0.1:<port>` for deployments
+    /// without filesystem access to the Unix socket
+    pub async fn start_tcp(&self, port: u16) -> Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+        info!("Admin console listening on {}", listener.local_addr()?);
+        if self.secret.is_none() {
+            warn!("Admin TCP endpoint has no secret; any local process can use it");
+        }
+        self.serve_tcp(listener).await
+    }
+
+    async fn serve_tcp(&self, listener: TcpListener) -> Result<()> {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => self.spawn_connection(stream),
                 Err(e) => {
                     warn!("Failed to accept admin connection: {}", e);
                 }
             }
         }
     }
+
+    fn spawn_connection<S>(&self, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let db = Arc::clone(&self.db);
+        let budget = Arc::clone(&self.budget);
+        let identity = Arc::clone(&self.identity);
+        let secret = self.secret.clone();
+        let ingest = self.ingest.clone();
+        let info = self.info.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                handle_connection(stream, db, budget, identity, secret, ingest, info).await
+            {
+                error!("Error handling admin connection: {}", e);
+            }
+        });
+    }
+}
+
+fn format_explanation(uri: &str, checks: &[RuleCheck]) -> String {
+    let mut out = format!("{}\n", uri);
+    for check in checks {
+        out.push_str(&format!(
+            "  [{}] {}\n",
+            if check.passed { "PASS" } else { "FAIL" },
+            check.rule
+        ));
+    }
+    match checks.iter().find(|check| !check.passed) {
+        Some(check) => out.push_str(&format!("Excluded: {}\n", check.rule)),
+        None => out.push_str("Included\n"),
+    }
+    out
+}
+
+/// A feed page with the author and time of each stored post, as text or JSON
+fn format_feed_preview(
+    did: &str,
+    page: &FeedSkeletonResponse,
+    posts: &HashMap<String, Post>,
+    json: bool,
+) -> String {
+    if json {
+        let items: Vec<_> = page
+            .feed
+            .iter()
+            .map(|item| {
+                let post = posts.get(&item.post);
+                serde_json::json!({
+                    "post": item.post,
+                    "reason": item.reason,
+                    "author_did": post.map(|p| &p.author_did),
+                    "created_at": post.map(|p| p.created_at.to_rfc3339()),
+                })
+            })
+            .collect();
+        let preview = serde_json::json!({ "did": did, "cursor": page.cursor, "feed": items });
+        return format!("{}\n", preview);
+    }
+
+    let mut out = format!("Feed for {} ({} items)\n", did, page.feed.len());
+    for item in &page.feed {
+        let pinned = if item.reason.is_some() {
+            " [pinned]"
+        } else {
+            ""
+        };
+        match posts.get(&item.post) {
+            Some(post) => out.push_str(&format!(
+                "  {}  {}  {}{}\n",
+                post.created_at.to_rfc3339(),
+                post.author_did,
+                item.post,
+                pinned
+            )),
+            None => out.push_str(&format!("  (not stored)  {}{}\n", item.post, pinned)),
+        }
+    }
+    out.push_str(&format!(
+        "Cursor: {}\n",
+        page.cursor.as_deref().unwrap_or("none")
+    ));
+    out
+}
+
+const CLEANUP_USAGE: &str =
+    "Usage: cleanup posts [hours] [--dry-run] | cleanup follows [--dry-run] | cleanup authors [--dry-run]\n";
+
+/// Runs an admin `cleanup` command and returns its reply. A dry run only
+/// counts, and `cleanup follows --dry-run` skips re-syncing active users'
+/// follows, which would write.
+async fn run_cleanup<W>(
+    db: &Arc<Database>,
+    budget: &Arc<ApiBudget>,
+    args: &[&str],
+    settings: FeedSettings,
+    writer: &mut W,
+) -> Result<String>
+where
+    W: AsyncWrite + Unpin,
+{
+    let dry_run = args.contains(&"--dry-run");
+    let args: Vec<&str> = args.iter().copied().filter(|a| *a != "--dry-run").collect();
+    let verb = if dry_run { "Would delete" } else { "Deleted" };
+
+    let (result, what) = match args.as_slice() {
+        ["posts", hours @ ..] if hours.len() <= 1 => {
+            let hours = match hours.first().map(|h| h.parse::<i64>()) {
+                None => settings.retention_hours,
+                Some(Ok(hours)) if hours > 0 => hours,
+                Some(_) => return Ok(CLEANUP_USAGE.to_string()),
+            };
+            (
+                cleanup::cleanup_old_posts(db, hours, settings.keep_posts_per_author, dry_run)
+                    .await,
+                format!("past retention (default {}h)", hours),
+            )
+        }
+        ["follows"] => {
+            let mut verified = Ok(());
+            if !dry_run {
+                writer
+                    .write_all(b"Verifying follows of active users...\n")
+                    .await?;
+                writer.flush().await?;
+                verified =
+                    cleanup::verify_active_user_follows(Arc::clone(db), Arc::clone(budget)).await;
+            }
+            let result = match verified {
+                Ok(()) => cleanup::cleanup_inactive_user_follows(Arc::clone(db), dry_run).await,
+                Err(e) => Err(e),
+            };
+            (result, "of inactive users".to_string())
+        }
+        ["authors"] => (
+            cleanup::cleanup_stale_authors(Arc::clone(db), dry_run).await,
+            "by authors nobody follows".to_string(),
+        ),
+        _ => return Ok(CLEANUP_USAGE.to_string()),
+    };
+
+    Ok(match result {
+        Ok(stats) => {
+            let mut reply = format!("{} {} {}\n", verb, stats, what);
+            for user in &stats.inactive_users {
+                reply.push_str(&format!("  {}: {} follows\n", user.did, user.follows));
+            }
+            reply
+        }
+        Err(e) => format!("Cleanup failed: {}\n", e),
+    })
+}
+
+/// Backfills an account's follows and then their posts, writing the
+/// readable progress to `log`. Returns the JSON reply data, or the failure.
+async fn run_backfill<W>(
+    log: &mut W,
+    db: &Arc<Database>,
+    budget: &Arc<ApiBudget>,
+    identity: &Arc<IdentityCache>,
+    actor: &str,
+    limits: BackfillLimits,
+) -> Result<std::result::Result<serde_json::Value, String>>
+where
+    W: AsyncWrite + Unpin,
+{
+    let did = match backfill::resolve_actor(budget, actor).await {
+        Ok(did) => did,
+        Err(e) => {
+            log.write_all(format!("{}\n", e).as_bytes()).await?;
+            return Ok(Err(e.to_string()));
+        }
+    };
+    if did != actor {
+        log.write_all(format!("Resolved {} to {}\n", actor, did).as_bytes())
+            .await?;
+        if let Err(e) = db.set_handle(&did, actor.trim_start_matches('@')).await {
+            warn!("Failed to store the handle of {}: {}", did, e);
+        }
+    }
+    let did = did.as_str();
+    log.write_all(format!("Starting backfill for {}...\n", did).as_bytes())
+        .await?;
+    log.flush().await?;
+
+    // First backfill follows
+    let (tx, rx) = mpsc::unbounded_channel();
+    let step = backfill::backfill_follows(
+        Arc::clone(db),
+        Arc::clone(budget),
+        Arc::clone(identity),
+        did,
+        limits,
+        Some(&tx),
+    );
+    let follows_inserted = match forward_progress(log, rx, step).await? {
+        Ok(follows) => {
+            log.write_all(b"Follows backfilled successfully\n").await?;
+            follows
+        }
+        Err(e) => {
+            let message = format!("Follow backfill failed: {}", e);
+            log.write_all(format!("{}\n", message).as_bytes()).await?;
+            return Ok(Err(message));
+        }
+    };
+
+    // Then backfill posts
+    log.write_all(b"Starting post backfill...\n").await?;
+    log.flush().await?;
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let step = backfill::backfill_posts_for_follows(
+        Arc::clone(db),
+        Arc::clone(budget),
+        Arc::clone(identity),
+        did,
+        limits,
+        Some(&tx),
+    );
+    match forward_progress(log, rx, step).await? {
+        Ok(posts_inserted) => {
+            log.write_all(b"Posts backfilled successfully\n").await?;
+            Ok(Ok(serde_json::json!({
+                "did": did,
+                "follows_inserted": follows_inserted,
+                "posts_inserted": posts_inserted,
+            })))
+        }
+        Err(e) => {
+            let message = format!("Post backfill failed: {}", e);
+            log.write_all(format!("{}\n", message).as_bytes()).await?;
+            Ok(Err(message))
+        }
+    }
+}
+
+/// Awaits a backfill step, writing each progress line it sends as it
+/// arrives rather than once the step is over
+async fn forward_progress<W, F, T>(
+    writer: &mut W,
+    mut progress: mpsc::UnboundedReceiver<String>,
+    step: F,
+) -> Result<Result<T>>
+where
+    W: AsyncWrite + Unpin,
+    F: Future<Output = Result<T>>,
+{
+    tokio::pin!(step);
+    loop {
+        tokio::select! {
+            Some(line) = progress.recv() => {
+                writer.write_all(format!("{}\n", line).as_bytes()).await?;
+                writer.flush().await?;
+            }
+            result = &mut step => {
+                while let Ok(line) = progress.try_recv() {
+                    writer.write_all(format!("{}\n", line).as_bytes()).await?;
+                }
+                return Ok(result);
+            }
+        }
+    }
+}
+
+fn format_uptime(secs: u64) -> String {
+    let (days, hours) = (secs / 86400, secs % 86400 / 3600);
+    let (minutes, seconds) = (secs % 3600 / 60, secs % 60);
+    if days > 0 {
+        format!("{}d {}h {}m {}s", days, hours, minutes, seconds)
+    } else {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    }
+}
+
+/// Build and runtime details. The git hash is taken from `GIT_HASH` at
+/// build time, when set.
+fn format_version(info: &ServerInfo, ingest: Option<&IngestStats>) -> String {
+    let mut out = format!(
+        "Version: {} ({})\n  Uptime: {}\n  Service DID: {}\n  Jetstream: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        option_env!("GIT_HASH").unwrap_or("unknown commit"),
+        format_uptime(info.started_at.elapsed().as_secs()),
+        info.service_did.as_deref().unwrap_or("not configured"),
+        info.jetstream_hostname
+            .as_deref()
+            .unwrap_or("not configured"),
+    );
+    match ingest.and_then(|stats| stats.written_us) {
+        Some(cursor) => {
+            let age_secs = (chrono::Utc::now().timestamp_micros() - cursor).max(0) / 1_000_000;
+            out.push_str(&format!(
+                "  Jetstream cursor: {} (last event {}s ago)\n",
+                cursor, age_secs
+            ));
+        }
+        None => out.push_str("  Jetstream cursor: no events yet\n"),
+    }
+    if let Some(lag) = ingest.and_then(|stats| stats.lag) {
+        out.push_str(&format!(
+            "  Ingest lag: {:.1}s average, {:.1}s max over the last minute\n",
+            lag.average_ms as f64 / 1000.0,
+            lag.max_ms as f64 / 1000.0
+        ));
+    }
+    out
+}
+
+/// Compares secrets without bailing out at the first differing byte
+pub(crate) fn secret_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
 }
 
-async fn handle_connection(stream: UnixStream, db: Arc<Database>) -> Result<()> {
-    let (reader, mut writer) = stream.into_split();
+async fn handle_connection<S>(
+    stream: S,
+    db: Arc<Database>,
+    budget: Arc<ApiBudget>,
+    identity: Arc<IdentityCache>,
+    secret: Option<String>,
+    ingest: Option<IngestQueue>,
+    info: ServerInfo,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
     let mut reader = BufReader::new(reader);
     let mut line = String::new();
 
+    if let Some(secret) = secret {
+        writer.write_all(b"Secret: ").await?;
+        writer.flush().await?;
+        reader.read_line(&mut line).await?;
+        if !secret_matches(line.trim(), &secret) {
+            warn!("Rejected admin connection with a wrong secret");
+            writer.write_all(b"Authentication failed\n").await?;
+            writer.flush().await?;
+            return Ok(());
+        }
+    }
+
     writer.write_all(b"Feed Generator Admin Console\n").await?;
     writer
-        .write_all(b"Commands: backfill <did>, stats, help, quit\n> ")
+        .write_all(b"Commands: backfill <did|handle> [n], set-retention <did> <hours>, set-author-cap <did> <n|off>, set-author-max <did> <n|off>, set-min-length <did> <n|off>, stranger-quotes <did> <show|hide>, add-tag-block <did> <tag>, remove-tag-block <did> <tag>, explain <did> [<post-uri> [default|strict|media]], pin <post-uri>, unpin <post-uri>, restore-post <post-uri>, purge-user <did> [--cascade-posts] --confirm, search-posts <query>, top-authors [N], feed <did> [limit] [cursor] [--json], cleanup <posts [hours]|follows|authors> [--dry-run], stats [--json], stats-user <did>, resolve-did <did>, request-stats, mode <text|json>, version, help, quit\n> ")
         .await?;
     writer.flush().await?;
 
+    let mut format = ResponseFormatter::Text;
     loop {
         line.clear();
         let bytes_read = reader.read_line(&mut line).await?;
@@ -72,59 +559,614 @@ async fn handle_connection(stream: UnixStream, db: Arc<Database>) -> Result<()>
 
         let command = line.trim();
         if command.is_empty() {
-            writer.write_all(b"> ").await?;
+            writer.write_all(format.prompt()).await?;
             writer.flush().await?;
             continue;
         }
 
         let parts: Vec<&str> = command.split_whitespace().collect();
 
+        if format == ResponseFormatter::Json
+            && !ResponseFormatter::JSON_COMMANDS.contains(&parts[0])
+        {
+            let message = format!("{} has no JSON output; use 'mode text'", parts[0]);
+            writer
+                .write_all(format.error(parts[0], &message).as_bytes())
+                .await?;
+            writer.flush().await?;
+            continue;
+        }
+
         match parts.first().copied() {
+            Some("mode") => match parts.get(1).copied() {
+                Some("json") => {
+                    format = ResponseFormatter::Json;
+                    let data = serde_json::json!({ "mode": "json" });
+                    writer
+                        .write_all(ResponseFormatter::json("mode", Ok(data)).as_bytes())
+                        .await?;
+                }
+                Some("text") => {
+                    format = ResponseFormatter::Text;
+                    writer.write_all(b"Switched to text mode\n").await?;
+                }
+                _ => {
+                    writer
+                        .write_all(format.error("mode", "Usage: mode <text|json>").as_bytes())
+                        .await?;
+                }
+            },
             Some("backfill") => {
-                if let Some(did) = parts.get(1) {
+                let posts_per_user = match parts.get(2).map(|n| n.parse::<usize>()) {
+                    None => Some(info.backfill.posts_per_user),
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    Some(_) => None,
+                };
+                if let (Some(actor), Some(posts_per_user)) = (parts.get(1), posts_per_user) {
+                    let limits = BackfillLimits {
+                        posts_per_user,
+                        ..info.backfill
+                    };
+                    let result = match format {
+                        ResponseFormatter::Text => {
+                            run_backfill(&mut writer, &db, &budget, &identity, actor, limits)
+                                .await?
+                        }
+                        ResponseFormatter::Json => {
+                            let mut log = tokio::io::sink();
+                            run_backfill(&mut log, &db, &budget, &identity, actor, limits).await?
+                        }
+                    };
+                    if format == ResponseFormatter::Json {
+                        writer
+                            .write_all(ResponseFormatter::json("backfill", result).as_bytes())
+                            .await?;
+                    }
+                } else {
+                    let usage = "Usage: backfill <did|handle> [posts-per-user]";
                     writer
-                        .write_all(format!("Starting backfill for {}...\n", did).as_bytes())
+                        .write_all(format.error("backfill", usage).as_bytes())
                         .await?;
-                    writer.flush().await?;
-
-                    // First backfill follows
-                    match backfill::backfill_follows(Arc::clone(&db), did).await {
+                }
+            }
+            Some("set-retention") => match (parts.get(1), parts.get(2)) {
+                (Some(did), Some(hours)) => match hours.parse::<i64>() {
+                    Ok(hours) if hours > 0 => match db.set_post_retention(did, Some(hours)).await {
                         Ok(_) => {
                             writer
-                                .write_all(b"Follows backfilled successfully\n")
+                                .write_all(
+                                    format!("Retention for {} set to {} hours\n", did, hours)
+                                        .as_bytes(),
+                                )
                                 .await?;
                         }
                         Err(e) => {
                             writer
-                                .write_all(format!("Follow backfill failed: {}\n", e).as_bytes())
+                                .write_all(format!("Failed to set retention: {}\n", e).as_bytes())
                                 .await?;
-                            writer.write_all(b"> ").await?;
-                            writer.flush().await?;
-                            continue;
                         }
+                    },
+                    _ => {
+                        writer
+                            .write_all(b"Hours must be a positive integer\n")
+                            .await?;
                     }
-
-                    // Then backfill posts
-                    writer.write_all(b"Starting post backfill...\n").await?;
-                    writer.flush().await?;
-
-                    match backfill::backfill_posts_for_follows(Arc::clone(&db), did, 10).await {
+                },
+                _ => {
+                    writer
+                        .write_all(b"Usage: set-retention <did> <hours>\n")
+                        .await?;
+                }
+            },
+            Some("set-author-cap") => {
+                let cap = match parts.get(2).copied() {
+                    Some("off") => Ok(None),
+                    Some(n) => match n.parse::<i64>() {
+                        Ok(n) if n > 0 => Ok(Some(n)),
+                        _ => Err("Cap must be a positive integer or 'off'\n"),
+                    },
+                    None => Err("Usage: set-author-cap <did> <posts-per-day|off>\n"),
+                };
+                match (parts.get(1), cap) {
+                    (Some(did), Ok(cap)) => match db.set_author_daily_cap(did, cap).await {
                         Ok(_) => {
-                            writer.write_all(b"Posts backfilled successfully\n").await?;
+                            let setting = cap.map_or("off".to_string(), |n| n.to_string());
+                            writer
+                                .write_all(
+                                    format!(
+                                        "Daily per-author cap for {} set to {}\n",
+                                        did, setting
+                                    )
+                                    .as_bytes(),
+                                )
+                                .await?;
                         }
                         Err(e) => {
                             writer
-                                .write_all(format!("Post backfill failed: {}\n", e).as_bytes())
+                                .write_all(format!("Failed to set cap: {}\n", e).as_bytes())
                                 .await?;
                         }
+                    },
+                    (None, _) => {
+                        writer
+                            .write_all(b"Usage: set-author-cap <did> <posts-per-day|off>\n")
+                            .await?;
                     }
-                } else {
-                    writer.write_all(b"Usage: backfill <did>\n").await?;
+                    (_, Err(message)) => {
+                        writer.write_all(message.as_bytes()).await?;
+                    }
+                }
+            }
+            Some("set-author-max") => {
+                let max = match parts.get(2).copied() {
+                    Some("off") => Ok(None),
+                    Some(n) => match n.parse::<i64>() {
+                        Ok(n) if (1..=MAX_POSTS_PER_AUTHOR as i64).contains(&n) => Ok(Some(n)),
+                        _ => Err("Maximum must be from 1 to 10 or 'off'\n"),
+                    },
+                    None => Err("Usage: set-author-max <did> <posts|off>\n"),
+                };
+                match (parts.get(1), max) {
+                    (Some(did), Ok(max)) => match db.set_max_posts_per_author(did, max).await {
+                        Ok(_) => {
+                            let setting = max.map_or("off".to_string(), |n| n.to_string());
+                            writer
+                                .write_all(
+                                    format!("Posts per author for {} set to {}\n", did, setting)
+                                        .as_bytes(),
+                                )
+                                .await?;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(format!("Failed to set maximum: {}\n", e).as_bytes())
+                                .await?;
+                        }
+                    },
+                    (None, _) => {
+                        writer
+                            .write_all(b"Usage: set-author-max <did> <posts|off>\n")
+                            .await?;
+                    }
+                    (_, Err(message)) => {
+                        writer.write_all(message.as_bytes()).await?;
+                    }
+                }
+            }
+            Some("set-min-length") => {
+                let length = match parts.get(2).copied() {
+                    Some("off") => Ok(None),
+                    Some(n) => match n.parse::<i64>() {
+                        Ok(n) if n > 0 => Ok(Some(n)),
+                        _ => Err("Length must be a positive integer or 'off'\n"),
+                    },
+                    None => Err("Usage: set-min-length <did> <characters|off>\n"),
+                };
+                match (parts.get(1), length) {
+                    (Some(did), Ok(length)) => match db.set_min_post_length(did, length).await {
+                        Ok(_) => {
+                            let setting = length.map_or("off".to_string(), |n| n.to_string());
+                            writer
+                                .write_all(
+                                    format!("Minimum post length for {} set to {}\n", did, setting)
+                                        .as_bytes(),
+                                )
+                                .await?;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(format!("Failed to set length: {}\n", e).as_bytes())
+                                .await?;
+                        }
+                    },
+                    (None, _) => {
+                        writer
+                            .write_all(b"Usage: set-min-length <did> <characters|off>\n")
+                            .await?;
+                    }
+                    (_, Err(message)) => {
+                        writer.write_all(message.as_bytes()).await?;
+                    }
+                }
+            }
+            Some("stranger-quotes") => match (parts.get(1), parts.get(2).copied()) {
+                (Some(did), Some(setting @ ("show" | "hide"))) => {
+                    match db.set_hide_stranger_quotes(did, setting == "hide").await {
+                        Ok(_) => {
+                            writer
+                                .write_all(
+                                    format!("Quotes of non-follows will {} for {}\n", setting, did)
+                                        .as_bytes(),
+                                )
+                                .await?;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(format!("Failed to update setting: {}\n", e).as_bytes())
+                                .await?;
+                        }
+                    }
+                }
+                _ => {
+                    writer
+                        .write_all(b"Usage: stranger-quotes <did> <show|hide>\n")
+                        .await?;
+                }
+            },
+            Some("add-tag-block") => match (parts.get(1), parts.get(2)) {
+                (Some(did), Some(tag)) => match db.add_tag_block(did, tag).await {
+                    Ok(_) => {
+                        writer
+                            .write_all(
+                                format!("Posts tagged {} hidden for {}\n", tag, did).as_bytes(),
+                            )
+                            .await?;
+                    }
+                    Err(e) => {
+                        writer
+                            .write_all(format!("Failed to block tag: {}\n", e).as_bytes())
+                            .await?;
+                    }
+                },
+                _ => {
+                    writer
+                        .write_all(b"Usage: add-tag-block <did> <tag>\n")
+                        .await?;
+                }
+            },
+            Some("remove-tag-block") => match (parts.get(1), parts.get(2)) {
+                (Some(did), Some(tag)) => match db.remove_tag_block(did, tag).await {
+                    Ok(true) => {
+                        writer
+                            .write_all(
+                                format!("Posts tagged {} shown again for {}\n", tag, did)
+                                    .as_bytes(),
+                            )
+                            .await?;
+                    }
+                    Ok(false) => {
+                        writer
+                            .write_all(format!("{} had not blocked {}\n", did, tag).as_bytes())
+                            .await?;
+                    }
+                    Err(e) => {
+                        writer
+                            .write_all(format!("Failed to unblock tag: {}\n", e).as_bytes())
+                            .await?;
+                    }
+                },
+                _ => {
+                    writer
+                        .write_all(b"Usage: remove-tag-block <did> <tag>\n")
+                        .await?;
+                }
+            },
+            Some("explain") => {
+                let filter = match parts.get(3).copied() {
+                    None | Some("default") => Some(FeedFilter::default()),
+                    Some("strict") => Some(FeedFilter::strict()),
+                    Some("media") => Some(FeedFilter::media()),
+                    Some(_) => None,
+                };
+                match (parts.get(1), parts.get(2), filter) {
+                    (Some(did), None, _) => match db.explain_feed_query(did).await {
+                        Ok(plan) => {
+                            writer
+                                .write_all(
+                                    format!("Feed query plan for {}:\n{}", did, plan).as_bytes(),
+                                )
+                                .await?;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(
+                                    format!("Failed to explain feed query: {}\n", e).as_bytes(),
+                                )
+                                .await?;
+                        }
+                    },
+                    (Some(did), Some(uri), Some(filter)) => {
+                        let result = match db.get_preferences(did).await {
+                            Ok(preferences) => {
+                                db.explain_post(did, uri, &filter.with_preferences(&preferences))
+                                    .await
+                            }
+                            Err(e) => Err(e),
+                        };
+                        match result {
+                            Ok(checks) => {
+                                writer
+                                    .write_all(format_explanation(uri, &checks).as_bytes())
+                                    .await?;
+                            }
+                            Err(e) => {
+                                writer
+                                    .write_all(
+                                        format!("Failed to explain post: {}\n", e).as_bytes(),
+                                    )
+                                    .await?;
+                            }
+                        }
+                    }
+                    _ => {
+                        writer
+                            .write_all(
+                                b"Usage: explain <did> [<post-uri> [default|strict|media]]\n",
+                            )
+                            .await?;
+                    }
+                }
+            }
+            Some("pin") => match parts.get(1) {
+                Some(uri) if uri.starts_with("at://") => match db.pin_post(uri).await {
+                    Ok(_) => {
+                        writer
+                            .write_all(format!("Pinned {}\n", uri).as_bytes())
+                            .await?;
+                    }
+                    Err(e) => {
+                        writer
+                            .write_all(format!("Failed to pin post: {}\n", e).as_bytes())
+                            .await?;
+                    }
+                },
+                _ => {
+                    writer.write_all(b"Usage: pin <post-uri>\n").await?;
+                }
+            },
+            Some("unpin") => match parts.get(1) {
+                Some(uri) => match db.unpin_post(uri).await {
+                    Ok(true) => {
+                        writer
+                            .write_all(format!("Unpinned {}\n", uri).as_bytes())
+                            .await?;
+                    }
+                    Ok(false) => {
+                        writer
+                            .write_all(format!("{} isn't pinned\n", uri).as_bytes())
+                            .await?;
+                    }
+                    Err(e) => {
+                        writer
+                            .write_all(format!("Failed to unpin post: {}\n", e).as_bytes())
+                            .await?;
+                    }
+                },
+                None => {
+                    writer.write_all(b"Usage: unpin <post-uri>\n").await?;
+                }
+            },
+            Some("restore-post") => match parts.get(1) {
+                Some(uri) => match db.restore_post(uri).await {
+                    Ok(true) => {
+                        writer
+                            .write_all(format!("Restored {}\n", uri).as_bytes())
+                            .await?;
+                    }
+                    Ok(false) => {
+                        writer
+                            .write_all(format!("{} isn't a deleted post\n", uri).as_bytes())
+                            .await?;
+                    }
+                    Err(e) => {
+                        writer
+                            .write_all(format!("Failed to restore post: {}\n", e).as_bytes())
+                            .await?;
+                    }
+                },
+                None => {
+                    writer
+                        .write_all(b"Usage: restore-post <post-uri>\n")
+                        .await?;
                 }
+            },
+            Some("purge-user") => {
+                let flags = parts.get(2..).unwrap_or_default();
+                let cascade_posts = flags.contains(&"--cascade-posts");
+                let confirmed = flags.contains(&"--confirm");
+                match parts.get(1) {
+                    Some(did) if confirmed => {
+                        match db.delete_all_user_data(did, cascade_posts).await {
+                            Ok(deleted) => {
+                                info!(
+                                    did = %did,
+                                    cascade_posts,
+                                    deleted,
+                                    "Purged user via admin socket"
+                                );
+                                writer
+                                    .write_all(
+                                        format!("Purged {}: {} rows deleted\n", did, deleted)
+                                            .as_bytes(),
+                                    )
+                                    .await?;
+                            }
+                            Err(e) => {
+                                writer
+                                    .write_all(format!("Failed to purge user: {}\n", e).as_bytes())
+                                    .await?;
+                            }
+                        }
+                    }
+                    Some(did) => {
+                        writer
+                            .write_all(
+                                format!(
+                                    "This deletes everything stored for {}. Run `purge-user {} {}--confirm` to go ahead\n",
+                                    did,
+                                    did,
+                                    if cascade_posts { "--cascade-posts " } else { "" }
+                                )
+                                .as_bytes(),
+                            )
+                            .await?;
+                    }
+                    None => {
+                        writer
+                            .write_all(b"Usage: purge-user <did> [--cascade-posts] --confirm\n")
+                            .await?;
+                    }
+                }
+            }
+            Some("search-posts") if parts.len() > 1 => {
+                let query = parts[1..].join(" ");
+                match db.search_posts(&query, SEARCH_RESULTS).await {
+                    Ok(posts) if posts.is_empty() => {
+                        writer.write_all(b"No matching posts\n").await?;
+                    }
+                    Ok(posts) => {
+                        for post in posts {
+                            writer
+                                .write_all(
+                                    format!("{}  {}\n", post.uri, post.author_did).as_bytes(),
+                                )
+                                .await?;
+                        }
+                    }
+                    Err(e) => {
+                        writer
+                            .write_all(format!("Search failed: {}\n", e).as_bytes())
+                            .await?;
+                    }
+                }
+            }
+            Some("search-posts") => {
+                writer.write_all(b"Usage: search-posts <query>\n").await?;
+            }
+            Some("stats-user") => match parts.get(1) {
+                Some(did) => {
+                    let reply = match format_user_stats(&db, did).await {
+                        Ok(reply) => reply,
+                        Err(e) => format!("Failed to get stats for {}: {}\n", did, e),
+                    };
+                    writer.write_all(reply.as_bytes()).await?;
+                }
+                None => writer.write_all(b"Usage: stats-user <did>\n").await?,
+            },
+            Some("resolve-did") => match parts.get(1) {
+                Some(did) => {
+                    let reply = match db.get_profile(did).await {
+                        Ok(Some(profile)) => format_profile(&profile),
+                        Ok(None) => format!("No profile stored for {}\n", did),
+                        Err(e) => format!("Failed to get profile: {}\n", e),
+                    };
+                    writer.write_all(reply.as_bytes()).await?;
+                }
+                None => writer.write_all(b"Usage: resolve-did <did>\n").await?,
+            },
+            Some("top-authors") => match parts.get(1).map_or(Ok(20), |n| n.parse::<usize>()) {
+                Ok(limit) => match db.get_top_followed_authors(limit).await {
+                    Ok(authors) => {
+                        writer
+                            .write_all(
+                                format!("{:>4}  {:>9}  DID\n", "Rank", "Followers").as_bytes(),
+                            )
+                            .await?;
+                        for (rank, (did, count)) in authors.iter().enumerate() {
+                            let author = db.describe_did(did).await;
+                            writer
+                                .write_all(
+                                    format!("{:>4}  {:>9}  {}\n", rank + 1, count, author)
+                                        .as_bytes(),
+                                )
+                                .await?;
+                        }
+                    }
+                    Err(e) => {
+                        writer
+                            .write_all(format!("Failed to get top authors: {}\n", e).as_bytes())
+                            .await?;
+                    }
+                },
+                Err(_) => {
+                    writer.write_all(b"Usage: top-authors [N]\n").await?;
+                }
+            },
+            Some("feed") => {
+                let json = parts.contains(&"--json");
+                let mut args = parts[1..].iter().filter(|part| **part != "--json");
+                let did = args.next().filter(|did| did.starts_with("did:"));
+                let limit = args
+                    .next()
+                    .map(|n| n.parse::<i32>().ok().filter(|n| *n > 0));
+                let cursor = args.next().map(|c| c.to_string());
+                match (did, limit) {
+                    (Some(did), None | Some(Some(_))) => {
+                        let limit = limit.flatten();
+                        let feed = FollowingNoRepostsFeed::new(Arc::clone(&db))
+                            .with_settings(info.feed_settings);
+                        let result = match feed
+                            .generate_feed(Some(did.to_string()), limit, cursor)
+                            .await
+                        {
+                            Ok(page) => {
+                                let uris: Vec<String> = page
+                                    .response
+                                    .feed
+                                    .iter()
+                                    .map(|item| item.post.clone())
+                                    .collect();
+                                db.get_posts_by_uris(&uris).await.map(|posts| {
+                                    let posts =
+                                        posts.into_iter().map(|p| (p.uri.clone(), p)).collect();
+                                    format_feed_preview(did, &page.response, &posts, json)
+                                })
+                            }
+                            Err(e) => Err(e),
+                        };
+                        match result {
+                            Ok(preview) => writer.write_all(preview.as_bytes()).await?,
+                            Err(e) => {
+                                writer
+                                    .write_all(
+                                        format!("Failed to generate feed: {}\n", e).as_bytes(),
+                                    )
+                                    .await?;
+                            }
+                        }
+                    }
+                    _ => {
+                        writer
+                            .write_all(b"Usage: feed <did> [limit] [cursor] [--json]\n")
+                            .await?;
+                    }
+                }
+            }
+            Some("cleanup") => {
+                let reply =
+                    run_cleanup(&db, &budget, &parts[1..], info.feed_settings, &mut writer).await?;
+                writer.write_all(reply.as_bytes()).await?;
+            }
+            Some("stats") if format == ResponseFormatter::Json => {
+                let result = db
+                    .get_stats()
+                    .await
+                    .map(|stats| {
+                        serde_json::json!({
+                            "posts": stats.posts,
+                            "follows": stats.follows,
+                            "users": stats.users,
+                        })
+                    })
+                    .map_err(|e| format!("Failed to get stats: {}", e));
+                writer
+                    .write_all(ResponseFormatter::json("stats", result).as_bytes())
+                    .await?;
+            }
+            Some("stats") if parts.get(1) == Some(&"--json") => {
+                let reply = match db.get_stats().await {
+                    Ok(stats) => serde_json::to_string_pretty(&stats)? + "\n",
+                    Err(e) => format!("Failed to get stats: {}\n", e),
+                };
+                writer.write_all(reply.as_bytes()).await?;
             }
             Some("stats") => match get_stats(&db).await {
                 Ok(stats) => {
                     writer.write_all(stats.as_bytes()).await?;
+                    writer.write_all(budget.format_stats().as_bytes()).await?;
+                    if let Some(ingest) = &ingest {
+                        writer.write_all(ingest.format_stats().as_bytes()).await?;
+                    }
                 }
                 Err(e) => {
                     writer
@@ -132,13 +1174,120 @@ async fn handle_connection(stream: UnixStream, db: Arc<Database>) -> Result<()>
                         .await?;
                 }
             },
+            Some("request-stats") => match &info.requests {
+                Some(requests) => writer.write_all(requests.format_stats().as_bytes()).await?,
+                None => {
+                    writer
+                        .write_all(b"Request stats are not being collected\n")
+                        .await?
+                }
+            },
+            Some("version" | "describe") => {
+                let stats = ingest.as_ref().map(IngestQueue::stats);
+                writer
+                    .write_all(format_version(&info, stats.as_ref()).as_bytes())
+                    .await?;
+            }
             Some("help") => {
                 writer.write_all(b"Available commands:\n").await?;
                 writer
-                    .write_all(b"  backfill <did>  - Backfill follows and posts for a user\n")
+                    .write_all(b"  backfill <did|handle> [n] - Backfill follows and n posts per follow for a user\n")
+                    .await?;
+                writer
+                    .write_all(b"  set-retention <did> <hours> - Keep posts for a user's follows this long\n")
+                    .await?;
+                writer
+                    .write_all(b"  set-author-cap <did> <n|off> - Show at most n posts per author per day\n")
+                    .await?;
+                writer
+                    .write_all(b"  set-author-max <did> <n|off> - Show at most n posts per author in the feed\n")
+                    .await?;
+                writer
+                    .write_all(b"  set-min-length <did> <n|off> - Hide text posts shorter than n characters\n")
+                    .await?;
+                writer
+                    .write_all(b"  stranger-quotes <did> <show|hide> - Show or hide quotes of non-follows\n")
+                    .await?;
+                writer
+                    .write_all(b"  add-tag-block <did> <tag> - Hide posts with a hashtag from a user's feeds\n")
+                    .await?;
+                writer
+                    .write_all(
+                        b"  remove-tag-block <did> <tag> - Show posts with a hashtag again\n",
+                    )
+                    .await?;
+                writer
+                    .write_all(
+                        b"  explain <did> <post-uri> [feed] - Show which feed rule hides a post\n",
+                    )
+                    .await?;
+                writer
+                    .write_all(b"  explain <did> - Show how SQLite runs the user's feed query\n")
+                    .await?;
+                writer
+                    .write_all(
+                        b"  pin <post-uri>  - Show a post at the top of every first feed page\n",
+                    )
+                    .await?;
+                writer
+                    .write_all(b"  unpin <post-uri> - Stop pinning a post\n")
+                    .await?;
+                writer
+                    .write_all(b"  restore-post <post-uri> - Undo a post's deletion before cleanup removes it\n")
+                    .await?;
+                writer
+                    .write_all(b"  purge-user <did> [--cascade-posts] --confirm - Delete a user's follows and settings, and with --cascade-posts the posts only they needed\n")
+                    .await?;
+                writer
+                    .write_all(b"  search-posts <query> - List stored posts matching words in their text\n")
+                    .await?;
+                writer
+                    .write_all(
+                        b"  top-authors [N] - Show the N most followed authors (default 20)\n",
+                    )
+                    .await?;
+                writer
+                    .write_all(
+                        b"  feed <did> [limit] [cursor] [--json] - Preview a user's default feed\n",
+                    )
+                    .await?;
+                writer
+                    .write_all(b"  cleanup posts [hours] - Delete posts past retention now (default: server retention)\n")
+                    .await?;
+                writer
+                    .write_all(b"  cleanup follows - Re-sync active users' follows and drop inactive users'\n")
+                    .await?;
+                writer
+                    .write_all(b"  cleanup authors - Delete posts by authors nobody follows\n")
+                    .await?;
+                writer
+                    .write_all(b"  cleanup ... --dry-run - Count what a cleanup would delete, deleting nothing\n")
+                    .await?;
+                writer
+                    .write_all(b"  stats [--json]  - Show database statistics\n")
+                    .await?;
+                writer
+                    .write_all(
+                        b"  stats-user <did> - Show a user's profile, follows and feed requests\n",
+                    )
+                    .await?;
+                writer
+                    .write_all(
+                        b"  resolve-did <did> - Show the stored handle and display name of a DID\n",
+                    )
                     .await?;
                 writer
-                    .write_all(b"  stats           - Show database statistics\n")
+                    .write_all(b"  mode <text|json> - Reply in text, or one JSON object per line\n")
+                    .await?;
+                writer
+                    .write_all(
+                        b"  request-stats   - Show HTTP request counts and latency percentiles\n",
+                    )
+                    .await?;
+                writer
+                    .write_all(
+                        b"  version         - Show the build, uptime and Jetstream position\n",
+                    )
                     .await?;
                 writer
                     .write_all(b"  help            - Show this help message\n")
@@ -147,6 +1296,14 @@ async fn handle_connection(stream: UnixStream, db: Arc<Database>) -> Result<()>
                     .write_all(b"  quit            - Close connection\n")
                     .await?;
             }
+            Some(command @ ("quit" | "exit")) if format == ResponseFormatter::Json => {
+                let data = serde_json::json!({});
+                writer
+                    .write_all(ResponseFormatter::json(command, Ok(data)).as_bytes())
+                    .await?;
+                writer.flush().await?;
+                break;
+            }
             Some("quit") | Some("exit") => {
                 writer.write_all(b"Goodbye!\n").await?;
                 writer.flush().await?;
@@ -165,31 +1322,425 @@ async fn handle_connection(stream: UnixStream, db: Arc<Database>) -> Result<()>
             }
         }
 
-        writer.write_all(b"> ").await?;
+        writer.write_all(format.prompt()).await?;
         writer.flush().await?;
     }
 
     Ok(())
 }
 
+fn format_profile(profile: &ProfileInfo) -> String {
+    format!(
+        "{}\n  Handle: {}\n  Display name: {}\n",
+        profile.did,
+        profile
+            .handle
+            .as_deref()
+            .map_or("unknown".to_string(), |handle| format!("@{}", handle)),
+        profile.display_name.as_deref().unwrap_or("unknown")
+    )
+}
+
+/// A user's profile, follows and feed activity
+async fn format_user_stats(db: &Database, did: &str) -> Result<String> {
+    let mut out = match db.get_profile(did).await? {
+        Some(profile) => format_profile(&profile),
+        None => format!("{}\n", did),
+    };
+    out.push_str(&format!(
+        "  Followed by: {}\n",
+        db.get_follow_count_for_author(did).await?
+    ));
+    match db.get_active_user_summary(did).await? {
+        Some(user) => out.push_str(&format!(
+            "  Follows: {}\n  Feed requests: {} (first {}, last {})\n",
+            user.follows, user.request_count, user.first_seen, user.last_feed_request
+        )),
+        None => out.push_str("  Feed requests: none\n"),
+    }
+    Ok(out)
+}
+
 async fn get_stats(db: &Database) -> Result<String> {
-    let post_count: i64 = sqlx::query("SELECT COUNT(*) as count FROM posts")
-        .fetch_one(&db.pool)
-        .await?
-        .try_get("count")?;
-
-    let follow_count: i64 = sqlx::query("SELECT COUNT(*) as count FROM follows")
-        .fetch_one(&db.pool)
-        .await?
-        .try_get("count")?;
-
-    let user_count: i64 = sqlx::query("SELECT COUNT(DISTINCT follower_did) as count FROM follows")
-        .fetch_one(&db.pool)
-        .await?
-        .try_get("count")?;
-
-    Ok(format!(
-        "Database Statistics:\n  Posts: {}\n  Follows: {}\n  Users: {}\n",
-        post_count, follow_count, user_count
-    ))
+    let stats = db.get_stats().await?;
+    let mut out = format!(
+        "Database Statistics:\n  Posts: {} ({} in the last hour, {} in the last day)\n  Follows: {} ({} in the last day)\n  Users: {}\n  Authors: {}\n  Daily active users: {}\n  Weekly active users: {}\n",
+        stats.posts,
+        stats.posts_last_hour,
+        stats.posts_last_day,
+        stats.follows,
+        stats.follows_last_day,
+        stats.users,
+        stats.authors,
+        stats.daily_active,
+        stats.weekly_active
+    );
+    if let (Some(oldest), Some(newest)) = (stats.oldest_post, stats.newest_post) {
+        out.push_str(&format!(
+            "  Posts indexed: {} to {}\n",
+            oldest.to_rfc3339(),
+            newest.to_rfc3339()
+        ));
+    }
+    match db.get_jetstream_cursor().await? {
+        Some(cursor) => {
+            let lag_secs = (chrono::Utc::now().timestamp_micros() - cursor).max(0) / 1_000_000;
+            out.push_str(&format!(
+                "  Saved Jetstream cursor: {} ({}s behind)\n",
+                cursor, lag_secs
+            ));
+        }
+        None => out.push_str("  Saved Jetstream cursor: none\n"),
+    }
+    if let Some(sizes) = stats.file_sizes {
+        out.push_str(&format!(
+            "  Database file: {:.1} MiB\n  WAL file: {:.1} MiB\n",
+            sizes.db_bytes as f64 / 1_048_576.0,
+            sizes.wal_bytes as f64 / 1_048_576.0
+        ));
+    }
+    match db.last_maintenance() {
+        Some(at) => out.push_str(&format!("  Last maintenance: {}\n", at.to_rfc3339())),
+        None => out.push_str("  Last maintenance: not since startup\n"),
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    async fn run_session<S>(mut stream: S, input: &str) -> Result<String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        stream.write_all(input.as_bytes()).await?;
+        let mut output = String::new();
+        stream.read_to_string(&mut output).await?;
+        Ok(output)
+    }
+
+    async fn test_console(secret: Option<&str>) -> Result<Arc<AdminSocket>> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let socket_path = std::env::temp_dir()
+            .join(format!("admin-{}.sock", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string();
+        Ok(Arc::new(
+            AdminSocket::new(
+                db,
+                Arc::new(ApiBudget::default()),
+                Arc::new(IdentityCache::default()),
+                socket_path,
+            )
+            .with_secret(secret.map(|s| s.to_string())),
+        ))
+    }
+
+    async fn spawn_tcp(admin: &Arc<AdminSocket>) -> Result<std::net::SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let admin = Arc::clone(admin);
+        tokio::spawn(async move { admin.serve_tcp(listener).await });
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn test_tcp_stats_match_unix_socket() -> Result<()> {
+        let admin = test_console(None).await?;
+        let addr = spawn_tcp(&admin).await?;
+
+        let unix_admin = Arc::clone(&admin);
+        tokio::spawn(async move { unix_admin.start().await });
+        let mut unix_stream = None;
+        for _ in 0..100 {
+            if let Ok(stream) = tokio::net::UnixStream::connect(&admin.socket_path).await {
+                unix_stream = Some(stream);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let tcp_output =
+            run_session(tokio::net::TcpStream::connect(addr).await?, "stats\nquit\n").await?;
+        let unix_output = run_session(unix_stream.unwrap(), "stats\nquit\n").await?;
+        let _ = std::fs::remove_file(&admin.socket_path);
+
+        assert!(tcp_output.contains("Database Statistics:"));
+        assert!(tcp_output.contains("API Budget:"));
+        assert_eq!(tcp_output, unix_output);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tcp_requires_secret() -> Result<()> {
+        let admin = test_console(Some("hunter2")).await?;
+        let addr = spawn_tcp(&admin).await?;
+
+        let rejected =
+            run_session(tokio::net::TcpStream::connect(addr).await?, "stats\nquit\n").await?;
+        assert!(rejected.contains("Authentication failed"));
+        assert!(!rejected.contains("Database Statistics:"));
+
+        let accepted = run_session(
+            tokio::net::TcpStream::connect(addr).await?,
+            "hunter2\nstats\nquit\n",
+        )
+        .await?;
+        assert!(accepted.contains("Database Statistics:"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_version() {
+        assert_eq!(format_uptime(59), "0h 0m 59s");
+        assert_eq!(format_uptime(90061), "1d 1h 1m 1s");
+
+        let info = ServerInfo {
+            started_at: Instant::now(),
+            service_did: Some("did:web:feed.example.com".to_string()),
+            jetstream_hostname: Some("jetstream.example.com".to_string()),
+            feed_settings: FeedSettings::default(),
+            backfill: BackfillLimits::default(),
+            requests: None,
+        };
+        let out = format_version(&info, None);
+        assert!(out.starts_with(&format!("Version: {} (", env!("CARGO_PKG_VERSION"))));
+        assert!(out.contains("Service DID: did:web:feed.example.com"));
+        assert!(out.contains("Jetstream: jetstream.example.com"));
+        assert!(out.contains("Jetstream cursor: no events yet"));
+
+        let cursor = chrono::Utc::now().timestamp_micros() - 3_000_000;
+        let stats = IngestStats {
+            depth: 0,
+            capacity: 10,
+            written: 1,
+            failed: 0,
+            batches: 1,
+            last_event_us: Some(cursor),
+            written_us: Some(cursor),
+            connection_failures: 0,
+            lag: Some(crate::jetstream_consumer::IngestLag {
+                average_ms: 1500,
+                max_ms: 4000,
+            }),
+        };
+        let out = format_version(&info, Some(&stats));
+        assert!(out.contains(&format!("Jetstream cursor: {} (last event 3s ago)", cursor)));
+        assert!(out.contains("Ingest lag: 1.5s average, 4.0s max"));
+    }
+
+    #[tokio::test]
+    async fn test_forward_progress_writes_lines_in_order() -> Result<()> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let step = async {
+            tx.send("backfilled 100 follows".to_string())?;
+            tokio::task::yield_now().await;
+            tx.send("backfilled 150 follows".to_string())?;
+            Ok(())
+        };
+
+        let mut output = Vec::new();
+        forward_progress(&mut output, rx, step).await??;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "backfilled 100 follows\nbackfilled 150 follows\n"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_posts_command() -> Result<()> {
+        let admin = test_console(None).await?;
+        let addr = spawn_tcp(&admin).await?;
+        let post = |rkey: &str, age_hours: i64| crate::types::Post {
+            uri: format!("at://did:example:bob/app.bsky.feed.post/{}", rkey),
+            cid: "cid".to_string(),
+            author_did: "did:example:bob".to_string(),
+            text: "hello".to_string(),
+            reply_parent_uri: None,
+            reply_root_uri: None,
+            quoted_uri: None,
+            is_link_only: false,
+            has_media: false,
+            embed_type: None,
+            hashtags: Vec::new(),
+            created_at: chrono::Utc::now() - chrono::Duration::hours(age_hours),
+            indexed_at: chrono::Utc::now() - chrono::Duration::hours(age_hours),
+        };
+        for (rkey, age_hours) in [("new", 1), ("day", 30), ("old", 50)] {
+            admin.db.insert_post(&post(rkey, age_hours)).await?;
+        }
+
+        let output = run_session(
+            tokio::net::TcpStream::connect(addr).await?,
+            "cleanup posts --dry-run\ncleanup posts\ncleanup posts 24\ncleanup posts soon\nquit\n",
+        )
+        .await?;
+        assert!(output.contains("Would delete 1 posts, 0 follows past retention (default 48h)"));
+        assert!(output.contains("Deleted 1 posts, 0 follows past retention (default 48h)"));
+        assert!(output.contains("Deleted 1 posts, 0 follows past retention (default 24h)"));
+        assert!(output.contains("Usage: cleanup posts [hours]"));
+        assert_eq!(admin.db.get_stats().await?.posts, 1);
+
+        // A dry run of the follow pass names the users it would affect
+        admin
+            .db
+            .insert_follow(&crate::types::Follow {
+                uri: "at://did:example:carol/app.bsky.graph.follow/1".to_string(),
+                follower_did: "did:example:carol".to_string(),
+                target_did: "did:example:bob".to_string(),
+                created_at: chrono::Utc::now(),
+                indexed_at: chrono::Utc::now(),
+            })
+            .await?;
+        let output = run_session(
+            tokio::net::TcpStream::connect(addr).await?,
+            "cleanup follows --dry-run\nquit\n",
+        )
+        .await?;
+        assert!(output.contains("Would delete 0 posts, 1 follows of inactive users"));
+        assert!(output.contains("  did:example:carol: 1 follows\n"));
+        assert_eq!(admin.db.get_stats().await?.follows, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_feed_preview_command() -> Result<()> {
+        let admin = test_console(None).await?;
+        let addr = spawn_tcp(&admin).await?;
+        admin
+            .db
+            .insert_follow(&crate::types::Follow {
+                uri: "at://did:example:alice/app.bsky.graph.follow/1".to_string(),
+                follower_did: "did:example:alice".to_string(),
+                target_did: "did:example:bob".to_string(),
+                created_at: chrono::Utc::now(),
+                indexed_at: chrono::Utc::now(),
+            })
+            .await?;
+        for minutes in [1, 2, 3] {
+            let created_at = chrono::Utc::now() - chrono::Duration::minutes(minutes);
+            admin
+                .db
+                .insert_post(&Post {
+                    uri: format!("at://did:example:bob/app.bsky.feed.post/{}", minutes),
+                    cid: "cid".to_string(),
+                    author_did: "did:example:bob".to_string(),
+                    text: "hello".to_string(),
+                    reply_parent_uri: None,
+                    reply_root_uri: None,
+                    quoted_uri: None,
+                    is_link_only: false,
+                    has_media: false,
+                    embed_type: None,
+                    hashtags: Vec::new(),
+                    created_at,
+                    indexed_at: created_at,
+                })
+                .await?;
+        }
+
+        let output = run_session(
+            tokio::net::TcpStream::connect(addr).await?,
+            "feed did:example:alice 2\nquit\n",
+        )
+        .await?;
+        assert!(output.contains("Feed for did:example:alice (2 items)"));
+        assert!(output.contains("did:example:bob  at://did:example:bob/app.bsky.feed.post/1"));
+        assert!(!output.contains("app.bsky.feed.post/3"));
+
+        let output = run_session(
+            tokio::net::TcpStream::connect(addr).await?,
+            "feed did:example:alice 2 --json\nquit\n",
+        )
+        .await?;
+        let line = output
+            .lines()
+            .find_map(|line| line.trim_start_matches("> ").strip_prefix('{'))
+            .unwrap();
+        let preview: serde_json::Value = serde_json::from_str(&format!("{{{}", line))?;
+        assert_eq!(preview["did"], "did:example:alice");
+        assert_eq!(preview["feed"].as_array().unwrap().len(), 2);
+        assert_eq!(preview["feed"][0]["author_did"], "did:example:bob");
+        assert!(preview["feed"][0]["reason"].is_null());
+
+        // The cursor picks up where the page left off
+        let cursor = preview["cursor"].as_str().unwrap();
+        let output = run_session(
+            tokio::net::TcpStream::connect(addr).await?,
+            &format!("feed did:example:alice 2 {}\nquit\n", cursor),
+        )
+        .await?;
+        assert!(output.contains("(1 items)"));
+        assert!(output.contains("app.bsky.feed.post/3"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_json_mode() -> Result<()> {
+        let admin = test_console(None).await?;
+        let addr = spawn_tcp(&admin).await?;
+
+        let output = run_session(
+            tokio::net::TcpStream::connect(addr).await?,
+            "mode json\nstats\nbackfill did:example:alice 0\nversion\nquit\n",
+        )
+        .await?;
+        // Everything after the greeting's prompt is one JSON object per line
+        let (_, replies) = output.split_once("quit\n> ").unwrap();
+        let replies = replies
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<serde_json::Result<Vec<serde_json::Value>>>()?;
+        assert_eq!(replies.len(), 5);
+
+        assert_eq!(replies[0]["command"], "mode");
+        assert_eq!(replies[0]["status"], "ok");
+        assert_eq!(replies[1]["command"], "stats");
+        for field in ["posts", "follows", "users"] {
+            assert!(replies[1]["data"][field].is_u64(), "{}", field);
+        }
+        assert_eq!(replies[2]["status"], "error");
+        assert!(replies[2]["data"]["message"].is_string());
+        assert_eq!(replies[3]["command"], "version");
+        assert_eq!(replies[3]["status"], "error");
+        assert_eq!(replies[4]["status"], "ok");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stats_user_shows_display_name() -> Result<()> {
+        let admin = test_console(None).await?;
+        let addr = spawn_tcp(&admin).await?;
+        admin.db.record_feed_request("did:example:alice").await?;
+        admin
+            .db
+            .upsert_profile("did:example:alice", None, Some("Alice Example"))
+            .await?;
+        admin
+            .db
+            .set_handle("did:example:alice", "alice.example.com")
+            .await?;
+
+        let output = run_session(
+            tokio::net::TcpStream::connect(addr).await?,
+            "stats-user did:example:alice\nresolve-did did:example:bob\nquit\n",
+        )
+        .await?;
+        assert!(output.contains("Display name: Alice Example"), "{}", output);
+        assert!(output.contains("Handle: @alice.example.com"), "{}", output);
+        assert!(output.contains("Feed requests: 1"), "{}", output);
+        assert!(output.contains("No profile stored for did:example:bob"));
+
+        Ok(())
+    }
 }
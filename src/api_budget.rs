@@ -0,0 +1,484 @@
+use anyhow::Result;
+use reqwest::header::HeaderMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+use tokio::time::{Duration, Instant};
+use tracing::debug;
+
+/// Default budget: the public AppView allows 3000 requests per 5 minutes per IP
+const DEFAULT_CAPACITY: f64 = 3000.0;
+const DEFAULT_REFILL_PER_SEC: f64 = 10.0;
+
+/// A waiting class passed over this many times gets the next token regardless of priority
+const STARVATION_LIMIT: u32 = 4;
+
+/// Who is asking for budget, in priority order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Backfill for a user who is waiting on their feed
+    Interactive = 0,
+    /// Periodic follow verification
+    Verification = 1,
+}
+
+impl Priority {
+    const ALL: [Priority; 2] = [Priority::Interactive, Priority::Verification];
+
+    /// How long a caller of this class waits before deferring its job
+    fn deadline(self) -> Duration {
+        match self {
+            Priority::Interactive => Duration::from_secs(30),
+            Priority::Verification => Duration::from_secs(10),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Interactive => "interactive",
+            Priority::Verification => "verification",
+        }
+    }
+}
+
+/// Returned when a caller could not acquire budget before its deadline.
+/// Jobs should treat this as "try again later" rather than a failure.
+#[derive(Debug)]
+pub struct BudgetDeferred(pub Priority);
+
+impl fmt::Display for BudgetDeferred {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "API budget exhausted for {} requests", self.0.name())
+    }
+}
+
+impl std::error::Error for BudgetDeferred {}
+
+/// Whether an error means the job should defer itself
+pub fn is_deferred(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<BudgetDeferred>().is_some()
+}
+
+struct BucketState {
+    tokens: f64,
+    capacity: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+    waiting: [usize; 2],
+    skipped: [u32; 2],
+}
+
+impl BucketState {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Picks the class that gets the next token: a starved class first,
+    /// otherwise the highest priority class with waiters
+    fn next_class(&self) -> Option<Priority> {
+        let starved = Priority::ALL
+            .iter()
+            .filter(|p| self.waiting[**p as usize] > 0)
+            .filter(|p| self.skipped[**p as usize] >= STARVATION_LIMIT)
+            .max_by_key(|p| self.skipped[**p as usize]);
+
+        starved
+            .or_else(|| {
+                Priority::ALL
+                    .iter()
+                    .find(|p| self.waiting[**p as usize] > 0)
+            })
+            .copied()
+    }
+
+    fn grant(&mut self, priority: Priority) {
+        self.tokens -= 1.0;
+        for other in Priority::ALL {
+            if other != priority && self.waiting[other as usize] > 0 {
+                self.skipped[other as usize] += 1;
+            }
+        }
+        self.skipped[priority as usize] = 0;
+    }
+
+    fn time_to_next_token(&self) -> Duration {
+        if self.refill_per_sec <= 0.0 {
+            return Duration::from_secs(1);
+        }
+        let missing = (1.0 - self.tokens).max(0.0);
+        Duration::from_secs_f64(missing / self.refill_per_sec).max(Duration::from_millis(1))
+    }
+}
+
+#[derive(Default)]
+struct ClassMetrics {
+    acquired: AtomicU64,
+    rejected: AtomicU64,
+    wait_ms: AtomicU64,
+}
+
+/// Snapshot of one class's budget metrics
+#[derive(Debug, Clone, Copy)]
+pub struct ClassStats {
+    pub priority: Priority,
+    pub acquired: u64,
+    pub rejected: u64,
+    pub total_wait_ms: u64,
+}
+
+/// Token bucket shared by every outbound AppView request
+pub struct ApiBudget {
+    state: Mutex<BucketState>,
+    notify: Notify,
+    metrics: [ClassMetrics; 2],
+}
+
+impl ApiBudget {
+    pub fn new(capacity: f64, refill_per_sec: f64) -> Self {
+        Self {
+            state: Mutex::new(BucketState {
+                tokens: capacity,
+                capacity,
+                refill_per_sec,
+                last_refill: Instant::now(),
+                waiting: [0; 2],
+                skipped: [0; 2],
+            }),
+            notify: Notify::new(),
+            metrics: Default::default(),
+        }
+    }
+
+    /// Waits for a token, giving up after the class deadline
+    pub async fn acquire(&self, priority: Priority) -> Result<(), BudgetDeferred> {
+        self.acquire_within(priority, priority.deadline()).await
+    }
+
+    async fn acquire_within(
+        &self,
+        priority: Priority,
+        timeout: Duration,
+    ) -> Result<(), BudgetDeferred> {
+        let started = Instant::now();
+        let deadline = started + timeout;
+        let metrics = &self.metrics[priority as usize];
+
+        let _waiting = Waiting::new(self, priority);
+
+        loop {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                state.refill();
+                if state.tokens >= 1.0 && state.next_class() == Some(priority) {
+                    state.grant(priority);
+                    None
+                } else {
+                    Some(state.time_to_next_token())
+                }
+            };
+
+            let Some(wait) = wait else {
+                let waited = started.elapsed().as_millis() as u64;
+                metrics.acquired.fetch_add(1, Ordering::Relaxed);
+                metrics.wait_ms.fetch_add(waited, Ordering::Relaxed);
+                return Ok(());
+            };
+
+            let now = Instant::now();
+            if now >= deadline {
+                metrics.rejected.fetch_add(1, Ordering::Relaxed);
+                metrics
+                    .wait_ms
+                    .fetch_add(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+                return Err(BudgetDeferred(priority));
+            }
+
+            let _ = tokio::time::timeout(wait.min(deadline - now), notified).await;
+        }
+    }
+
+    /// Adjusts the bucket to the AppView's `ratelimit-*` response headers
+    pub fn observe_headers(&self, headers: &HeaderMap) {
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+        let mut state = self.state.lock().unwrap();
+        state.refill();
+
+        // e.g. "3000;w=300": 3000 requests per 300 seconds
+        if let Some((limit, window)) = header("ratelimit-policy").and_then(parse_policy) {
+            state.capacity = limit;
+            state.refill_per_sec = limit / window;
+        }
+
+        if let Some(remaining) = header("ratelimit-remaining").and_then(|v| v.parse::<f64>().ok()) {
+            state.tokens = state.tokens.min(remaining);
+        }
+
+        debug!(
+            "API budget: {:.0}/{:.0} tokens, refill {:.2}/s",
+            state.tokens, state.capacity, state.refill_per_sec
+        );
+        drop(state);
+        self.notify.notify_waiters();
+    }
+
+    /// Fetches a JSON document from the AppView once budget is available.
+    /// A 429 defers the caller like an exhausted budget, and other error
+    /// statuses fail rather than passing back the error body.
+    pub async fn get_json(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        priority: Priority,
+    ) -> Result<serde_json::Value> {
+        self.acquire(priority).await?;
+        let response = client.get(url).send().await?;
+        self.observe_headers(response.headers());
+        if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            // The AppView knows better than our estimate: wait for a refill
+            self.state.lock().unwrap().tokens = 0.0;
+            self.metrics[priority as usize]
+                .rejected
+                .fetch_add(1, Ordering::Relaxed);
+            return Err(BudgetDeferred(priority).into());
+        }
+        Ok(response.error_for_status()?.json().await?)
+    }
+
+    pub fn stats(&self) -> Vec<ClassStats> {
+        Priority::ALL
+            .iter()
+            .map(|p| {
+                let metrics = &self.metrics[*p as usize];
+                ClassStats {
+                    priority: *p,
+                    acquired: metrics.acquired.load(Ordering::Relaxed),
+                    rejected: metrics.rejected.load(Ordering::Relaxed),
+                    total_wait_ms: metrics.wait_ms.load(Ordering::Relaxed),
+                }
+            })
+            .collect()
+    }
+
+    /// Human-readable metrics for the admin console
+    pub fn format_stats(&self) -> String {
+        let mut out = String::from("API Budget:\n");
+        for stats in self.stats() {
+            out.push_str(&format!(
+                "  {}: {} acquired, {} deferred, {} ms waited\n",
+                stats.priority.name(),
+                stats.acquired,
+                stats.rejected,
+                stats.total_wait_ms
+            ));
+        }
+        out
+    }
+
+    #[cfg(test)]
+    fn add_tokens(&self, tokens: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.tokens = (state.tokens + tokens).min(state.capacity);
+        drop(state);
+        self.notify.notify_waiters();
+    }
+}
+
+/// Counts a caller as waiting for its class until dropped, so a caller
+/// that gives up or is cancelled never stays ahead of the other classes
+struct Waiting<'a> {
+    budget: &'a ApiBudget,
+    priority: Priority,
+}
+
+impl<'a> Waiting<'a> {
+    fn new(budget: &'a ApiBudget, priority: Priority) -> Self {
+        budget.state.lock().unwrap().waiting[priority as usize] += 1;
+        Self { budget, priority }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.budget.state.lock().unwrap().waiting[self.priority as usize] -= 1;
+        // Let the other waiters re-evaluate who is next
+        self.budget.notify.notify_waiters();
+    }
+}
+
+impl Default for ApiBudget {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, DEFAULT_REFILL_PER_SEC)
+    }
+}
+
+fn parse_policy(policy: &str) -> Option<(f64, f64)> {
+    let (limit, window) = policy.split_once(";w=")?;
+    let limit: f64 = limit.trim().parse().ok()?;
+    let window: f64 = window.trim().parse().ok()?;
+    (window > 0.0).then_some((limit, window))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    async fn wait_for_grants(grants: &Mutex<Vec<Priority>>, count: usize) {
+        for _ in 0..200 {
+            if grants.lock().unwrap().len() >= count {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("timed out waiting for {} grants", count);
+    }
+
+    #[tokio::test]
+    async fn test_priority_order_and_fairness_floor() {
+        let budget = Arc::new(ApiBudget::new(1.0, 0.0));
+        budget.acquire(Priority::Interactive).await.unwrap(); // drain the bucket
+
+        let grants = Arc::new(Mutex::new(Vec::new()));
+        for priority in [Priority::Verification, Priority::Interactive] {
+            for _ in 0..6 {
+                let budget = Arc::clone(&budget);
+                let grants = Arc::clone(&grants);
+                tokio::spawn(async move {
+                    if budget.acquire(priority).await.is_ok() {
+                        grants.lock().unwrap().push(priority);
+                    }
+                });
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        for granted in 1..=6 {
+            budget.add_tokens(1.0);
+            wait_for_grants(&grants, granted).await;
+        }
+
+        // Interactive callers go first, but verification gets a turn after
+        // being passed over STARVATION_LIMIT times
+        let grants = grants.lock().unwrap().clone();
+        assert_eq!(
+            grants,
+            vec![
+                Priority::Interactive,
+                Priority::Interactive,
+                Priority::Interactive,
+                Priority::Interactive,
+                Priority::Verification,
+                Priority::Interactive,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deadline_defers() {
+        let budget = ApiBudget::new(1.0, 0.0);
+        budget.acquire(Priority::Interactive).await.unwrap();
+
+        let started = std::time::Instant::now();
+        let result = budget
+            .acquire_within(Priority::Verification, Duration::from_millis(50))
+            .await;
+        assert!(result.is_err());
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        let stats = budget.stats();
+        assert_eq!(stats[Priority::Verification as usize].rejected, 1);
+        assert_eq!(stats[Priority::Interactive as usize].acquired, 1);
+    }
+
+    #[tokio::test]
+    async fn test_error_statuses_are_not_returned_as_json() -> Result<()> {
+        use axum::{http::StatusCode, routing::get, Json, Router};
+
+        let app = Router::new()
+            .route(
+                "/ok",
+                get(|| async { Json(serde_json::json!({ "follows": [] })) }),
+            )
+            .route(
+                "/limited",
+                get(|| async {
+                    (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        Json(serde_json::json!({ "error": "RateLimitExceeded" })),
+                    )
+                }),
+            )
+            .route(
+                "/broken",
+                get(|| async {
+                    (
+                        StatusCode::BAD_GATEWAY,
+                        Json(serde_json::json!({ "error": "UpstreamFailure" })),
+                    )
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        let budget = ApiBudget::new(10.0, 0.0);
+        let client = reqwest::Client::new();
+        let get = |path: &str| {
+            let url = format!("{}{}", base, path);
+            let budget = &budget;
+            let client = &client;
+            async move { budget.get_json(client, &url, Priority::Verification).await }
+        };
+        assert_eq!(get("/ok").await?, serde_json::json!({ "follows": [] }));
+
+        // A server error fails the request
+        let err = get("/broken").await.unwrap_err();
+        assert!(!is_deferred(&err));
+        assert!(err.to_string().contains("502"), "{}", err);
+
+        // Rate limiting defers the job and empties the bucket
+        assert!(is_deferred(&get("/limited").await.unwrap_err()));
+        assert!(budget.state.lock().unwrap().tokens < 1.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter_is_forgotten() {
+        let budget = Arc::new(ApiBudget::new(1.0, 0.0));
+        budget.acquire(Priority::Interactive).await.unwrap();
+
+        // An interactive caller gives up mid-wait, e.g. its socket closed
+        let waiter = {
+            let budget = Arc::clone(&budget);
+            tokio::spawn(async move { budget.acquire(Priority::Interactive).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        waiter.abort();
+        let _ = waiter.await;
+
+        budget.add_tokens(1.0);
+        budget
+            .acquire_within(Priority::Verification, Duration::from_millis(200))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(parse_policy("3000;w=300"), Some((3000.0, 300.0)));
+        assert_eq!(parse_policy("3000"), None);
+        assert_eq!(parse_policy("3000;w=0"), None);
+    }
+}
@@ -2,27 +2,154 @@ use anyhow::{anyhow, Result};
 use atrium_common::resolver::Resolver;
 use atrium_crypto::did::{format_did_key, parse_multikey};
 use atrium_identity::did::{CommonDidResolver, CommonDidResolverConfig, DEFAULT_PLC_DIRECTORY_URL};
-use atrium_xrpc_client::reqwest::ReqwestClient;
+use atrium_xrpc_client::reqwest::{ReqwestClient, ReqwestClientBuilder};
 use base64::Engine;
-use jwt_compact::UntrustedToken;
+use jwt_compact::{Claims, UntrustedToken};
+use moka::{future::Cache, Expiry};
+use serde::Deserialize;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{debug, warn};
 
-use crate::types::JwtClaims;
-
-// Unused structs kept for reference if needed in future
-// #[derive(Debug, Deserialize)]
-// struct EmptyCustomClaims {}
-//
-// #[derive(Debug, Deserialize)]
-// struct StandardClaims {
-//     #[serde(rename = "iss")]
-//     issuer: Option<String>,
-//     #[serde(rename = "aud")]
-//     audience: Option<String>,
-//     #[serde(rename = "exp")]
-//     expiration: Option<i64>,
-// }
+use crate::{identity::IdentityCache, types::JwtClaims};
+
+/// The atproto service-auth claims besides the registered time claims,
+/// which `Claims` itself reads
+#[derive(Debug, Deserialize)]
+struct ServiceAuthClaims {
+    iss: String,
+    aud: String,
+    /// The XRPC method the token is for
+    #[serde(default)]
+    lxm: Option<String>,
+    /// Unique token ID
+    #[serde(default)]
+    jti: Option<String>,
+}
+
+/// The token's expiry in seconds since the epoch. `exp` may be an integer
+/// or, from some clients, a float.
+fn expiration(claims: &Claims<ServiceAuthClaims>) -> Result<i64> {
+    claims
+        .expiration
+        .map(|exp| exp.timestamp())
+        .ok_or_else(|| anyhow!("Missing 'exp' claim"))
+}
+
+/// Most token ids remembered for replay protection
+const REPLAY_CACHE_CAPACITY: u64 = 100_000;
+
+/// Forgets a token id once its token has expired
+struct UntilExpiry;
+
+impl Expiry<String, i64> for UntilExpiry {
+    fn expire_after_create(
+        &self,
+        _id: &String,
+        exp: &i64,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        let remaining = exp.saturating_sub(chrono::Utc::now().timestamp());
+        Some(Duration::from_secs(remaining.max(0) as u64 + 1))
+    }
+}
+
+/// Ids of tokens already accepted, each kept until its token expires, so a
+/// captured token can't be used again
+#[derive(Clone)]
+pub struct ReplayGuard {
+    seen: Cache<String, i64>,
+}
+
+impl ReplayGuard {
+    pub fn new() -> Self {
+        Self {
+            seen: Cache::builder()
+                .max_capacity(REPLAY_CACHE_CAPACITY)
+                .expire_after(UntilExpiry)
+                .build(),
+        }
+    }
+
+    /// Records the first use of a token, failing on any later one
+    pub async fn check(&self, id: &str, exp: i64) -> Result<()> {
+        let entry = self.seen.entry(id.to_string()).or_insert(exp).await;
+        if !entry.is_fresh() {
+            warn!("Rejected replayed JWT");
+            return Err(anyhow!("JWT has already been used"));
+        }
+        Ok(())
+    }
+}
+
+/// The id a token is remembered under. A `jti` is only unique per issuer,
+/// so another issuer reusing one mustn't lock out the first.
+fn replay_id(iss: &str, jti: Option<&str>, signature: &str) -> String {
+    match jti {
+        Some(jti) => format!("{}:{}", iss, jti),
+        None => signature.to_string(),
+    }
+}
+
+impl Default for ReplayGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks that a JWT issuer is a did:plc or did:web DID, returning it in the
+/// canonical lowercase form used as the follower DID everywhere else
+pub fn normalize_did(iss: &str) -> Result<String> {
+    let invalid = || anyhow!("Invalid 'iss' claim: expected a did:plc or did:web DID");
+
+    if let Some(id) = iss.strip_prefix("did:plc:") {
+        // 24 characters of lowercase base32
+        let id = id.to_ascii_lowercase();
+        let valid = id.len() == 24 && id.chars().all(|c| matches!(c, 'a'..='z' | '2'..='7'));
+        return if valid {
+            Ok(format!("did:plc:{}", id))
+        } else {
+            Err(invalid())
+        };
+    }
+
+    if let Some(host) = iss.strip_prefix("did:web:") {
+        // Hostnames are case-insensitive; atproto doesn't allow paths
+        let host = host.to_ascii_lowercase();
+        let valid = !host.is_empty()
+            && host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '%'));
+        return if valid {
+            Ok(format!("did:web:{}", host))
+        } else {
+            Err(invalid())
+        };
+    }
+
+    Err(invalid())
+}
+
+/// Longest a DID document fetch may take, so a slow did:web host can't hold
+/// a feed request open
+const DID_RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+const DID_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Builds the resolver for JWT issuers, on an HTTP client with timeouts
+fn did_resolver(plc_directory_url: &str) -> Result<CommonDidResolver<ReqwestClient>> {
+    let client = reqwest::Client::builder()
+        .timeout(DID_RESOLVE_TIMEOUT)
+        .connect_timeout(DID_CONNECT_TIMEOUT)
+        .build()?;
+    // Note: base_uri is not used for DID resolution, so we use a placeholder
+    let http_client = ReqwestClientBuilder::new("https://plc.directory")
+        .client(client)
+        .build();
+    Ok(CommonDidResolver::new(CommonDidResolverConfig {
+        plc_directory_url: plc_directory_url.to_string(),
+        http_client: Arc::new(http_client),
+    }))
+}
 
 /// Resolves a DID and extracts the atproto signing key as a did:key string
 async fn resolve_signing_key(
@@ -31,6 +158,11 @@ async fn resolve_signing_key(
 ) -> Result<String> {
     debug!("Resolving DID: {}", did_str);
 
+    if !did_str.starts_with("did:plc:") && !did_str.starts_with("did:web:") {
+        warn!("Refusing to resolve {}", did_str);
+        return Err(anyhow!("Unsupported DID method"));
+    }
+
     // Convert string to Did type
     let did = did_str.parse().map_err(|e| {
         warn!("Invalid DID format: {}", e);
@@ -86,7 +218,16 @@ async fn resolve_signing_key(
     Ok(did_key)
 }
 
-pub async fn validate_jwt(token: &str, service_did: &str) -> Result<JwtClaims> {
+/// Validates a service-auth JWT. The issuer's signing key is cached in
+/// `identity` until an identity event or a failed signature evicts it. With
+/// `replay`, a token is only accepted once, identified by its issuer and
+/// `jti` or, if it has no `jti`, its signature.
+pub async fn validate_jwt(
+    token: &str,
+    service_did: &str,
+    identity: &IdentityCache,
+    replay: Option<&ReplayGuard>,
+) -> Result<JwtClaims> {
     // Token should already have "Bearer " prefix stripped by caller
     debug!("Validating JWT token (length: {})", token.len());
     debug!("Expected audience: {}", service_did);
@@ -97,39 +238,21 @@ pub async fn validate_jwt(token: &str, service_did: &str) -> Result<JwtClaims> {
         anyhow!("Invalid JWT format: {}", e)
     })?;
 
-    // First, try to deserialize as raw JSON to see the actual structure
-    let claims_wrapper = untrusted
-        .deserialize_claims_unchecked::<serde_json::Value>()
+    let claims = untrusted
+        .deserialize_claims_unchecked::<ServiceAuthClaims>()
         .map_err(|e| {
             warn!("Failed to deserialize JWT claims: {}", e);
             anyhow!("Invalid JWT claims: {}", e)
         })?;
 
-    debug!("Raw JWT claims: {:?}", claims_wrapper);
-
-    // Extract the actual claims from the Value
-    let iss = claims_wrapper
-        .custom
-        .get("iss")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow!("Missing 'iss' claim"))?
-        .to_string();
-
-    let aud = claims_wrapper
-        .custom
-        .get("aud")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow!("Missing 'aud' claim"))?
-        .to_string();
-
-    let exp = claims_wrapper
-        .custom
-        .get("exp")
-        .and_then(|v| v.as_i64())
-        .or_else(|| claims_wrapper.expiration.map(|ts| ts.timestamp()))
-        .ok_or_else(|| anyhow!("Missing 'exp' claim"))?;
+    let iss =
+        normalize_did(&claims.custom.iss).inspect_err(|e| warn!("Rejected JWT issuer: {}", e))?;
+    let aud = claims.custom.aud.clone();
+    let exp = expiration(&claims)?;
 
     debug!(
+        lxm = ?claims.custom.lxm,
+        jti = ?claims.custom.jti,
         "JWT claims extracted - issuer: {}, audience: {}, exp: {}",
         iss, aud, exp
     );
@@ -157,18 +280,6 @@ pub async fn validate_jwt(token: &str, service_did: &str) -> Result<JwtClaims> {
     // Verify signature
     debug!("Verifying JWT signature for issuer: {}", iss);
 
-    // Create DID resolver
-    // Note: base_uri is not used for DID resolution, so we use a placeholder
-    let http_client = ReqwestClient::new("https://plc.directory");
-    let resolver_config = CommonDidResolverConfig {
-        plc_directory_url: DEFAULT_PLC_DIRECTORY_URL.to_string(),
-        http_client: Arc::new(http_client),
-    };
-    let resolver = CommonDidResolver::new(resolver_config);
-
-    // Resolve the issuer's signing key
-    let did_key = resolve_signing_key(&resolver, &iss).await?;
-
     // Extract the signed portion of the JWT (header.payload)
     // JWT format is: header.payload.signature
     let parts: Vec<&str> = token.split('.').collect();
@@ -188,13 +299,176 @@ pub async fn validate_jwt(token: &str, service_did: &str) -> Result<JwtClaims> {
             anyhow!("Invalid JWT signature encoding: {}", e)
         })?;
 
-    // Verify the signature
-    atrium_crypto::verify::verify_signature(&did_key, signed_data.as_bytes(), &signature_bytes)
-        .map_err(|e| {
-            warn!("JWT signature verification failed: {}", e);
-            anyhow!("Invalid JWT signature: {}", e)
-        })?;
+    // Verify the signature with the issuer's signing key, resolving it unless
+    // we already have it. A failure re-resolves it once, in case the key
+    // rotated without us seeing the identity event.
+    let issuer = iss.as_str();
+    identity
+        .verify_with_signing_key(
+            issuer,
+            move || async move {
+                let resolver = did_resolver(DEFAULT_PLC_DIRECTORY_URL)?;
+                resolve_signing_key(&resolver, issuer).await
+            },
+            |did_key| {
+                atrium_crypto::verify::verify_signature(
+                    did_key,
+                    signed_data.as_bytes(),
+                    &signature_bytes,
+                )
+                .map_err(|e| anyhow!("Invalid JWT signature: {}", e))
+            },
+        )
+        .await
+        .inspect_err(|e| warn!("JWT signature verification failed: {}", e))?;
 
     debug!("JWT signature verified successfully for issuer: {}", iss);
+    if let Some(replay) = replay {
+        let id = replay_id(&iss, claims.custom.jti.as_deref(), signature_b64);
+        replay.check(&id, exp).await?;
+    }
     Ok(JwtClaims { iss, aud, exp })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_did() {
+        assert_eq!(
+            normalize_did("did:plc:EWVI7NXZYOUN6QBSTVFQXA3J").unwrap(),
+            "did:plc:ewvi7nxzyoun6qbstvfqxa3j"
+        );
+        assert_eq!(
+            normalize_did("did:web:Feed.Example.COM").unwrap(),
+            "did:web:feed.example.com"
+        );
+
+        for iss in [
+            "alice.bsky.social",
+            "@alice.bsky.social",
+            "did:plc:tooshort",
+            "did:plc:ewvi7nxzyoun6qbstvfqxa31",
+            "did:web:",
+            "did:web:example.com/path",
+            "did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK",
+            "",
+        ] {
+            assert!(normalize_did(iss).is_err(), "{}", iss);
+        }
+    }
+
+    #[test]
+    fn test_service_auth_claims() -> Result<()> {
+        let claims = |json: serde_json::Value| {
+            serde_json::from_value::<Claims<ServiceAuthClaims>>(json).map_err(anyhow::Error::from)
+        };
+        let parsed = claims(serde_json::json!({
+            "iss": "did:plc:ewvi7nxzyoun6qbstvfqxa3j",
+            "aud": "did:web:feed.example.com",
+            "exp": 1_700_000_000,
+            "iat": 1_699_999_940,
+            "lxm": "app.bsky.feed.getFeedSkeleton",
+            "jti": "abc",
+        }))?;
+        assert_eq!(parsed.custom.aud, "did:web:feed.example.com");
+        assert_eq!(
+            parsed.custom.lxm.as_deref(),
+            Some("app.bsky.feed.getFeedSkeleton")
+        );
+        assert_eq!(expiration(&parsed)?, 1_700_000_000);
+
+        // Fractional expiries are accepted, and lxm and jti are optional
+        let parsed = claims(serde_json::json!({
+            "iss": "did:plc:ewvi7nxzyoun6qbstvfqxa3j",
+            "aud": "did:web:feed.example.com",
+            "exp": 1_700_000_000.5,
+        }))?;
+        assert_eq!(expiration(&parsed)?, 1_700_000_000);
+        assert_eq!(parsed.custom.jti, None);
+
+        let parsed = claims(serde_json::json!({
+            "iss": "did:plc:ewvi7nxzyoun6qbstvfqxa3j",
+            "aud": "did:web:feed.example.com",
+        }))?;
+        assert!(expiration(&parsed).is_err());
+        assert!(claims(serde_json::json!({ "aud": "did:web:feed.example.com" })).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replayed_tokens_are_rejected() -> Result<()> {
+        let guard = ReplayGuard::new();
+        let exp = chrono::Utc::now().timestamp() + 60;
+        guard.check("token-1", exp).await?;
+        assert_eq!(
+            guard.check("token-1", exp).await.unwrap_err().to_string(),
+            "JWT has already been used"
+        );
+        guard.check("token-2", exp).await?;
+
+        // A token is forgotten once it expires, when it's rejected anyway
+        let expired = chrono::Utc::now().timestamp() - 60;
+        guard.check("token-3", expired).await?;
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        guard.seen.run_pending_tasks().await;
+        assert!(!guard.seen.contains_key("token-3"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jti_is_scoped_to_its_issuer() -> Result<()> {
+        let guard = ReplayGuard::new();
+        let exp = chrono::Utc::now().timestamp() + 60;
+        // Another issuer claiming the same jti first doesn't lock alice out
+        let mallory = replay_id("did:plc:mallory", Some("abc"), "sig-1");
+        let alice = replay_id("did:plc:alice", Some("abc"), "sig-2");
+        guard.check(&mallory, exp).await?;
+        guard.check(&alice, exp).await?;
+        assert!(guard.check(&alice, exp).await.is_err());
+
+        // Tokens without a jti fall back to their signature
+        assert_eq!(replay_id("did:plc:alice", None, "sig-3"), "sig-3");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_did_method_is_rejected() -> Result<()> {
+        let resolver = did_resolver(DEFAULT_PLC_DIRECTORY_URL)?;
+        let err = resolve_signing_key(
+            &resolver,
+            "did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK",
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Unsupported DID method");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_did_resolution_times_out() -> Result<()> {
+        // A directory that accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let mut open = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                open.push(stream);
+            }
+        });
+
+        let resolver = did_resolver(&format!("http://{}", addr))?;
+        let started = std::time::Instant::now();
+        let result = resolve_signing_key(&resolver, "did:plc:ewvi7nxzyoun6qbstvfqxa3j").await;
+
+        assert!(result.is_err());
+        assert!(started.elapsed() < DID_RESOLVE_TIMEOUT * 2);
+
+        Ok(())
+    }
+}
@@ -1,44 +1,210 @@
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use sqlx::Row;
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedSender;
 use tracing::{debug, info, warn};
 
 use crate::{
+    api_budget::{self, ApiBudget, Priority},
     database::Database,
-    types::{Follow, Post},
+    identity::IdentityCache,
+    types::{Block, Follow, Post},
 };
 
-pub async fn backfill_follows(db: Arc<Database>, user_did: &str) -> Result<()> {
+/// Public AppView used for unauthenticated reads
+pub const PUBLIC_API_URL: &str = "https://public.api.bsky.app";
+
+/// DIDs with a backfill in flight, so a feed request can tell a user whose
+/// follows are still being fetched from one who follows nobody
+#[derive(Clone, Default)]
+pub struct BackfillTracker {
+    running: Arc<Mutex<HashSet<String>>>,
+}
+
+impl BackfillTracker {
+    /// Marks a backfill for `did` as running until the guard is dropped.
+    /// Returns `None` if one is already running.
+    pub fn start(&self, did: &str) -> Option<BackfillGuard> {
+        let inserted = self.running.lock().unwrap().insert(did.to_string());
+        inserted.then(|| BackfillGuard {
+            tracker: self.clone(),
+            did: did.to_string(),
+        })
+    }
+
+    pub fn is_running(&self, did: &str) -> bool {
+        self.running.lock().unwrap().contains(did)
+    }
+
+    /// Whether a backfill for anyone is running
+    pub fn any_running(&self) -> bool {
+        !self.running.lock().unwrap().is_empty()
+    }
+}
+
+pub struct BackfillGuard {
+    tracker: BackfillTracker,
+    did: String,
+}
+
+impl Drop for BackfillGuard {
+    fn drop(&mut self) {
+        self.tracker.running.lock().unwrap().remove(&self.did);
+    }
+}
+
+/// Recent posts fetched per followed account when a user is backfilled
+pub const DEFAULT_BACKFILL_POSTS_PER_USER: usize = 20;
+
+/// Most follows a backfill stores for one user
+pub const DEFAULT_BACKFILL_MAX_FOLLOWS: usize = 5000;
+
+/// Most blocks a backfill stores for one user
+pub const DEFAULT_BACKFILL_MAX_BLOCKS: usize = 5000;
+
+/// How much a backfill fetches for one user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::Args)]
+pub struct BackfillLimits {
+    /// Recent posts fetched from each followed account when a user is backfilled
+    #[arg(
+        long = "backfill-posts-per-user",
+        env = "BACKFILL_POSTS_PER_USER",
+        default_value_t = DEFAULT_BACKFILL_POSTS_PER_USER
+    )]
+    pub posts_per_user: usize,
+
+    /// Most follows a backfill stores for one user; Jetstream fills in the rest
+    #[arg(
+        long = "backfill-max-follows",
+        env = "BACKFILL_MAX_FOLLOWS",
+        default_value_t = DEFAULT_BACKFILL_MAX_FOLLOWS
+    )]
+    pub max_follows: usize,
+
+    /// Most blocks a backfill stores for one user; Jetstream adds later ones
+    #[arg(
+        long = "backfill-max-blocks",
+        env = "BACKFILL_MAX_BLOCKS",
+        default_value_t = DEFAULT_BACKFILL_MAX_BLOCKS
+    )]
+    pub max_blocks: usize,
+}
+
+impl Default for BackfillLimits {
+    fn default() -> Self {
+        Self {
+            posts_per_user: DEFAULT_BACKFILL_POSTS_PER_USER,
+            max_follows: DEFAULT_BACKFILL_MAX_FOLLOWS,
+            max_blocks: DEFAULT_BACKFILL_MAX_BLOCKS,
+        }
+    }
+}
+
+/// Authors followed by more of our users than this are worth backfilling first
+const POPULAR_AUTHOR_FOLLOWERS: i64 = 50;
+
+/// Returns the DID for a DID or handle, resolving handles with the AppView
+pub async fn resolve_actor(budget: &ApiBudget, actor: &str) -> Result<String> {
+    resolve_actor_at(budget, PUBLIC_API_URL, actor).await
+}
+
+async fn resolve_actor_at(budget: &ApiBudget, api_base: &str, actor: &str) -> Result<String> {
+    if actor.starts_with("did:") {
+        return Ok(actor.to_string());
+    }
+
+    let handle = actor.trim_start_matches('@');
+    let url = format!(
+        "{}/xrpc/com.atproto.identity.resolveHandle?handle={}",
+        api_base, handle
+    );
+    let client = reqwest::Client::new();
+    let response = budget
+        .get_json(&client, &url, Priority::Interactive)
+        .await?;
+    match response["did"].as_str() {
+        Some(did) => Ok(did.to_string()),
+        None => anyhow::bail!(
+            "Couldn't resolve handle {}: {}",
+            handle,
+            response["message"].as_str().unwrap_or("no DID returned")
+        ),
+    }
+}
+
+/// Accounts whose posts are backfilled between progress reports
+const POST_PROGRESS_EVERY: usize = 10;
+
+/// Sends a line of backfill progress, if anyone is listening
+fn report(progress: Option<&UnboundedSender<String>>, line: String) {
+    if let Some(progress) = progress {
+        let _ = progress.send(line);
+    }
+}
+
+/// Stores a user's follows, reporting the running total to `progress` after
+/// each page. Returns how many were stored.
+pub async fn backfill_follows(
+    db: Arc<Database>,
+    budget: Arc<ApiBudget>,
+    identity: Arc<IdentityCache>,
+    user_did: &str,
+    limits: BackfillLimits,
+    progress: Option<&UnboundedSender<String>>,
+) -> Result<usize> {
     info!("Starting backfill of follows for {}", user_did);
 
+    // The follow records carry their real URIs, so a later unfollow from
+    // Jetstream finds the row. The AppView only names the accounts followed.
+    match backfill_follows_from_pds(&db, &identity, user_did, limits.max_follows, progress).await {
+        Ok(total_follows) => {
+            info!(
+                "Backfilled {} follows for {} from PDS",
+                total_follows, user_did
+            );
+            return Ok(total_follows);
+        }
+        Err(e) => warn!(
+            "Listing follow records for {} failed: {}. Falling back to the AppView",
+            user_did, e
+        ),
+    }
+
     let client = reqwest::Client::new();
     let mut cursor: Option<String> = None;
     let mut total_follows = 0;
 
     loop {
         let mut url = format!(
-            "https://public.api.bsky.app/xrpc/app.bsky.graph.getFollows?actor={}&limit=100",
-            user_did
+            "{}/xrpc/app.bsky.graph.getFollows?actor={}&limit=100",
+            PUBLIC_API_URL, user_did
         );
         if let Some(ref c) = cursor {
             url.push_str(&format!("&cursor={}", c));
         }
 
-        let response: serde_json::Value = client.get(&url).send().await?.json().await?;
+        let response = budget
+            .get_json(&client, &url, Priority::Interactive)
+            .await?;
 
         let follows = response["follows"].as_array();
         if follows.is_none() {
             break;
         }
 
+        let mut page = Vec::new();
         for follow in follows.unwrap() {
             let target_did = follow["did"].as_str().unwrap_or("");
             if target_did.is_empty() {
                 continue;
             }
 
-            let follow_record = Follow {
+            if total_follows + page.len() >= limits.max_follows {
+                break;
+            }
+            page.push(Follow {
                 uri: format!(
                     "at://{}/app.bsky.graph.follow/{}",
                     user_did,
@@ -48,25 +214,141 @@ pub async fn backfill_follows(db: Arc<Database>, user_did: &str) -> Result<()> {
                 target_did: target_did.to_string(),
                 created_at: chrono::Utc::now(),
                 indexed_at: chrono::Utc::now(),
-            };
+            });
+        }
 
-            match db.insert_follow(&follow_record).await {
-                Ok(_) => total_follows += 1,
-                Err(e) => warn!("Failed to insert follow {}: {}", target_did, e),
-            }
+        // Each page of up to 100 follows is written in one transaction
+        match db.insert_follows_batch(&page).await {
+            Ok(()) => total_follows += page.len(),
+            Err(e) => warn!("Failed to insert {} follows: {}", page.len(), e),
         }
+        report(progress, format!("backfilled {} follows", total_follows));
 
         cursor = response["cursor"].as_str().map(|s| s.to_string());
-        if cursor.is_none() {
+        if cursor.is_none() || total_follows >= limits.max_follows {
             break;
         }
     }
 
     info!("Backfilled {} follows for {}", total_follows, user_did);
-    Ok(())
+    Ok(total_follows)
 }
 
-pub async fn backfill_posts(db: Arc<Database>, target_did: &str, limit: usize) -> Result<()> {
+/// Backfills a user's follows from the follow records in their repo,
+/// returning how many were stored
+async fn backfill_follows_from_pds(
+    db: &Database,
+    identity: &IdentityCache,
+    user_did: &str,
+    max_follows: usize,
+    progress: Option<&UnboundedSender<String>>,
+) -> Result<usize> {
+    let mut cursor: Option<String> = None;
+    let mut total_follows = 0;
+
+    loop {
+        let response = identity
+            .list_records_page(user_did, "app.bsky.graph.follow", 100, cursor.as_deref())
+            .await?;
+
+        let mut page = Vec::new();
+        for record in response["records"].as_array().into_iter().flatten() {
+            let uri = record["uri"].as_str().unwrap_or("");
+            let target_did = record["value"]["subject"].as_str().unwrap_or("");
+            if uri.is_empty() || target_did.is_empty() {
+                continue;
+            }
+            if total_follows + page.len() >= max_follows {
+                break;
+            }
+
+            let created_at =
+                DateTime::parse_from_rfc3339(record["value"]["createdAt"].as_str().unwrap_or(""))
+                    .map(|t| t.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now());
+            page.push(Follow {
+                uri: uri.to_string(),
+                follower_did: user_did.to_string(),
+                target_did: target_did.to_string(),
+                created_at,
+                indexed_at: Utc::now(),
+            });
+        }
+
+        db.insert_follows_batch(&page).await?;
+        total_follows += page.len();
+        report(progress, format!("backfilled {} follows", total_follows));
+
+        cursor = response["cursor"].as_str().map(|s| s.to_string());
+        if cursor.is_none() || page.is_empty() || total_follows >= max_follows {
+            break;
+        }
+    }
+
+    Ok(total_follows)
+}
+
+/// Stores the accounts a user has blocked, from the block records in their
+/// repo, returning how many were stored. Blocks aren't public through the
+/// AppView, but the records are.
+pub async fn backfill_blocks(
+    db: Arc<Database>,
+    identity: Arc<IdentityCache>,
+    user_did: &str,
+    max_blocks: usize,
+) -> Result<usize> {
+    let mut cursor: Option<String> = None;
+    let mut total_blocks = 0;
+
+    loop {
+        let response = identity
+            .list_records_page(user_did, "app.bsky.graph.block", 100, cursor.as_deref())
+            .await?;
+
+        let mut page = Vec::new();
+        for record in response["records"].as_array().into_iter().flatten() {
+            let uri = record["uri"].as_str().unwrap_or("");
+            let target_did = record["value"]["subject"].as_str().unwrap_or("");
+            if uri.is_empty() || target_did.is_empty() {
+                continue;
+            }
+            if total_blocks + page.len() >= max_blocks {
+                break;
+            }
+
+            let created_at =
+                DateTime::parse_from_rfc3339(record["value"]["createdAt"].as_str().unwrap_or(""))
+                    .map(|t| t.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now());
+            page.push(Block {
+                uri: uri.to_string(),
+                blocker_did: user_did.to_string(),
+                target_did: target_did.to_string(),
+                created_at,
+            });
+        }
+
+        db.insert_blocks(&page).await?;
+        total_blocks += page.len();
+
+        cursor = response["cursor"].as_str().map(|s| s.to_string());
+        if cursor.is_none() || page.is_empty() || total_blocks >= max_blocks {
+            break;
+        }
+    }
+
+    info!("Backfilled {} blocks for {}", total_blocks, user_did);
+    Ok(total_blocks)
+}
+
+/// Stores an author's recent posts, returning how many were stored
+pub async fn backfill_posts(
+    db: Arc<Database>,
+    budget: Arc<ApiBudget>,
+    identity: Arc<IdentityCache>,
+    target_did: &str,
+    limit: usize,
+) -> Result<usize> {
     debug!("Starting backfill of posts for {}", target_did);
 
     let client = reqwest::Client::new();
@@ -76,20 +358,33 @@ pub async fn backfill_posts(db: Arc<Database>, target_did: &str, limit: usize) -
 
     loop {
         let mut url = format!(
-            "https://public.api.bsky.app/xrpc/app.bsky.feed.getAuthorFeed?actor={}&limit=100",
-            target_did
+            "{}/xrpc/app.bsky.feed.getAuthorFeed?actor={}&limit=100",
+            PUBLIC_API_URL, target_did
         );
         if let Some(ref c) = cursor {
             url.push_str(&format!("&cursor={}", c));
         }
 
-        let response: serde_json::Value = client.get(&url).send().await?.json().await?;
+        let response = match budget.get_json(&client, &url, Priority::Interactive).await {
+            Ok(response) => response,
+            // The AppView may lag behind a freshly migrated account, so go to the PDS
+            Err(e) if cursor.is_none() && !api_budget::is_deferred(&e) => {
+                debug!(
+                    "AppView backfill failed for {}: {}. Falling back to PDS",
+                    target_did, e
+                );
+                return backfill_posts_from_pds(db, &identity, target_did, limit).await;
+            }
+            Err(e) => return Err(e),
+        };
 
         let feed = response["feed"].as_array();
         if feed.is_none() {
             break;
         }
 
+        let mut page = Vec::new();
+        let mut limit_reached = false;
         for item in feed.unwrap() {
             let post = &item["post"];
 
@@ -108,6 +403,12 @@ pub async fn backfill_posts(db: Arc<Database>, target_did: &str, limit: usize) -
             let cid = post["cid"].as_str().unwrap_or("");
             let text = record["text"].as_str().unwrap_or("");
             let created_at_str = record["createdAt"].as_str().unwrap_or("");
+            let reply_parent_uri = record["reply"]["parent"]["uri"]
+                .as_str()
+                .map(|s| s.to_string());
+            let reply_root_uri = record["reply"]["root"]["uri"]
+                .as_str()
+                .map(|s| s.to_string());
 
             if uri.is_empty() || cid.is_empty() {
                 continue;
@@ -117,30 +418,42 @@ pub async fn backfill_posts(db: Arc<Database>, target_did: &str, limit: usize) -
                 .unwrap_or_else(|_| Utc::now().into())
                 .with_timezone(&Utc);
 
-            let post_record = Post {
+            page.push(Post {
                 uri: uri.to_string(),
                 cid: cid.to_string(),
                 author_did: target_did.to_string(),
                 text: text.to_string(),
+                reply_parent_uri,
+                reply_root_uri,
+                quoted_uri: Post::quoted_uri_of(record),
+                is_link_only: Post::is_link_only_record(record),
+                has_media: Post::has_media_record(record),
+                embed_type: Post::embed_type_of(record),
+                hashtags: Post::hashtags_of(record),
                 created_at,
                 indexed_at: Utc::now(),
-            };
-
-            match db.insert_post(&post_record).await {
-                Ok(_) => total_posts += 1,
-                Err(e) => debug!("Failed to insert post {}: {}", uri, e),
-            }
+            });
 
             fetched += 1;
             if fetched >= limit {
-                debug!(
-                    "Backfilled {} posts for {} (limit reached)",
-                    total_posts, target_did
-                );
-                return Ok(());
+                limit_reached = true;
+                break;
             }
         }
 
+        // Each page of up to 100 posts is written in one transaction
+        match db.insert_posts_batch(&page).await {
+            Ok(()) => total_posts += page.len(),
+            Err(e) => debug!("Failed to insert {} posts: {}", page.len(), e),
+        }
+        if limit_reached {
+            debug!(
+                "Backfilled {} posts for {} (limit reached)",
+                total_posts, target_did
+            );
+            return Ok(total_posts);
+        }
+
         cursor = response["cursor"].as_str().map(|s| s.to_string());
         if cursor.is_none() {
             break;
@@ -148,24 +461,84 @@ pub async fn backfill_posts(db: Arc<Database>, target_did: &str, limit: usize) -
     }
 
     debug!("Backfilled {} posts for {}", total_posts, target_did);
-    Ok(())
+    Ok(total_posts)
+}
+
+/// Backfills an author's recent posts straight from their PDS
+pub async fn backfill_posts_from_pds(
+    db: Arc<Database>,
+    identity: &IdentityCache,
+    target_did: &str,
+    limit: usize,
+) -> Result<usize> {
+    let response = identity
+        .list_records(target_did, "app.bsky.feed.post", limit)
+        .await?;
+
+    let mut posts = Vec::new();
+    for record in response["records"].as_array().into_iter().flatten() {
+        let uri = record["uri"].as_str().unwrap_or("");
+        let cid = record["cid"].as_str().unwrap_or("");
+        if uri.is_empty() || cid.is_empty() {
+            continue;
+        }
+
+        let value = &record["value"];
+        let created_at = DateTime::parse_from_rfc3339(value["createdAt"].as_str().unwrap_or(""))
+            .unwrap_or_else(|_| Utc::now().into())
+            .with_timezone(&Utc);
+
+        posts.push(Post {
+            uri: uri.to_string(),
+            cid: cid.to_string(),
+            author_did: target_did.to_string(),
+            text: value["text"].as_str().unwrap_or("").to_string(),
+            reply_parent_uri: value["reply"]["parent"]["uri"]
+                .as_str()
+                .map(|s| s.to_string()),
+            reply_root_uri: value["reply"]["root"]["uri"]
+                .as_str()
+                .map(|s| s.to_string()),
+            quoted_uri: Post::quoted_uri_of(value),
+            is_link_only: Post::is_link_only_record(value),
+            has_media: Post::has_media_record(value),
+            embed_type: Post::embed_type_of(value),
+            hashtags: Post::hashtags_of(value),
+            created_at,
+            indexed_at: Utc::now(),
+        });
+    }
+
+    db.insert_posts_batch(&posts).await?;
+    debug!(
+        "Backfilled {} posts for {} from PDS",
+        posts.len(),
+        target_did
+    );
+    Ok(posts.len())
 }
 
+/// Fetches recent posts from each account a user follows, reporting to
+/// `progress` every few accounts. Returns how many posts were stored.
 pub async fn backfill_posts_for_follows(
     db: Arc<Database>,
+    budget: Arc<ApiBudget>,
+    identity: Arc<IdentityCache>,
     user_did: &str,
-    posts_per_user: usize,
-) -> Result<()> {
+    limits: BackfillLimits,
+    progress: Option<&UnboundedSender<String>>,
+) -> Result<usize> {
     info!("Starting backfill of posts for {}'s follows", user_did);
 
-    // Get all follows for this user
-    let follows = sqlx::query("SELECT target_did FROM follows WHERE follower_did = ?")
+    let follows = sqlx::query("SELECT target_did FROM follows WHERE follower_did = ? LIMIT ?")
         .bind(user_did)
+        .bind(limits.max_follows as i64)
         .fetch_all(&db.pool)
         .await?;
 
     let total_follows = follows.len();
     info!("Found {} follows to backfill posts from", total_follows);
+    let mut total_posts = 0;
 
     for (idx, row) in follows.iter().enumerate() {
         let target_did: String = row.try_get("target_did")?;
@@ -177,14 +550,202 @@ pub async fn backfill_posts_for_follows(
             total_follows
         );
 
-        if let Err(e) = backfill_posts(Arc::clone(&db), &target_did, posts_per_user).await {
-            warn!("Failed to backfill posts from {}: {}", target_did, e);
+        match db.get_follow_count_for_author(&target_did).await {
+            Ok(followers) if followers > POPULAR_AUTHOR_FOLLOWERS => warn!(
+                "{} is followed by {} users; consider prioritizing their backfill",
+                db.describe_did(&target_did).await,
+                followers
+            ),
+            Ok(_) => {}
+            Err(e) => debug!("Failed to count followers of {}: {}", target_did, e),
         }
 
-        // Small delay to avoid rate limiting
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        match backfill_posts(
+            Arc::clone(&db),
+            Arc::clone(&budget),
+            Arc::clone(&identity),
+            &target_did,
+            limits.posts_per_user,
+        )
+        .await
+        {
+            Ok(posts) => total_posts += posts,
+            Err(e) if api_budget::is_deferred(&e) => {
+                info!(
+                    "Deferring remaining post backfill for {}'s follows: {}",
+                    user_did, e
+                );
+                break;
+            }
+            Err(e) => warn!("Failed to backfill posts from {}: {}", target_did, e),
+        }
+
+        let done = idx + 1;
+        if done % POST_PROGRESS_EVERY == 0 || done == total_follows {
+            report(
+                progress,
+                format!("posts {}/{} accounts", done, total_follows),
+            );
+        }
     }
 
-    info!("Completed backfill of posts for {}'s follows", user_did);
-    Ok(())
+    info!(
+        "Completed backfill of {} posts for {}'s follows",
+        total_posts, user_did
+    );
+    Ok(total_posts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Query, routing::get, Json, Router};
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_resolve_actor() -> Result<()> {
+        let app = Router::new().route(
+            "/xrpc/com.atproto.identity.resolveHandle",
+            get(|Query(params): Query<HashMap<String, String>>| async move {
+                Json(match params["handle"].as_str() {
+                    "alice.example.com" => serde_json::json!({ "did": "did:plc:alice" }),
+                    _ => serde_json::json!({
+                        "error": "InvalidRequest",
+                        "message": "Unable to resolve handle"
+                    }),
+                })
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let api_base = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        let budget = ApiBudget::default();
+        let resolve = |actor: &'static str| resolve_actor_at(&budget, &api_base, actor);
+        assert_eq!(resolve("did:plc:bob").await?, "did:plc:bob");
+        assert_eq!(resolve("alice.example.com").await?, "did:plc:alice");
+        assert_eq!(resolve("@alice.example.com").await?, "did:plc:alice");
+        let message = resolve("nobody.example.com").await.unwrap_err().to_string();
+        assert!(message.contains("Unable to resolve handle"), "{}", message);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backfill_blocks() -> Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base = format!("http://{}", listener.local_addr()?);
+        let pds = base.clone();
+        let block = |rkey: &str, target: &str| {
+            serde_json::json!({
+                "uri": format!("at://did:plc:alice/app.bsky.graph.block/{}", rkey),
+                "value": { "subject": target, "createdAt": "2024-01-01T00:00:00Z" },
+            })
+        };
+        // Two pages of blocks, the second reached through the cursor
+        let pages = [
+            serde_json::json!({ "records": [block("1", "did:plc:bob")], "cursor": "next" }),
+            serde_json::json!({ "records": [block("2", "did:plc:carol")] }),
+        ];
+        let app = Router::new()
+            .route(
+                "/xrpc/com.atproto.repo.listRecords",
+                get(move |Query(params): Query<HashMap<String, String>>| {
+                    assert_eq!(params["collection"], "app.bsky.graph.block");
+                    let page = usize::from(params.contains_key("cursor"));
+                    let page = pages[page].clone();
+                    async move { Json(page) }
+                }),
+            )
+            .route(
+                "/{did}",
+                get(move || async move {
+                    Json(serde_json::json!({
+                        "id": "did:plc:alice",
+                        "service": [{
+                            "id": "#atproto_pds",
+                            "type": "AtprotoPersonalDataServer",
+                            "serviceEndpoint": pds,
+                        }]
+                    }))
+                }),
+            );
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let identity = Arc::new(IdentityCache::new(&base));
+        assert!(!db.has_blocks("did:plc:alice").await?);
+        // The cap stops paging once it's reached
+        let capped = Arc::new(Database::new(":memory:").await?);
+        capped.migrate().await?;
+        let stored = backfill_blocks(capped, Arc::clone(&identity), "did:plc:alice", 1).await?;
+        assert_eq!(stored, 1);
+
+        let stored = backfill_blocks(Arc::clone(&db), identity, "did:plc:alice", 10).await?;
+        assert_eq!(stored, 2);
+        assert!(db.has_blocks("did:plc:alice").await?);
+
+        // Posts by a blocked account stay out of the feed, even if followed
+        for target in ["did:plc:bob", "did:plc:dave"] {
+            db.insert_follow(&Follow {
+                uri: format!("at://did:plc:alice/app.bsky.graph.follow/{}", target),
+                follower_did: "did:plc:alice".to_string(),
+                target_did: target.to_string(),
+                created_at: Utc::now(),
+                indexed_at: Utc::now(),
+            })
+            .await?;
+            db.insert_post(&Post {
+                uri: format!("at://{}/app.bsky.feed.post/1", target),
+                cid: "cid".to_string(),
+                author_did: target.to_string(),
+                text: "text".to_string(),
+                reply_parent_uri: None,
+                reply_root_uri: None,
+                quoted_uri: None,
+                is_link_only: false,
+                has_media: false,
+                embed_type: None,
+                hashtags: Vec::new(),
+                created_at: Utc::now(),
+                indexed_at: Utc::now(),
+            })
+            .await?;
+        }
+        let posts = db
+            .get_following_posts(
+                "did:plc:alice",
+                10,
+                None,
+                &crate::types::FeedFilter::default(),
+            )
+            .await?;
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].author_did, "did:plc:dave");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_backfill_tracker() {
+        let tracker = BackfillTracker::default();
+        assert!(!tracker.is_running("did:example:alice"));
+
+        let guard = tracker.start("did:example:alice").unwrap();
+        assert!(tracker.is_running("did:example:alice"));
+        assert!(tracker.any_running());
+        assert!(!tracker.is_running("did:example:bob"));
+        // A second backfill for the same user waits for the first to finish
+        assert!(tracker.start("did:example:alice").is_none());
+
+        drop(guard);
+        assert!(!tracker.is_running("did:example:alice"));
+        assert!(!tracker.any_running());
+        assert!(tracker.start("did:example:alice").is_some());
+    }
 }
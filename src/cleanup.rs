@@ -1,11 +1,131 @@
 use anyhow::Result;
+use chrono::Utc;
+use serde::Serialize;
 use sqlx::Row;
+use std::fmt;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
 use tracing::{info, warn};
 
-use crate::database::Database;
+use crate::{
+    api_budget::{self, ApiBudget, Priority},
+    backfill::{BackfillTracker, PUBLIC_API_URL},
+    database::{delete_or_count, Database, MaintenanceStats},
+    shutdown::ShutdownCoordinator,
+};
 
-pub async fn verify_active_user_follows(db: Arc<Database>) -> Result<()> {
+// Each kind of pass runs one at a time, so one started from the admin
+// console waits for the scheduled one instead of overlapping it
+static POST_CLEANUP: Mutex<()> = Mutex::const_new(());
+static FOLLOW_CLEANUP: Mutex<()> = Mutex::const_new(());
+
+/// How long deleted posts are kept before they're removed for good
+pub const DELETED_POST_RETENTION_DAYS: i64 = 7;
+
+/// How often the follow sync task looks for users due a sync
+const FOLLOW_SYNC_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Pause between batches of follow syncs, to spread out AppView requests
+const FOLLOW_SYNC_BATCH_PAUSE: Duration = Duration::from_secs(5);
+
+/// How often a user's follows are fetched in full rather than from the
+/// stored cursor, dropping follows removed while Jetstream was disconnected
+const FULL_FOLLOW_SYNC_INTERVAL: chrono::Duration = chrono::Duration::hours(24);
+
+/// Rows a cleanup pass deleted, or would delete in a dry run
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct CleanupStats {
+    pub posts_to_delete: u64,
+    pub follows_to_delete: u64,
+    /// Users whose follows were removed as inactive, so a dry run shows
+    /// operators who would be affected
+    pub inactive_users: Vec<InactiveUser>,
+}
+
+/// A user the inactive-user pass removed follows for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InactiveUser {
+    pub did: String,
+    pub follows: u64,
+}
+
+impl std::ops::Add for CleanupStats {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        self.inactive_users.extend(other.inactive_users);
+        Self {
+            posts_to_delete: self.posts_to_delete + other.posts_to_delete,
+            follows_to_delete: self.follows_to_delete + other.follows_to_delete,
+            inactive_users: self.inactive_users,
+        }
+    }
+}
+
+impl fmt::Display for CleanupStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} posts, {} follows",
+            self.posts_to_delete, self.follows_to_delete
+        )
+    }
+}
+
+/// Deletes posts past their retention, sparing each author's newest
+/// `keep_per_author`, and posts deleted by their authors more than
+/// `DELETED_POST_RETENTION_DAYS` ago
+pub async fn cleanup_old_posts(
+    db: &Database,
+    default_hours: i64,
+    keep_per_author: i64,
+    dry_run: bool,
+) -> Result<CleanupStats> {
+    let _running = POST_CLEANUP.lock().await;
+    let expired = db
+        .cleanup_old_posts(default_hours, keep_per_author, dry_run)
+        .await?;
+    let deleted = db
+        .hard_delete_old_soft_deleted_posts(DELETED_POST_RETENTION_DAYS, dry_run)
+        .await?;
+    Ok(CleanupStats {
+        posts_to_delete: expired + deleted,
+        ..CleanupStats::default()
+    })
+}
+
+/// Runs a cleanup pass every `period` until shutdown, logging what each pass
+/// deleted. The first pass runs straight away, and a pass that has
+/// started is finished before the task stops.
+pub fn spawn_periodic<F, Fut>(
+    tasks: &mut JoinSet<()>,
+    shutdown: ShutdownCoordinator,
+    name: &'static str,
+    period: Duration,
+    mut pass: F,
+) where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<CleanupStats>> + Send + 'static,
+{
+    tasks.spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => match pass().await {
+                    Ok(deleted) => info!("{}: deleted {}", name, deleted),
+                    Err(e) => warn!("{} failed: {}", name, e),
+                },
+                _ = shutdown.wait() => break,
+            }
+        }
+    });
+}
+
+pub async fn verify_active_user_follows(db: Arc<Database>, budget: Arc<ApiBudget>) -> Result<()> {
+    let _running = FOLLOW_CLEANUP.lock().await;
     info!("Starting follow verification for active users");
 
     // Only verify follows for users who have accessed the feed in the last 7 days
@@ -15,7 +135,9 @@ pub async fn verify_active_user_follows(db: Arc<Database>) -> Result<()> {
     let client = reqwest::Client::new();
 
     for user_did in active_users {
-        match verify_follows_for_user(&client, Arc::clone(&db), &user_did).await {
+        match verify_follows_for_user(&client, Arc::clone(&db), &budget, PUBLIC_API_URL, &user_did)
+            .await
+        {
             Ok(_) => {
                 // Record that we synced this user's follows
                 if let Err(e) = db.update_follow_sync(&user_did).await {
@@ -25,6 +147,10 @@ pub async fn verify_active_user_follows(db: Arc<Database>) -> Result<()> {
                     );
                 }
             }
+            Err(e) if api_budget::is_deferred(&e) => {
+                info!("Deferring remaining follow verification: {}", e);
+                break;
+            }
             Err(e) => {
                 warn!("Failed to verify follows for {}: {}", user_did, e);
             }
@@ -35,9 +161,144 @@ pub async fn verify_active_user_follows(db: Arc<Database>) -> Result<()> {
     Ok(())
 }
 
-pub async fn cleanup_inactive_user_follows(db: Arc<Database>) -> Result<()> {
-    info!("Starting cleanup of follows for inactive users");
+/// Runs `sync_stale_follows` every few minutes until shutdown
+pub fn spawn_follow_sync(
+    tasks: &mut JoinSet<()>,
+    shutdown: ShutdownCoordinator,
+    db: Arc<Database>,
+    budget: Arc<ApiBudget>,
+    sync_interval: Duration,
+    batch_size: usize,
+) {
+    tasks.spawn(async move {
+        let mut interval = tokio::time::interval(FOLLOW_SYNC_CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if let Err(e) = sync_stale_follows(
+                        Arc::clone(&db),
+                        &budget,
+                        PUBLIC_API_URL,
+                        sync_interval,
+                        batch_size,
+                        &shutdown,
+                    )
+                    .await
+                    {
+                        warn!("Follow sync failed: {}", e);
+                    }
+                }
+                _ = shutdown.wait() => break,
+            }
+        }
+    });
+}
+
+/// Re-syncs the follows of active users last synced more than
+/// `sync_interval` ago, `batch_size` users at a time. Stops early on
+/// shutdown or when the API budget defers. Returns the users synced.
+async fn sync_stale_follows(
+    db: Arc<Database>,
+    budget: &ApiBudget,
+    api_base: &str,
+    sync_interval: Duration,
+    batch_size: usize,
+    shutdown: &ShutdownCoordinator,
+) -> Result<Vec<String>> {
+    let _running = FOLLOW_CLEANUP.lock().await;
+    let synced_before = Utc::now() - chrono::Duration::from_std(sync_interval)?;
+    let due = db.get_users_due_follow_sync(7, synced_before).await?;
+    if due.is_empty() {
+        return Ok(Vec::new());
+    }
+    info!(users = due.len(), "Starting follow sync");
+
+    let client = reqwest::Client::new();
+    let mut synced = Vec::new();
+    let batches: Vec<&[String]> = due.chunks(batch_size.max(1)).collect();
+    for (batch, users) in batches.iter().enumerate() {
+        if batch > 0 {
+            tokio::select! {
+                _ = tokio::time::sleep(FOLLOW_SYNC_BATCH_PAUSE) => {}
+                _ = shutdown.wait() => break,
+            }
+        }
+        info!(
+            batch = batch + 1,
+            batches = batches.len(),
+            users = users.len(),
+            "Syncing follows"
+        );
 
+        for user_did in users.iter() {
+            match verify_follows_for_user(&client, Arc::clone(&db), budget, api_base, user_did)
+                .await
+            {
+                Ok(()) => {
+                    db.update_follow_sync(user_did).await?;
+                    info!(user = %user_did, "Synced follows");
+                    synced.push(user_did.clone());
+                }
+                Err(e) if api_budget::is_deferred(&e) => {
+                    info!("Deferring remaining follow sync: {}", e);
+                    return Ok(synced);
+                }
+                Err(e) => warn!(user = %user_did, error = %e, "Follow sync failed"),
+            }
+        }
+    }
+
+    info!(synced = synced.len(), "Follow sync completed");
+    Ok(synced)
+}
+
+/// Runs `maintenance_pass` every `period` until shutdown, starting one
+/// period after startup
+pub fn spawn_maintenance(
+    tasks: &mut JoinSet<()>,
+    shutdown: ShutdownCoordinator,
+    db: Arc<Database>,
+    backfills: BackfillTracker,
+    period: Duration,
+) {
+    tasks.spawn(async move {
+        let start = tokio::time::Instant::now() + period;
+        let mut interval = tokio::time::interval_at(start, period);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => match maintenance_pass(&db, &backfills).await {
+                    Ok(Some(stats)) => info!(
+                        freed_pages = stats.freed_pages,
+                        checkpoint_busy = stats.checkpoint_busy,
+                        "Database maintenance completed"
+                    ),
+                    Ok(None) => info!("Skipping database maintenance while a backfill runs"),
+                    Err(e) => warn!("Database maintenance failed: {}", e),
+                },
+                _ = shutdown.wait() => break,
+            }
+        }
+    });
+}
+
+/// Checkpoints the WAL and vacuums free pages, unless a backfill is writing,
+/// in which case it returns `None` and waits for the next run
+async fn maintenance_pass(
+    db: &Database,
+    backfills: &BackfillTracker,
+) -> Result<Option<MaintenanceStats>> {
+    if backfills.any_running() {
+        return Ok(None);
+    }
+    Ok(Some(db.run_maintenance().await?))
+}
+
+/// Removes follows of users who haven't requested a feed in the last 7 days
+pub async fn cleanup_inactive_user_follows(
+    db: Arc<Database>,
+    dry_run: bool,
+) -> Result<CleanupStats> {
+    let _running = FOLLOW_CLEANUP.lock().await;
     // Get all unique follower DIDs from the follows table
     let all_follower_dids: Vec<String> = sqlx::query("SELECT DISTINCT follower_did FROM follows")
         .fetch_all(&db.pool)
@@ -46,79 +307,517 @@ pub async fn cleanup_inactive_user_follows(db: Arc<Database>) -> Result<()> {
         .filter_map(|row| row.try_get("follower_did").ok())
         .collect();
 
-    info!("Found {} unique users with follows", all_follower_dids.len());
-
     // Get active users (accessed feed in last 7 days)
     let active_users = db.get_active_users(7).await?;
-    let active_user_set: std::collections::HashSet<String> =
-        active_users.into_iter().collect();
+    let active_user_set: std::collections::HashSet<String> = active_users.into_iter().collect();
 
     // Delete follows for users who are not active
     let mut deleted_count = 0;
+    let mut inactive_users = Vec::new();
     for follower_did in all_follower_dids {
         if !active_user_set.contains(&follower_did) {
-            let result = sqlx::query("DELETE FROM follows WHERE follower_did = ?")
-                .bind(&follower_did)
-                .execute(&db.pool)
+            let follows =
+                delete_or_count(&db.pool, "follows", "follower_did = ?", dry_run, |query| {
+                    query.bind(follower_did.clone())
+                })
                 .await?;
-
-            deleted_count += result.rows_affected();
+            // A dry run names who would lose their follows, so operators can
+            // check the active-user detection before trusting it
+            if dry_run {
+                info!(user = %follower_did, follows, "Would delete follows of inactive user");
+            } else {
+                db.invalidate_follows(&follower_did).await;
+            }
+            deleted_count += follows;
+            inactive_users.push(InactiveUser {
+                did: follower_did,
+                follows,
+            });
         }
     }
+    info!(
+        users = inactive_users.len(),
+        follows = deleted_count,
+        dry_run,
+        "Inactive-user follow cleanup finished"
+    );
 
-    if deleted_count > 0 {
-        info!("Cleaned up {} follows from inactive users", deleted_count);
-    } else {
-        info!("No inactive user follows to clean up");
-    }
+    Ok(CleanupStats {
+        follows_to_delete: deleted_count,
+        inactive_users,
+        ..CleanupStats::default()
+    })
+}
 
-    Ok(())
+/// Removes posts by authors nobody follows any more
+pub async fn cleanup_stale_authors(db: Arc<Database>, dry_run: bool) -> Result<CleanupStats> {
+    let posts_to_delete = delete_or_count(
+        &db.pool,
+        "posts",
+        "NOT EXISTS (SELECT 1 FROM follows f WHERE f.target_did = posts.author_did)",
+        dry_run,
+        |query| query,
+    )
+    .await?;
+
+    Ok(CleanupStats {
+        posts_to_delete,
+        ..CleanupStats::default()
+    })
 }
 
+/// Syncs a user's follows against the AppView.
+///
+/// A full sync fetches the whole follow list, drops the follows no longer in
+/// it, and remembers the cursor that fetched its final page. Syncs in between
+/// only re-fetch from that cursor: follows before it are kept current by
+/// Jetstream follow events. If the tail comes back empty the list has shrunk
+/// underneath the cursor, and a full sync runs instead. One also runs every
+/// `FULL_FOLLOW_SYNC_INTERVAL`, for unfollows Jetstream missed.
 async fn verify_follows_for_user(
     client: &reqwest::Client,
     db: Arc<Database>,
+    budget: &ApiBudget,
+    api_base: &str,
     user_did: &str,
 ) -> Result<()> {
-    let mut cursor: Option<String> = None;
-    let mut current_follows = Vec::new();
+    let full_sync_due = db
+        .get_full_follow_sync(user_did)
+        .await?
+        .is_none_or(|synced| synced < Utc::now() - FULL_FOLLOW_SYNC_INTERVAL);
+    let stored_cursor = db.get_follows_cursor(user_did).await?;
+    if let Some(stored_cursor) = stored_cursor.filter(|_| !full_sync_due) {
+        let (tail, last_cursor) =
+            fetch_follows(client, budget, api_base, user_did, Some(stored_cursor)).await?;
+
+        if !tail.is_empty() {
+            db.insert_missing_follows(user_did, &tail).await?;
+            if let Some(cursor) = last_cursor {
+                db.set_follows_cursor(user_did, &cursor).await?;
+            }
+            return Ok(());
+        }
+
+        info!(
+            "Incremental follow sync for {} returned nothing, falling back to full re-fetch",
+            user_did
+        );
+        db.clear_follows_cursor(user_did).await?;
+    }
+
+    let (current_follows, last_cursor) =
+        fetch_follows(client, budget, api_base, user_did, None).await?;
+
+    // Sync the database with current follows
+    db.insert_missing_follows(user_did, &current_follows)
+        .await?;
+    db.sync_follows_for_user(user_did, current_follows).await?;
+    db.record_full_follow_sync(user_did).await?;
+
+    if let Some(cursor) = last_cursor {
+        db.set_follows_cursor(user_did, &cursor).await?;
+    }
+
+    Ok(())
+}
+
+/// Fetches follows starting at `start_cursor`, returning the target DIDs and the
+/// cursor that fetched the last non-empty page (`None` for the first page)
+async fn fetch_follows(
+    client: &reqwest::Client,
+    budget: &ApiBudget,
+    api_base: &str,
+    user_did: &str,
+    start_cursor: Option<String>,
+) -> Result<(Vec<String>, Option<String>)> {
+    let mut cursor = start_cursor;
+    let mut last_page_cursor = None;
+    let mut follows_found = Vec::new();
 
     loop {
         let mut url = format!(
-            "https://public.api.bsky.app/xrpc/app.bsky.graph.getFollows?actor={}&limit=100",
-            user_did
+            "{}/xrpc/app.bsky.graph.getFollows?actor={}&limit=100",
+            api_base, user_did
         );
         if let Some(ref c) = cursor {
             url.push_str(&format!("&cursor={}", c));
         }
 
-        let response: serde_json::Value = match client.get(&url).send().await {
-            Ok(r) => r.json().await?,
+        let response = match budget.get_json(client, &url, Priority::Verification).await {
+            Ok(r) => r,
             Err(e) => {
                 warn!("Failed to fetch follows for {}: {}", user_did, e);
-                return Err(e.into());
+                return Err(e);
             }
         };
 
-        let follows = response["follows"].as_array();
-        if follows.is_none() {
-            break;
-        }
+        let follows = match response["follows"].as_array() {
+            Some(follows) if !follows.is_empty() => follows,
+            _ => break,
+        };
 
-        for follow in follows.unwrap() {
+        for follow in follows {
             if let Some(target_did) = follow["did"].as_str() {
-                current_follows.push(target_did.to_string());
+                follows_found.push(target_did.to_string());
             }
         }
 
+        last_page_cursor = cursor;
         cursor = response["cursor"].as_str().map(|s| s.to_string());
         if cursor.is_none() {
             break;
         }
     }
 
-    // Sync the database with current follows
-    db.sync_follows_for_user(user_did, current_follows).await?;
+    Ok((follows_found, last_page_cursor))
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Follow;
+    use axum::{extract::Query, routing::get, Json, Router};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Serves a three-page follow list, recording the cursor of every request
+    async fn spawn_follows_api(requests: Arc<Mutex<Vec<Option<String>>>>) -> Result<String> {
+        let app = Router::new().route(
+            "/xrpc/app.bsky.graph.getFollows",
+            get(move |Query(params): Query<HashMap<String, String>>| {
+                let requests = Arc::clone(&requests);
+                async move {
+                    let cursor = params.get("cursor").cloned();
+                    requests.lock().unwrap().push(cursor.clone());
+                    let body = match cursor.as_deref() {
+                        None => serde_json::json!({
+                            "follows": [{"did": "did:example:a"}, {"did": "did:example:b"}],
+                            "cursor": "page2",
+                        }),
+                        Some("page2") => serde_json::json!({
+                            "follows": [{"did": "did:example:c"}, {"did": "did:example:d"}],
+                            "cursor": "page3",
+                        }),
+                        Some("page3") => serde_json::json!({
+                            "follows": [{"did": "did:example:e"}],
+                        }),
+                        Some(_) => serde_json::json!({ "follows": [] }),
+                    };
+                    Json(body)
+                }
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        Ok(format!("http://{}", addr))
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_stale_authors() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        db.insert_follow(&Follow {
+            uri: "at://did:example:alice/app.bsky.graph.follow/1".to_string(),
+            follower_did: "did:example:alice".to_string(),
+            target_did: "did:example:bob".to_string(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await?;
+        for author in ["did:example:bob", "did:example:carol", "did:example:carol"] {
+            db.insert_post(&crate::types::Post {
+                uri: format!(
+                    "at://{}/app.bsky.feed.post/{}",
+                    author,
+                    uuid::Uuid::new_v4()
+                ),
+                cid: "cid".to_string(),
+                author_did: author.to_string(),
+                text: "hello".to_string(),
+                reply_parent_uri: None,
+                reply_root_uri: None,
+                quoted_uri: None,
+                is_link_only: false,
+                has_media: false,
+                embed_type: None,
+                hashtags: Vec::new(),
+                created_at: Utc::now(),
+                indexed_at: Utc::now(),
+            })
+            .await?;
+        }
+
+        // Carol is followed by nobody, so her posts go
+        let stats = cleanup_stale_authors(Arc::clone(&db), false).await?;
+        assert_eq!(stats.posts_to_delete, 2);
+        assert_eq!(db.get_stats().await?.posts, 1);
+        let stats = cleanup_stale_authors(Arc::clone(&db), false).await?;
+        assert_eq!(stats, CleanupStats::default());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_counts_without_deleting() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        // Alice uses the feed, Bob stopped; Dave is followed only by Bob
+        db.record_feed_request("did:example:alice").await?;
+        for (follower, target) in [
+            ("did:example:alice", "did:example:carol"),
+            ("did:example:bob", "did:example:carol"),
+            ("did:example:bob", "did:example:dave"),
+        ] {
+            db.insert_follow(&Follow {
+                uri: format!("at://{}/app.bsky.graph.follow/{}", follower, target),
+                follower_did: follower.to_string(),
+                target_did: target.to_string(),
+                created_at: Utc::now(),
+                indexed_at: Utc::now(),
+            })
+            .await?;
+        }
+        for (author, age_hours) in [
+            ("did:example:carol", 1),
+            ("did:example:carol", 60),
+            ("did:example:dave", 1),
+            ("did:example:erin", 1),
+        ] {
+            let at = Utc::now() - chrono::Duration::hours(age_hours);
+            db.insert_post(&crate::types::Post {
+                uri: format!(
+                    "at://{}/app.bsky.feed.post/{}",
+                    author,
+                    uuid::Uuid::new_v4()
+                ),
+                cid: "cid".to_string(),
+                author_did: author.to_string(),
+                text: "hello".to_string(),
+                reply_parent_uri: None,
+                reply_root_uri: None,
+                quoted_uri: None,
+                is_link_only: false,
+                has_media: false,
+                embed_type: None,
+                hashtags: Vec::new(),
+                created_at: at,
+                indexed_at: at,
+            })
+            .await?;
+        }
+
+        // Each dry run leaves the data alone and predicts the real pass
+        let counts = || async {
+            let stats = db.get_stats().await?;
+            Ok::<_, anyhow::Error>((stats.posts, stats.follows))
+        };
+        let before = counts().await?;
+        let predicted = cleanup_old_posts(&db, 48, 0, true).await?;
+        assert_eq!(counts().await?, before);
+        assert_eq!(predicted.posts_to_delete, 1);
+        assert_eq!(cleanup_old_posts(&db, 48, 0, false).await?, predicted);
+
+        let before = counts().await?;
+        let predicted = cleanup_inactive_user_follows(Arc::clone(&db), true).await?;
+        assert_eq!(counts().await?, before);
+        assert_eq!(predicted.follows_to_delete, 2);
+        assert_eq!(
+            predicted.inactive_users,
+            vec![InactiveUser {
+                did: "did:example:bob".to_string(),
+                follows: 2,
+            }]
+        );
+        assert_eq!(
+            cleanup_inactive_user_follows(Arc::clone(&db), false).await?,
+            predicted
+        );
+
+        // With Bob's follows gone, nobody follows Dave or Erin
+        let before = counts().await?;
+        let predicted = cleanup_stale_authors(Arc::clone(&db), true).await?;
+        assert_eq!(counts().await?, before);
+        assert_eq!(predicted.posts_to_delete, 2);
+        assert_eq!(
+            cleanup_stale_authors(Arc::clone(&db), false).await?,
+            predicted
+        );
+        assert_eq!(counts().await?, (1, 1));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_periodic_task_stops_on_shutdown() -> Result<()> {
+        let shutdown = ShutdownCoordinator::new();
+        let passes = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let mut tasks = JoinSet::new();
+        let counter = Arc::clone(&passes);
+        spawn_periodic(
+            &mut tasks,
+            shutdown.clone(),
+            "test cleanup",
+            Duration::from_millis(10),
+            move || {
+                let counter = Arc::clone(&counter);
+                async move {
+                    counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    Ok(CleanupStats::default())
+                }
+            },
+        );
+
+        tokio::time::sleep(Duration::from_millis(35)).await;
+        shutdown.trigger();
+        tokio::time::timeout(Duration::from_secs(1), tasks.join_all()).await?;
+        assert!(passes.load(std::sync::atomic::Ordering::SeqCst) >= 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_follow_sync_stores_and_reuses_cursor() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        let user_did = "did:example:alice";
+        db.record_feed_request(user_did).await?;
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let api_base = spawn_follows_api(Arc::clone(&requests)).await?;
+        let client = reqwest::Client::new();
+        let budget = ApiBudget::default();
+
+        // First sync walks the whole list and remembers the final page's cursor
+        verify_follows_for_user(&client, Arc::clone(&db), &budget, &api_base, user_did).await?;
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![None, Some("page2".to_string()), Some("page3".to_string())]
+        );
+        assert_eq!(
+            db.get_follows_cursor(user_did).await?,
+            Some("page3".to_string())
+        );
+
+        // Second sync only re-fetches the tail
+        requests.lock().unwrap().clear();
+        verify_follows_for_user(&client, Arc::clone(&db), &budget, &api_base, user_did).await?;
+        assert_eq!(*requests.lock().unwrap(), vec![Some("page3".to_string())]);
+
+        // Follows already stored keep their record URIs, so Jetstream
+        // unfollows still find them
+        let real_uri = "at://did:example:alice/app.bsky.graph.follow/3kreal";
+        sqlx::query("DELETE FROM follows WHERE target_did = 'did:example:e'")
+            .execute(&db.pool)
+            .await?;
+        db.insert_follow(&Follow {
+            uri: real_uri.to_string(),
+            follower_did: user_did.to_string(),
+            target_did: "did:example:e".to_string(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await?;
+        verify_follows_for_user(&client, Arc::clone(&db), &budget, &api_base, user_did).await?;
+        assert!(db.delete_follow(real_uri).await?);
+
+        // A full sync drops follows missing from the list, even with a cursor
+        db.insert_follow(&Follow {
+            uri: "at://did:example:alice/app.bsky.graph.follow/3kgone".to_string(),
+            follower_did: user_did.to_string(),
+            target_did: "did:example:gone".to_string(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await?;
+        sqlx::query("UPDATE active_users SET last_full_follow_sync = ? WHERE did = ?")
+            .bind((Utc::now() - chrono::Duration::days(2)).to_rfc3339())
+            .bind(user_did)
+            .execute(&db.pool)
+            .await?;
+        requests.lock().unwrap().clear();
+        verify_follows_for_user(&client, Arc::clone(&db), &budget, &api_base, user_did).await?;
+        assert_eq!(requests.lock().unwrap()[0], None);
+        assert!(!db.is_following(user_did, "did:example:gone").await?);
+        assert_eq!(db.get_stats().await?.follows, 5);
+
+        // An empty tail falls back to a full re-fetch
+        db.set_follows_cursor(user_did, "stale").await?;
+        requests.lock().unwrap().clear();
+        verify_follows_for_user(&client, Arc::clone(&db), &budget, &api_base, user_did).await?;
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                Some("stale".to_string()),
+                None,
+                Some("page2".to_string()),
+                Some("page3".to_string())
+            ]
+        );
+        assert_eq!(
+            db.get_follows_cursor(user_did).await?,
+            Some("page3".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_follow_sync_skips_recently_synced_users() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        // Alice was just synced, Bob never was, Carol was synced a day ago
+        for did in ["did:example:alice", "did:example:bob", "did:example:carol"] {
+            db.record_feed_request(did).await?;
+        }
+        db.update_follow_sync("did:example:alice").await?;
+        sqlx::query("UPDATE active_users SET last_follow_sync = ? WHERE did = ?")
+            .bind((Utc::now() - chrono::Duration::days(1)).to_rfc3339())
+            .bind("did:example:carol")
+            .execute(&db.pool)
+            .await?;
+
+        let api_base = spawn_follows_api(Arc::new(Mutex::new(Vec::new()))).await?;
+        let synced = sync_stale_follows(
+            Arc::clone(&db),
+            &ApiBudget::default(),
+            &api_base,
+            Duration::from_secs(6 * 3600),
+            10,
+            &ShutdownCoordinator::new(),
+        )
+        .await?;
+        assert_eq!(synced, vec!["did:example:bob", "did:example:carol"]);
+        assert!(db
+            .get_users_due_follow_sync(7, Utc::now() - chrono::Duration::hours(6))
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_waits_for_backfills() -> Result<()> {
+        let db = Database::new(":memory:").await?;
+        db.migrate().await?;
+        let backfills = BackfillTracker::default();
+
+        let guard = backfills.start("did:example:alice").unwrap();
+        assert!(maintenance_pass(&db, &backfills).await?.is_none());
+        assert!(db.last_maintenance().is_none());
+
+        drop(guard);
+        assert!(maintenance_pass(&db, &backfills).await?.is_some());
+        assert!(db.last_maintenance().is_some());
+
+        Ok(())
+    }
 }
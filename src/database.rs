@@ -1,118 +1,1018 @@
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use sqlx::{Row, SqlitePool};
+use serde::Serialize;
+use sqlx::{
+    query::Query,
+    sqlite::{
+        SqliteArguments, SqliteAutoVacuum, SqliteConnectOptions, SqliteConnection,
+        SqlitePoolOptions, SqliteRow,
+    },
+    Row, Sqlite, SqlitePool,
+};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crate::types::{Follow, Post};
+use crate::follow_cache::FollowCache;
+use crate::types::{Block, FeedFilter, Follow, Post, UserPreferences};
+
+/// Authors whose effective retention differs from the global default (bound as `?1`).
+/// An author's retention is the longest retention among the active users following
+/// them, where users without a preference use the default.
+const RETENTION_OVERRIDES: &str = r#"
+    SELECT f.target_did AS author_did,
+           MAX(COALESCE(up.post_retention_hours, ?1)) AS hours
+    FROM follows f
+    INNER JOIN active_users au ON au.did = f.follower_did
+    LEFT JOIN user_preferences up ON up.did = f.follower_did
+    GROUP BY f.target_did
+    HAVING hours != ?1
+"#;
+
+/// Columns of `posts p` that `post_from_row` reads
+const POST_COLUMNS: &str =
+    "p.uri, p.cid, p.author_did, p.text, p.reply_parent_uri, p.reply_root_uri, \
+                            p.quoted_uri, p.is_link_only, p.has_media, p.embed_type, \
+                            p.hashtags, p.created_at, p.indexed_at";
+
+/// A condition a post must meet to be in a feed: a SQL expression over the
+/// post `p` and the follow `f` that brings it into the follower's feed
+#[derive(Debug, Clone, Copy)]
+struct FeedRule {
+    name: &'static str,
+    predicate: &'static str,
+}
+
+/// The rules a feed filter applies in SQL, in the order `explain` reports them
+fn feed_rules(filter: &FeedFilter) -> Vec<FeedRule> {
+    let mut rules = vec![FeedRule {
+        name: "no blocked hashtags",
+        predicate: "(p.hashtags IS NULL OR NOT EXISTS (
+                    SELECT 1 FROM hashtag_blocklist hb
+                    WHERE hb.owner_did = f.follower_did
+                        AND INSTR(',' || p.hashtags || ',', ',' || hb.tag || ',') > 0
+                ))",
+    }];
+    rules.push(FeedRule {
+        name: "author not blocked",
+        predicate: "NOT EXISTS (
+                    SELECT 1 FROM blocks b
+                    WHERE b.blocker_did = f.follower_did AND b.target_did = p.author_did
+                )",
+    });
+    if !filter.replies {
+        rules.push(FeedRule {
+            name: "not a reply",
+            predicate: "p.reply_parent_uri IS NULL",
+        });
+    }
+    if !filter.quotes {
+        rules.push(FeedRule {
+            name: "not a quote",
+            predicate: "p.quoted_uri IS NULL",
+        });
+    }
+    if !filter.link_only {
+        rules.push(FeedRule {
+            name: "not link-only",
+            predicate: "p.is_link_only = 0",
+        });
+    }
+    if filter.media_only {
+        rules.push(FeedRule {
+            name: "has media",
+            predicate: "p.has_media = 1",
+        });
+    }
+    if !filter.stranger_quotes {
+        // Quotes of the user themselves or of someone they follow are fine
+        rules.push(FeedRule {
+            name: "quotes someone followed",
+            predicate: "(p.quoted_author_did IS NULL
+                    OR p.quoted_author_did = f.follower_did
+                    OR EXISTS (
+                        SELECT 1 FROM follows qf
+                        WHERE qf.follower_did = f.follower_did
+                            AND qf.target_did = p.quoted_author_did
+                    ))",
+        });
+    }
+    rules
+}
+
+/// Starts from the follower's follows, deduplicated so a post can't be
+/// returned twice, and reaches posts through the (author_did, created_at)
+/// index. Binds the follower DID.
+const FOLLOWED_POSTS: &str =
+    "FROM (SELECT DISTINCT follower_did, target_did FROM follows WHERE follower_did = ?) f
+            INNER JOIN posts p ON p.author_did = f.target_did AND p.deleted_at IS NULL";
+
+/// `FOLLOWED_POSTS` with the follower counted as following themselves. The
+/// UNION keeps a real self-follow from bringing their posts in twice, and
+/// CROSS JOIN keeps SQLite from scanning posts by time instead.
+const FOLLOWED_AND_OWN_POSTS: &str = "FROM (
+                WITH me(did) AS (SELECT ?)
+                SELECT follows.follower_did, follows.target_did
+                FROM me INNER JOIN follows ON follows.follower_did = me.did
+                UNION SELECT did, did FROM me
+            ) f
+            CROSS JOIN posts p ON p.author_did = f.target_did AND p.deleted_at IS NULL";
+
+/// Most followed accounts a feed query names as bound parameters; users who
+/// follow more are served through the follows join
+const MAX_BOUND_AUTHORS: usize = 500;
+
+/// Posts older than the retention cutoff are shown only if cleanup keeps
+/// them, as one of their author's newest. Binds the number kept per author.
+const KEPT_PAST_RETENTION: &str = "p.uri IN (SELECT k.uri FROM posts k
+                    WHERE k.author_did = p.author_did AND k.deleted_at IS NULL
+                    ORDER BY k.created_at DESC LIMIT ?)";
+
+/// Builds the following-posts query from the rules a feed filter needs.
+/// Binds are always follower DID, the named authors if any, cursor time,
+/// oldest time shown, retention cutoff, posts kept per author, then limit.
+struct FollowingPostsQuery {
+    rules: Vec<FeedRule>,
+    author_daily_cap: Option<i64>,
+    max_posts_per_author: Option<i64>,
+    include_self: bool,
+    /// How many authors are bound in place of the follows join
+    bound_authors: Option<usize>,
+}
+
+impl FollowingPostsQuery {
+    fn new(filter: &FeedFilter) -> Self {
+        Self {
+            rules: feed_rules(filter),
+            author_daily_cap: filter.author_daily_cap,
+            max_posts_per_author: filter.max_posts_per_author,
+            include_self: filter.include_self,
+            bound_authors: None,
+        }
+    }
+
+    /// Reads posts by `count` bound authors rather than joining follows
+    fn with_bound_authors(mut self, count: usize) -> Self {
+        self.bound_authors = Some(count);
+        self
+    }
+
+    fn from(&self) -> String {
+        match self.bound_authors {
+            Some(count) => format!(
+                "FROM (SELECT ? AS follower_did) f
+            CROSS JOIN posts p ON p.author_did IN ({}) AND p.deleted_at IS NULL",
+                vec!["?"; count].join(", ")
+            ),
+            None if self.include_self => FOLLOWED_AND_OWN_POSTS.to_string(),
+            None => FOLLOWED_POSTS.to_string(),
+        }
+    }
+
+    fn predicates(&self) -> String {
+        self.rules
+            .iter()
+            .map(|rule| rule.predicate)
+            .collect::<Vec<_>>()
+            .join("\n                AND ")
+    }
+
+    fn sql(&self) -> String {
+        let columns = POST_COLUMNS;
+        let from = self.from();
+        let predicates = self.predicates();
+
+        let caps: Vec<String> = [
+            self.author_daily_cap
+                .map(|cap| format!("p.author_day_rank <= {}", cap)),
+            self.max_posts_per_author
+                .map(|max| format!("p.author_rank <= {}", max)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if caps.is_empty() {
+            return format!(
+                r#"
+            SELECT {columns}
+            {from}
+            WHERE {predicates}
+                AND p.created_at < ?
+                AND p.created_at > ?
+                AND (p.created_at > ? OR {KEPT_PAST_RETENTION})
+            ORDER BY p.created_at DESC
+            LIMIT ?
+            "#
+            );
+        }
+
+        // Ranks are computed over every post, not just those past the cursor,
+        // so a capped post can't come back on a later page
+        format!(
+            r#"
+            SELECT {columns}
+            FROM ({ranked}) p
+            WHERE p.created_at < ?
+                AND p.created_at > ?
+                AND (p.created_at > ? OR {KEPT_PAST_RETENTION})
+                AND {caps}
+            ORDER BY p.created_at DESC
+            LIMIT ?
+            "#,
+            ranked = self.ranked_sql(),
+            caps = caps.join(" AND ")
+        )
+    }
+
+    /// Every post the rules let through, numbered newest first within each
+    /// author's UTC day and within each author. Binds the follower DID.
+    fn ranked_sql(&self) -> String {
+        format!(
+            r#"
+                SELECT {columns},
+                       ROW_NUMBER() OVER (
+                           PARTITION BY p.author_did, p.created_at / 86400000000
+                           ORDER BY p.created_at DESC
+                       ) AS author_day_rank,
+                       ROW_NUMBER() OVER (
+                           PARTITION BY p.author_did
+                           ORDER BY p.created_at DESC
+                       ) AS author_rank
+                {from}
+                WHERE {predicates}
+            "#,
+            columns = POST_COLUMNS,
+            from = self.from(),
+            predicates = self.predicates()
+        )
+    }
+}
+
+/// The following-posts query for a follower, and the authors it binds in
+/// place of the follows join when their follows are known
+fn following_posts_sql<'a>(
+    follower_did: &'a str,
+    filter: &FeedFilter,
+    follows: Option<&'a HashSet<String>>,
+) -> (String, Vec<&'a str>) {
+    let query = FollowingPostsQuery::new(filter);
+    let Some(follows) = follows else {
+        return (query.sql(), Vec::new());
+    };
+    let mut authors: Vec<&str> = follows.iter().map(String::as_str).collect();
+    if filter.include_self && !follows.contains(follower_did) {
+        authors.push(follower_did);
+    }
+    (query.with_bound_authors(authors.len()).sql(), authors)
+}
+
+/// Outcome of one feed rule for a post, as reported by `explain_post`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleCheck {
+    pub rule: &'static str,
+    pub passed: bool,
+}
+
+/// Deletes the rows of `table` matching `condition`, or with `dry_run` only
+/// counts them
+pub(crate) async fn delete_or_count<F>(
+    pool: &SqlitePool,
+    table: &str,
+    condition: &str,
+    dry_run: bool,
+    bind: F,
+) -> Result<u64>
+where
+    F: for<'q> FnOnce(
+        Query<'q, Sqlite, SqliteArguments<'q>>,
+    ) -> Query<'q, Sqlite, SqliteArguments<'q>>,
+{
+    if dry_run {
+        let sql = format!("SELECT COUNT(*) AS count FROM {table} WHERE {condition}");
+        let count: i64 = bind(sqlx::query(&sql))
+            .fetch_one(pool)
+            .await?
+            .try_get("count")?;
+        Ok(count as u64)
+    } else {
+        let sql = format!("DELETE FROM {table} WHERE {condition}");
+        Ok(bind(sqlx::query(&sql)).execute(pool).await?.rows_affected())
+    }
+}
+
+/// Most rows one insert statement carries. At 14 variables a post row, this
+/// stays well under SQLite's limit of 32766 variables per statement.
+const INSERT_CHUNK_ROWS: usize = 500;
+
+const INSERT_POSTS: &str = "INSERT INTO posts
+    (uri, cid, author_did, text, reply_parent_uri, reply_root_uri, quoted_uri,
+     quoted_author_did, is_link_only, has_media, embed_type, hashtags, created_at,
+     indexed_at)
+    VALUES";
+
+const POST_ROW: &str = "(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+// An update in place rather than a REPLACE, so the search index triggers see
+// it. deleted_at is kept, as a replayed create mustn't bring back a deleted
+// post, and so are the times, as it mustn't move the post either.
+const POSTS_ON_CONFLICT: &str = "ON CONFLICT(uri) DO UPDATE SET
+    cid = excluded.cid, author_did = excluded.author_did, text = excluded.text,
+    reply_parent_uri = excluded.reply_parent_uri, reply_root_uri = excluded.reply_root_uri,
+    quoted_uri = excluded.quoted_uri, quoted_author_did = excluded.quoted_author_did,
+    is_link_only = excluded.is_link_only, has_media = excluded.has_media,
+    embed_type = excluded.embed_type, hashtags = excluded.hashtags";
+
+// A follow already stored under another URI, as AppView backfills make them
+// up, takes the real one so an unfollow finds it
+const INSERT_FOLLOWS: &str =
+    "INSERT INTO follows (uri, follower_did, target_did, created_at, indexed_at) VALUES";
+
+const FOLLOWS_ON_CONFLICT: &str = "ON CONFLICT(uri) DO NOTHING
+    ON CONFLICT(follower_did, target_did) DO UPDATE SET uri = excluded.uri";
+
+const FOLLOW_ROW: &str = "(?, ?, ?, ?, ?)";
+
+/// An insert of `rows` rows, each with the placeholders in `row`
+fn multi_row_sql(insert: &str, row: &str, rows: usize) -> String {
+    format!("{} {}", insert, vec![row; rows].join(",\n    "))
+}
+
+fn bind_post<'q>(
+    query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    post: &'q Post,
+) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    query
+        .bind(&post.uri)
+        .bind(&post.cid)
+        .bind(&post.author_did)
+        .bind(&post.text)
+        .bind(&post.reply_parent_uri)
+        .bind(&post.reply_root_uri)
+        .bind(&post.quoted_uri)
+        .bind(post.quoted_author_did())
+        .bind(post.is_link_only)
+        .bind(post.has_media)
+        .bind(&post.embed_type)
+        .bind((!post.hashtags.is_empty()).then(|| post.hashtags.join(",")))
+        .bind(post.created_at.timestamp_micros())
+        .bind(post.indexed_at.timestamp_micros())
+}
+
+fn bind_follow<'q>(
+    query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    follow: &'q Follow,
+) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    query
+        .bind(&follow.uri)
+        .bind(&follow.follower_did)
+        .bind(&follow.target_did)
+        .bind(follow.created_at.timestamp_micros())
+        .bind(follow.indexed_at.timestamp_micros())
+}
+
+/// Reads a timestamp stored as microseconds since the Unix epoch
+fn from_micros(micros: i64) -> Result<DateTime<Utc>> {
+    DateTime::from_timestamp_micros(micros)
+        .ok_or_else(|| anyhow::anyhow!("Timestamp out of range: {}", micros))
+}
+
+fn post_from_row(row: &SqliteRow) -> Result<Post> {
+    let hashtags: Option<String> = row.try_get("hashtags")?;
+    let created_at: i64 = row.try_get("created_at")?;
+    let indexed_at: i64 = row.try_get("indexed_at")?;
+
+    Ok(Post {
+        uri: row.try_get("uri")?,
+        cid: row.try_get("cid")?,
+        author_did: row.try_get("author_did")?,
+        text: row.try_get("text")?,
+        reply_parent_uri: row.try_get("reply_parent_uri")?,
+        reply_root_uri: row.try_get("reply_root_uri")?,
+        quoted_uri: row.try_get("quoted_uri")?,
+        is_link_only: row.try_get("is_link_only")?,
+        has_media: row.try_get("has_media")?,
+        embed_type: row.try_get("embed_type")?,
+        hashtags: hashtags
+            .map(|tags| tags.split(',').map(|t| t.to_string()).collect())
+            .unwrap_or_default(),
+        created_at: from_micros(created_at)?,
+        indexed_at: from_micros(indexed_at)?,
+    })
+}
+
+/// SQLite tuning applied to every pooled connection. Unset options keep
+/// SQLite's defaults; the suggested values suit a busy production instance.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct DatabaseConfig {
+    /// Page size in bytes for a new database file; existing files keep theirs
+    /// [suggested: 8192]
+    #[arg(long = "db-page-size", env = "DB_PAGE_SIZE")]
+    pub page_size: Option<u32>,
+
+    /// Page cache per connection, in KiB [suggested: 65536]
+    #[arg(long = "db-cache-size-kb", env = "DB_CACHE_SIZE_KB", value_parser = clap::value_parser!(i64).range(0..))]
+    pub cache_size_kb: Option<i64>,
+
+    /// Most of the database file to memory-map, in MiB [suggested: 256]
+    #[arg(long = "db-mmap-size-mb", env = "DB_MMAP_SIZE_MB", value_parser = clap::value_parser!(i64).range(0..))]
+    pub mmap_size_mb: Option<i64>,
+
+    /// Where temporary tables and sort indexes live [suggested: memory]
+    #[arg(long = "db-temp-store", env = "DB_TEMP_STORE", value_parser = ["memory", "file"])]
+    pub temp_store: Option<String>,
+
+    /// How often SQLite syncs to disk; normal is safe with WAL [suggested: normal]
+    #[arg(long = "db-synchronous", env = "DB_SYNCHRONOUS", value_parser = ["normal", "full"])]
+    pub synchronous: Option<String>,
+
+    /// Most connections the pool opens [default: 10]
+    #[arg(long = "db-max-connections", env = "DB_MAX_CONNECTIONS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_connections: Option<u32>,
+
+    /// Seconds a query waits for a free connection before failing [default: 30]
+    #[arg(long = "db-acquire-timeout-secs", env = "DB_ACQUIRE_TIMEOUT_SECS")]
+    pub acquire_timeout_secs: Option<u64>,
+
+    /// Milliseconds SQLite waits on another connection's lock before a write
+    /// backs off and retries [default: 5000]
+    #[arg(long = "db-busy-timeout-ms", env = "DB_BUSY_TIMEOUT_MS")]
+    pub busy_timeout_ms: Option<u64>,
+}
+
+impl DatabaseConfig {
+    fn apply(&self, mut options: SqliteConnectOptions) -> SqliteConnectOptions {
+        if let Some(bytes) = self.page_size {
+            options = options.page_size(bytes);
+        }
+        if let Some(kb) = self.cache_size_kb {
+            // A negative cache_size is in KiB rather than pages
+            options = options.pragma("cache_size", format!("-{}", kb));
+        }
+        if let Some(mb) = self.mmap_size_mb {
+            options = options.pragma("mmap_size", (mb * 1024 * 1024).to_string());
+        }
+        if let Some(store) = &self.temp_store {
+            options = options.pragma("temp_store", store.clone());
+        }
+        if let Some(mode) = &self.synchronous {
+            options = options.pragma("synchronous", mode.clone());
+        }
+        if let Some(ms) = self.busy_timeout_ms {
+            options = options.busy_timeout(Duration::from_millis(ms));
+        }
+        options
+    }
+
+    fn pool_options(&self) -> SqlitePoolOptions {
+        let mut pool = SqlitePoolOptions::new();
+        if let Some(max) = self.max_connections {
+            pool = pool.max_connections(max);
+        }
+        if let Some(secs) = self.acquire_timeout_secs {
+            pool = pool.acquire_timeout(Duration::from_secs(secs));
+        }
+        pool
+    }
+}
+
+/// Retries a busy write gets before its error is returned
+const BUSY_RETRIES: u32 = 4;
+
+/// Wait before the first retry of a busy write, doubled for each one after
+const BUSY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Whether SQLite gave up waiting on another connection's lock
+fn is_busy(e: &sqlx::Error) -> bool {
+    let sqlx::Error::Database(e) = e else {
+        return false;
+    };
+    // Extended codes keep the primary SQLITE_BUSY code in the low byte
+    e.code()
+        .and_then(|code| code.parse::<i32>().ok())
+        .is_some_and(|code| code & 0xff == 5)
+}
+
+/// Runs a write, retrying it with exponential backoff while the database is
+/// busy. `statement` names the write in the warning each retry logs.
+async fn retry_busy<T, F, Fut>(statement: &'static str, mut write: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut backoff = BUSY_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        match write().await {
+            Err(e) if is_busy(&e) && attempt < BUSY_RETRIES => {
+                attempt += 1;
+                tracing::warn!(
+                    statement,
+                    attempt,
+                    "Database busy, retrying in {:?}",
+                    backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            result => return Ok(result?),
+        }
+    }
+}
 
 pub struct Database {
     pub pool: SqlitePool,
+    last_maintenance: std::sync::Mutex<Option<DateTime<Utc>>>,
+    follow_cache: std::sync::OnceLock<FollowCache>,
+    max_clock_skew: std::sync::OnceLock<chrono::Duration>,
+}
+
+/// Table sizes reported by the admin consoles
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DatabaseStats {
+    pub posts: i64,
+    /// Posts indexed in the last hour and day
+    pub posts_last_hour: i64,
+    pub posts_last_day: i64,
+    pub follows: i64,
+    /// Follows indexed in the last day
+    pub follows_last_day: i64,
+    /// Distinct followers we hold follows for
+    pub users: i64,
+    /// Distinct authors we hold posts by
+    pub authors: i64,
+    /// When the oldest and newest stored posts were indexed
+    pub oldest_post: Option<DateTime<Utc>>,
+    pub newest_post: Option<DateTime<Utc>>,
+    /// None for an in-memory database
+    pub file_sizes: Option<FileSizes>,
+    /// Users who requested a feed in the last day
+    pub daily_active: i64,
+    /// Users who requested a feed in the last week
+    pub weekly_active: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveUserSummary {
+    pub did: String,
+    pub last_feed_request: String,
+    pub first_seen: String,
+    pub request_count: i64,
+    pub follows: i64,
+}
+
+/// What we know of an account besides its DID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileInfo {
+    pub did: String,
+    pub handle: Option<String>,
+    pub display_name: Option<String>,
+}
+
+/// What a `run_maintenance` pass did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceStats {
+    /// Free pages handed back to the file system
+    pub freed_pages: i64,
+    /// Whether readers kept the WAL from being fully checkpointed and truncated
+    pub checkpoint_busy: bool,
+}
+
+/// On-disk size of the database and its write-ahead log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FileSizes {
+    pub db_bytes: u64,
+    pub wal_bytes: u64,
+}
+
+/// Whether the database uses incremental auto-vacuum. Other connections may
+/// go on reporting the mode from before a VACUUM switched it, so ask the
+/// connection that ran it or one opened since.
+async fn has_incremental_vacuum(conn: &mut SqliteConnection) -> Result<bool> {
+    let auto_vacuum: i64 = sqlx::query_scalar("PRAGMA auto_vacuum")
+        .fetch_one(conn)
+        .await?;
+    Ok(auto_vacuum == 2)
 }
 
 impl Database {
     pub async fn new(database_url: &str) -> Result<Self> {
-        let pool = SqlitePool::connect(database_url).await?;
+        Self::with_config(database_url, &DatabaseConfig::default()).await
+    }
+
+    pub async fn with_config(database_url: &str, config: &DatabaseConfig) -> Result<Self> {
+        // Lets maintenance hand pages freed by cleanup back to the file
+        // system. Only a new file takes it up straight away; an existing one
+        // needs `enable_incremental_vacuum`.
+        let options = config
+            .apply(SqliteConnectOptions::from_str(database_url)?)
+            .auto_vacuum(SqliteAutoVacuum::Incremental);
+        let pool = config.pool_options().connect_with(options).await?;
 
         // Enable WAL mode for better concurrency
         sqlx::query("PRAGMA journal_mode=WAL;")
             .execute(&pool)
             .await?;
 
-        // Set busy timeout to 5 seconds
-        sqlx::query("PRAGMA busy_timeout=5000;")
-            .execute(&pool)
-            .await?;
+        Ok(Self {
+            pool,
+            last_maintenance: std::sync::Mutex::new(None),
+            follow_cache: std::sync::OnceLock::new(),
+            max_clock_skew: std::sync::OnceLock::new(),
+        })
+    }
+
+    /// How far past its indexing time a post may be dated before it is
+    /// stored as created when it was indexed. Defaults to five minutes.
+    pub fn set_max_clock_skew(&self, skew: chrono::Duration) {
+        let _ = self.max_clock_skew.set(skew);
+    }
+
+    /// `posts`, with any dated too far ahead brought back to when they
+    /// were indexed
+    fn clamp_future_dated<'a>(&self, posts: &'a [Post]) -> Cow<'a, [Post]> {
+        let skew = *self
+            .max_clock_skew
+            .get()
+            .unwrap_or(&chrono::Duration::minutes(5));
+        if !posts.iter().any(|post| post.is_future_dated(skew)) {
+            return Cow::Borrowed(posts);
+        }
+        Cow::Owned(
+            posts
+                .iter()
+                .map(|post| {
+                    if !post.is_future_dated(skew) {
+                        return post.clone();
+                    }
+                    tracing::warn!(
+                        "Post {} claims to be from {}, ahead of when it was indexed; storing it as created at {}",
+                        post.uri,
+                        post.created_at,
+                        post.indexed_at
+                    );
+                    Post {
+                        created_at: post.indexed_at,
+                        ..post.clone()
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Keeps `cache` in step with the follows this database writes and
+    /// lets feed queries read follow sets from it
+    pub fn set_follow_cache(&self, cache: FollowCache) {
+        let _ = self.follow_cache.set(cache);
+    }
 
-        Ok(Self { pool })
+    /// Drops a user's cached follow set after their follows change outside
+    /// the methods here
+    pub async fn invalidate_follows(&self, follower_did: &str) {
+        if let Some(cache) = self.follow_cache.get() {
+            cache.invalidate(follower_did).await;
+        }
+    }
+
+    /// The accounts a user follows, when a follow cache is set and they
+    /// follow few enough to name in a query
+    async fn cached_follows(&self, follower_did: &str) -> Result<Option<Arc<HashSet<String>>>> {
+        let Some(cache) = self.follow_cache.get() else {
+            return Ok(None);
+        };
+        let targets = match cache.get(follower_did).await {
+            Some(targets) => targets,
+            None => {
+                let generation = cache.generation(follower_did);
+                let targets =
+                    sqlx::query_scalar("SELECT target_did FROM follows WHERE follower_did = ?")
+                        .bind(follower_did)
+                        .fetch_all(&self.pool)
+                        .await?
+                        .into_iter()
+                        .collect();
+                cache.insert(follower_did, targets, generation).await
+            }
+        };
+        Ok((targets.len() <= MAX_BOUND_AUTHORS).then_some(targets))
     }
 
     pub async fn migrate(&self) -> Result<()> {
         sqlx::migrate!("./migrations").run(&self.pool).await?;
+
+        if !has_incremental_vacuum(&mut *self.pool.acquire().await?).await? {
+            tracing::info!(
+                "The database predates incremental auto-vacuum, so maintenance can't shrink it; run the vacuum command while the server is stopped to switch"
+            );
+        }
         Ok(())
     }
 
+    /// Switches an existing database to incremental auto-vacuum, returning
+    /// false if it already was. This rewrites the whole file with a VACUUM,
+    /// which can take minutes and briefly needs twice the disk space.
+    pub async fn enable_incremental_vacuum(&self) -> Result<bool> {
+        let mut conn = self.pool.acquire().await?;
+        if has_incremental_vacuum(&mut conn).await? {
+            return Ok(false);
+        }
+        sqlx::query("PRAGMA auto_vacuum = INCREMENTAL")
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("VACUUM").execute(&mut *conn).await?;
+        // VACUUM may renumber the rowids of posts, which has no INTEGER
+        // PRIMARY KEY, leaving the search index pointing at the wrong posts
+        sqlx::query("INSERT INTO posts_fts(posts_fts) VALUES ('rebuild')")
+            .execute(&mut *conn)
+            .await?;
+        Ok(true)
+    }
+
+    /// Runs SQLite's integrity and foreign key checks, returning every
+    /// problem found. An empty list means the database is healthy.
+    pub async fn check_integrity(&self) -> Result<Vec<String>> {
+        let mut problems = Vec::new();
+
+        for row in sqlx::query("PRAGMA integrity_check")
+            .fetch_all(&self.pool)
+            .await?
+        {
+            let message: String = row.try_get(0)?;
+            if message != "ok" {
+                problems.push(message);
+            }
+        }
+
+        for row in sqlx::query("PRAGMA foreign_key_check")
+            .fetch_all(&self.pool)
+            .await?
+        {
+            let table: String = row.try_get(0)?;
+            let rowid: Option<i64> = row.try_get(1)?;
+            let parent: String = row.try_get(2)?;
+            problems.push(format!(
+                "foreign key violation in {} row {} referencing {}",
+                table,
+                rowid.map_or("?".to_string(), |id| id.to_string()),
+                parent
+            ));
+        }
+
+        Ok(problems)
+    }
+
     // Post operations
     pub async fn insert_post(&self, post: &Post) -> Result<()> {
-        sqlx::query(
+        self.insert_posts_batch(std::slice::from_ref(post)).await
+    }
+
+    /// Inserts posts in one transaction with multi-row statements, which is
+    /// far cheaper per post than committing each one on its own
+    pub async fn insert_posts_batch(&self, posts: &[Post]) -> Result<()> {
+        let posts = self.clamp_future_dated(posts);
+        retry_busy("insert_posts", || self.write_posts_batch(&posts)).await
+    }
+
+    async fn write_posts_batch(&self, posts: &[Post]) -> Result<(), sqlx::Error> {
+        // Taking the write lock up front keeps concurrent writers from
+        // deadlocking on the search index tables
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+        for chunk in posts.chunks(INSERT_CHUNK_ROWS) {
+            let sql = format!(
+                "{}\n{}",
+                multi_row_sql(INSERT_POSTS, POST_ROW, chunk.len()),
+                POSTS_ON_CONFLICT
+            );
+            chunk
+                .iter()
+                .fold(sqlx::query(&sql), bind_post)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Applies an edit to a stored post, returning whether there was one.
+    /// Thread refs can't change, so they're left alone. So are `created_at`
+    /// and `indexed_at`, so an edit can't move a post up the feed.
+    pub async fn update_post(&self, post: &Post) -> Result<bool> {
+        let result = sqlx::query(
             r#"
-            INSERT OR REPLACE INTO posts (uri, cid, author_did, text, created_at, indexed_at)
-            VALUES (?, ?, ?, ?, ?, ?)
+            UPDATE posts SET cid = ?, text = ?, quoted_uri = ?, quoted_author_did = ?,
+                is_link_only = ?, has_media = ?, embed_type = ?, hashtags = ?
+            WHERE uri = ?
             "#,
         )
-        .bind(&post.uri)
         .bind(&post.cid)
-        .bind(&post.author_did)
         .bind(&post.text)
-        .bind(post.created_at.to_rfc3339())
-        .bind(post.indexed_at.to_rfc3339())
+        .bind(&post.quoted_uri)
+        .bind(post.quoted_author_did())
+        .bind(post.is_link_only)
+        .bind(post.has_media)
+        .bind(&post.embed_type)
+        .bind((!post.hashtags.is_empty()).then(|| post.hashtags.join(",")))
+        .bind(&post.uri)
         .execute(&self.pool)
         .await?;
-        Ok(())
+        Ok(result.rows_affected() > 0)
     }
 
+    /// Marks a post deleted, keeping the row until
+    /// `hard_delete_old_soft_deleted_posts` removes it
     pub async fn delete_post(&self, uri: &str) -> Result<()> {
-        sqlx::query("DELETE FROM posts WHERE uri = ?")
-            .bind(uri)
-            .execute(&self.pool)
-            .await?;
+        let deleted_at = Utc::now().timestamp_micros();
+        retry_busy("delete_post", || {
+            sqlx::query("UPDATE posts SET deleted_at = ? WHERE uri = ? AND deleted_at IS NULL")
+                .bind(deleted_at)
+                .bind(uri)
+                .execute(&self.pool)
+        })
+        .await?;
         Ok(())
     }
 
-    // Follow operations
-    pub async fn insert_follow(&self, follow: &Follow) -> Result<()> {
-        sqlx::query(
-            r#"
-            INSERT OR REPLACE INTO follows (uri, follower_did, target_did, created_at, indexed_at)
-            VALUES (?, ?, ?, ?, ?)
-            "#,
+    /// Brings back a soft-deleted post, returning whether there was one
+    pub async fn restore_post(&self, uri: &str) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE posts SET deleted_at = NULL WHERE uri = ? AND deleted_at IS NOT NULL",
         )
-        .bind(&follow.uri)
-        .bind(&follow.follower_did)
-        .bind(&follow.target_did)
-        .bind(follow.created_at.to_rfc3339())
-        .bind(follow.indexed_at.to_rfc3339())
+        .bind(uri)
         .execute(&self.pool)
         .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Removes posts soft-deleted more than `days` ago, returning how many.
+    /// With `dry_run`, only counts them.
+    pub async fn hard_delete_old_soft_deleted_posts(
+        &self,
+        days: i64,
+        dry_run: bool,
+    ) -> Result<u64> {
+        let cutoff = Utc::now() - chrono::Duration::days(days);
+        delete_or_count(&self.pool, "posts", "deleted_at < ?", dry_run, |query| {
+            query.bind(cutoff.timestamp_micros())
+        })
+        .await
+    }
+
+    // Follow operations
+    pub async fn insert_follow(&self, follow: &Follow) -> Result<()> {
+        self.insert_follows_batch(std::slice::from_ref(follow))
+            .await
+    }
+
+    /// Inserts follows in one transaction with multi-row statements
+    pub async fn insert_follows_batch(&self, follows: &[Follow]) -> Result<()> {
+        retry_busy("insert_follows", || self.write_follows_batch(follows)).await?;
+        let followers: HashSet<&str> = follows.iter().map(|f| f.follower_did.as_str()).collect();
+        for follower_did in followers {
+            self.invalidate_follows(follower_did).await;
+        }
         Ok(())
     }
 
-    pub async fn delete_follow(&self, uri: &str) -> Result<()> {
-        sqlx::query("DELETE FROM follows WHERE uri = ?")
-            .bind(uri)
-            .execute(&self.pool)
-            .await?;
+    async fn write_follows_batch(&self, follows: &[Follow]) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for chunk in follows.chunks(INSERT_CHUNK_ROWS) {
+            let sql = format!(
+                "{}\n{}",
+                multi_row_sql(INSERT_FOLLOWS, FOLLOW_ROW, chunk.len()),
+                FOLLOWS_ON_CONFLICT
+            );
+            chunk
+                .iter()
+                .fold(sqlx::query(&sql), bind_follow)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
         Ok(())
     }
 
+    /// Deletes the follow stored under `uri`, returning whether there was one
+    pub async fn delete_follow(&self, uri: &str) -> Result<bool> {
+        let followers: Vec<String> = retry_busy("delete_follow", || {
+            sqlx::query_scalar("DELETE FROM follows WHERE uri = ? RETURNING follower_did")
+                .bind(uri)
+                .fetch_all(&self.pool)
+        })
+        .await?;
+        for follower_did in &followers {
+            self.invalidate_follows(follower_did).await;
+        }
+        Ok(!followers.is_empty())
+    }
+
+    /// Removes an account's posts and the follows it made, returning how
+    /// many of each were deleted
+    pub async fn remove_account_content(&self, did: &str) -> Result<(u64, u64)> {
+        let mut tx = self.pool.begin().await?;
+        let posts = sqlx::query("DELETE FROM posts WHERE author_did = ?")
+            .bind(did)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        let follows = sqlx::query("DELETE FROM follows WHERE follower_did = ?")
+            .bind(did)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        sqlx::query("DELETE FROM inactive_authors WHERE did = ?")
+            .bind(did)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        self.invalidate_follows(did).await;
+        Ok((posts, follows))
+    }
+
+    /// Hides an author's posts while their account is deactivated or taken
+    /// down, returning how many were hidden. They stay soft-deleted until
+    /// `reactivate_author` or cleanup.
+    pub async fn deactivate_author(&self, did: &str) -> Result<u64> {
+        let deactivated_at = Utc::now().timestamp_micros();
+        let mut tx = self.pool.begin().await?;
+        // A second deactivation keeps the first time, so the posts it hid
+        // still come back
+        sqlx::query("INSERT OR IGNORE INTO inactive_authors (did, deactivated_at) VALUES (?, ?)")
+            .bind(did)
+            .bind(deactivated_at)
+            .execute(&mut *tx)
+            .await?;
+        let hidden = sqlx::query(
+            "UPDATE posts SET deleted_at = ? WHERE author_did = ? AND deleted_at IS NULL",
+        )
+        .bind(deactivated_at)
+        .bind(did)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        tx.commit().await?;
+        Ok(hidden)
+    }
+
+    /// Brings back the posts `deactivate_author` hid, but not ones the author
+    /// deleted beforehand, returning how many. Cleanup may have removed some
+    /// in the meantime.
+    pub async fn reactivate_author(&self, did: &str) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let deactivated_at: Option<i64> = sqlx::query_scalar(
+            "DELETE FROM inactive_authors WHERE did = ? RETURNING deactivated_at",
+        )
+        .bind(did)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(deactivated_at) = deactivated_at else {
+            return Ok(0);
+        };
+        let restored = sqlx::query(
+            "UPDATE posts SET deleted_at = NULL WHERE author_did = ? AND deleted_at >= ?",
+        )
+        .bind(did)
+        .bind(deactivated_at)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        tx.commit().await?;
+        Ok(restored)
+    }
+
     // Feed generation queries
     pub async fn get_following_posts(
         &self,
         follower_did: &str,
         limit: i32,
-        cursor: Option<&str>,
+        cursor: Option<DateTime<Utc>>,
+        filter: &FeedFilter,
     ) -> Result<Vec<Post>> {
-        let cursor_time = cursor
-            .and_then(|c| DateTime::parse_from_rfc3339(c).ok())
-            .map(|dt| dt.with_timezone(&Utc))
-            .unwrap_or_else(Utc::now);
+        let cursor_time = cursor.unwrap_or_else(Utc::now);
+        let hours_ago = |hours: Option<i64>| {
+            hours
+                .map(|hours| (Utc::now() - chrono::Duration::hours(hours)).timestamp_micros())
+                .unwrap_or(i64::MIN)
+        };
+        let oldest_time = hours_ago(filter.max_age_hours);
+        let retention_time = hours_ago(filter.retention_hours);
 
         let start = Instant::now();
-        let rows_result = sqlx::query(
-            r#"
-            SELECT p.uri, p.cid, p.author_did, p.text, p.created_at, p.indexed_at
-            FROM posts p
-            INNER JOIN follows f ON f.target_did = p.author_did
-            WHERE f.follower_did = ?
-                AND p.created_at < ?
-            ORDER BY p.created_at DESC
-            LIMIT ?
-            "#,
-        )
-        .bind(follower_did)
-        .bind(cursor_time.to_rfc3339())
-        .bind(limit)
-        .fetch_all(&self.pool)
-        .await;
+        let follows = self.cached_follows(follower_did).await?;
+        let (sql, authors) = following_posts_sql(follower_did, filter, follows.as_deref());
+        let rows_result = authors
+            .iter()
+            .fold(sqlx::query(&sql).bind(follower_did), |query, author| {
+                query.bind(*author)
+            })
+            .bind(cursor_time.timestamp_micros())
+            .bind(oldest_time)
+            .bind(retention_time)
+            .bind(filter.kept_per_author)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await;
 
         let rows = match rows_result {
             Ok(rows) => {
@@ -138,55 +1038,546 @@ impl Database {
             }
         };
 
-        let mut posts = Vec::new();
-        for row in rows {
-            let uri: String = row.try_get("uri")?;
-            let cid: String = row.try_get("cid")?;
-            let author_did: String = row.try_get("author_did")?;
-            let text: String = row.try_get("text")?;
-            let created_at_str: String = row.try_get("created_at")?;
-            let indexed_at_str: String = row.try_get("indexed_at")?;
-
-            posts.push(Post {
-                uri,
-                cid,
-                author_did,
-                text,
-                created_at: DateTime::parse_from_rfc3339(&created_at_str)?.with_timezone(&Utc),
-                indexed_at: DateTime::parse_from_rfc3339(&indexed_at_str)?.with_timezone(&Utc),
-            });
-        }
-
-        Ok(posts)
-    }
-
-    pub async fn cleanup_old_posts(&self, hours: i64) -> Result<()> {
-        let cutoff = Utc::now() - chrono::Duration::hours(hours);
-        let result = sqlx::query("DELETE FROM posts WHERE indexed_at < ?")
-            .bind(cutoff.to_rfc3339())
-            .execute(&self.pool)
+        rows.iter().map(post_from_row).collect()
+    }
+
+    /// SQLite's plan for a follower's default feed query, one step per
+    /// line, to check it still reaches posts through the indexes
+    pub async fn explain_feed_query(&self, follower_did: &str) -> Result<String> {
+        let follows = self.cached_follows(follower_did).await?;
+        let (sql, authors) =
+            following_posts_sql(follower_did, &FeedFilter::default(), follows.as_deref());
+        let sql = format!("EXPLAIN QUERY PLAN {}", sql);
+        let rows = authors
+            .iter()
+            .fold(sqlx::query(&sql).bind(follower_did), |query, author| {
+                query.bind(*author)
+            })
+            .bind(Utc::now().timestamp_micros())
+            .bind(i64::MIN)
+            .bind(i64::MIN)
+            .bind(0)
+            .bind(50)
+            .fetch_all(&self.pool)
             .await?;
 
-        let deleted = result.rows_affected();
-        if deleted > 0 {
-            tracing::info!("Cleaned up {} posts older than {} hours", deleted, hours);
+        // Steps are indented under their parent, as the sqlite3 shell does
+        let mut depths = std::collections::HashMap::new();
+        let mut out = String::new();
+        for row in rows {
+            let id: i64 = row.try_get("id")?;
+            let parent: i64 = row.try_get("parent")?;
+            let detail: String = row.try_get("detail")?;
+            let depth = depths.get(&parent).map_or(0, |depth| depth + 1);
+            depths.insert(id, depth);
+            out.push_str(&format!("{}{}\n", "  ".repeat(depth), detail));
         }
-        Ok(())
+        Ok(out)
     }
 
-    pub async fn record_feed_request(&self, user_did: &str) -> Result<()> {
-        sqlx::query(
-            r#"
-            INSERT INTO active_users (did, last_feed_request)
-            VALUES (?, ?)
-            ON CONFLICT(did) DO UPDATE SET last_feed_request = excluded.last_feed_request
-            "#,
-        )
-        .bind(user_did)
-        .bind(Utc::now().to_rfc3339())
-        .execute(&self.pool)
-        .await?;
-        Ok(())
+    /// Like `get_following_posts`, but only posts with images or video
+    pub async fn get_following_posts_with_media(
+        &self,
+        follower_did: &str,
+        limit: i32,
+        cursor: Option<DateTime<Utc>>,
+        filter: &FeedFilter,
+    ) -> Result<Vec<Post>> {
+        let filter = FeedFilter {
+            media_only: true,
+            ..*filter
+        };
+        self.get_following_posts(follower_did, limit, cursor, &filter)
+            .await
+    }
+
+    /// The stored posts among `uris`, in no particular order
+    pub async fn get_posts_by_uris(&self, uris: &[String]) -> Result<Vec<Post>> {
+        if uris.is_empty() {
+            return Ok(Vec::new());
+        }
+        let placeholders = vec!["?"; uris.len()].join(", ");
+        let sql = format!(
+            "SELECT {} FROM posts p WHERE p.uri IN ({}) AND p.deleted_at IS NULL",
+            POST_COLUMNS, placeholders
+        );
+        let mut query = sqlx::query(&sql);
+        for uri in uris {
+            query = query.bind(uri);
+        }
+        query
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(post_from_row)
+            .collect()
+    }
+
+    /// Posts whose text matches an FTS5 query, newest first. Words match
+    /// their stems, so "running" finds "runs".
+    pub async fn search_posts(&self, query: &str, limit: usize) -> Result<Vec<Post>> {
+        let sql = format!(
+            r#"
+            SELECT {} FROM posts p
+            WHERE p.rowid IN (SELECT rowid FROM posts_fts WHERE posts_fts MATCH ?)
+              AND p.deleted_at IS NULL
+            ORDER BY p.created_at DESC
+            LIMIT ?
+            "#,
+            POST_COLUMNS
+        );
+        sqlx::query(&sql)
+            .bind(query)
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(post_from_row)
+            .collect()
+    }
+
+    /// Checks a post against each rule of a feed in turn, stopping at the
+    /// first one it fails, to tell why a post is or isn't in someone's feed
+    pub async fn explain_post(
+        &self,
+        follower_did: &str,
+        uri: &str,
+        filter: &FeedFilter,
+    ) -> Result<Vec<RuleCheck>> {
+        let mut checks = Vec::new();
+        let mut check = |rule, passed| {
+            checks.push(RuleCheck { rule, passed });
+            passed
+        };
+
+        let row = sqlx::query(&format!(
+            "SELECT {}, p.deleted_at FROM posts p WHERE p.uri = ?",
+            POST_COLUMNS
+        ))
+        .bind(uri)
+        .fetch_optional(&self.pool)
+        .await?;
+        // Reposts are never stored, and old posts are cleaned up
+        let Some(row) = row else {
+            check("stored", false);
+            return Ok(checks);
+        };
+        check("stored", true);
+        let deleted_at: Option<i64> = row.try_get("deleted_at")?;
+        if !check("not deleted", deleted_at.is_none()) {
+            return Ok(checks);
+        }
+        let post = post_from_row(&row)?;
+
+        if filter.include_self && post.author_did == follower_did {
+            check("own post", true);
+        } else {
+            let follows = self.is_following(follower_did, &post.author_did).await?;
+            if !check("follows author", follows) {
+                return Ok(checks);
+            }
+        }
+
+        // Rules only look at the follow for who the follower is
+        let query = FollowingPostsQuery::new(filter);
+        for rule in &query.rules {
+            let passed: bool = sqlx::query(&format!(
+                "SELECT ({}) AS passed
+                FROM posts p, (SELECT ? AS follower_did) f
+                WHERE p.uri = ?",
+                rule.predicate
+            ))
+            .bind(follower_did)
+            .bind(uri)
+            .fetch_one(&self.pool)
+            .await?
+            .try_get("passed")?;
+            if !check(rule.name, passed) {
+                return Ok(checks);
+            }
+        }
+
+        if query.author_daily_cap.is_some() || query.max_posts_per_author.is_some() {
+            let ranks = sqlx::query(&format!(
+                "SELECT author_day_rank, author_rank FROM ({}) WHERE uri = ?",
+                query.ranked_sql()
+            ))
+            .bind(follower_did)
+            .bind(uri)
+            .fetch_one(&self.pool)
+            .await?;
+            if let Some(cap) = query.author_daily_cap {
+                let rank: i64 = ranks.try_get("author_day_rank")?;
+                if !check("within author daily cap", rank <= cap) {
+                    return Ok(checks);
+                }
+            }
+            if let Some(max) = query.max_posts_per_author {
+                let rank: i64 = ranks.try_get("author_rank")?;
+                if !check("within posts per author", rank <= max) {
+                    return Ok(checks);
+                }
+            }
+        }
+
+        if let Some(hours) = filter.max_age_hours {
+            let recent = post.created_at > Utc::now() - chrono::Duration::hours(hours);
+            if !check("recent enough", recent) {
+                return Ok(checks);
+            }
+        }
+
+        if let Some(hours) = filter.retention_hours {
+            let newer: i64 = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM posts
+                WHERE author_did = ? AND deleted_at IS NULL AND created_at > ?",
+            )
+            .bind(&post.author_did)
+            .bind(post.created_at.timestamp_micros())
+            .fetch_one(&self.pool)
+            .await?;
+            let retained = post.created_at > Utc::now() - chrono::Duration::hours(hours)
+                || newer < filter.kept_per_author;
+            if !check("within retention", retained) {
+                return Ok(checks);
+            }
+        }
+
+        if filter.min_post_length.is_some() {
+            check("long enough", filter.allows_length(&post));
+        }
+
+        Ok(checks)
+    }
+
+    /// Deletes posts past their retention, returning how many were deleted.
+    /// Each author's newest `keep_per_author` posts are kept however old, so
+    /// quiet accounts don't drop out of feeds; 0 keeps none. With `dry_run`,
+    /// only counts them.
+    pub async fn cleanup_old_posts(
+        &self,
+        default_hours: i64,
+        keep_per_author: i64,
+        dry_run: bool,
+    ) -> Result<u64> {
+        // Authors nobody has a retention preference for get the global default
+        let cutoff = Utc::now() - chrono::Duration::hours(default_hours);
+        let mut deleted = delete_or_count(
+            &self.pool,
+            "posts",
+            &format!(
+                "indexed_at < ?2 AND author_did NOT IN (SELECT author_did FROM ({})){}",
+                RETENTION_OVERRIDES,
+                newest_per_author(3)
+            ),
+            dry_run,
+            |query| {
+                query
+                    .bind(default_hours)
+                    .bind(cutoff.timestamp_micros())
+                    .bind(keep_per_author)
+            },
+        )
+        .await?;
+
+        // Then one pass per distinct retention that users asked for
+        let overrides: Vec<i64> = sqlx::query(&format!(
+            "SELECT DISTINCT hours FROM ({})",
+            RETENTION_OVERRIDES
+        ))
+        .bind(default_hours)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .filter_map(|row| row.try_get("hours").ok())
+        .collect();
+
+        for hours in overrides {
+            let cutoff = Utc::now() - chrono::Duration::hours(hours);
+            deleted += delete_or_count(
+                &self.pool,
+                "posts",
+                &format!(
+                    "indexed_at < ?2 AND author_did IN (SELECT author_did FROM ({}) WHERE hours = ?3){}",
+                    RETENTION_OVERRIDES,
+                    newest_per_author(4)
+                ),
+                dry_run,
+                |query| {
+                    query
+                        .bind(default_hours)
+                        .bind(cutoff.timestamp_micros())
+                        .bind(hours)
+                        .bind(keep_per_author)
+                },
+            )
+            .await?;
+        }
+
+        Ok(deleted)
+    }
+
+    pub async fn get_preferences(&self, did: &str) -> Result<UserPreferences> {
+        let row = sqlx::query(
+            r#"
+            SELECT post_retention_hours, author_daily_cap, hide_stranger_quotes, min_post_length,
+                   max_posts_per_author
+            FROM user_preferences
+            WHERE did = ?
+            "#,
+        )
+        .bind(did)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match row {
+            Some(row) => Ok(UserPreferences {
+                post_retention_hours: row.try_get("post_retention_hours")?,
+                author_daily_cap: row.try_get("author_daily_cap")?,
+                hide_stranger_quotes: row.try_get("hide_stranger_quotes")?,
+                min_post_length: row.try_get("min_post_length")?,
+                max_posts_per_author: row.try_get("max_posts_per_author")?,
+            }),
+            None => Ok(UserPreferences::default()),
+        }
+    }
+
+    pub async fn set_hide_stranger_quotes(&self, did: &str, hide: bool) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_preferences (did, hide_stranger_quotes, updated_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT(did) DO UPDATE SET
+                hide_stranger_quotes = excluded.hide_stranger_quotes,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(did)
+        .bind(hide)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_author_daily_cap(&self, did: &str, cap: Option<i64>) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_preferences (did, author_daily_cap, updated_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT(did) DO UPDATE SET
+                author_daily_cap = excluded.author_daily_cap,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(did)
+        .bind(cap)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_max_posts_per_author(&self, did: &str, max: Option<i64>) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_preferences (did, max_posts_per_author, updated_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT(did) DO UPDATE SET
+                max_posts_per_author = excluded.max_posts_per_author,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(did)
+        .bind(max)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_min_post_length(&self, did: &str, length: Option<i64>) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_preferences (did, min_post_length, updated_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT(did) DO UPDATE SET
+                min_post_length = excluded.min_post_length,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(did)
+        .bind(length)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_post_retention(&self, did: &str, hours: Option<i64>) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_preferences (did, post_retention_hours, updated_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT(did) DO UPDATE SET
+                post_retention_hours = excluded.post_retention_hours,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(did)
+        .bind(hours)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_stats(&self) -> Result<DatabaseStats> {
+        let now = Utc::now();
+        let row = sqlx::query(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM posts) AS posts,
+                (SELECT COUNT(*) FROM posts WHERE indexed_at > ?3) AS posts_last_hour,
+                (SELECT COUNT(*) FROM posts WHERE indexed_at > ?4) AS posts_last_day,
+                (SELECT COUNT(*) FROM follows) AS follows,
+                (SELECT COUNT(*) FROM follows WHERE indexed_at > ?4) AS follows_last_day,
+                (SELECT COUNT(DISTINCT follower_did) FROM follows) AS users,
+                (SELECT COUNT(DISTINCT author_did) FROM posts) AS authors,
+                (SELECT MIN(indexed_at) FROM posts) AS oldest_post,
+                (SELECT MAX(indexed_at) FROM posts) AS newest_post,
+                (SELECT COUNT(*) FROM active_users WHERE last_feed_request > ?1) AS daily_active,
+                (SELECT COUNT(*) FROM active_users WHERE last_feed_request > ?2) AS weekly_active
+            "#,
+        )
+        .bind((now - chrono::Duration::days(1)).to_rfc3339())
+        .bind((now - chrono::Duration::days(7)).to_rfc3339())
+        .bind((now - chrono::Duration::hours(1)).timestamp_micros())
+        .bind((now - chrono::Duration::days(1)).timestamp_micros())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(DatabaseStats {
+            posts: row.try_get("posts")?,
+            posts_last_hour: row.try_get("posts_last_hour")?,
+            posts_last_day: row.try_get("posts_last_day")?,
+            follows: row.try_get("follows")?,
+            follows_last_day: row.try_get("follows_last_day")?,
+            users: row.try_get("users")?,
+            authors: row.try_get("authors")?,
+            oldest_post: row
+                .try_get::<Option<i64>, _>("oldest_post")?
+                .map(from_micros)
+                .transpose()?,
+            newest_post: row
+                .try_get::<Option<i64>, _>("newest_post")?
+                .map(from_micros)
+                .transpose()?,
+            file_sizes: self.file_sizes().await?,
+            daily_active: row.try_get("daily_active")?,
+            weekly_active: row.try_get("weekly_active")?,
+        })
+    }
+
+    /// Users who have requested a feed, most recent first
+    pub async fn get_active_user_summaries(&self) -> Result<Vec<ActiveUserSummary>> {
+        self.active_user_summaries(None).await
+    }
+
+    /// One user's feed activity, if they have ever requested a feed
+    pub async fn get_active_user_summary(&self, did: &str) -> Result<Option<ActiveUserSummary>> {
+        Ok(self.active_user_summaries(Some(did)).await?.pop())
+    }
+
+    async fn active_user_summaries(&self, did: Option<&str>) -> Result<Vec<ActiveUserSummary>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT au.did, au.last_feed_request, au.first_seen, au.request_count,
+                   COUNT(f.uri) AS follows
+            FROM active_users au
+            LEFT JOIN follows f ON f.follower_did = au.did
+            WHERE ?1 IS NULL OR au.did = ?1
+            GROUP BY au.did
+            ORDER BY au.last_feed_request DESC
+            "#,
+        )
+        .bind(did)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(ActiveUserSummary {
+                    did: row.try_get("did")?,
+                    last_feed_request: row.try_get("last_feed_request")?,
+                    first_seen: row.try_get("first_seen")?,
+                    request_count: row.try_get("request_count")?,
+                    follows: row.try_get("follows")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Deletes everything stored on behalf of a feed user: their follows,
+    /// activity and settings, and with `cascade_posts` the posts only stored
+    /// for them, those by authors no one else follows. It all happens in one
+    /// transaction, so a failure leaves the user untouched. Returns the rows
+    /// deleted.
+    pub async fn delete_all_user_data(&self, did: &str, cascade_posts: bool) -> Result<u64> {
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+        let mut deleted = 0;
+        if cascade_posts {
+            deleted += sqlx::query(
+                r#"
+                DELETE FROM posts
+                WHERE author_did IN (SELECT target_did FROM follows WHERE follower_did = ?)
+                  AND author_did NOT IN (SELECT target_did FROM follows WHERE follower_did != ?)
+                "#,
+            )
+            .bind(did)
+            .bind(did)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+
+        for statement in [
+            "DELETE FROM follows WHERE follower_did = ?",
+            "DELETE FROM active_users WHERE did = ?",
+            "DELETE FROM user_preferences WHERE did = ?",
+            "DELETE FROM hashtag_blocklist WHERE owner_did = ?",
+            "DELETE FROM handles WHERE did = ?",
+            "DELETE FROM profiles WHERE did = ?",
+            "DELETE FROM blocks WHERE blocker_did = ?",
+        ] {
+            deleted += sqlx::query(statement)
+                .bind(did)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        tx.commit().await?;
+        self.invalidate_follows(did).await;
+        Ok(deleted)
+    }
+
+    /// Notes a feed request, counting it and keeping when the user was
+    /// first seen
+    pub async fn record_feed_request(&self, user_did: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO active_users (did, last_feed_request, first_seen, request_count)
+            VALUES (?1, ?2, ?2, 1)
+            ON CONFLICT(did) DO UPDATE SET
+                last_feed_request = excluded.last_feed_request,
+                request_count = active_users.request_count + 1
+            "#,
+        )
+        .bind(user_did)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
     }
 
     pub async fn get_active_users(&self, days: i64) -> Result<Vec<String>> {
@@ -206,6 +1597,30 @@ impl Database {
         Ok(dids)
     }
 
+    /// Users active in the last `days` whose follows haven't been synced
+    /// since `synced_before`, never-synced and longest-waiting first
+    pub async fn get_users_due_follow_sync(
+        &self,
+        days: i64,
+        synced_before: DateTime<Utc>,
+    ) -> Result<Vec<String>> {
+        let cutoff = Utc::now() - chrono::Duration::days(days);
+        let rows = sqlx::query(
+            r#"
+            SELECT did FROM active_users
+            WHERE last_feed_request > ?
+              AND (last_follow_sync IS NULL OR last_follow_sync < ?)
+            ORDER BY last_follow_sync IS NOT NULL, last_follow_sync
+            "#,
+        )
+        .bind(cutoff.to_rfc3339())
+        .bind(synced_before.to_rfc3339())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(|row| Ok(row.try_get("did")?)).collect()
+    }
+
     pub async fn update_follow_sync(&self, user_did: &str) -> Result<()> {
         sqlx::query("UPDATE active_users SET last_follow_sync = ? WHERE did = ?")
             .bind(Utc::now().to_rfc3339())
@@ -215,6 +1630,87 @@ impl Database {
         Ok(())
     }
 
+    pub async fn get_follows_cursor(&self, did: &str) -> Result<Option<String>> {
+        let row = sqlx::query("SELECT last_follows_cursor FROM active_users WHERE did = ?")
+            .bind(did)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.and_then(|row| row.try_get("last_follows_cursor").ok().flatten()))
+    }
+
+    pub async fn set_follows_cursor(&self, did: &str, cursor: &str) -> Result<()> {
+        sqlx::query("UPDATE active_users SET last_follows_cursor = ? WHERE did = ?")
+            .bind(cursor)
+            .bind(did)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn clear_follows_cursor(&self, did: &str) -> Result<()> {
+        sqlx::query("UPDATE active_users SET last_follows_cursor = NULL WHERE did = ?")
+            .bind(did)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// When the user's follows were last fetched in full
+    pub async fn get_full_follow_sync(&self, did: &str) -> Result<Option<DateTime<Utc>>> {
+        let synced: Option<String> =
+            sqlx::query_scalar("SELECT last_full_follow_sync FROM active_users WHERE did = ?")
+                .bind(did)
+                .fetch_optional(&self.pool)
+                .await?
+                .flatten();
+        Ok(synced
+            .and_then(|synced| DateTime::parse_from_rfc3339(&synced).ok())
+            .map(|synced| synced.with_timezone(&Utc)))
+    }
+
+    pub async fn record_full_follow_sync(&self, did: &str) -> Result<()> {
+        sqlx::query("UPDATE active_users SET last_full_follow_sync = ? WHERE did = ?")
+            .bind(Utc::now().to_rfc3339())
+            .bind(did)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Adds the follows of `user_did` we don't have yet, returning how many.
+    /// Follows already stored keep their record URI, so Jetstream unfollows
+    /// still find them.
+    pub async fn insert_missing_follows(&self, user_did: &str, targets: &[String]) -> Result<u64> {
+        let now = Utc::now().timestamp_micros();
+        let mut tx = self.pool.begin().await?;
+        let mut inserted = 0;
+        for target_did in targets {
+            inserted += sqlx::query(
+                "INSERT INTO follows (uri, follower_did, target_did, created_at, indexed_at)
+                 VALUES (?, ?, ?, ?, ?)
+                 ON CONFLICT DO NOTHING",
+            )
+            .bind(format!(
+                "at://{}/app.bsky.graph.follow/{}",
+                user_did,
+                uuid::Uuid::new_v4()
+            ))
+            .bind(user_did)
+            .bind(target_did)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+        tx.commit().await?;
+        if inserted > 0 {
+            self.invalidate_follows(user_did).await;
+        }
+        Ok(inserted)
+    }
+
     pub async fn sync_follows_for_user(
         &self,
         user_did: &str,
@@ -247,6 +1743,7 @@ impl Database {
         }
 
         if removed_count > 0 {
+            self.invalidate_follows(user_did).await;
             tracing::info!(
                 "Cleaned up {} stale follows for {}",
                 removed_count,
@@ -257,18 +1754,1654 @@ impl Database {
         Ok(())
     }
 
-    // Unused but kept for potential future use
-    #[allow(dead_code)]
-    pub async fn is_following(&self, follower_did: &str, target_did: &str) -> Result<bool> {
+    /// Users who have requested a feed, and every account they follow
+    pub async fn get_feed_users_and_follows(&self) -> Result<(HashSet<String>, HashSet<String>)> {
+        let users = sqlx::query_scalar("SELECT did FROM active_users")
+            .fetch_all(&self.pool)
+            .await?;
+        let authors = sqlx::query_scalar(
+            "SELECT DISTINCT f.target_did FROM follows f INNER JOIN active_users au ON au.did = f.follower_did",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok((users.into_iter().collect(), authors.into_iter().collect()))
+    }
+
+    /// Whether a DID appears in our follow graph, as follower or followed
+    pub async fn is_tracked_did(&self, did: &str) -> Result<bool> {
         let row = sqlx::query(
-            "SELECT COUNT(*) as count FROM follows WHERE follower_did = ? AND target_did = ?",
+            "SELECT EXISTS(SELECT 1 FROM follows WHERE target_did = ?1 OR follower_did = ?1) as tracked",
         )
-        .bind(follower_did)
-        .bind(target_did)
+        .bind(did)
         .fetch_one(&self.pool)
         .await?;
 
-        let count: i64 = row.try_get("count")?;
-        Ok(count > 0)
+        let tracked: bool = row.try_get("tracked")?;
+        Ok(tracked)
+    }
+
+    /// Remembers the handle a DID goes by, replacing any earlier one
+    pub async fn set_handle(&self, did: &str, handle: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO handles (did, handle, updated_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT(did) DO UPDATE SET handle = excluded.handle, updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(did)
+        .bind(handle)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_handle(&self, did: &str) -> Result<Option<String>> {
+        Ok(
+            sqlx::query_scalar("SELECT handle FROM handles WHERE did = ?")
+                .bind(did)
+                .fetch_optional(&self.pool)
+                .await?,
+        )
+    }
+
+    /// Stores what a profile record says, keeping the stored handle when
+    /// the record names none
+    pub async fn upsert_profile(
+        &self,
+        did: &str,
+        handle: Option<&str>,
+        display_name: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO profiles (did, handle, display_name, updated_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT(did) DO UPDATE SET
+                handle = COALESCE(excluded.handle, profiles.handle),
+                display_name = excluded.display_name,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(did)
+        .bind(handle)
+        .bind(display_name)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The stored profile, with the handle from identity events when the
+    /// profile has none. `None` if we know nothing of the DID.
+    pub async fn get_profile(&self, did: &str) -> Result<Option<ProfileInfo>> {
+        let row = sqlx::query(
+            r#"
+            SELECT COALESCE(p.handle, h.handle) AS handle, p.display_name
+            FROM (SELECT ?1 AS did) d
+            LEFT JOIN profiles p ON p.did = d.did
+            LEFT JOIN handles h ON h.did = d.did
+            WHERE p.did IS NOT NULL OR h.did IS NOT NULL
+            "#,
+        )
+        .bind(did)
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(|row| {
+            Ok(ProfileInfo {
+                did: did.to_string(),
+                handle: row.try_get("handle")?,
+                display_name: row.try_get("display_name")?,
+            })
+        })
+        .transpose()
+    }
+
+    /// `@handle (did)` when the handle is known, for admin output and logs
+    pub async fn describe_did(&self, did: &str) -> String {
+        match self.get_handle(did).await {
+            Ok(Some(handle)) => format!("@{} ({})", handle, did),
+            _ => did.to_string(),
+        }
+    }
+
+    /// Hides posts tagged `tag` from `owner_did`'s feeds
+    pub async fn add_tag_block(&self, owner_did: &str, tag: &str) -> Result<()> {
+        sqlx::query("INSERT OR IGNORE INTO hashtag_blocklist (owner_did, tag) VALUES (?, ?)")
+            .bind(owner_did)
+            .bind(normalize_tag(tag))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns whether the tag was blocked
+    pub async fn remove_tag_block(&self, owner_did: &str, tag: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM hashtag_blocklist WHERE owner_did = ? AND tag = ?")
+            .bind(owner_did)
+            .bind(normalize_tag(tag))
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Pins a post to the top of everyone's first feed page
+    pub async fn pin_post(&self, uri: &str) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO pinned_posts (uri, pinned_at) VALUES (?, ?)")
+            .bind(uri)
+            .bind(Utc::now().to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns whether the post was pinned
+    pub async fn unpin_post(&self, uri: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM pinned_posts WHERE uri = ?")
+            .bind(uri)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// `time_us` of the last Jetstream event saved by the consumer
+    pub async fn get_jetstream_cursor(&self) -> Result<Option<i64>> {
+        let row = sqlx::query("SELECT cursor_us FROM consumer_state WHERE name = 'jetstream'")
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|row| row.try_get("cursor_us")).transpose()?)
+    }
+
+    pub async fn set_jetstream_cursor(&self, cursor_us: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO consumer_state (name, cursor_us) VALUES ('jetstream', ?)
+            ON CONFLICT(name) DO UPDATE SET cursor_us = excluded.cursor_us
+            "#,
+        )
+        .bind(cursor_us)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Pinned post URIs, most recently pinned first
+    pub async fn get_pinned_posts(&self) -> Result<Vec<String>> {
+        let rows = sqlx::query("SELECT uri FROM pinned_posts ORDER BY pinned_at DESC")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter().map(|row| Ok(row.try_get("uri")?)).collect()
+    }
+
+    /// Authors with the most followers among the users we track
+    pub async fn get_top_followed_authors(&self, limit: usize) -> Result<Vec<(String, i64)>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT target_did, COUNT(*) as cnt
+            FROM follows
+            GROUP BY target_did
+            ORDER BY cnt DESC, target_did
+            LIMIT ?
+            "#,
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| Ok((row.try_get("target_did")?, row.try_get("cnt")?)))
+            .collect()
+    }
+
+    /// Stores a user's blocks in one transaction
+    pub async fn insert_blocks(&self, blocks: &[Block]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for block in blocks {
+            sqlx::query(
+                "INSERT OR IGNORE INTO blocks (uri, blocker_did, target_did, created_at)
+                 VALUES (?, ?, ?, ?)",
+            )
+            .bind(&block.uri)
+            .bind(&block.blocker_did)
+            .bind(&block.target_did)
+            .bind(block.created_at.timestamp_micros())
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Deletes the block stored under `uri`, returning whether there was one
+    pub async fn delete_block(&self, uri: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM blocks WHERE uri = ?")
+            .bind(uri)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn has_blocks(&self, blocker_did: &str) -> Result<bool> {
+        Ok(
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM blocks WHERE blocker_did = ?)")
+                .bind(blocker_did)
+                .fetch_one(&self.pool)
+                .await?,
+        )
+    }
+
+    pub async fn has_follows(&self, follower_did: &str) -> Result<bool> {
+        let row =
+            sqlx::query("SELECT EXISTS(SELECT 1 FROM follows WHERE follower_did = ?) as found")
+                .bind(follower_did)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(row.try_get("found")?)
+    }
+
+    pub async fn get_follow_count_for_author(&self, target_did: &str) -> Result<i64> {
+        let row = sqlx::query("SELECT COUNT(*) as count FROM follows WHERE target_did = ?")
+            .bind(target_did)
+            .fetch_one(&self.pool)
+            .await?;
+
+        let count: i64 = row.try_get("count")?;
+        Ok(count)
+    }
+
+    pub async fn is_following(&self, follower_did: &str, target_did: &str) -> Result<bool> {
+        let row = sqlx::query(
+            "SELECT COUNT(*) as count FROM follows WHERE follower_did = ? AND target_did = ?",
+        )
+        .bind(follower_did)
+        .bind(target_did)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let count: i64 = row.try_get("count")?;
+        Ok(count > 0)
+    }
+
+    /// Returns free pages left by deletes to the file system, then
+    /// checkpoints the WAL into the database file and truncates it. The file
+    /// only shrinks once the vacuum is checkpointed.
+    pub async fn run_maintenance(&self) -> Result<MaintenanceStats> {
+        let mut conn = self.pool.acquire().await?;
+        let free_before: i64 = sqlx::query_scalar("PRAGMA freelist_count")
+            .fetch_one(&mut *conn)
+            .await?;
+        sqlx::query("PRAGMA incremental_vacuum")
+            .execute(&mut *conn)
+            .await?;
+        let free_after: i64 = sqlx::query_scalar("PRAGMA freelist_count")
+            .fetch_one(&mut *conn)
+            .await?;
+        let checkpoint = sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+            .fetch_one(&mut *conn)
+            .await?;
+
+        *self.last_maintenance.lock().unwrap() = Some(Utc::now());
+        Ok(MaintenanceStats {
+            freed_pages: free_before - free_after,
+            checkpoint_busy: checkpoint.try_get::<i64, _>(0)? != 0,
+        })
+    }
+
+    /// When `run_maintenance` last finished, if it has since startup
+    pub fn last_maintenance(&self) -> Option<DateTime<Utc>> {
+        *self.last_maintenance.lock().unwrap()
+    }
+
+    /// Sizes of the database file and its WAL, or `None` for an in-memory
+    /// database
+    pub async fn file_sizes(&self) -> Result<Option<FileSizes>> {
+        let row = sqlx::query("PRAGMA database_list")
+            .fetch_one(&self.pool)
+            .await?;
+        let path: String = row.try_get("file")?;
+        if path.is_empty() {
+            return Ok(None);
+        }
+        let size = |path: &str| std::fs::metadata(path).map_or(0, |m| m.len());
+        Ok(Some(FileSizes {
+            db_bytes: size(&path),
+            wal_bytes: size(&format!("{}-wal", path)),
+        }))
+    }
+}
+
+/// Spares each author's newest posts, as many as parameter `?{param}`, from a
+/// purge of `posts`. Each candidate reads only its author's newest through
+/// the author index, and with 0 none are read.
+fn newest_per_author(param: usize) -> String {
+    format!(
+        " AND (?{0} = 0 OR uri NOT IN (SELECT k.uri FROM posts k WHERE k.author_did = posts.author_did AND k.deleted_at IS NULL ORDER BY k.created_at DESC LIMIT ?{0}))",
+        param
+    )
+}
+
+/// Tags are stored the way `Post::hashtags_of` extracts them
+fn normalize_tag(tag: &str) -> String {
+    tag.trim_start_matches('#').to_lowercase().replace(',', "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    async fn test_db() -> Result<Database> {
+        let db = Database::new(":memory:").await?;
+        db.migrate().await?;
+        Ok(db)
+    }
+
+    async fn follow(db: &Database, follower_did: &str, target_did: &str) -> Result<()> {
+        db.insert_follow(&Follow {
+            uri: format!("at://{}/app.bsky.graph.follow/{}", follower_did, target_did),
+            follower_did: follower_did.to_string(),
+            target_did: target_did.to_string(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await
+    }
+
+    fn test_post(author_did: &str, rkey: &str) -> Post {
+        Post {
+            uri: format!("at://{}/app.bsky.feed.post/{}", author_did, rkey),
+            cid: format!("cid-{}", rkey),
+            author_did: author_did.to_string(),
+            text: "text".to_string(),
+            reply_parent_uri: None,
+            reply_root_uri: None,
+            quoted_uri: None,
+            is_link_only: false,
+            has_media: false,
+            embed_type: None,
+            hashtags: Vec::new(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        }
+    }
+
+    async fn post(db: &Database, author_did: &str, rkey: &str, age_hours: i64) -> Result<()> {
+        let timestamp = Utc::now() - chrono::Duration::hours(age_hours);
+        db.insert_post(&Post {
+            created_at: timestamp,
+            indexed_at: timestamp,
+            ..test_post(author_did, rkey)
+        })
+        .await
+    }
+
+    async fn post_count(db: &Database, author_did: &str) -> Result<i64> {
+        let row = sqlx::query("SELECT COUNT(*) as count FROM posts WHERE author_did = ?")
+            .bind(author_did)
+            .fetch_one(&db.pool)
+            .await?;
+        Ok(row.try_get("count")?)
+    }
+
+    #[tokio::test]
+    async fn test_user_retention_outlives_global_cleanup() -> Result<()> {
+        let db = test_db().await?;
+
+        // Alice keeps a week of posts, Bob uses the default
+        db.record_feed_request("did:example:alice").await?;
+        db.record_feed_request("did:example:bob").await?;
+        db.set_post_retention("did:example:alice", Some(168))
+            .await?;
+        follow(&db, "did:example:alice", "did:example:carol").await?;
+        follow(&db, "did:example:bob", "did:example:dave").await?;
+
+        for author in ["did:example:carol", "did:example:dave"] {
+            post(&db, author, "fresh", 1).await?;
+            post(&db, author, "old", 72).await?;
+            post(&db, author, "ancient", 200).await?;
+        }
+
+        db.cleanup_old_posts(48, 0, false).await?;
+
+        assert_eq!(post_count(&db, "did:example:carol").await?, 2);
+        assert_eq!(post_count(&db, "did:example:dave").await?, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_stats() -> Result<()> {
+        let db = test_db().await?;
+        let empty = db.get_stats().await?;
+        assert_eq!((empty.posts, empty.authors), (0, 0));
+        assert!(empty.oldest_post.is_none() && empty.file_sizes.is_none());
+
+        follow(&db, "did:example:alice", "did:example:carol").await?;
+        follow(&db, "did:example:alice", "did:example:dave").await?;
+        follow(&db, "did:example:bob", "did:example:carol").await?;
+        post(&db, "did:example:carol", "new", 1).await?;
+        post(&db, "did:example:carol", "old", 30).await?;
+        post(&db, "did:example:dave", "mid", 5).await?;
+        db.record_feed_request("did:example:alice").await?;
+
+        let stats = db.get_stats().await?;
+        assert_eq!(
+            (stats.posts, stats.follows, stats.users, stats.authors),
+            (3, 3, 2, 2)
+        );
+        assert_eq!((stats.daily_active, stats.weekly_active), (1, 1));
+        assert_eq!((stats.posts_last_hour, stats.posts_last_day), (0, 2));
+        assert_eq!(stats.follows_last_day, 3);
+        let span = stats.newest_post.unwrap() - stats.oldest_post.unwrap();
+        assert!(span > chrono::Duration::hours(28) && span <= chrono::Duration::hours(29));
+
+        let json = serde_json::to_value(stats)?;
+        assert_eq!(json["authors"], 2);
+        assert!(json["file_sizes"].is_null());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_feed_requests_track_active_users() -> Result<()> {
+        let db = test_db().await?;
+        db.record_feed_request("did:example:alice").await?;
+        db.record_feed_request("did:example:alice").await?;
+        db.record_feed_request("did:example:bob").await?;
+        sqlx::query("UPDATE active_users SET last_feed_request = ? WHERE did = ?")
+            .bind((Utc::now() - chrono::Duration::days(3)).to_rfc3339())
+            .bind("did:example:bob")
+            .execute(&db.pool)
+            .await?;
+
+        let users = db.get_active_user_summaries().await?;
+        let alice = users.iter().find(|u| u.did == "did:example:alice").unwrap();
+        assert_eq!(alice.request_count, 2);
+        assert!(alice.first_seen <= alice.last_feed_request);
+
+        let stats = db.get_stats().await?;
+        assert_eq!((stats.daily_active, stats.weekly_active), (1, 2));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_keeps_newest_posts_per_author() -> Result<()> {
+        let db = test_db().await?;
+        follow(&db, "did:example:alice", "did:example:quiet").await?;
+        follow(&db, "did:example:alice", "did:example:firehose").await?;
+
+        // A weekly poster, and an account posting every hour for four days
+        post(&db, "did:example:quiet", "last-week", 168).await?;
+        post(&db, "did:example:quiet", "week-before", 336).await?;
+        for hours in 0..96 {
+            post(&db, "did:example:firehose", &hours.to_string(), hours).await?;
+        }
+
+        assert_eq!(db.cleanup_old_posts(48, 5, true).await?, 48);
+        db.cleanup_old_posts(48, 5, false).await?;
+
+        assert_eq!(post_count(&db, "did:example:quiet").await?, 2);
+        assert_eq!(post_count(&db, "did:example:firehose").await?, 48);
+
+        // Without a floor the quiet author's posts go too
+        db.cleanup_old_posts(48, 0, false).await?;
+        assert_eq!(post_count(&db, "did:example:quiet").await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_uses_configured_retention() -> Result<()> {
+        let db = test_db().await?;
+        follow(&db, "did:example:alice", "did:example:carol").await?;
+        post(&db, "did:example:carol", "fresh", 6).await?;
+        post(&db, "did:example:carol", "day", 13).await?;
+        post(&db, "did:example:carol", "old", 30).await?;
+
+        assert_eq!(db.cleanup_old_posts(12, 0, false).await?, 2);
+        assert_eq!(post_count(&db, "did:example:carol").await?, 1);
+        let fresh = "at://did:example:carol/app.bsky.feed.post/fresh".to_string();
+        assert_eq!(db.get_posts_by_uris(&[fresh]).await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_author_daily_cap_holds_across_pages() -> Result<()> {
+        let db = test_db().await?;
+        follow(&db, "did:example:alice", "did:example:chatty").await?;
+        follow(&db, "did:example:alice", "did:example:quiet").await?;
+
+        // Fixed timestamps so every post lands in the same UTC day
+        let noon = DateTime::parse_from_rfc3339("2024-03-01T12:00:00Z")?.with_timezone(&Utc);
+        let insert = |author: &'static str, rkey: String, minutes: i64| {
+            let created_at = noon - chrono::Duration::minutes(minutes);
+            let db = &db;
+            async move {
+                db.insert_post(&Post {
+                    uri: format!("at://{}/app.bsky.feed.post/{}", author, rkey),
+                    cid: format!("cid-{}", rkey),
+                    author_did: author.to_string(),
+                    text: "text".to_string(),
+                    reply_parent_uri: None,
+                    reply_root_uri: None,
+                    quoted_uri: None,
+                    is_link_only: false,
+                    has_media: false,
+                    embed_type: None,
+                    hashtags: Vec::new(),
+                    created_at,
+                    indexed_at: created_at,
+                })
+                .await
+            }
+        };
+        for i in 0..8 {
+            insert("did:example:chatty", format!("c{}", i), i * 10).await?;
+        }
+        for i in 0..2 {
+            insert("did:example:quiet", format!("q{}", i), i * 10 + 5).await?;
+        }
+
+        let filter = FeedFilter {
+            author_daily_cap: Some(5),
+            ..FeedFilter::default()
+        };
+        let mut seen = Vec::new();
+        let mut cursor: Option<DateTime<Utc>> = None;
+        loop {
+            let page = db
+                .get_following_posts("did:example:alice", 3, cursor, &filter)
+                .await?;
+            let Some(last) = page.last() else { break };
+            cursor = Some(last.created_at);
+            seen.extend(
+                page.iter()
+                    .map(|p| p.uri.rsplit('/').next().unwrap().to_string()),
+            );
+        }
+
+        // Chatty's five newest posts and both of Quiet's, nothing older from Chatty
+        let mut expected: Vec<String> = (0..5).map(|i| format!("c{}", i)).collect();
+        expected.extend(["q0".to_string(), "q1".to_string()]);
+        seen.sort();
+        expected.sort();
+        assert_eq!(seen, expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_posts_per_author() -> Result<()> {
+        let db = test_db().await?;
+        let alice = "did:example:alice";
+        follow(&db, alice, "did:example:bob").await?;
+        follow(&db, alice, "did:example:carol").await?;
+        for i in 0..20 {
+            post(&db, "did:example:bob", &format!("bob-{}", i), i).await?;
+        }
+        for i in 0..5 {
+            post(&db, "did:example:carol", &format!("carol-{}", i), i).await?;
+        }
+        let capped = FeedFilter {
+            max_posts_per_author: Some(3),
+            ..FeedFilter::default()
+        };
+
+        let posts = db.get_following_posts(alice, 50, None, &capped).await?;
+        assert_eq!(posts.len(), 6);
+        for author in ["did:example:bob", "did:example:carol"] {
+            // The newest three of each
+            let ages: Vec<&str> = posts
+                .iter()
+                .filter(|p| p.author_did == author)
+                .map(|p| p.uri.rsplit('-').next().unwrap())
+                .collect();
+            assert_eq!(ages, vec!["0", "1", "2"]);
+        }
+
+        // A cap the request asks for wins over the user's preference
+        db.set_max_posts_per_author(alice, Some(1)).await?;
+        let preferences = db.get_preferences(alice).await?;
+        assert_eq!(
+            FeedFilter::default()
+                .with_preferences(&preferences)
+                .max_posts_per_author,
+            Some(1)
+        );
+        assert_eq!(
+            capped.with_preferences(&preferences).max_posts_per_author,
+            Some(3)
+        );
+
+        let checks = db
+            .explain_post(
+                alice,
+                "at://did:example:bob/app.bsky.feed.post/bob-5",
+                &capped,
+            )
+            .await?;
+        assert_eq!(
+            checks.last(),
+            Some(&RuleCheck {
+                rule: "within posts per author",
+                passed: false
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocked_hashtags_are_hidden() -> Result<()> {
+        let db = test_db().await?;
+        follow(&db, "did:example:alice", "did:example:bob").await?;
+
+        let record = serde_json::json!({
+            "text": "#Rust and #cats",
+            "facets": [
+                { "features": [{ "$type": "app.bsky.richtext.facet#tag", "tag": "Rust" }] },
+                { "features": [{ "$type": "app.bsky.richtext.facet#tag", "tag": "cats" }] },
+                { "features": [{ "$type": "app.bsky.richtext.facet#link", "uri": "https://x.y" }] }
+            ]
+        });
+        let hashtags = Post::hashtags_of(&record);
+        assert_eq!(hashtags, vec!["rust".to_string(), "cats".to_string()]);
+
+        let now = Utc::now();
+        db.insert_post(&Post {
+            uri: "at://did:example:bob/app.bsky.feed.post/tagged".to_string(),
+            cid: "cid-tagged".to_string(),
+            author_did: "did:example:bob".to_string(),
+            text: "#Rust and #cats".to_string(),
+            reply_parent_uri: None,
+            reply_root_uri: None,
+            quoted_uri: None,
+            is_link_only: false,
+            has_media: false,
+            embed_type: None,
+            hashtags,
+            created_at: now,
+            indexed_at: now,
+        })
+        .await?;
+        post(&db, "did:example:bob", "plain", 0).await?;
+
+        let filter = FeedFilter::default();
+        let posts = db
+            .get_following_posts("did:example:alice", 10, None, &filter)
+            .await?;
+        assert_eq!(posts.len(), 2);
+        assert!(posts.iter().any(|p| p.hashtags == ["rust", "cats"]));
+
+        db.add_tag_block("did:example:alice", "#CATS").await?;
+        let posts = db
+            .get_following_posts("did:example:alice", 10, None, &filter)
+            .await?;
+        assert_eq!(posts.len(), 1);
+        assert!(posts[0].uri.ends_with("/plain"));
+
+        assert!(db.remove_tag_block("did:example:alice", "cats").await?);
+        assert!(!db.remove_tag_block("did:example:alice", "cats").await?);
+        let posts = db
+            .get_following_posts("did:example:alice", 10, None, &filter)
+            .await?;
+        assert_eq!(posts.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_explain_post() -> Result<()> {
+        let db = test_db().await?;
+        let alice = "did:example:alice";
+        follow(&db, alice, "did:example:bob").await?;
+
+        let bob_post = |rkey: &str, hour: u32| Post {
+            uri: format!("at://did:example:bob/app.bsky.feed.post/{}", rkey),
+            cid: format!("cid-{}", rkey),
+            author_did: "did:example:bob".to_string(),
+            text: "text".to_string(),
+            reply_parent_uri: None,
+            reply_root_uri: None,
+            quoted_uri: None,
+            is_link_only: false,
+            has_media: false,
+            embed_type: None,
+            hashtags: Vec::new(),
+            created_at: Utc.with_ymd_and_hms(2024, 1, 1, hour, 0, 0).unwrap(),
+            indexed_at: Utc::now(),
+        };
+        db.insert_post(&bob_post("plain", 9)).await?;
+        db.insert_post(&Post {
+            reply_parent_uri: Some("at://did:example:carol/app.bsky.feed.post/1".to_string()),
+            reply_root_uri: Some("at://did:example:carol/app.bsky.feed.post/1".to_string()),
+            ..bob_post("reply", 10)
+        })
+        .await?;
+        db.insert_post(&Post {
+            hashtags: vec!["spoilers".to_string()],
+            ..bob_post("tagged", 11)
+        })
+        .await?;
+        post(&db, "did:example:carol", "stranger", 0).await?;
+        db.add_tag_block(alice, "spoilers").await?;
+
+        let outcome = |checks: Vec<RuleCheck>| {
+            let last = checks.last().unwrap();
+            (last.rule, last.passed)
+        };
+        let uri = |author: &str, rkey: &str| format!("at://{}/app.bsky.feed.post/{}", author, rkey);
+        let default = FeedFilter::default();
+
+        let checks = db
+            .explain_post(alice, &uri("did:example:bob", "missing"), &default)
+            .await?;
+        assert_eq!(outcome(checks), ("stored", false));
+
+        let checks = db
+            .explain_post(alice, &uri("did:example:carol", "stranger"), &default)
+            .await?;
+        assert_eq!(outcome(checks), ("follows author", false));
+
+        let checks = db
+            .explain_post(alice, &uri("did:example:bob", "reply"), &default)
+            .await?;
+        assert!(checks.iter().all(|check| check.passed));
+        let checks = db
+            .explain_post(
+                alice,
+                &uri("did:example:bob", "reply"),
+                &FeedFilter::strict(),
+            )
+            .await?;
+        assert_eq!(outcome(checks), ("not a reply", false));
+
+        let checks = db
+            .explain_post(alice, &uri("did:example:bob", "tagged"), &default)
+            .await?;
+        assert_eq!(outcome(checks), ("no blocked hashtags", false));
+
+        // The newest post of the day takes the only slot
+        let capped = FeedFilter {
+            author_daily_cap: Some(1),
+            ..default
+        };
+        let checks = db
+            .explain_post(alice, &uri("did:example:bob", "plain"), &capped)
+            .await?;
+        assert_eq!(outcome(checks), ("within author daily cap", false));
+        let checks = db
+            .explain_post(alice, &uri("did:example:bob", "reply"), &capped)
+            .await?;
+        assert_eq!(outcome(checks), ("within author daily cap", true));
+
+        let short = FeedFilter {
+            min_post_length: Some(10),
+            ..default
+        };
+        let checks = db
+            .explain_post(alice, &uri("did:example:bob", "plain"), &short)
+            .await?;
+        assert_eq!(outcome(checks), ("long enough", false));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_database_config_pragmas() -> Result<()> {
+        let config = DatabaseConfig {
+            page_size: Some(8192),
+            cache_size_kb: Some(2048),
+            mmap_size_mb: Some(64),
+            temp_store: Some("memory".to_string()),
+            synchronous: Some("normal".to_string()),
+            max_connections: Some(4),
+            acquire_timeout_secs: Some(5),
+            busy_timeout_ms: Some(250),
+        };
+        let db = Database::with_config(":memory:", &config).await?;
+        db.migrate().await?;
+
+        let pragma = |name: &'static str| {
+            let pool = db.pool.clone();
+            async move {
+                let value: i64 = sqlx::query(&format!("PRAGMA {}", name))
+                    .fetch_one(&pool)
+                    .await?
+                    .try_get(0)?;
+                anyhow::Ok(value)
+            }
+        };
+        assert_eq!(pragma("page_size").await?, 8192);
+        assert_eq!(pragma("cache_size").await?, -2048);
+        assert_eq!(pragma("temp_store").await?, 2);
+        assert_eq!(pragma("synchronous").await?, 1);
+        assert_eq!(pragma("busy_timeout").await?, 250);
+        assert_eq!(db.pool.options().get_max_connections(), 4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_busy_writes_retry() -> Result<()> {
+        // Contention needs a file database; in-memory ones lock differently
+        let path = std::env::temp_dir().join(format!("noreposts-busy-{}.db", uuid::Uuid::new_v4()));
+        let config = DatabaseConfig {
+            busy_timeout_ms: Some(10),
+            ..DatabaseConfig::default()
+        };
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        let db = Database::with_config(&url, &config).await?;
+        db.migrate().await?;
+
+        // A write held past every retry fails with the busy error
+        let holder = db.pool.begin_with("BEGIN IMMEDIATE").await?;
+        let err = db
+            .insert_post(&test_post("did:example:bob", "1"))
+            .await
+            .unwrap_err();
+        assert!(
+            err.downcast_ref::<sqlx::Error>().is_some_and(is_busy),
+            "{}",
+            err
+        );
+        holder.rollback().await?;
+
+        // One released while the write backs off lets it through
+        let holder = db.pool.begin_with("BEGIN IMMEDIATE").await?;
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(120)).await;
+            holder.commit().await
+        });
+        db.insert_post(&test_post("did:example:bob", "1")).await?;
+        db.delete_post("at://did:example:bob/app.bsky.feed.post/1")
+            .await?;
+        db.pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_inserts() -> Result<()> {
+        let db = test_db().await?;
+        let posts = |prefix: &str| -> Vec<Post> {
+            (0..500)
+                .map(|i| Post {
+                    uri: format!("at://did:example:bob/app.bsky.feed.post/{}{}", prefix, i),
+                    cid: format!("cid-{}", i),
+                    author_did: "did:example:bob".to_string(),
+                    text: "text".to_string(),
+                    reply_parent_uri: None,
+                    reply_root_uri: None,
+                    quoted_uri: None,
+                    is_link_only: false,
+                    has_media: false,
+                    embed_type: None,
+                    hashtags: vec!["rust".to_string()],
+                    created_at: Utc::now(),
+                    indexed_at: Utc::now(),
+                })
+                .collect()
+        };
+
+        for post in &posts("single") {
+            db.insert_post(post).await?;
+        }
+        db.insert_posts_batch(&posts("batch")).await?;
+
+        let follows: Vec<Follow> = (0..500)
+            .map(|i| Follow {
+                uri: format!("at://did:example:alice/app.bsky.graph.follow/{}", i),
+                follower_did: "did:example:alice".to_string(),
+                target_did: format!("did:example:{}", i),
+                created_at: Utc::now(),
+                indexed_at: Utc::now(),
+            })
+            .collect();
+        db.insert_follows_batch(&follows).await?;
+
+        let stats = db.get_stats().await?;
+        assert_eq!(stats.posts, 1000);
+        assert_eq!(stats.follows, 500);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_inserts_replace_duplicates() -> Result<()> {
+        let db = test_db().await?;
+
+        // 1000 posts span two statements; the last repeats the first with a
+        // new CID, and one in the middle repeats its neighbour
+        let mut posts: Vec<Post> = (0..999)
+            .map(|i| test_post("did:example:bob", &i.to_string()))
+            .collect();
+        posts[501] = Post {
+            cid: "cid-replaced".to_string(),
+            ..test_post("did:example:bob", "500")
+        };
+        posts.push(Post {
+            cid: "cid-latest".to_string(),
+            ..test_post("did:example:bob", "0")
+        });
+        db.insert_posts_batch(&posts).await?;
+
+        assert_eq!(post_count(&db, "did:example:bob").await?, 998);
+        let stored = db
+            .get_posts_by_uris(&[posts[0].uri.clone(), posts[500].uri.clone()])
+            .await?;
+        let mut cids: Vec<&str> = stored.iter().map(|p| p.cid.as_str()).collect();
+        cids.sort();
+        assert_eq!(cids, vec!["cid-latest", "cid-replaced"]);
+
+        // A second follow of the same account replaces the first
+        let follows: Vec<Follow> = ["1", "2"]
+            .iter()
+            .map(|rkey| Follow {
+                uri: format!("at://did:example:alice/app.bsky.graph.follow/{}", rkey),
+                follower_did: "did:example:alice".to_string(),
+                target_did: "did:example:bob".to_string(),
+                created_at: Utc::now(),
+                indexed_at: Utc::now(),
+            })
+            .collect();
+        db.insert_follows_batch(&follows).await?;
+        assert_eq!(db.get_stats().await?.follows, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_top_followed_authors() -> Result<()> {
+        let db = test_db().await?;
+
+        for follower in ["did:example:a", "did:example:b", "did:example:c"] {
+            follow(&db, follower, "did:example:popular").await?;
+        }
+        for follower in ["did:example:a", "did:example:b"] {
+            follow(&db, follower, "did:example:known").await?;
+        }
+        follow(&db, "did:example:a", "did:example:niche").await?;
+
+        assert_eq!(
+            db.get_top_followed_authors(2).await?,
+            vec![
+                ("did:example:popular".to_string(), 3),
+                ("did:example:known".to_string(), 2),
+            ]
+        );
+        assert_eq!(db.get_top_followed_authors(20).await?.len(), 3);
+        assert_eq!(
+            db.get_follow_count_for_author("did:example:niche").await?,
+            1
+        );
+        assert_eq!(
+            db.get_follow_count_for_author("did:example:nobody").await?,
+            0
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_offset_timestamps_sort_by_instant() -> Result<()> {
+        let db = test_db().await?;
+        follow(&db, "did:example:alice", "did:example:bob").await?;
+
+        // 13:30+02:00 is 11:30Z, so it's older despite sorting later as text
+        for (rkey, created_at) in [
+            ("offset", "2024-03-01T13:30:00+02:00"),
+            ("utc", "2024-03-01T12:00:00Z"),
+        ] {
+            let created_at = DateTime::parse_from_rfc3339(created_at)?.with_timezone(&Utc);
+            db.insert_post(&Post {
+                created_at,
+                ..test_post("did:example:bob", rkey)
+            })
+            .await?;
+        }
+
+        let posts = db
+            .get_following_posts("did:example:alice", 10, None, &FeedFilter::default())
+            .await?;
+        let rkeys: Vec<&str> = posts
+            .iter()
+            .map(|p| p.uri.rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(rkeys, vec!["utc", "offset"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_text_timestamps_migrate_to_micros() -> Result<()> {
+        // Apply every migration before the switch to integer timestamps
+        let dir = std::env::temp_dir().join(format!("migrations-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir)?;
+        for entry in std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations"))? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            if name.as_str() < "015" {
+                std::fs::copy(&path, dir.join(&name))?;
+            }
+        }
+        let db = Database::new(":memory:").await?;
+        sqlx::migrate::Migrator::new(dir.as_path())
+            .await?
+            .run(&db.pool)
+            .await?;
+        std::fs::remove_dir_all(&dir)?;
+
+        for (rkey, created_at) in [
+            ("offset", "2024-03-01T13:30:00.25+02:00"),
+            ("utc", "2024-03-01T12:00:00.123456789Z"),
+        ] {
+            sqlx::query(
+                "INSERT INTO posts (uri, cid, author_did, text, created_at, indexed_at)
+                VALUES (?, 'cid', 'did:example:bob', 'text', ?, '2024-03-01T12:00:00+00:00')",
+            )
+            .bind(format!("at://did:example:bob/app.bsky.feed.post/{}", rkey))
+            .bind(created_at)
+            .execute(&db.pool)
+            .await?;
+        }
+        sqlx::query(
+            "INSERT INTO follows VALUES ('at://follow', 'did:example:alice', 'did:example:bob',
+                '2024-01-01T00:00:00+00:00', '2024-01-01T00:00:00+00:00')",
+        )
+        .execute(&db.pool)
+        .await?;
+
+        db.migrate().await?;
+
+        let posts = db
+            .get_following_posts("did:example:alice", 10, None, &FeedFilter::default())
+            .await?;
+        let expected = [
+            Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()
+                + chrono::Duration::microseconds(123_456),
+            Utc.with_ymd_and_hms(2024, 3, 1, 11, 30, 0).unwrap()
+                + chrono::Duration::milliseconds(250),
+        ];
+        assert_eq!(
+            posts.iter().map(|p| p.created_at).collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(
+            posts[0].indexed_at,
+            Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_follow_cache_tracks_follows() -> Result<()> {
+        let db = test_db().await?;
+        let cache = FollowCache::default();
+        db.set_follow_cache(cache.clone());
+        for author in ["did:example:alice", "did:example:bob", "did:example:carol"] {
+            post(&db, author, "1", 1).await?;
+        }
+        let authors = |filter: FeedFilter| {
+            let db = &db;
+            async move {
+                let posts = db
+                    .get_following_posts("did:example:alice", 10, None, &filter)
+                    .await?;
+                let mut authors: Vec<String> = posts.into_iter().map(|p| p.author_did).collect();
+                authors.sort();
+                Ok::<_, anyhow::Error>(authors)
+            }
+        };
+
+        follow(&db, "did:example:alice", "did:example:bob").await?;
+        assert_eq!(authors(FeedFilter::default()).await?, ["did:example:bob"]);
+        assert!(cache.get("did:example:alice").await.is_some());
+
+        // A follow drops the cached set, so the next page sees the new author
+        follow(&db, "did:example:alice", "did:example:carol").await?;
+        assert!(cache.get("did:example:alice").await.is_none());
+        assert_eq!(
+            authors(FeedFilter::default()).await?,
+            ["did:example:bob", "did:example:carol"]
+        );
+        let with_self = FeedFilter {
+            include_self: true,
+            ..FeedFilter::default()
+        };
+        assert_eq!(authors(with_self).await?.len(), 3);
+
+        // So does an unfollow, and a sync that removes follows
+        db.delete_follow("at://did:example:alice/app.bsky.graph.follow/did:example:carol")
+            .await?;
+        assert!(cache.get("did:example:alice").await.is_none());
+        assert_eq!(authors(FeedFilter::default()).await?, ["did:example:bob"]);
+
+        db.sync_follows_for_user("did:example:alice", Vec::new())
+            .await?;
+        assert!(cache.get("did:example:alice").await.is_none());
+        assert!(authors(FeedFilter::default()).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_feed_query_uses_indexes() -> Result<()> {
+        let db = test_db().await?;
+
+        // The capped query scans its ranked subquery, also aliased p
+        // (itself reached through the index), so only the plain one is checked
+        let with_self = FeedFilter {
+            include_self: true,
+            ..FeedFilter::default()
+        };
+        for filter in [FeedFilter::default(), FeedFilter::strict(), with_self] {
+            let sql = FollowingPostsQuery::new(&filter).sql();
+            let plan: Vec<String> = sqlx::query(&format!("EXPLAIN QUERY PLAN {}", sql))
+                .bind("did:example:alice")
+                .bind(Utc::now().timestamp_micros())
+                .bind(i64::MIN)
+                .bind(10)
+                .fetch_all(&db.pool)
+                .await?
+                .iter()
+                .map(|row| row.try_get("detail"))
+                .collect::<Result<_, _>>()?;
+
+            assert!(
+                plan.iter()
+                    .any(|step| step.contains("idx_posts_author_created")),
+                "{:#?}",
+                plan
+            );
+            assert!(
+                !plan.iter().any(|step| step.starts_with("SCAN p")),
+                "{:#?}",
+                plan
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_feed_indexes_exist() -> Result<()> {
+        let db = test_db().await?;
+        let indexes: Vec<String> =
+            sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'index'")
+                .fetch_all(&db.pool)
+                .await?;
+        for index in [
+            "idx_posts_author_created",
+            "idx_posts_created",
+            "idx_follows_unique",
+            "idx_follows_target",
+        ] {
+            assert!(indexes.iter().any(|name| name == index), "{}", index);
+        }
+
+        let plan = db.explain_feed_query("did:example:alice").await?;
+        assert!(plan.contains("idx_posts_author_created"), "{}", plan);
+        db.set_follow_cache(FollowCache::default());
+        follow(&db, "did:example:alice", "did:example:bob").await?;
+        let plan = db.explain_feed_query("did:example:alice").await?;
+        assert!(plan.contains("idx_posts_author_created"), "{}", plan);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_include_self_posts() -> Result<()> {
+        let db = test_db().await?;
+        let alice = "did:example:alice";
+        follow(&db, alice, "did:example:bob").await?;
+        post(&db, "did:example:bob", "bob", 2).await?;
+        post(&db, alice, "own", 1).await?;
+        let with_self = FeedFilter {
+            include_self: true,
+            ..FeedFilter::default()
+        };
+        let rkeys = |filter: FeedFilter| {
+            let db = &db;
+            async move {
+                let posts = db.get_following_posts(alice, 10, None, &filter).await?;
+                anyhow::Ok(
+                    posts
+                        .iter()
+                        .map(|p| p.uri.rsplit('/').next().unwrap().to_string())
+                        .collect::<Vec<_>>(),
+                )
+            }
+        };
+
+        assert_eq!(rkeys(FeedFilter::default()).await?, vec!["bob"]);
+        assert_eq!(rkeys(with_self).await?, vec!["own", "bob"]);
+        let own = "at://did:example:alice/app.bsky.feed.post/own";
+        let checks = db.explain_post(alice, own, &with_self).await?;
+        assert!(checks.iter().all(|check| check.passed), "{:?}", checks);
+
+        // Following yourself doesn't show your posts twice
+        follow(&db, alice, alice).await?;
+        assert_eq!(rkeys(with_self).await?, vec!["own", "bob"]);
+        assert_eq!(rkeys(FeedFilter::default()).await?, vec!["own", "bob"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_age_hours() -> Result<()> {
+        let db = test_db().await?;
+        let alice = "did:example:alice";
+        follow(&db, alice, "did:example:bob").await?;
+        post(&db, "did:example:bob", "new", 1).await?;
+        post(&db, "did:example:bob", "old", 30).await?;
+        let recent = FeedFilter {
+            max_age_hours: Some(6),
+            ..FeedFilter::default()
+        };
+
+        let posts = db.get_following_posts(alice, 10, None, &recent).await?;
+        assert_eq!(posts.len(), 1);
+        assert!(posts[0].uri.ends_with("/new"));
+        let capped = FeedFilter {
+            max_posts_per_author: Some(5),
+            ..recent
+        };
+        assert_eq!(
+            db.get_following_posts(alice, 10, None, &capped)
+                .await?
+                .len(),
+            1
+        );
+        assert_eq!(
+            db.get_following_posts(alice, 10, None, &FeedFilter::default())
+                .await?
+                .len(),
+            2
+        );
+
+        let old = "at://did:example:bob/app.bsky.feed.post/old";
+        let checks = db.explain_post(alice, old, &recent).await?;
+        assert_eq!(
+            checks.last(),
+            Some(&RuleCheck {
+                rule: "recent enough",
+                passed: false
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retention_spares_only_kept_posts() -> Result<()> {
+        let db = test_db().await?;
+        let alice = "did:example:alice";
+        follow(&db, alice, "did:example:bob").await?;
+        follow(&db, alice, "did:example:carol").await?;
+        post(&db, "did:example:bob", "old", 30).await?;
+        post(&db, "did:example:bob", "older", 40).await?;
+        post(&db, "did:example:carol", "new", 1).await?;
+        post(&db, "did:example:carol", "old", 30).await?;
+        let kept = FeedFilter {
+            retention_hours: Some(12),
+            kept_per_author: 1,
+            ..FeedFilter::default()
+        };
+
+        // Bob's newest is kept however old; carol's old one isn't
+        let mut uris: Vec<String> = db
+            .get_following_posts(alice, 10, None, &kept)
+            .await?
+            .into_iter()
+            .map(|post| post.uri)
+            .collect();
+        uris.sort();
+        assert_eq!(
+            uris,
+            vec![
+                "at://did:example:bob/app.bsky.feed.post/old",
+                "at://did:example:carol/app.bsky.feed.post/new",
+            ]
+        );
+
+        let older = "at://did:example:bob/app.bsky.feed.post/older";
+        let checks = db.explain_post(alice, older, &kept).await?;
+        assert_eq!(
+            checks.last(),
+            Some(&RuleCheck {
+                rule: "within retention",
+                passed: false
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_future_dated_posts_are_clamped() -> Result<()> {
+        let db = test_db().await?;
+        let alice = "did:example:alice";
+        follow(&db, alice, "did:example:bob").await?;
+        follow(&db, alice, "did:example:spammer").await?;
+        post(&db, "did:example:bob", "recent", 0).await?;
+        let indexed_at = Utc::now() - chrono::Duration::hours(1);
+        db.insert_post(&Post {
+            created_at: Utc::now() + chrono::Duration::days(365),
+            indexed_at,
+            ..test_post("did:example:spammer", "spam")
+        })
+        .await?;
+
+        let posts = db
+            .get_following_posts(alice, 10, None, &FeedFilter::default())
+            .await?;
+        assert_eq!(posts.len(), 2);
+        assert!(posts[0].uri.ends_with("/recent"));
+        assert_eq!(
+            posts[1].created_at.timestamp_micros(),
+            indexed_at.timestamp_micros()
+        );
+
+        // An edit can't move it ahead again
+        db.update_post(&Post {
+            created_at: Utc::now() + chrono::Duration::days(365),
+            indexed_at,
+            ..test_post("did:example:spammer", "spam")
+        })
+        .await?;
+        let posts = db
+            .get_following_posts(alice, 10, None, &FeedFilter::default())
+            .await?;
+        assert!(posts[0].uri.ends_with("/recent"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reinserts_keep_indexed_at() -> Result<()> {
+        let db = test_db().await?;
+        let indexed_at = |table: &'static str| {
+            let db = &db;
+            async move {
+                anyhow::Ok(
+                    sqlx::query_scalar::<_, i64>(&format!("SELECT indexed_at FROM {}", table))
+                        .fetch_one(&db.pool)
+                        .await?,
+                )
+            }
+        };
+        let first = Utc::now() - chrono::Duration::hours(1);
+        let post = Post {
+            created_at: first,
+            indexed_at: first,
+            ..test_post("did:example:bob", "1")
+        };
+        let follow = Follow {
+            uri: "at://did:example:alice/app.bsky.graph.follow/1".to_string(),
+            follower_did: "did:example:alice".to_string(),
+            target_did: "did:example:bob".to_string(),
+            created_at: first,
+            indexed_at: first,
+        };
+        db.insert_post(&post).await?;
+        db.insert_follow(&follow).await?;
+
+        db.insert_post(&Post {
+            indexed_at: Utc::now(),
+            text: "edited".to_string(),
+            ..post
+        })
+        .await?;
+        db.insert_follow(&Follow {
+            indexed_at: Utc::now(),
+            ..follow.clone()
+        })
+        .await?;
+        assert_eq!(indexed_at("posts").await?, first.timestamp_micros());
+        assert_eq!(indexed_at("follows").await?, first.timestamp_micros());
+
+        // The same follow under a new URI takes the URI, but keeps its times
+        db.insert_follow(&Follow {
+            uri: "at://did:example:alice/app.bsky.graph.follow/2".to_string(),
+            indexed_at: Utc::now(),
+            ..follow
+        })
+        .await?;
+        assert_eq!(indexed_at("follows").await?, first.timestamp_micros());
+        assert!(
+            db.delete_follow("at://did:example:alice/app.bsky.graph.follow/2")
+                .await?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_soft_deleted_posts() -> Result<()> {
+        let db = test_db().await?;
+        follow(&db, "did:example:alice", "did:example:bob").await?;
+        post(&db, "did:example:bob", "kept", 1).await?;
+        post(&db, "did:example:bob", "gone", 1).await?;
+        let gone = "at://did:example:bob/app.bsky.feed.post/gone";
+
+        let feed_rkeys = || async {
+            let posts = db
+                .get_following_posts("did:example:alice", 10, None, &FeedFilter::default())
+                .await?;
+            anyhow::Ok(
+                posts
+                    .iter()
+                    .map(|p| p.uri.rsplit('/').next().unwrap().to_string())
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        db.delete_post(gone).await?;
+        assert_eq!(feed_rkeys().await?, vec!["kept"]);
+        assert!(db.get_posts_by_uris(&[gone.to_string()]).await?.is_empty());
+        // The row is still there, and a replayed create doesn't revive it
+        assert_eq!(post_count(&db, "did:example:bob").await?, 2);
+        post(&db, "did:example:bob", "gone", 1).await?;
+        assert_eq!(feed_rkeys().await?, vec!["kept"]);
+
+        assert!(db.restore_post(gone).await?);
+        assert!(!db.restore_post(gone).await?);
+        assert_eq!(feed_rkeys().await?, vec!["gone", "kept"]);
+
+        // Only deletions older than the cutoff are removed for good
+        db.delete_post(gone).await?;
+        assert_eq!(db.hard_delete_old_soft_deleted_posts(1, false).await?, 0);
+        sqlx::query("UPDATE posts SET deleted_at = deleted_at - 2 * 86400000000")
+            .execute(&db.pool)
+            .await?;
+        assert_eq!(db.hard_delete_old_soft_deleted_posts(1, true).await?, 1);
+        assert_eq!(db.hard_delete_old_soft_deleted_posts(1, false).await?, 1);
+        assert_eq!(post_count(&db, "did:example:bob").await?, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_all_user_data() -> Result<()> {
+        let db = test_db().await?;
+        let alice = "did:example:alice";
+        follow(&db, alice, "did:example:bob").await?;
+        follow(&db, alice, "did:example:carol").await?;
+        follow(&db, "did:example:dave", "did:example:carol").await?;
+        post(&db, "did:example:bob", "b", 1).await?;
+        post(&db, "did:example:carol", "c", 1).await?;
+        db.record_feed_request(alice).await?;
+        db.set_post_retention(alice, Some(12)).await?;
+        db.add_tag_block(alice, "rust").await?;
+
+        // A failure part way through leaves everything in place
+        sqlx::query(
+            "CREATE TRIGGER fail_forget BEFORE DELETE ON hashtag_blocklist \
+             BEGIN SELECT RAISE(ABORT, 'boom'); END",
+        )
+        .execute(&db.pool)
+        .await?;
+        assert!(db.delete_all_user_data(alice, true).await.is_err());
+        assert_eq!(post_count(&db, "did:example:bob").await?, 1);
+        assert!(db.has_follows(alice).await?);
+        assert_eq!(
+            db.get_preferences(alice).await?.post_retention_hours,
+            Some(12)
+        );
+
+        sqlx::query("DROP TRIGGER fail_forget")
+            .execute(&db.pool)
+            .await?;
+        // A post, two follows, and a row each of activity, settings and blocks
+        assert_eq!(db.delete_all_user_data(alice, true).await?, 6);
+        // Carol's posts are still wanted by dave
+        assert_eq!(post_count(&db, "did:example:bob").await?, 0);
+        assert_eq!(post_count(&db, "did:example:carol").await?, 1);
+        assert!(!db.has_follows(alice).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_posts() -> Result<()> {
+        let db = test_db().await?;
+        for (rkey, text) in [
+            ("runs", "She runs every morning"),
+            ("walk", "A quiet walk"),
+            ("ran", "Nobody ran today"),
+        ] {
+            db.insert_post(&Post {
+                text: text.to_string(),
+                ..test_post("did:example:bob", rkey)
+            })
+            .await?;
+        }
+        let rkeys = |posts: Vec<Post>| -> Vec<String> {
+            posts
+                .iter()
+                .map(|p| p.uri.rsplit('/').next().unwrap().to_string())
+                .collect()
+        };
+
+        assert_eq!(rkeys(db.search_posts("running", 10).await?), vec!["runs"]);
+
+        // Replaced, edited and deleted posts leave the index in step
+        db.insert_post(&Post {
+            text: "Still running".to_string(),
+            ..test_post("did:example:bob", "walk")
+        })
+        .await?;
+        assert_eq!(
+            rkeys(db.search_posts("quiet", 10).await?),
+            Vec::<String>::new()
+        );
+        let mut found = rkeys(db.search_posts("run", 10).await?);
+        found.sort();
+        assert_eq!(found, vec!["runs", "walk"]);
+        sqlx::query("UPDATE posts SET text = 'Sitting down' WHERE uri LIKE '%/runs'")
+            .execute(&db.pool)
+            .await?;
+        assert_eq!(rkeys(db.search_posts("running", 10).await?), vec!["walk"]);
+        sqlx::query("DELETE FROM posts").execute(&db.pool).await?;
+        assert!(db.search_posts("running OR sitting", 10).await?.is_empty());
+        sqlx::query("INSERT INTO posts_fts(posts_fts) VALUES ('integrity-check')")
+            .execute(&db.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_enable_incremental_vacuum() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("noreposts-auto-vacuum-{}.db", uuid::Uuid::new_v4()));
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+
+        // A file made before auto-vacuum was set isn't switched by opening it
+        let pool = SqlitePool::connect(&url).await?;
+        sqlx::query("CREATE TABLE old (id INTEGER)")
+            .execute(&pool)
+            .await?;
+        pool.close().await;
+        let db = Database::new(&url).await?;
+        db.migrate().await?;
+        // A removed post leaves a gap in the rowids for VACUUM to close
+        for (rkey, text) in [("gone", "Removed"), ("kept", "Still searchable")] {
+            db.insert_post(&Post {
+                text: text.to_string(),
+                ..test_post("did:example:bob", rkey)
+            })
+            .await?;
+        }
+        sqlx::query("DELETE FROM posts WHERE uri LIKE '%/gone'")
+            .execute(&db.pool)
+            .await?;
+        let before = has_incremental_vacuum(&mut *db.pool.acquire().await?).await?;
+        let switched = db.enable_incremental_vacuum().await?;
+        let found = db.search_posts("searchable", 10).await?;
+        db.pool.close().await;
+
+        let db = Database::new(&url).await?;
+        let after = has_incremental_vacuum(&mut *db.pool.acquire().await?).await?;
+        let again = db.enable_incremental_vacuum().await?;
+        db.pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+
+        assert!(!before);
+        assert!(switched);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].uri.ends_with("/kept"));
+        assert!(after);
+        assert!(!again);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_maintenance() -> Result<()> {
+        assert!(test_db().await?.file_sizes().await?.is_none());
+
+        let path =
+            std::env::temp_dir().join(format!("noreposts-vacuum-{}.db", uuid::Uuid::new_v4()));
+        let db = Database::new(&format!("sqlite://{}?mode=rwc", path.display())).await?;
+        db.migrate().await?;
+        assert!(db.last_maintenance().is_none());
+
+        let posts: Vec<Post> = (0..2000)
+            .map(|i| Post {
+                text: "x".repeat(200),
+                ..test_post("did:example:bob", &i.to_string())
+            })
+            .collect();
+        db.insert_posts_batch(&posts).await?;
+        sqlx::query("DELETE FROM posts").execute(&db.pool).await?;
+        let before = db.file_sizes().await?.unwrap();
+        assert!(before.wal_bytes > 0);
+
+        let stats = db.run_maintenance().await?;
+        let after = db.file_sizes().await?.unwrap();
+        db.pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+
+        assert!(stats.freed_pages > 0);
+        assert!(!stats.checkpoint_busy);
+        assert!(db.last_maintenance().is_some());
+        // The deleted posts' pages are gone from the file
+        assert!(after.db_bytes < 2000 * 200);
+        assert_eq!(after.wal_bytes, 0);
+
+        Ok(())
     }
 }
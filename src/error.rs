@@ -0,0 +1,80 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use tracing::error;
+
+use crate::types::ErrorResponse;
+
+/// A failed XRPC request, answered with one of a fixed set of error names.
+/// Internal failures are logged in full but reach the client only as a
+/// generic message.
+#[derive(Debug)]
+pub enum AppError {
+    AuthenticationRequired(String),
+    InvalidRequest(String),
+    /// The requested feed URI
+    UnknownFeed(String),
+    Internal(anyhow::Error),
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(e: anyhow::Error) -> Self {
+        AppError::Internal(e)
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, error, message) = match self {
+            AppError::AuthenticationRequired(message) => {
+                (StatusCode::UNAUTHORIZED, "AuthenticationRequired", message)
+            }
+            AppError::InvalidRequest(message) => {
+                (StatusCode::BAD_REQUEST, "InvalidRequest", message)
+            }
+            AppError::UnknownFeed(feed) => (
+                StatusCode::BAD_REQUEST,
+                "UnknownFeed",
+                format!("Unknown feed: {}", feed),
+            ),
+            AppError::Internal(e) => {
+                error!(error = ?e, "Request failed");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "InternalServerError",
+                    "Internal server error".to_string(),
+                )
+            }
+        };
+        (
+            status,
+            Json(ErrorResponse {
+                error: error.to_string(),
+                message,
+            }),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_internal_errors_hide_details() -> anyhow::Result<()> {
+        let response = AppError::from(anyhow::anyhow!("no such table: posts")).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+        let body: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(body["error"], "InternalServerError");
+        assert!(!body.to_string().contains("posts"));
+
+        let response = AppError::UnknownFeed("at://x/y/z".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+}
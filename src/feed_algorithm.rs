@@ -1,19 +1,413 @@
 use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use moka::future::Cache;
+use std::collections::HashSet;
 use std::sync::Arc;
-use tracing::warn;
+use std::time::Instant;
+use tracing::{debug, warn};
 
 use crate::{
     database::Database,
-    types::{FeedSkeletonResponse, SkeletonFeedPost},
+    request_log::hash_did,
+    types::{
+        FeedFilter, FeedSkeletonResponse, Post, SkeletonFeedPost, SkeletonReason, UserPreferences,
+    },
 };
 
-pub struct FollowingNoRepostsFeed {
-    db: Arc<Database>,
+/// Posts older than this are removed by the periodic cleanup task, unless a
+/// user asked for a different retention
+pub const DEFAULT_RETENTION_HOURS: i64 = 48;
+
+/// How often the cleanup task runs
+pub const CLEANUP_INTERVAL_SECS: u64 = 300;
+
+/// Page size when the client doesn't ask for one
+pub const DEFAULT_FEED_LIMIT: i32 = 50;
+
+/// Largest page size a client can ask for
+pub const MAX_FEED_LIMIT: i32 = 100;
+
+/// Seconds a generated page is reused for an identical request
+pub const DEFAULT_PAGE_CACHE_TTL_SECS: u64 = 5;
+
+/// Most generated pages kept in memory
+pub const DEFAULT_PAGE_CACHE_CAPACITY: u64 = 10_000;
+
+/// Applies the default to a missing limit and caps it at `max_limit`.
+/// Callers must reject non-positive limits before getting here.
+pub fn clamp_limit(limit: Option<i32>, default_limit: i32, max_limit: i32) -> i32 {
+    limit.unwrap_or(default_limit).clamp(1, max_limit)
+}
+
+/// Where a page sits relative to the end of the feed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageBoundary {
+    /// More posts may follow, so the page carries a cursor
+    More,
+    /// This is the last page, so the page carries no cursor
+    Final,
+    /// The request cursor already points past the retention boundary
+    PastRetention,
+}
+
+/// Decides whether a page is the last one the client should request.
+///
+/// A cursor at or before the retention cutoff is past retention. Otherwise the
+/// page is final when it holds fewer rows than requested, or when its last post
+/// is within one cleanup interval of the cutoff (those posts may be deleted
+/// before the client asks for the next page).
+pub fn page_boundary(
+    cursor_time: Option<DateTime<Utc>>,
+    limit: usize,
+    page_len: usize,
+    last_created_at: Option<DateTime<Utc>>,
+    retention_cutoff: DateTime<Utc>,
+    cleanup_interval: Duration,
+) -> PageBoundary {
+    if cursor_time.is_some_and(|t| t <= retention_cutoff) {
+        return PageBoundary::PastRetention;
+    }
+
+    if page_len < limit {
+        return PageBoundary::Final;
+    }
+
+    match last_created_at {
+        Some(last) if last > retention_cutoff + cleanup_interval => PageBoundary::More,
+        _ => PageBoundary::Final,
+    }
+}
+
+/// Cursors are the last post's creation time in epoch microseconds
+fn encode_cursor(created_at: DateTime<Utc>) -> String {
+    created_at.timestamp_micros().to_string()
+}
+
+/// Also accepts the RFC3339 cursors handed out before cursors were numeric
+fn decode_cursor(cursor: &str) -> Option<DateTime<Utc>> {
+    match cursor.parse::<i64>() {
+        Ok(micros) => DateTime::from_timestamp_micros(micros),
+        Err(_) => DateTime::parse_from_rfc3339(cursor)
+            .ok()
+            .map(|dt| dt.with_timezone(&Utc)),
+    }
+}
+
+/// Turns a page of posts into skeleton items, each URI at most once.
+///
+/// With `include_reply_parents`, each reply is preceded by its parent unless the
+/// parent is already somewhere in the page. Parents are one level deep only and
+/// are supplementary: they don't count towards the page limit or the cursor.
+fn build_skeleton(posts: &[Post], include_reply_parents: bool) -> Vec<SkeletonFeedPost> {
+    let in_page: HashSet<&str> = posts.iter().map(|post| post.uri.as_str()).collect();
+    let mut seen: HashSet<&str> = HashSet::with_capacity(posts.len());
+    let mut feed = Vec::with_capacity(posts.len());
+
+    for post in posts {
+        if include_reply_parents {
+            if let Some(parent_uri) = post.reply_parent_uri.as_deref() {
+                // A parent that is in the page anyway keeps its own position
+                if !in_page.contains(parent_uri) && seen.insert(parent_uri) {
+                    feed.push(SkeletonFeedPost {
+                        post: parent_uri.to_string(),
+                        reason: None,
+                        reply: None,
+                        feed_context: None,
+                    });
+                }
+            }
+        }
+
+        // Each URI appears once, at its first position
+        if seen.insert(post.uri.as_str()) {
+            feed.push(SkeletonFeedPost {
+                post: post.uri.clone(),
+                reason: None,
+                reply: post.reply_ref(),
+                feed_context: None,
+            });
+        }
+    }
+
+    feed
+}
+
+/// The form in which two posts' texts count as the same
+fn normalize_text(text: &str) -> String {
+    text.trim().to_lowercase()
+}
+
+/// Drops posts whose text repeats that of an older post in the page, so a
+/// text posted by many accounts shows up once, from whoever posted it first.
+/// Posts without text are never collapsed.
+fn collapse_duplicate_text(posts: Vec<Post>) -> Vec<Post> {
+    let mut seen = HashSet::new();
+    let mut kept: Vec<Post> = posts
+        .into_iter()
+        .rev()
+        .filter(|post| {
+            let text = normalize_text(&post.text);
+            text.is_empty() || seen.insert(text)
+        })
+        .collect();
+    kept.reverse();
+    kept
+}
+
+/// The queries the feed algorithm runs against storage
+#[async_trait]
+pub trait FeedStore: Send + Sync {
+    async fn get_following_posts(
+        &self,
+        follower_did: &str,
+        limit: i32,
+        cursor: Option<DateTime<Utc>>,
+        filter: &FeedFilter,
+    ) -> Result<Vec<Post>>;
+
+    async fn get_following_posts_with_media(
+        &self,
+        follower_did: &str,
+        limit: i32,
+        cursor: Option<DateTime<Utc>>,
+        filter: &FeedFilter,
+    ) -> Result<Vec<Post>>;
+
+    async fn get_preferences(&self, did: &str) -> Result<UserPreferences>;
+
+    async fn get_pinned_posts(&self) -> Result<Vec<String>>;
+}
+
+#[async_trait]
+impl FeedStore for Database {
+    async fn get_following_posts(
+        &self,
+        follower_did: &str,
+        limit: i32,
+        cursor: Option<DateTime<Utc>>,
+        filter: &FeedFilter,
+    ) -> Result<Vec<Post>> {
+        Database::get_following_posts(self, follower_did, limit, cursor, filter).await
+    }
+
+    async fn get_following_posts_with_media(
+        &self,
+        follower_did: &str,
+        limit: i32,
+        cursor: Option<DateTime<Utc>>,
+        filter: &FeedFilter,
+    ) -> Result<Vec<Post>> {
+        Database::get_following_posts_with_media(self, follower_did, limit, cursor, filter).await
+    }
+
+    async fn get_preferences(&self, did: &str) -> Result<UserPreferences> {
+        Database::get_preferences(self, did).await
+    }
+
+    async fn get_pinned_posts(&self) -> Result<Vec<String>> {
+        Database::get_pinned_posts(self).await
+    }
+}
+
+/// A feed this generator serves, published under its own record key
+#[derive(Debug, Clone)]
+pub struct RegisteredFeed {
+    pub rkey: String,
+    pub filter: FeedFilter,
+}
+
+/// The feeds this generator serves
+#[derive(Debug, Clone, Default)]
+pub struct FeedRegistry {
+    feeds: Vec<RegisteredFeed>,
+}
+
+impl FeedRegistry {
+    pub fn register(mut self, rkey: &str, filter: FeedFilter) -> Self {
+        self.feeds.push(RegisteredFeed {
+            rkey: rkey.to_string(),
+            filter,
+        });
+        self
+    }
+
+    /// Finds the feed for an `at://<did>/app.bsky.feed.generator/<rkey>` URI
+    pub fn get(&self, feed_uri: &str) -> Option<&RegisteredFeed> {
+        let rkey = feed_uri.rsplit('/').next()?;
+        self.feeds.iter().find(|feed| feed.rkey == rkey)
+    }
+
+    pub fn feeds(&self) -> &[RegisteredFeed] {
+        &self.feeds
+    }
+}
+
+/// Server-wide settings every generated feed shares
+#[derive(Debug, Clone, Copy)]
+pub struct FeedSettings {
+    pub retention_hours: i64,
+    /// Newest posts per author kept past retention; 0 keeps none
+    pub keep_posts_per_author: i64,
+    pub include_reply_parents: bool,
+    pub collapse_duplicate_text: bool,
+    pub default_limit: i32,
+    pub max_limit: i32,
+}
+
+impl Default for FeedSettings {
+    fn default() -> Self {
+        Self {
+            retention_hours: DEFAULT_RETENTION_HOURS,
+            keep_posts_per_author: 0,
+            include_reply_parents: false,
+            collapse_duplicate_text: false,
+            default_limit: DEFAULT_FEED_LIMIT,
+            max_limit: MAX_FEED_LIMIT,
+        }
+    }
+}
+
+/// How long each phase of generating a page took. Phases that didn't run
+/// are zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeedTimings {
+    pub cursor_decode: std::time::Duration,
+    /// Fetching the page's posts from the store
+    pub sql_query: std::time::Duration,
+    /// Filtering, pins and the skeleton
+    pub build_response: std::time::Duration,
 }
 
-impl FollowingNoRepostsFeed {
-    pub fn new(db: Arc<Database>) -> Self {
-        Self { db }
+impl FeedTimings {
+    pub fn total(&self) -> std::time::Duration {
+        self.cursor_decode + self.sql_query + self.build_response
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PageCacheKey {
+    requester_did: String,
+    filter: FeedFilter,
+    cursor: Option<String>,
+    limit: i32,
+}
+
+/// Recently generated pages, so clients retrying the same request don't run
+/// the feed query again. Pages are keyed by requester, so one user's page is
+/// never served to another.
+pub struct FeedPageCache {
+    pages: Cache<PageCacheKey, (FeedSkeletonResponse, PageBoundary)>,
+}
+
+impl FeedPageCache {
+    pub fn new(ttl: std::time::Duration, capacity: u64) -> Self {
+        Self {
+            pages: Cache::builder()
+                .max_capacity(capacity)
+                .time_to_live(ttl)
+                .build(),
+        }
+    }
+}
+
+/// A generated feed page together with its boundary decision
+pub struct FeedPage {
+    pub response: FeedSkeletonResponse,
+    pub boundary: PageBoundary,
+    pub timings: FeedTimings,
+    /// Served from the page cache
+    pub cached: bool,
+}
+
+pub struct FollowingNoRepostsFeed<S: FeedStore = Database> {
+    db: Arc<S>,
+    filter: FeedFilter,
+    retention: Duration,
+    keep_posts_per_author: i64,
+    cleanup_interval: Duration,
+    include_reply_parents: bool,
+    collapse_duplicate_text: bool,
+    backfill_placeholder: Option<String>,
+    page_cache: Option<Arc<FeedPageCache>>,
+    default_limit: i32,
+    max_limit: i32,
+}
+
+impl<S: FeedStore> FollowingNoRepostsFeed<S> {
+    pub fn new(db: Arc<S>) -> Self {
+        Self {
+            db,
+            filter: FeedFilter::default(),
+            retention: Duration::hours(DEFAULT_RETENTION_HOURS),
+            keep_posts_per_author: 0,
+            cleanup_interval: Duration::seconds(CLEANUP_INTERVAL_SECS as i64),
+            include_reply_parents: false,
+            collapse_duplicate_text: false,
+            backfill_placeholder: None,
+            page_cache: None,
+            default_limit: DEFAULT_FEED_LIMIT,
+            max_limit: MAX_FEED_LIMIT,
+        }
+    }
+
+    pub fn with_settings(self, settings: FeedSettings) -> Self {
+        self.with_retention_hours(settings.retention_hours)
+            .with_posts_kept_per_author(settings.keep_posts_per_author)
+            .with_reply_parents(settings.include_reply_parents)
+            .with_duplicate_text_collapsed(settings.collapse_duplicate_text)
+            .with_limits(settings.default_limit, settings.max_limit)
+    }
+
+    /// Which kinds of posts to let through
+    pub fn with_filter(mut self, filter: FeedFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Page size when the client doesn't ask for one, and the largest it can ask for
+    pub fn with_limits(mut self, default_limit: i32, max_limit: i32) -> Self {
+        self.default_limit = default_limit;
+        self.max_limit = max_limit;
+        self
+    }
+
+    /// Retention for users without a retention preference
+    pub fn with_retention_hours(mut self, hours: i64) -> Self {
+        self.retention = Duration::hours(hours);
+        self
+    }
+
+    /// Cleanup keeps each author's newest `kept` posts past retention, so
+    /// those are shown however old and paging shouldn't stop at retention
+    pub fn with_posts_kept_per_author(mut self, kept: i64) -> Self {
+        self.keep_posts_per_author = kept;
+        self
+    }
+
+    /// Show the parent of each reply alongside it for context
+    pub fn with_reply_parents(mut self, include_reply_parents: bool) -> Self {
+        self.include_reply_parents = include_reply_parents;
+        self
+    }
+
+    /// Show only the earliest of posts in a page that share the same text
+    pub fn with_duplicate_text_collapsed(mut self, collapse: bool) -> Self {
+        self.collapse_duplicate_text = collapse;
+        self
+    }
+
+    /// Serve only this post, pinned, on the first page. For a user with no
+    /// follows yet whose backfill is still running.
+    pub fn with_backfill_placeholder(mut self, uri: Option<String>) -> Self {
+        self.backfill_placeholder = uri;
+        self
+    }
+
+    /// Reuse pages generated for identical recent requests
+    pub fn with_page_cache(mut self, cache: Option<Arc<FeedPageCache>>) -> Self {
+        self.page_cache = cache;
+        self
     }
 
     pub async fn generate_feed(
@@ -21,46 +415,209 @@ impl FollowingNoRepostsFeed {
         requester_did: Option<String>,
         limit: Option<i32>,
         cursor: Option<String>,
-    ) -> Result<FeedSkeletonResponse> {
+    ) -> Result<FeedPage> {
         // Require authentication for this feed since it's personalized
         let follower_did = match requester_did {
             Some(did) => did,
             None => {
                 warn!("Unauthenticated request to following feed");
-                return Ok(FeedSkeletonResponse {
+                return Ok(FeedPage {
+                    response: FeedSkeletonResponse {
+                        cursor: None,
+                        feed: vec![],
+                    },
+                    boundary: PageBoundary::Final,
+                    timings: FeedTimings::default(),
+                    cached: false,
+                });
+            }
+        };
+
+        if let (Some(uri), None) = (&self.backfill_placeholder, &cursor) {
+            return Ok(FeedPage {
+                response: FeedSkeletonResponse {
                     cursor: None,
-                    feed: vec![],
+                    feed: vec![SkeletonFeedPost {
+                        post: uri.clone(),
+                        reason: Some(SkeletonReason::Pin),
+                        reply: None,
+                        feed_context: None,
+                    }],
+                },
+                boundary: PageBoundary::Final,
+                timings: FeedTimings::default(),
+                cached: false,
+            });
+        }
+
+        let limit = clamp_limit(limit, self.default_limit, self.max_limit);
+        let cache_key = PageCacheKey {
+            requester_did: follower_did.clone(),
+            filter: self.filter,
+            cursor: cursor.clone(),
+            limit,
+        };
+        if let Some(cache) = &self.page_cache {
+            if let Some((response, boundary)) = cache.pages.get(&cache_key).await {
+                debug!(
+                    follower = %hash_did(&follower_did),
+                    limit,
+                    "Serving cached feed page"
+                );
+                return Ok(FeedPage {
+                    response,
+                    boundary,
+                    timings: FeedTimings::default(),
+                    cached: true,
                 });
             }
+        }
+
+        let preferences = self.db.get_preferences(&follower_did).await?;
+        let retention = preferences
+            .post_retention_hours
+            .map(Duration::hours)
+            .unwrap_or(self.retention);
+        // Posts kept past retention can be any age, so paging goes on past
+        // it; the query still leaves out the rest of those older
+        let mut retention_cutoff = if self.keep_posts_per_author > 0 {
+            DateTime::<Utc>::MIN_UTC
+        } else {
+            Utc::now() - retention
+        };
+        // Posts past the feed's maximum age are stored but never shown
+        if let Some(hours) = self.filter.max_age_hours {
+            retention_cutoff = retention_cutoff.max(Utc::now() - Duration::hours(hours));
+        }
+        let mut filter = self.filter.with_preferences(&preferences);
+        if self.keep_posts_per_author > 0 {
+            filter.retention_hours = Some(retention.num_hours());
+            filter.kept_per_author = self.keep_posts_per_author;
+        }
+        let started = Instant::now();
+        let cursor_time = cursor.as_deref().and_then(decode_cursor);
+        let mut timings = FeedTimings {
+            cursor_decode: started.elapsed(),
+            ..FeedTimings::default()
         };
 
-        let limit = limit.unwrap_or(50).min(100); // Cap at 100 items
+        // Don't bother querying for a cursor that is already past retention
+        if page_boundary(
+            cursor_time,
+            limit as usize,
+            0,
+            None,
+            retention_cutoff,
+            self.cleanup_interval,
+        ) == PageBoundary::PastRetention
+        {
+            return Ok(FeedPage {
+                response: FeedSkeletonResponse {
+                    cursor: None,
+                    feed: vec![],
+                },
+                boundary: PageBoundary::PastRetention,
+                timings,
+                cached: false,
+            });
+        }
 
         // Get posts from accounts the user follows
-        let posts = self
-            .db
-            .get_following_posts(&follower_did, limit, cursor.as_deref())
-            .await?;
+        let started = Instant::now();
+        let posts = if filter.media_only {
+            self.db
+                .get_following_posts_with_media(&follower_did, limit, cursor_time, &filter)
+                .await?
+        } else {
+            self.db
+                .get_following_posts(&follower_did, limit, cursor_time, &filter)
+                .await?
+        };
+
+        timings.sql_query = started.elapsed();
+        let started = Instant::now();
 
         tracing::info!(
             "Feed generated for {}: found {} posts from followed accounts",
-            follower_did,
+            hash_did(&follower_did),
             posts.len()
         );
 
-        let feed_posts: Vec<SkeletonFeedPost> = posts
+        // Short and repeated posts are dropped after paging so the cursor
+        // still advances past them
+        let mut shown: Vec<Post> = posts
             .iter()
-            .map(|post| SkeletonFeedPost {
-                post: post.uri.clone(),
-            })
+            .filter(|post| filter.allows_length(post))
+            .cloned()
             .collect();
+        if self.collapse_duplicate_text {
+            shown = collapse_duplicate_text(shown);
+        }
+        let mut feed_posts = build_skeleton(&shown, self.include_reply_parents);
+
+        // Pins head the first page only, and don't count towards the limit.
+        // A pinned post the user would see anyway keeps its place.
+        if cursor.is_none() {
+            let pinned: Vec<SkeletonFeedPost> = self
+                .db
+                .get_pinned_posts()
+                .await?
+                .into_iter()
+                .filter(|uri| !feed_posts.iter().any(|item| &item.post == uri))
+                .map(|uri| SkeletonFeedPost {
+                    post: uri,
+                    reason: Some(SkeletonReason::Pin),
+                    reply: None,
+                    feed_context: None,
+                })
+                .collect();
+            feed_posts.splice(0..0, pinned);
+        }
+
+        let last_created_at = posts.last().map(|post| post.created_at);
+        let boundary = page_boundary(
+            cursor_time,
+            limit as usize,
+            posts.len(),
+            last_created_at,
+            retention_cutoff,
+            self.cleanup_interval,
+        );
 
         // Generate cursor for pagination (use created_at for chronological order)
-        let cursor = posts.last().map(|post| post.created_at.to_rfc3339());
+        let cursor = match boundary {
+            PageBoundary::More => last_created_at.map(encode_cursor),
+            PageBoundary::Final | PageBoundary::PastRetention => None,
+        };
 
-        Ok(FeedSkeletonResponse {
+        timings.build_response = started.elapsed();
+        debug!(
+            follower = %hash_did(&follower_did),
+            limit,
+            posts = posts.len(),
+            "feed_timing phase=cursor_decode us={} phase=sql_query us={} phase=build_response us={} total_us={}",
+            timings.cursor_decode.as_micros(),
+            timings.sql_query.as_micros(),
+            timings.build_response.as_micros(),
+            timings.total().as_micros()
+        );
+
+        let response = FeedSkeletonResponse {
             cursor,
             feed: feed_posts,
+        };
+        if let Some(cache) = &self.page_cache {
+            cache
+                .pages
+                .insert(cache_key, (response.clone(), boundary))
+                .await;
+        }
+
+        Ok(FeedPage {
+            response,
+            boundary,
+            timings,
+            cached: false,
         })
     }
 }
@@ -96,6 +653,13 @@ mod tests {
             cid: "test-cid".to_string(),
             author_did: target_did.to_string(),
             text: "Hello world!".to_string(),
+            reply_parent_uri: None,
+            reply_root_uri: None,
+            quoted_uri: None,
+            is_link_only: false,
+            has_media: false,
+            embed_type: None,
+            hashtags: Vec::new(),
             created_at: Utc::now(),
             indexed_at: Utc::now(),
         };
@@ -104,11 +668,988 @@ mod tests {
         let feed_algorithm = FollowingNoRepostsFeed::new(Arc::clone(&db));
         let response = feed_algorithm
             .generate_feed(Some(follower_did.to_string()), Some(10), None)
+            .await?
+            .response;
+
+        assert_eq!(response.feed.len(), 1);
+        assert_eq!(response.feed[0].post, post.uri);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_posts_appear_once() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        // Older databases could hold the same follow under two record URIs
+        sqlx::query("DROP INDEX idx_follows_unique")
+            .execute(&db.pool)
             .await?;
+        for rkey in ["first", "second"] {
+            sqlx::query(
+                "INSERT INTO follows (uri, follower_did, target_did, created_at, indexed_at)
+                 VALUES (?, 'did:example:alice', 'did:example:bob', 0, 0)",
+            )
+            .bind(format!(
+                "at://did:example:alice/app.bsky.graph.follow/{}",
+                rkey
+            ))
+            .execute(&db.pool)
+            .await?;
+        }
+        let post = mock_post("dup", Utc::now() - Duration::minutes(5));
+        db.insert_post(&post).await?;
 
+        let response = FollowingNoRepostsFeed::new(Arc::clone(&db))
+            .generate_feed(Some("did:example:alice".to_string()), Some(10), None)
+            .await?
+            .response;
         assert_eq!(response.feed.len(), 1);
         assert_eq!(response.feed[0].post, post.uri);
 
+        // The feed dedupes whatever the store returns, keeping the first position
+        let store = Arc::new(MockFeedStore {
+            posts: vec![
+                mock_post("a", Utc::now() - Duration::minutes(1)),
+                mock_post("b", Utc::now() - Duration::minutes(2)),
+                mock_post("a", Utc::now() - Duration::minutes(3)),
+            ],
+            ..Default::default()
+        });
+        let response = FollowingNoRepostsFeed::new(store)
+            .generate_feed(Some("did:example:alice".to_string()), Some(10), None)
+            .await?
+            .response;
+        let uris: Vec<&str> = response.feed.iter().map(|p| p.post.as_str()).collect();
+        assert_eq!(
+            uris,
+            [
+                "at://did:example:bob/app.bsky.feed.post/a",
+                "at://did:example:bob/app.bsky.feed.post/b"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reply_parents() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        let follower_did = "did:example:alice";
+        for target_did in ["did:example:bob", "did:example:carol"] {
+            db.insert_follow(&Follow {
+                uri: format!("at://{}/app.bsky.graph.follow/{}", follower_did, target_did),
+                follower_did: follower_did.to_string(),
+                target_did: target_did.to_string(),
+                created_at: Utc::now(),
+                indexed_at: Utc::now(),
+            })
+            .await?;
+        }
+
+        let post = |author: &str, rkey: &str, parent: Option<String>, age_secs: i64| Post {
+            uri: format!("at://{}/app.bsky.feed.post/{}", author, rkey),
+            cid: format!("cid-{}", rkey),
+            author_did: author.to_string(),
+            text: "text".to_string(),
+            // Every reply here starts its own thread
+            reply_root_uri: parent.clone(),
+            reply_parent_uri: parent,
+            quoted_uri: None,
+            is_link_only: false,
+            has_media: false,
+            embed_type: None,
+            hashtags: Vec::new(),
+            created_at: Utc::now() - Duration::seconds(age_secs),
+            indexed_at: Utc::now(),
+        };
+
+        // Bob's post is in the feed; Carol replies to it and to a stranger
+        let bob_post = post("did:example:bob", "1", None, 30);
+        let stranger_uri = "at://did:example:stranger/app.bsky.feed.post/9".to_string();
+        let reply_to_follow = post("did:example:carol", "2", Some(bob_post.uri.clone()), 20);
+        let reply_to_stranger = post("did:example:carol", "3", Some(stranger_uri.clone()), 10);
+        for p in [&bob_post, &reply_to_follow, &reply_to_stranger] {
+            db.insert_post(p).await?;
+        }
+
+        let plain = FollowingNoRepostsFeed::new(Arc::clone(&db))
+            .generate_feed(Some(follower_did.to_string()), Some(10), None)
+            .await?
+            .response;
+        assert_eq!(plain.feed.len(), 3);
+
+        // Replies carry their thread context; other posts leave it out
+        let json = serde_json::to_value(&plain)?;
+        assert_eq!(json["feed"][1]["reply"]["parent"], bob_post.uri.as_str());
+        assert_eq!(json["feed"][1]["reply"]["root"], bob_post.uri.as_str());
+        assert!(json["feed"][2].get("reply").is_none());
+
+        let with_parents = FollowingNoRepostsFeed::new(Arc::clone(&db))
+            .with_reply_parents(true)
+            .generate_feed(Some(follower_did.to_string()), Some(10), None)
+            .await?
+            .response;
+        let uris: Vec<&str> = with_parents.feed.iter().map(|p| p.post.as_str()).collect();
+        assert_eq!(
+            uris,
+            vec![
+                stranger_uri.as_str(),
+                reply_to_stranger.uri.as_str(),
+                reply_to_follow.uri.as_str(),
+                bob_post.uri.as_str(),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reply_parents_do_not_move_cursor() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        let follower_did = "did:example:alice";
+        let target_did = "did:example:bob";
+        db.insert_follow(&Follow {
+            uri: format!("at://{}/app.bsky.graph.follow/test", follower_did),
+            follower_did: follower_did.to_string(),
+            target_did: target_did.to_string(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await?;
+
+        for i in 0..3 {
+            db.insert_post(&Post {
+                uri: format!("at://{}/app.bsky.feed.post/{}", target_did, i),
+                cid: format!("cid-{}", i),
+                author_did: target_did.to_string(),
+                text: "reply".to_string(),
+                reply_parent_uri: Some(format!(
+                    "at://did:example:stranger/app.bsky.feed.post/{}",
+                    i
+                )),
+                reply_root_uri: None,
+                quoted_uri: None,
+                is_link_only: false,
+                has_media: false,
+                embed_type: None,
+                hashtags: Vec::new(),
+                created_at: Utc::now() - Duration::hours(i),
+                indexed_at: Utc::now(),
+            })
+            .await?;
+        }
+
+        let page = FollowingNoRepostsFeed::new(Arc::clone(&db))
+            .with_reply_parents(true)
+            .generate_feed(Some(follower_did.to_string()), Some(2), None)
+            .await?;
+
+        // Two requested posts plus their two parents; cursor follows the real posts
+        assert_eq!(page.response.feed.len(), 4);
+        assert_eq!(
+            page.response.feed[3].post,
+            format!("at://{}/app.bsky.feed.post/1", target_did)
+        );
+        let cursor = decode_cursor(page.response.cursor.as_deref().unwrap()).unwrap();
+        assert!(cursor < Utc::now() - Duration::minutes(59));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_strict_feed_differs() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        let follower_did = "did:example:alice";
+        let target_did = "did:example:bob";
+        db.insert_follow(&Follow {
+            uri: format!("at://{}/app.bsky.graph.follow/test", follower_did),
+            follower_did: follower_did.to_string(),
+            target_did: target_did.to_string(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await?;
+
+        let records = [
+            serde_json::json!({ "text": "just a thought" }),
+            serde_json::json!({
+                "text": "replying",
+                "reply": { "parent": { "uri": "at://did:example:carol/app.bsky.feed.post/1" } },
+            }),
+            serde_json::json!({
+                "text": "look at this",
+                "embed": {
+                    "$type": "app.bsky.embed.record",
+                    "record": { "uri": "at://did:example:carol/app.bsky.feed.post/2" },
+                },
+            }),
+            serde_json::json!({
+                "text": "example.com/article...",
+                "facets": [{
+                    "index": { "byteStart": 0, "byteEnd": 22 },
+                    "features": [{
+                        "$type": "app.bsky.richtext.facet#link",
+                        "uri": "https://example.com/article",
+                    }],
+                }],
+                "embed": {
+                    "$type": "app.bsky.embed.external",
+                    "external": { "uri": "https://example.com/article" },
+                },
+            }),
+            serde_json::json!({
+                "text": "worth reading https://example.com/other",
+                "embed": {
+                    "$type": "app.bsky.embed.external",
+                    "external": { "uri": "https://example.com/other" },
+                },
+            }),
+        ];
+        for (i, record) in records.iter().enumerate() {
+            db.insert_post(&Post {
+                uri: format!("at://{}/app.bsky.feed.post/{}", target_did, i),
+                cid: format!("cid-{}", i),
+                author_did: target_did.to_string(),
+                text: record["text"].as_str().unwrap().to_string(),
+                reply_parent_uri: record["reply"]["parent"]["uri"]
+                    .as_str()
+                    .map(|s| s.to_string()),
+                reply_root_uri: record["reply"]["root"]["uri"]
+                    .as_str()
+                    .map(|s| s.to_string()),
+                quoted_uri: Post::quoted_uri_of(record),
+                is_link_only: Post::is_link_only_record(record),
+                has_media: Post::has_media_record(record),
+                embed_type: Post::embed_type_of(record),
+                hashtags: Post::hashtags_of(record),
+                created_at: Utc::now() - Duration::minutes(i as i64),
+                indexed_at: Utc::now(),
+            })
+            .await?;
+        }
+
+        let registry = FeedRegistry::default()
+            .register("following-no-reposts", FeedFilter::default())
+            .register("following-strict", FeedFilter::strict());
+
+        let mut results = Vec::new();
+        for rkey in ["following-no-reposts", "following-strict"] {
+            let feed = registry
+                .get(&format!(
+                    "at://did:example:pub/app.bsky.feed.generator/{}",
+                    rkey
+                ))
+                .unwrap();
+            let page = FollowingNoRepostsFeed::new(Arc::clone(&db))
+                .with_filter(feed.filter)
+                .generate_feed(Some(follower_did.to_string()), Some(10), None)
+                .await?;
+            let rkeys: Vec<String> = page
+                .response
+                .feed
+                .iter()
+                .map(|p| p.post.rsplit('/').next().unwrap().to_string())
+                .collect();
+            results.push(rkeys);
+        }
+
+        assert_eq!(results[0], vec!["0", "1", "2", "3", "4"]);
+        // Replies, quotes and bare link cards are gone; a link with commentary stays
+        assert_eq!(results[1], vec!["0", "4"]);
+        assert!(registry
+            .get("at://did:example:pub/app.bsky.feed.generator/unknown")
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_media_feed() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        let follower_did = "did:example:alice";
+        let target_did = "did:example:bob";
+        db.insert_follow(&Follow {
+            uri: format!("at://{}/app.bsky.graph.follow/test", follower_did),
+            follower_did: follower_did.to_string(),
+            target_did: target_did.to_string(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await?;
+
+        let quoted = serde_json::json!({ "uri": "at://did:example:carol/app.bsky.feed.post/1" });
+        let embeds = [
+            (
+                "images",
+                serde_json::json!({ "$type": "app.bsky.embed.images", "images": [] }),
+            ),
+            (
+                "video",
+                serde_json::json!({ "$type": "app.bsky.embed.video" }),
+            ),
+            (
+                "quote-with-images",
+                serde_json::json!({
+                    "$type": "app.bsky.embed.recordWithMedia",
+                    "record": { "record": quoted },
+                    "media": { "$type": "app.bsky.embed.images", "images": [] },
+                }),
+            ),
+            (
+                "quote",
+                serde_json::json!({ "$type": "app.bsky.embed.record", "record": quoted }),
+            ),
+            (
+                "link",
+                serde_json::json!({ "$type": "app.bsky.embed.external", "external": {} }),
+            ),
+            ("text", serde_json::Value::Null),
+        ];
+        for (i, (rkey, embed)) in embeds.iter().enumerate() {
+            let record = serde_json::json!({ "text": "look at this", "embed": embed });
+            db.insert_post(&Post {
+                uri: format!("at://{}/app.bsky.feed.post/{}", target_did, rkey),
+                cid: format!("cid-{}", rkey),
+                author_did: target_did.to_string(),
+                text: "look at this".to_string(),
+                reply_parent_uri: None,
+                reply_root_uri: None,
+                quoted_uri: Post::quoted_uri_of(&record),
+                is_link_only: Post::is_link_only_record(&record),
+                has_media: Post::has_media_record(&record),
+                embed_type: Post::embed_type_of(&record),
+                hashtags: Post::hashtags_of(&record),
+                created_at: Utc::now() - Duration::minutes(i as i64),
+                indexed_at: Utc::now(),
+            })
+            .await?;
+        }
+
+        let page = FollowingNoRepostsFeed::new(Arc::clone(&db))
+            .with_filter(FeedFilter::media())
+            .generate_feed(Some(follower_did.to_string()), Some(10), None)
+            .await?;
+        let rkeys: Vec<&str> = page
+            .response
+            .feed
+            .iter()
+            .map(|p| p.post.rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(rkeys, vec!["images", "video", "quote-with-images"]);
+
+        let posts = db
+            .get_following_posts(follower_did, 10, None, &FeedFilter::default())
+            .await?;
+        assert_eq!(posts.len(), 6);
+        assert_eq!(
+            posts[3].embed_type.as_deref(),
+            Some("app.bsky.embed.record")
+        );
+        assert!(!posts[3].has_media);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hide_quotes_of_non_follows() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        let alice = "did:example:alice";
+        for target_did in ["did:example:bob", "did:example:carol"] {
+            db.insert_follow(&Follow {
+                uri: format!("at://{}/app.bsky.graph.follow/{}", alice, target_did),
+                follower_did: alice.to_string(),
+                target_did: target_did.to_string(),
+                created_at: Utc::now(),
+                indexed_at: Utc::now(),
+            })
+            .await?;
+        }
+
+        // Bob quotes Carol (followed), a stranger, and Alice herself
+        let quotes = [
+            ("quotes-follow", "did:example:carol"),
+            ("quotes-stranger", "did:example:stranger"),
+            ("quotes-me", alice),
+        ];
+        for (i, (rkey, quoted_did)) in quotes.iter().enumerate() {
+            db.insert_post(&Post {
+                uri: format!("at://did:example:bob/app.bsky.feed.post/{}", rkey),
+                cid: format!("cid-{}", rkey),
+                author_did: "did:example:bob".to_string(),
+                text: "look".to_string(),
+                reply_parent_uri: None,
+                reply_root_uri: None,
+                quoted_uri: Some(format!("at://{}/app.bsky.feed.post/x", quoted_did)),
+                is_link_only: false,
+                has_media: false,
+                embed_type: None,
+                hashtags: Vec::new(),
+                created_at: Utc::now() - Duration::minutes(i as i64),
+                indexed_at: Utc::now(),
+            })
+            .await?;
+        }
+
+        let feed = FollowingNoRepostsFeed::new(Arc::clone(&db));
+        let rkeys = |page: FeedPage| -> Vec<String> {
+            page.response
+                .feed
+                .iter()
+                .map(|p| p.post.rsplit('/').next().unwrap().to_string())
+                .collect()
+        };
+
+        let page = feed
+            .generate_feed(Some(alice.to_string()), Some(10), None)
+            .await?;
+        assert_eq!(
+            rkeys(page),
+            vec!["quotes-follow", "quotes-stranger", "quotes-me"]
+        );
+
+        db.set_hide_stranger_quotes(alice, true).await?;
+        let page = feed
+            .generate_feed(Some(alice.to_string()), Some(10), None)
+            .await?;
+        assert_eq!(rkeys(page), vec!["quotes-follow", "quotes-me"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skeleton_item_serialization() -> Result<()> {
+        let item = SkeletonFeedPost {
+            post: "at://did:example:bob/app.bsky.feed.post/1".to_string(),
+            reason: None,
+            reply: None,
+            feed_context: None,
+        };
+        // Unset fields are left out entirely
+        assert_eq!(
+            serde_json::to_string(&item)?,
+            r#"{"post":"at://did:example:bob/app.bsky.feed.post/1"}"#
+        );
+
+        let item = SkeletonFeedPost {
+            reason: Some(SkeletonReason::Pin),
+            feed_context: Some("slot=1".to_string()),
+            ..item
+        };
+        assert_eq!(
+            serde_json::to_string(&item)?,
+            r#"{"post":"at://did:example:bob/app.bsky.feed.post/1","reason":{"$type":"app.bsky.feed.defs#skeletonReasonPin"},"feedContext":"slot=1"}"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(normalize_text("  Copy THIS text\n"), "copy this text");
+        assert_eq!(normalize_text("ÉTÉ"), "été");
+        assert_eq!(normalize_text(" \t "), "");
+        assert_ne!(normalize_text("copy this"), normalize_text("copy  this"));
+    }
+
+    #[tokio::test]
+    async fn test_collapse_duplicate_text() -> Result<()> {
+        let minutes_ago = |n| Utc::now() - Duration::minutes(n);
+        let with_text = |rkey: &str, text: &str, age: i64| Post {
+            text: text.to_string(),
+            ..mock_post(rkey, minutes_ago(age))
+        };
+        let store = Arc::new(MockFeedStore {
+            posts: vec![
+                with_text("late", "Viral text", 1),
+                with_text("own", "something else", 2),
+                with_text("first", "  viral TEXT ", 3),
+                with_text("photo", "", 4),
+                with_text("photo2", "", 5),
+                with_text("older", "viral text", 10),
+            ],
+            ..Default::default()
+        });
+        let feed = FollowingNoRepostsFeed::new(store).with_duplicate_text_collapsed(true);
+
+        // Within the page the earliest copy wins
+        let page = feed
+            .generate_feed(Some("did:example:alice".to_string()), Some(5), None)
+            .await?;
+        let rkeys: Vec<&str> = page
+            .response
+            .feed
+            .iter()
+            .map(|p| p.post.rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(rkeys, ["own", "first", "photo", "photo2"]);
+
+        // The cursor moves past the suppressed post, so it isn't served again
+        let page = feed
+            .generate_feed(
+                Some("did:example:alice".to_string()),
+                Some(5),
+                page.response.cursor,
+            )
+            .await?;
+        assert_eq!(page.response.feed.len(), 1);
+        assert!(page.response.feed[0].post.ends_with("/older"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clamp_limit() {
+        assert_eq!(clamp_limit(None, 30, 50), 30);
+        assert_eq!(clamp_limit(Some(1), 30, 50), 1);
+        assert_eq!(clamp_limit(Some(49), 30, 50), 49);
+        assert_eq!(clamp_limit(Some(50), 30, 50), 50);
+        assert_eq!(clamp_limit(Some(51), 30, 50), 50);
+        assert_eq!(clamp_limit(Some(i32::MAX), 30, 50), 50);
+        // A default above the cap is still capped
+        assert_eq!(clamp_limit(None, 80, 50), 50);
+    }
+
+    #[test]
+    fn test_page_boundary_offsets() {
+        let now = Utc::now();
+        let cutoff = now - Duration::hours(DEFAULT_RETENTION_HOURS);
+        let interval = Duration::seconds(CLEANUP_INTERVAL_SECS as i64);
+        let minute = Duration::minutes(1);
+
+        // (cursor offset from cutoff, page_len, last post offset from cutoff, expected)
+        let cases = [
+            // Cursor at or past the cutoff
+            (Some(Duration::zero()), 0, None, PageBoundary::PastRetention),
+            (
+                Some(-minute),
+                10,
+                Some(-minute),
+                PageBoundary::PastRetention,
+            ),
+            (
+                Some(-Duration::hours(1)),
+                10,
+                None,
+                PageBoundary::PastRetention,
+            ),
+            // Short pages are always final
+            (None, 0, None, PageBoundary::Final),
+            (None, 9, Some(Duration::hours(10)), PageBoundary::Final),
+            (
+                Some(minute),
+                9,
+                Some(Duration::seconds(30)),
+                PageBoundary::Final,
+            ),
+            // Full pages ending inside the cleanup interval are final
+            (None, 10, Some(Duration::zero()), PageBoundary::Final),
+            (None, 10, Some(interval - minute), PageBoundary::Final),
+            (None, 10, Some(interval), PageBoundary::Final),
+            (None, 10, Some(-minute), PageBoundary::Final),
+            // Full pages ending beyond the cleanup interval continue
+            (
+                None,
+                10,
+                Some(interval + Duration::seconds(1)),
+                PageBoundary::More,
+            ),
+            (None, 10, Some(Duration::hours(24)), PageBoundary::More),
+            (
+                Some(Duration::hours(30)),
+                10,
+                Some(Duration::hours(20)),
+                PageBoundary::More,
+            ),
+        ];
+
+        for (cursor_offset, page_len, last_offset, expected) in cases {
+            let cursor_time = cursor_offset.map(|offset| cutoff + offset);
+            let last_created_at = last_offset.map(|offset| cutoff + offset);
+            assert_eq!(
+                page_boundary(cursor_time, 10, page_len, last_created_at, cutoff, interval),
+                expected,
+                "cursor={:?} page_len={} last={:?}",
+                cursor_offset,
+                page_len,
+                last_offset
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cursor_past_retention_returns_empty_page() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        let cursor = (Utc::now() - Duration::hours(DEFAULT_RETENTION_HOURS + 1)).to_rfc3339();
+        let feed_algorithm = FollowingNoRepostsFeed::new(Arc::clone(&db));
+        let page = feed_algorithm
+            .generate_feed(
+                Some("did:example:alice".to_string()),
+                Some(10),
+                Some(cursor),
+            )
+            .await?;
+
+        assert_eq!(page.boundary, PageBoundary::PastRetention);
+        assert!(page.response.feed.is_empty());
+        assert!(page.response.cursor.is_none());
+
+        Ok(())
+    }
+
+    /// Serves hand-crafted posts, all treated as coming from followed accounts
+    #[derive(Default)]
+    struct MockFeedStore {
+        posts: Vec<Post>,
+        preferences: UserPreferences,
+        pinned: Vec<String>,
+    }
+
+    #[async_trait]
+    impl FeedStore for MockFeedStore {
+        async fn get_following_posts(
+            &self,
+            _follower_did: &str,
+            limit: i32,
+            cursor: Option<DateTime<Utc>>,
+            filter: &FeedFilter,
+        ) -> Result<Vec<Post>> {
+            let cursor_time = cursor.unwrap_or_else(Utc::now);
+            let mut posts: Vec<Post> = self
+                .posts
+                .iter()
+                .filter(|p| p.created_at < cursor_time)
+                .filter(|p| filter.replies || p.reply_parent_uri.is_none())
+                .filter(|p| filter.quotes || p.quoted_uri.is_none())
+                .filter(|p| filter.link_only || !p.is_link_only)
+                .filter(|p| !filter.media_only || p.has_media)
+                .cloned()
+                .collect();
+            posts.sort_by_key(|p| std::cmp::Reverse(p.created_at));
+            posts.truncate(limit as usize);
+            Ok(posts)
+        }
+
+        async fn get_following_posts_with_media(
+            &self,
+            follower_did: &str,
+            limit: i32,
+            cursor: Option<DateTime<Utc>>,
+            filter: &FeedFilter,
+        ) -> Result<Vec<Post>> {
+            let filter = FeedFilter {
+                media_only: true,
+                ..*filter
+            };
+            self.get_following_posts(follower_did, limit, cursor, &filter)
+                .await
+        }
+
+        async fn get_preferences(&self, _did: &str) -> Result<UserPreferences> {
+            Ok(self.preferences.clone())
+        }
+
+        async fn get_pinned_posts(&self) -> Result<Vec<String>> {
+            Ok(self.pinned.clone())
+        }
+    }
+
+    fn mock_post(rkey: &str, created_at: DateTime<Utc>) -> Post {
+        Post {
+            uri: format!("at://did:example:bob/app.bsky.feed.post/{}", rkey),
+            cid: format!("cid-{}", rkey),
+            author_did: "did:example:bob".to_string(),
+            text: "text".to_string(),
+            reply_parent_uri: None,
+            reply_root_uri: None,
+            quoted_uri: None,
+            is_link_only: false,
+            has_media: false,
+            embed_type: None,
+            hashtags: Vec::new(),
+            created_at,
+            indexed_at: created_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_page_cache() -> Result<()> {
+        let cache = Arc::new(FeedPageCache::new(std::time::Duration::from_secs(60), 100));
+        let feed_with = |rkey: &str| {
+            let store = Arc::new(MockFeedStore {
+                posts: vec![mock_post(rkey, Utc::now() - Duration::minutes(1))],
+                ..Default::default()
+            });
+            FollowingNoRepostsFeed::new(store).with_page_cache(Some(Arc::clone(&cache)))
+        };
+        let first_post = |page: FeedPage| page.response.feed[0].post.clone();
+        let alice = || Some("did:example:alice".to_string());
+
+        let page = feed_with("a")
+            .generate_feed(alice(), Some(10), None)
+            .await?;
+        assert!(first_post(page).ends_with("/a"));
+
+        // A repeat request is answered without asking the store
+        let feed = feed_with("b");
+        let page = feed.generate_feed(alice(), Some(10), None).await?;
+        assert!(page.cached);
+        assert!(first_post(page).ends_with("/a"));
+
+        // Another requester, limit or feed is a different page
+        let page = feed
+            .generate_feed(Some("did:example:bob".to_string()), Some(10), None)
+            .await?;
+        assert!(first_post(page).ends_with("/b"));
+        let page = feed.generate_feed(alice(), Some(5), None).await?;
+        assert!(first_post(page).ends_with("/b"));
+        let page = feed_with("b")
+            .with_filter(FeedFilter::strict())
+            .generate_feed(alice(), Some(10), None)
+            .await?;
+        assert!(first_post(page).ends_with("/b"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_limit_is_capped() -> Result<()> {
+        let store = Arc::new(MockFeedStore {
+            posts: (0..120)
+                .map(|i| mock_post(&i.to_string(), Utc::now() - Duration::minutes(i + 1)))
+                .collect(),
+            ..Default::default()
+        });
+        let page = FollowingNoRepostsFeed::new(store)
+            .with_limits(30, 50)
+            .generate_feed(Some("did:example:alice".to_string()), Some(200), None)
+            .await?;
+        assert_eq!(page.response.feed.len(), 50);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_full_page_at_cleanup_margin_is_final() -> Result<()> {
+        let cutoff = Utc::now() - Duration::hours(DEFAULT_RETENTION_HOURS);
+        let margin = Duration::seconds(CLEANUP_INTERVAL_SECS as i64);
+
+        // A full page whose last post is about to be cleaned up ends the feed,
+        // even though older posts are still stored
+        let store = Arc::new(MockFeedStore {
+            posts: vec![
+                mock_post("new", Utc::now() - Duration::hours(1)),
+                mock_post("edge", cutoff + margin - Duration::seconds(30)),
+                mock_post("older", cutoff + Duration::seconds(30)),
+            ],
+            ..Default::default()
+        });
+        let page = FollowingNoRepostsFeed::new(Arc::clone(&store))
+            .generate_feed(Some("did:example:alice".to_string()), Some(2), None)
+            .await?;
+        assert_eq!(page.response.feed.len(), 2);
+        assert_eq!(page.boundary, PageBoundary::Final);
+        assert!(page.response.cursor.is_none());
+
+        // One minute further from the cutoff and the client may keep paging
+        let store = Arc::new(MockFeedStore {
+            posts: vec![
+                mock_post("new", Utc::now() - Duration::hours(1)),
+                mock_post("edge", cutoff + margin + Duration::minutes(1)),
+                mock_post("older", cutoff + Duration::seconds(30)),
+            ],
+            ..Default::default()
+        });
+        let page = FollowingNoRepostsFeed::new(store)
+            .generate_feed(Some("did:example:alice".to_string()), Some(2), None)
+            .await?;
+        assert_eq!(page.boundary, PageBoundary::More);
+        assert!(page.response.cursor.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_user_retention_bounds_pagination() -> Result<()> {
+        // The user keeps posts for 6 hours, so a 12 hour old cursor is past retention
+        // even though the global default would still serve it
+        let store = Arc::new(MockFeedStore {
+            posts: vec![mock_post("old", Utc::now() - Duration::hours(13))],
+            preferences: UserPreferences {
+                post_retention_hours: Some(6),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let cursor = (Utc::now() - Duration::hours(12)).to_rfc3339();
+        let page = FollowingNoRepostsFeed::new(store)
+            .generate_feed(
+                Some("did:example:alice".to_string()),
+                Some(10),
+                Some(cursor),
+            )
+            .await?;
+
+        assert_eq!(page.boundary, PageBoundary::PastRetention);
+        assert!(page.response.feed.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_min_post_length() -> Result<()> {
+        let minutes_ago = |n| Utc::now() - Duration::minutes(n);
+        let with_text = |rkey: &str, text: &str, age: i64| Post {
+            text: text.to_string(),
+            ..mock_post(rkey, minutes_ago(age))
+        };
+        let store = Arc::new(MockFeedStore {
+            posts: vec![
+                with_text("lol", "lol", 1),
+                with_text("emoji", "👍🏽", 2),
+                with_text("family", "👨‍👩‍👧‍👦 ok", 3),
+                with_text("cjk", "今日は晴れ", 4),
+                with_text("blank", "   \n\t ", 5),
+                Post {
+                    has_media: true,
+                    ..with_text("photo", "", 6)
+                },
+                with_text("long", "a real sentence", 7),
+            ],
+            preferences: UserPreferences {
+                min_post_length: Some(4),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        let page = FollowingNoRepostsFeed::new(store)
+            .generate_feed(Some("did:example:alice".to_string()), Some(7), None)
+            .await?;
+        let rkeys: Vec<&str> = page
+            .response
+            .feed
+            .iter()
+            .map(|p| p.post.rsplit('/').next().unwrap())
+            .collect();
+        // "👍🏽" is two code points but one grapheme, and the family emoji is one
+        // grapheme, so "👨‍👩‍👧‍👦 ok" counts as four characters
+        assert_eq!(rkeys, ["family", "cjk", "photo", "long"]);
+        // The cursor still points past the last fetched post
+        assert!(page.response.cursor.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pinned_posts_lead_first_page() -> Result<()> {
+        let minutes_ago = |n| Utc::now() - Duration::minutes(n);
+        let pin = "at://did:example:carol/app.bsky.feed.post/pinned".to_string();
+        let store = Arc::new(MockFeedStore {
+            posts: vec![
+                mock_post("new", minutes_ago(1)),
+                mock_post("mid", minutes_ago(2)),
+                mock_post("old", minutes_ago(3)),
+            ],
+            pinned: vec![pin.clone(), mock_post("mid", minutes_ago(2)).uri],
+            ..Default::default()
+        });
+        let feed = FollowingNoRepostsFeed::new(store);
+
+        let page = feed
+            .generate_feed(Some("did:example:alice".to_string()), Some(2), None)
+            .await?;
+        let uris: Vec<&str> = page.response.feed.iter().map(|p| p.post.as_str()).collect();
+        // The pin that's already on the page stays where it is
+        assert_eq!(
+            uris,
+            [
+                pin.as_str(),
+                "at://did:example:bob/app.bsky.feed.post/new",
+                "at://did:example:bob/app.bsky.feed.post/mid",
+            ]
+        );
+        assert_eq!(page.response.feed[0].reason, Some(SkeletonReason::Pin));
+        assert!(page.response.feed[1..].iter().all(|p| p.reason.is_none()));
+        let json = serde_json::to_value(&page.response)?;
+        assert_eq!(
+            json["feed"][0]["reason"]["$type"],
+            "app.bsky.feed.defs#skeletonReasonPin"
+        );
+        assert!(json["feed"][1].get("reason").is_none());
+
+        // Later pages don't repeat the pin
+        let page = feed
+            .generate_feed(
+                Some("did:example:alice".to_string()),
+                Some(2),
+                page.response.cursor,
+            )
+            .await?;
+        assert_eq!(page.response.feed.len(), 1);
+        assert!(page.response.feed[0].post.ends_with("/old"));
+        assert!(page.response.feed[0].reason.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backfill_placeholder() -> Result<()> {
+        let placeholder = "at://did:example:operator/app.bsky.feed.post/indexing".to_string();
+        let store = Arc::new(MockFeedStore::default());
+        let page = FollowingNoRepostsFeed::new(store)
+            .with_backfill_placeholder(Some(placeholder.clone()))
+            .generate_feed(Some("did:example:alice".to_string()), Some(10), None)
+            .await?;
+
+        assert_eq!(page.response.feed.len(), 1);
+        assert_eq!(page.response.feed[0].post, placeholder);
+        assert_eq!(page.response.feed[0].reason, Some(SkeletonReason::Pin));
+        assert!(page.response.cursor.is_none());
+        assert_eq!(page.boundary, PageBoundary::Final);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_feed_timings() -> Result<()> {
+        let posts = (1..=5)
+            .map(|n| mock_post(&n.to_string(), Utc::now() - Duration::minutes(n)))
+            .collect();
+        let store = Arc::new(MockFeedStore {
+            posts,
+            ..Default::default()
+        });
+        let feed = FollowingNoRepostsFeed::new(store);
+
+        let page = feed
+            .generate_feed(Some("did:example:alice".to_string()), Some(2), None)
+            .await?;
+        let started = Instant::now();
+        let page = feed
+            .generate_feed(
+                Some("did:example:alice".to_string()),
+                Some(2),
+                page.response.cursor,
+            )
+            .await?;
+        // Phases may be too quick for the clock to see, but they all run
+        // within the call, one after another
+        assert!(page.timings.total() <= started.elapsed());
+
         Ok(())
     }
 }
@@ -0,0 +1,108 @@
+use moka::future::Cache;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a user's follow set is trusted before it is read again
+pub const FOLLOW_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Invalidation counters, each shared by the followers hashing to it
+const GENERATION_SHARDS: usize = 64;
+
+/// The accounts each feed user follows, so a feed query can name the
+/// authors instead of joining follows. `Database` drops a user's entry
+/// whenever it writes or deletes one of their follows.
+#[derive(Clone)]
+pub struct FollowCache {
+    follows: Cache<String, Arc<HashSet<String>>>,
+    /// Bumped by each invalidation, so a set read before one isn't kept
+    generations: Arc<[AtomicU64; GENERATION_SHARDS]>,
+}
+
+impl FollowCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            follows: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(ttl)
+                .build(),
+            generations: Arc::new(std::array::from_fn(|_| AtomicU64::new(0))),
+        }
+    }
+
+    fn generation_of(&self, follower_did: &str) -> &AtomicU64 {
+        let mut hasher = DefaultHasher::new();
+        follower_did.hash(&mut hasher);
+        &self.generations[hasher.finish() as usize % GENERATION_SHARDS]
+    }
+
+    /// Taken before reading a follow set from the database, to pass to
+    /// `insert`
+    pub fn generation(&self, follower_did: &str) -> u64 {
+        self.generation_of(follower_did).load(Ordering::SeqCst)
+    }
+
+    pub async fn get(&self, follower_did: &str) -> Option<Arc<HashSet<String>>> {
+        self.follows.get(follower_did).await
+    }
+
+    /// Caches a follow set read at `generation`, unless the follower was
+    /// invalidated since, in which case it may already be stale
+    pub async fn insert(
+        &self,
+        follower_did: &str,
+        targets: HashSet<String>,
+        generation: u64,
+    ) -> Arc<HashSet<String>> {
+        let targets = Arc::new(targets);
+        if self.generation(follower_did) != generation {
+            return targets;
+        }
+        self.follows
+            .insert(follower_did.to_string(), Arc::clone(&targets))
+            .await;
+        // An invalidation between the check and the insert may have run first
+        if self.generation(follower_did) != generation {
+            self.follows.invalidate(follower_did).await;
+        }
+        targets
+    }
+
+    pub async fn invalidate(&self, follower_did: &str) {
+        self.generation_of(follower_did)
+            .fetch_add(1, Ordering::SeqCst);
+        self.follows.invalidate(follower_did).await;
+    }
+}
+
+impl Default for FollowCache {
+    fn default() -> Self {
+        Self::new(FOLLOW_CACHE_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sets_read_before_an_invalidation_are_not_kept() {
+        let cache = FollowCache::default();
+        let alice = "did:example:alice";
+        let stale = HashSet::from(["did:example:bob".to_string()]);
+
+        // A follow is written while the set is being read
+        let generation = cache.generation(alice);
+        cache.invalidate(alice).await;
+        let targets = cache.insert(alice, stale.clone(), generation).await;
+        assert_eq!(*targets, stale);
+        assert!(cache.get(alice).await.is_none());
+
+        let generation = cache.generation(alice);
+        cache.insert(alice, stale, generation).await;
+        assert!(cache.get(alice).await.is_some());
+    }
+}
@@ -0,0 +1,323 @@
+use anyhow::{anyhow, Result};
+use moka::future::Cache;
+use std::future::Future;
+use std::time::Duration;
+use tracing::debug;
+
+/// Default PLC directory used to resolve did:plc identities
+pub const PLC_DIRECTORY_URL: &str = "https://plc.directory";
+
+/// Least time between re-resolving a DID's signing key after a failed
+/// signature, so forged tokens naming someone else's DID can't each cost a
+/// directory lookup
+const KEY_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Resolved identity details for a DID
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub pds_endpoint: String,
+    pub handle: Option<String>,
+}
+
+/// Caches DID documents so PDS-direct calls don't resolve on every request,
+/// and feed users' signing keys so each JWT doesn't either. Entries are
+/// invalidated when Jetstream reports an identity change, which is how a
+/// rotated key is usually noticed; a failed signature re-resolves the key too.
+pub struct IdentityCache {
+    client: reqwest::Client,
+    plc_url: String,
+    identities: Cache<String, Identity>,
+    handles: Cache<String, String>,
+    signing_keys: Cache<String, String>,
+    /// DIDs whose key was re-resolved after a failed signature recently
+    key_refreshes: Cache<String, ()>,
+}
+
+impl IdentityCache {
+    pub fn new(plc_url: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            plc_url: plc_url.trim_end_matches('/').to_string(),
+            identities: Cache::builder()
+                .max_capacity(100_000)
+                .time_to_live(Duration::from_secs(24 * 60 * 60))
+                .build(),
+            handles: Cache::builder().max_capacity(100_000).build(),
+            signing_keys: Cache::builder()
+                .max_capacity(100_000)
+                .time_to_live(Duration::from_secs(24 * 60 * 60))
+                .build(),
+            key_refreshes: Cache::builder()
+                .max_capacity(100_000)
+                .time_to_live(KEY_REFRESH_INTERVAL)
+                .build(),
+        }
+    }
+
+    /// Returns the DID's PDS endpoint, resolving the DID document if needed
+    pub async fn pds_endpoint(&self, did: &str) -> Result<String> {
+        Ok(self.resolve(did).await?.pds_endpoint)
+    }
+
+    pub async fn resolve(&self, did: &str) -> Result<Identity> {
+        if let Some(identity) = self.identities.get(did).await {
+            return Ok(identity);
+        }
+
+        let url = if let Some(host) = did.strip_prefix("did:web:") {
+            format!("https://{}/.well-known/did.json", host)
+        } else {
+            format!("{}/{}", self.plc_url, did)
+        };
+
+        debug!("Resolving DID document for {} from {}", did, url);
+        let doc: serde_json::Value = self
+            .client
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let identity = parse_did_document(&doc)
+            .ok_or_else(|| anyhow!("No PDS endpoint in DID document for {}", did))?;
+        if let Some(handle) = &identity.handle {
+            self.handles.insert(did.to_string(), handle.clone()).await;
+        }
+        self.identities
+            .insert(did.to_string(), identity.clone())
+            .await;
+
+        Ok(identity)
+    }
+
+    /// Forgets the cached DID document and signing key so the next call
+    /// re-resolves them
+    pub async fn invalidate(&self, did: &str) {
+        self.identities.invalidate(did).await;
+        self.signing_keys.invalidate(did).await;
+    }
+
+    /// Whether we currently hold a resolved DID document or signing key for a DID
+    pub fn contains(&self, did: &str) -> bool {
+        self.identities.contains_key(did) || self.signing_keys.contains_key(did)
+    }
+
+    /// The DID's atproto signing key as a did:key, if resolved before
+    pub async fn signing_key(&self, did: &str) -> Option<String> {
+        self.signing_keys.get(did).await
+    }
+
+    pub async fn set_signing_key(&self, did: &str, did_key: String) {
+        self.signing_keys.insert(did.to_string(), did_key).await;
+    }
+
+    /// Checks a signature with the DID's signing key, resolving the key with
+    /// `resolve` when it isn't cached. A failure with a cached key may mean the
+    /// key rotated without us seeing the identity event, so the key is
+    /// resolved again and the check retried, at most once per
+    /// `KEY_REFRESH_INTERVAL` for each DID.
+    pub async fn verify_with_signing_key<R, F, V>(
+        &self,
+        did: &str,
+        resolve: R,
+        verify: V,
+    ) -> Result<()>
+    where
+        R: Fn() -> F,
+        F: Future<Output = Result<String>>,
+        V: Fn(&str) -> Result<()>,
+    {
+        let Some(cached) = self.signing_key(did).await else {
+            let did_key = resolve().await?;
+            self.set_signing_key(did, did_key.clone()).await;
+            return verify(&did_key);
+        };
+        let Err(e) = verify(&cached) else {
+            return Ok(());
+        };
+
+        let refresh = self
+            .key_refreshes
+            .entry(did.to_string())
+            .or_insert(())
+            .await;
+        if !refresh.is_fresh() {
+            return Err(e);
+        }
+        let did_key = resolve().await?;
+        if did_key == cached {
+            return Err(e);
+        }
+        debug!("Signing key of {} changed, retrying with the new key", did);
+        self.set_signing_key(did, did_key.clone()).await;
+        verify(&did_key)
+    }
+
+    pub async fn handle(&self, did: &str) -> Option<String> {
+        self.handles.get(did).await
+    }
+
+    /// Records a handle announced by an identity event
+    pub async fn set_handle(&self, did: &str, handle: &str) {
+        self.handles
+            .insert(did.to_string(), handle.to_string())
+            .await;
+    }
+
+    /// Fetches records of a collection straight from the DID's PDS
+    pub async fn list_records(
+        &self,
+        did: &str,
+        collection: &str,
+        limit: usize,
+    ) -> Result<serde_json::Value> {
+        self.list_records_page(did, collection, limit, None).await
+    }
+
+    /// Like `list_records`, continuing from the `cursor` of a previous page
+    pub async fn list_records_page(
+        &self,
+        did: &str,
+        collection: &str,
+        limit: usize,
+        cursor: Option<&str>,
+    ) -> Result<serde_json::Value> {
+        let pds = self.pds_endpoint(did).await?;
+        let mut url = format!(
+            "{}/xrpc/com.atproto.repo.listRecords?repo={}&collection={}&limit={}",
+            pds.trim_end_matches('/'),
+            did,
+            collection,
+            limit.min(100)
+        );
+        if let Some(cursor) = cursor {
+            url.push_str(&format!("&cursor={}", cursor));
+        }
+
+        Ok(self
+            .client
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+impl Default for IdentityCache {
+    fn default() -> Self {
+        Self::new(PLC_DIRECTORY_URL)
+    }
+}
+
+fn parse_did_document(doc: &serde_json::Value) -> Option<Identity> {
+    let pds_endpoint = doc["service"].as_array()?.iter().find(|service| {
+        service["id"]
+            .as_str()
+            .is_some_and(|id| id.ends_with("#atproto_pds"))
+    })?["serviceEndpoint"]
+        .as_str()?
+        .to_string();
+
+    let handle = doc["alsoKnownAs"].as_array().and_then(|aka| {
+        aka.iter()
+            .filter_map(|v| v.as_str())
+            .find_map(|v| v.strip_prefix("at://"))
+            .map(|h| h.to_string())
+    });
+
+    Some(Identity {
+        pds_endpoint,
+        handle,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_failed_signature_refreshes_key_once() -> Result<()> {
+        let identity = IdentityCache::default();
+        let did = "did:plc:alice";
+        let resolved = AtomicUsize::new(0);
+        let key = std::sync::Mutex::new("did:key:zOld".to_string());
+        let resolve = || {
+            resolved.fetch_add(1, Ordering::SeqCst);
+            let did_key = key.lock().unwrap().clone();
+            async move { Ok(did_key) }
+        };
+        // A stand-in for a signature check: tokens name the key they need
+        let signed_with = |token: &'static str| {
+            move |did_key: &str| {
+                if did_key == token {
+                    Ok(())
+                } else {
+                    Err(anyhow!("Invalid signature"))
+                }
+            }
+        };
+
+        // The first token resolves the key, later ones use the cached copy
+        identity
+            .verify_with_signing_key(did, resolve, signed_with("did:key:zOld"))
+            .await?;
+        identity
+            .verify_with_signing_key(did, resolve, signed_with("did:key:zOld"))
+            .await?;
+        assert_eq!(resolved.load(Ordering::SeqCst), 1);
+
+        // Forged tokens don't evict the key, and only the first one triggers
+        // a lookup
+        for _ in 0..3 {
+            assert!(identity
+                .verify_with_signing_key(did, resolve, signed_with("did:key:zForged"))
+                .await
+                .is_err());
+        }
+        assert_eq!(resolved.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            identity.signing_key(did).await.as_deref(),
+            Some("did:key:zOld")
+        );
+
+        // A rotation whose event we missed is picked up within one request
+        let rotated = IdentityCache::default();
+        rotated
+            .set_signing_key(did, "did:key:zOld".to_string())
+            .await;
+        *key.lock().unwrap() = "did:key:zNew".to_string();
+        rotated
+            .verify_with_signing_key(did, resolve, signed_with("did:key:zNew"))
+            .await?;
+        assert_eq!(
+            rotated.signing_key(did).await.as_deref(),
+            Some("did:key:zNew")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_did_document() {
+        let doc = serde_json::json!({
+            "id": "did:plc:abc",
+            "alsoKnownAs": ["at://alice.example.com"],
+            "service": [{
+                "id": "#atproto_pds",
+                "type": "AtprotoPersonalDataServer",
+                "serviceEndpoint": "https://pds.example.com"
+            }]
+        });
+
+        let identity = parse_did_document(&doc).unwrap();
+        assert_eq!(identity.pds_endpoint, "https://pds.example.com");
+        assert_eq!(identity.handle.as_deref(), Some("alice.example.com"));
+
+        assert!(parse_did_document(&serde_json::json!({ "service": [] })).is_none());
+    }
+}
@@ -2,74 +2,922 @@ use anyhow::Result;
 use chrono::{DateTime, Utc};
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::{HashSet, VecDeque};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
 use tokio_tungstenite::tungstenite::Message;
 use tracing::{debug, error, info, warn};
 
 use crate::{
+    api_budget::ApiBudget,
+    backfill,
     database::Database,
-    types::{Follow, Post},
+    identity::IdentityCache,
+    shutdown::ShutdownCoordinator,
+    types::{Block, Follow, Post},
 };
 
+/// Collections the consumer handles, subscribed to by default
+pub const SUPPORTED_COLLECTIONS: [&str; 5] = [
+    "app.bsky.feed.post",
+    "app.bsky.feed.repost",
+    "app.bsky.graph.follow",
+    "app.bsky.graph.block",
+    "app.bsky.actor.profile",
+];
+
+/// Warns about each collection the consumer has no handler for, returning
+/// them. Their events are still subscribed to but ignored.
+pub fn check_collections(collections: &[String]) -> Vec<&str> {
+    let unsupported: Vec<&str> = collections
+        .iter()
+        .map(String::as_str)
+        .filter(|collection| !SUPPORTED_COLLECTIONS.contains(collection))
+        .collect();
+    for collection in &unsupported {
+        warn!(
+            "Jetstream collection {} is not supported, its events will be ignored",
+            collection
+        );
+    }
+    unsupported
+}
+
+fn subscribe_url(
+    jetstream_hostname: &str,
+    collections: &[impl AsRef<str>],
+    cursor: Option<i64>,
+) -> String {
+    let wanted_collections = collections
+        .iter()
+        .map(|collection| format!("wantedCollections={}", collection.as_ref()))
+        .collect::<Vec<_>>()
+        .join("&");
+    let mut url = format!(
+        "wss://{}/subscribe?{}",
+        jetstream_hostname, wanted_collections
+    );
+    if let Some(cursor) = cursor {
+        url.push_str(&format!("&cursor={}", cursor));
+    }
+    url
+}
+
+/// Shortest time between saves of the Jetstream cursor
+const CURSOR_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How far before the saved cursor a resumed connection starts, to replay
+/// events that were read but possibly not yet written
+const CURSOR_REWIND_US: i64 = 5_000_000;
+
+/// Number of tasks writing Jetstream events to the database
+const INGEST_WRITERS: usize = 2;
+
+/// Shortest and longest wait before reconnecting to Jetstream
+const RECONNECT_BASE: Duration = Duration::from_secs(1);
+const RECONNECT_CAP: Duration = Duration::from_secs(180);
+
+/// Consecutive failures after which the next Jetstream hostname is tried
+pub const DEFAULT_FAILOVER_AFTER: u32 = 5;
+
+/// Waits between Jetstream reconnects: exponential with decorrelated
+/// jitter, so many consumers recovering from an outage don't reconnect in
+/// step. Each wait is random between the base and three times the last one.
+#[derive(Debug)]
+struct ReconnectBackoff {
+    base: Duration,
+    cap: Duration,
+    previous: Duration,
+    failures: u32,
+}
+
+impl ReconnectBackoff {
+    fn new(base: Duration, cap: Duration) -> Self {
+        Self {
+            base,
+            cap,
+            previous: base,
+            failures: 0,
+        }
+    }
+
+    /// Counts a failure and returns how long to wait before the next attempt
+    fn next_delay(&mut self) -> Duration {
+        self.failures += 1;
+        let high = (self.previous * 3).min(self.cap);
+        let span = high.saturating_sub(self.base).as_millis() as u64;
+        let random = RandomState::new().build_hasher().finish();
+        self.previous = self.base + Duration::from_millis(random % (span + 1));
+        self.previous
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.base, self.cap);
+    }
+}
+
+/// How often the followed authors are reloaded, picking up backfilled
+/// follows and new feed users
+pub const FOLLOWED_AUTHORS_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Newly followed authors waiting for a backfill; past this, more are
+/// skipped and only get posts from Jetstream
+const FOLLOW_BACKFILL_QUEUE: usize = 1_000;
+
+/// Tasks backfilling newly followed authors
+const FOLLOW_BACKFILL_WORKERS: usize = 2;
+
+/// Period ingest lag is averaged over
+const LAG_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long lag must stay above the threshold before it is logged
+const LAG_SUSTAINED: Duration = Duration::from_secs(5 * 60);
+
+/// How often the lag window is checked while no events arrive
+const LAG_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Time between events happening and being read, over the last full window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct IngestLag {
+    pub average_ms: u64,
+    pub max_ms: u64,
+}
+
+/// Ingest lag accumulated over the current window
+#[derive(Debug)]
+struct LagWindow {
+    started: Instant,
+    sum_us: i64,
+    count: i64,
+    max_us: i64,
+    last: Option<IngestLag>,
+    /// Average lag above which a sustained stretch is logged
+    warn_above: Option<Duration>,
+    behind_since: Option<Instant>,
+}
+
+impl Default for LagWindow {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            sum_us: 0,
+            count: 0,
+            max_us: 0,
+            last: None,
+            warn_above: None,
+            behind_since: None,
+        }
+    }
+}
+
+impl LagWindow {
+    fn record(&mut self, lag_us: i64, now: Instant) {
+        let lag_us = lag_us.max(0);
+        self.sum_us += lag_us;
+        self.count += 1;
+        self.max_us = self.max_us.max(lag_us);
+        self.close(now, None);
+    }
+
+    /// Ends the window once it has run its length. A window without events
+    /// means Jetstream stalled, so it counts as `idle_lag_us`, the lag of
+    /// the newest event read, if there is one.
+    fn close(&mut self, now: Instant, idle_lag_us: Option<i64>) {
+        if now.duration_since(self.started) < LAG_WINDOW {
+            return;
+        }
+        if self.count == 0 {
+            let Some(lag_us) = idle_lag_us else {
+                self.started = now;
+                return;
+            };
+            self.sum_us = lag_us.max(0);
+            self.count = 1;
+            self.max_us = self.sum_us;
+        }
+
+        let lag = IngestLag {
+            average_ms: (self.sum_us / self.count / 1000) as u64,
+            max_ms: (self.max_us / 1000) as u64,
+        };
+        self.last = Some(lag);
+        if let Some(threshold) = self.warn_above {
+            if lag.average_ms > threshold.as_millis() as u64 {
+                let since = *self.behind_since.get_or_insert(self.started);
+                if now.duration_since(since) >= LAG_SUSTAINED {
+                    warn!(
+                        average_ms = lag.average_ms,
+                        max_ms = lag.max_ms,
+                        "Jetstream ingest has been behind for {} minutes",
+                        now.duration_since(since).as_secs() / 60
+                    );
+                }
+            } else {
+                self.behind_since = None;
+            }
+        }
+        self.started = now;
+        self.sum_us = 0;
+        self.count = 0;
+        self.max_us = 0;
+    }
+}
+
+/// Snapshot of the ingest queue for the admin consoles
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct IngestStats {
+    pub depth: usize,
+    pub capacity: usize,
+    pub written: u64,
+    pub failed: u64,
+    pub batches: u64,
+    /// `time_us` of the newest event read from Jetstream
+    pub last_event_us: Option<i64>,
+    /// `time_us` up to which every event read has been written, the cursor
+    /// saved and resumed from
+    pub written_us: Option<i64>,
+    /// Jetstream connections that failed or dropped since events last came in
+    pub connection_failures: u32,
+    /// None until a full minute of events has been read
+    pub lag: Option<IngestLag>,
+}
+
+#[derive(Default)]
+struct IngestMetrics {
+    written: AtomicU64,
+    failed: AtomicU64,
+    batches: AtomicU64,
+    last_event_us: AtomicI64,
+    /// Per writer: events queued to it, events it is done with, and the
+    /// `time_us` of the newest of those
+    queued: [AtomicU64; INGEST_WRITERS],
+    done: [AtomicU64; INGEST_WRITERS],
+    done_us: [AtomicI64; INGEST_WRITERS],
+    connection_failures: AtomicU32,
+    lag: Mutex<LagWindow>,
+}
+
+impl IngestMetrics {
+    fn record_done(&self, shard: usize, count: u64, newest_us: i64) {
+        self.done_us[shard].fetch_max(newest_us, Ordering::SeqCst);
+        self.done[shard].fetch_add(count, Ordering::SeqCst);
+    }
+
+    /// The newest event read if every writer has caught up, else the
+    /// progress of the furthest behind. None until a busy writer has
+    /// finished a batch.
+    fn written_us(&self) -> Option<i64> {
+        let mut written = self.last_event_us.load(Ordering::SeqCst);
+        for shard in 0..INGEST_WRITERS {
+            if self.done[shard].load(Ordering::SeqCst) < self.queued[shard].load(Ordering::SeqCst) {
+                written = written.min(self.done_us[shard].load(Ordering::SeqCst));
+            }
+        }
+        Some(written).filter(|&us| us > 0)
+    }
+
+    /// Closes the lag window if it is due, even when no events arrive
+    fn close_lag_window(&self) {
+        let idle_lag_us = Some(self.last_event_us.load(Ordering::SeqCst))
+            .filter(|&us| us > 0)
+            .map(|us| Utc::now().timestamp_micros() - us);
+        self.lag.lock().unwrap().close(Instant::now(), idle_lag_us);
+    }
+}
+
+/// Bounded queues between the Jetstream reader and the database writers.
+/// When the writers fall behind, pushing waits, which stops the reader from
+/// pulling more events off the socket. Events are sharded by DID so each
+/// account's events are written in order.
+#[derive(Clone)]
+pub struct IngestQueue {
+    senders: Vec<mpsc::Sender<JetstreamEvent>>,
+    metrics: Arc<IngestMetrics>,
+}
+
+impl IngestQueue {
+    async fn push(&self, event: JetstreamEvent) -> Result<()> {
+        let mut hasher = DefaultHasher::new();
+        event.did().hash(&mut hasher);
+        let shard = hasher.finish() as usize % self.senders.len();
+        // Counted as queued before it counts as read, so `written_us` never
+        // takes a writer for idle while it has this event
+        self.metrics.queued[shard].fetch_add(1, Ordering::SeqCst);
+        self.metrics
+            .last_event_us
+            .fetch_max(event.time_us(), Ordering::SeqCst);
+        self.metrics.lag.lock().unwrap().record(
+            Utc::now().timestamp_micros() - event.time_us(),
+            Instant::now(),
+        );
+        self.senders[shard]
+            .send(event)
+            .await
+            .map_err(|_| anyhow::anyhow!("Ingest writer {} has stopped", shard))
+    }
+
+    /// Events waiting to be written
+    pub fn depth(&self) -> usize {
+        self.senders
+            .iter()
+            .map(|sender| sender.max_capacity() - sender.capacity())
+            .sum()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.senders
+            .iter()
+            .map(|sender| sender.max_capacity())
+            .sum()
+    }
+
+    pub fn stats(&self) -> IngestStats {
+        self.metrics.close_lag_window();
+        IngestStats {
+            depth: self.depth(),
+            capacity: self.capacity(),
+            written: self.metrics.written.load(Ordering::Relaxed),
+            failed: self.metrics.failed.load(Ordering::Relaxed),
+            batches: self.metrics.batches.load(Ordering::Relaxed),
+            last_event_us: Some(self.metrics.last_event_us.load(Ordering::Relaxed))
+                .filter(|&us| us > 0),
+            written_us: self.metrics.written_us(),
+            connection_failures: self.metrics.connection_failures.load(Ordering::Relaxed),
+            lag: self.metrics.lag.lock().unwrap().last,
+        }
+    }
+
+    /// Logs a warning when the average ingest lag stays above `threshold`
+    pub fn with_lag_warning(self, threshold: Duration) -> Self {
+        self.metrics.lag.lock().unwrap().warn_above = Some(threshold);
+        self
+    }
+
+    /// Checks the lag window every `LAG_CHECK_INTERVAL` until shutdown, so
+    /// a stalled Jetstream still reaches the stats and the lag warning
+    pub fn spawn_lag_checks(&self, tasks: &mut JoinSet<()>, shutdown: ShutdownCoordinator) {
+        let metrics = Arc::clone(&self.metrics);
+        tasks.spawn(async move {
+            let mut interval = tokio::time::interval(LAG_CHECK_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => metrics.close_lag_window(),
+                    _ = shutdown.wait() => break,
+                }
+            }
+        });
+    }
+
+    /// Human-readable metrics for the admin console
+    pub fn format_stats(&self) -> String {
+        let stats = self.stats();
+        let lag = match stats.lag {
+            Some(lag) => format!(
+                "{:.1}s average, {:.1}s max over the last minute",
+                lag.average_ms as f64 / 1000.0,
+                lag.max_ms as f64 / 1000.0
+            ),
+            None => "not measured yet".to_string(),
+        };
+        format!(
+            "Ingest Queue:\n  Depth: {}/{}\n  Written: {} events in {} batches, {} failed\n  Lag: {}\n  Jetstream connection failures in a row: {}\n",
+            stats.depth,
+            stats.capacity,
+            stats.written,
+            stats.batches,
+            stats.failed,
+            lag,
+            stats.connection_failures
+        )
+    }
+}
+
+/// Feed users and the authors they follow, so only posts someone will see
+/// are indexed. Reloaded from the database periodically, and grown as feed
+/// users follow new accounts in between.
+#[derive(Default)]
+pub struct FollowedAuthors {
+    sets: RwLock<FollowedSets>,
+}
+
+#[derive(Default)]
+struct FollowedSets {
+    users: HashSet<String>,
+    authors: HashSet<String>,
+}
+
+impl FollowedAuthors {
+    /// Replaces the sets with the database's, returning how many authors
+    /// feed users follow
+    pub async fn reload(&self, db: &Database) -> Result<usize> {
+        let (users, authors) = db.get_feed_users_and_follows().await?;
+        let count = authors.len();
+        *self.sets.write().unwrap() = FollowedSets { users, authors };
+        Ok(count)
+    }
+
+    /// Reloads every `period` from now on, until shutdown
+    pub fn spawn_reloads(
+        self: &Arc<Self>,
+        tasks: &mut JoinSet<()>,
+        shutdown: ShutdownCoordinator,
+        db: Arc<Database>,
+        period: Duration,
+    ) {
+        let authors = Arc::clone(self);
+        tasks.spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => match authors.reload(&db).await {
+                        Ok(count) => debug!("Reloaded {} followed authors", count),
+                        Err(e) => warn!("Failed to reload followed authors: {}", e),
+                    },
+                    _ = shutdown.wait() => break,
+                }
+            }
+        });
+    }
+
+    /// Whether the account is a feed user or followed by one
+    fn contains(&self, author_did: &str) -> bool {
+        let sets = self.sets.read().unwrap();
+        sets.authors.contains(author_did) || sets.users.contains(author_did)
+    }
+
+    /// Notes a feed user's follow, returning whether nobody followed its
+    /// target before
+    fn add_follow(&self, follow: &Follow) -> bool {
+        let mut sets = self.sets.write().unwrap();
+        sets.users.contains(&follow.follower_did) && sets.authors.insert(follow.target_did.clone())
+    }
+}
+
+/// Indexing limited to followed authors, with the queue of newly followed
+/// ones to backfill
+#[derive(Clone)]
+struct FollowedOnly {
+    authors: Arc<FollowedAuthors>,
+    backfills: mpsc::Sender<String>,
+}
+
+/// Commits remembered to skip replays
+const SEEN_COMMITS: usize = 10_000;
+
+/// The most recent commits applied, oldest first. A reconnect resumes a
+/// little before the last event written, so the first few seconds after it
+/// are replays; without this a follow deleted in that window would come back.
+#[derive(Debug, Default)]
+struct SeenCommits {
+    order: VecDeque<String>,
+    keys: HashSet<String>,
+}
+
+impl SeenCommits {
+    fn contains(&self, key: &str) -> bool {
+        self.keys.contains(key)
+    }
+
+    /// Remembers `key`, forgetting the oldest past capacity. Returns false
+    /// if it was already remembered.
+    fn insert(&mut self, key: String) -> bool {
+        if self.keys.contains(&key) {
+            return false;
+        }
+        if self.order.len() >= SEEN_COMMITS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+        self.keys.insert(key.clone());
+        self.order.push_back(key);
+        true
+    }
+}
+
 pub struct JetstreamEventHandler {
     db: Arc<Database>,
+    identity: Arc<IdentityCache>,
+    followed_only: Option<FollowedOnly>,
+    seen: Arc<Mutex<SeenCommits>>,
+    /// Tasks started through this handler and its clones
+    tasks_spawned: Arc<AtomicUsize>,
 }
 
 impl JetstreamEventHandler {
-    pub fn new(db: Arc<Database>) -> Self {
-        Self { db }
+    pub fn new(db: Arc<Database>, identity: Arc<IdentityCache>) -> Self {
+        Self {
+            db,
+            identity,
+            followed_only: None,
+            seen: Arc::new(Mutex::new(SeenCommits::default())),
+            tasks_spawned: Arc::new(AtomicUsize::new(0)),
+        }
     }
 
-    pub async fn start(&self, jetstream_hostname: String) -> Result<()> {
-        let wanted_collections =
-            "wantedCollections=app.bsky.feed.post&wantedCollections=app.bsky.graph.follow";
-        let ws_url = format!(
-            "wss://{}/subscribe?{}",
-            jetstream_hostname, wanted_collections
-        );
+    /// Index only posts by feed users and the authors in `authors`. When a
+    /// feed user follows an author nobody else does, their last
+    /// `posts_per_user` posts are backfilled to fill the gap, by a few tasks
+    /// in `tasks` that stop on shutdown.
+    pub fn with_followed_only(
+        mut self,
+        authors: Arc<FollowedAuthors>,
+        budget: Arc<ApiBudget>,
+        posts_per_user: usize,
+        tasks: &mut JoinSet<()>,
+        shutdown: ShutdownCoordinator,
+    ) -> Self {
+        let (backfills, receiver) = mpsc::channel::<String>(FOLLOW_BACKFILL_QUEUE);
+        let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+        for _ in 0..FOLLOW_BACKFILL_WORKERS {
+            let receiver = Arc::clone(&receiver);
+            let db = Arc::clone(&self.db);
+            let budget = Arc::clone(&budget);
+            let identity = Arc::clone(&self.identity);
+            let shutdown = shutdown.clone();
+            self.tasks_spawned.fetch_add(1, Ordering::Relaxed);
+            tasks.spawn(async move {
+                loop {
+                    let target_did = tokio::select! {
+                        target_did = async { receiver.lock().await.recv().await } => target_did,
+                        _ = shutdown.wait() => None,
+                    };
+                    let Some(target_did) = target_did else {
+                        break;
+                    };
+                    info!("Backfilling posts from newly followed {}", target_did);
+                    let backfill = backfill::backfill_posts(
+                        Arc::clone(&db),
+                        Arc::clone(&budget),
+                        Arc::clone(&identity),
+                        &target_did,
+                        posts_per_user,
+                    );
+                    if let Err(e) = backfill.await {
+                        warn!("Post backfill failed for {}: {}", target_did, e);
+                    }
+                }
+            });
+        }
+        self.followed_only = Some(FollowedOnly { authors, backfills });
+        self
+    }
 
-        info!("Connecting to Jetstream at {}", ws_url);
+    /// Whether posts by this author are stored
+    fn indexes(&self, author_did: &str) -> bool {
+        self.followed_only
+            .as_ref()
+            .is_none_or(|followed| followed.authors.contains(author_did))
+    }
+
+    /// Starts a backfill of the followed author if this follow is the first
+    /// to bring them into someone's feed
+    fn note_follow(&self, follow: &Follow) {
+        let Some(followed) = &self.followed_only else {
+            return;
+        };
+        if !followed.authors.add_follow(follow) {
+            return;
+        }
+        if followed
+            .backfills
+            .try_send(follow.target_did.clone())
+            .is_err()
+        {
+            debug!(
+                "Too many backfills queued, skipping newly followed {}",
+                follow.target_did
+            );
+        }
+    }
+
+    /// Starts the database writers. Each gathers events for up to
+    /// `flush_interval` or until it has `batch_size` of them, then writes the
+    /// creates among them in one transaction per table. The queues hold
+    /// `capacity` events between them.
+    pub fn spawn_writers(
+        &self,
+        capacity: usize,
+        batch_size: usize,
+        flush_interval: Duration,
+    ) -> IngestQueue {
+        let metrics = Arc::new(IngestMetrics::default());
+        let batch_size = batch_size.max(1);
+        let senders = (0..INGEST_WRITERS)
+            .map(|shard| {
+                let (sender, receiver) = mpsc::channel((capacity / INGEST_WRITERS).max(1));
+                self.tasks_spawned.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(self.clone().run_writer(
+                    shard,
+                    receiver,
+                    batch_size,
+                    flush_interval,
+                    Arc::clone(&metrics),
+                ));
+                sender
+            })
+            .collect();
+
+        IngestQueue { senders, metrics }
+    }
+
+    async fn run_writer(
+        self,
+        shard: usize,
+        mut receiver: mpsc::Receiver<JetstreamEvent>,
+        batch_size: usize,
+        flush_interval: Duration,
+        metrics: Arc<IngestMetrics>,
+    ) {
+        let mut batch = Vec::with_capacity(batch_size);
+        while receiver.recv_many(&mut batch, batch_size).await > 0 {
+            let deadline = tokio::time::Instant::now() + flush_interval;
+            while batch.len() < batch_size {
+                let wanted = batch_size - batch.len();
+                match tokio::time::timeout_at(deadline, receiver.recv_many(&mut batch, wanted))
+                    .await
+                {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+            let count = batch.len() as u64;
+            let newest_us = batch.iter().map(JetstreamEvent::time_us).max();
+            self.write_batch(std::mem::take(&mut batch), &metrics).await;
+            metrics.record_done(shard, count, newest_us.unwrap_or_default());
+        }
+    }
+
+    /// Applies events in order, writing runs of creates as batches
+    async fn write_batch(&self, events: Vec<JetstreamEvent>, metrics: &IngestMetrics) {
+        let mut posts = Vec::new();
+        let mut follows = Vec::new();
+        // Commits in `posts` and `follows`, remembered once they are written
+        let mut pending = Vec::new();
+
+        for event in events {
+            let key = event.commit_key();
+            if let Some(key) = &key {
+                if self.seen.lock().unwrap().contains(key) || pending.contains(key) {
+                    metrics.written.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            }
+            match IngestOp::from(event) {
+                IngestOp::InsertPost(post) if !self.indexes(&post.author_did) => {
+                    self.remember(key);
+                    metrics.written.fetch_add(1, Ordering::Relaxed);
+                }
+                IngestOp::InsertPost(post) => {
+                    pending.extend(key);
+                    posts.push(post);
+                }
+                IngestOp::InsertFollow(follow) => {
+                    self.note_follow(&follow);
+                    pending.extend(key);
+                    follows.push(follow);
+                }
+                IngestOp::Skip => {
+                    self.remember(key);
+                    metrics.written.fetch_add(1, Ordering::Relaxed);
+                }
+                IngestOp::Handle(event) => {
+                    // A delete must not overtake the create it undoes
+                    self.flush(&mut posts, &mut follows, &mut pending, metrics)
+                        .await;
+                    match self.handle_event(event).await {
+                        Ok(()) => {
+                            self.remember(key);
+                            metrics.written.fetch_add(1, Ordering::Relaxed)
+                        }
+                        Err(e) => {
+                            error!("Error handling event: {}", e);
+                            metrics.failed.fetch_add(1, Ordering::Relaxed)
+                        }
+                    };
+                }
+            }
+        }
+
+        self.flush(&mut posts, &mut follows, &mut pending, metrics)
+            .await;
+        metrics.batches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Marks a commit as applied, so a replay of it is skipped
+    fn remember(&self, key: Option<String>) {
+        if let Some(key) = key {
+            self.seen.lock().unwrap().insert(key);
+        }
+    }
+
+    /// Writes the gathered creates. Their commits are remembered only if
+    /// everything was written, so a replay can retry a failed write.
+    async fn flush(
+        &self,
+        posts: &mut Vec<Post>,
+        follows: &mut Vec<Follow>,
+        pending: &mut Vec<String>,
+        metrics: &IngestMetrics,
+    ) {
+        let mut written = true;
+        if !posts.is_empty() {
+            let count = posts.len() as u64;
+            match self.db.insert_posts_batch(posts).await {
+                Ok(()) => metrics.written.fetch_add(count, Ordering::Relaxed),
+                Err(e) => {
+                    error!("Failed to insert {} posts: {}", count, e);
+                    written = false;
+                    metrics.failed.fetch_add(count, Ordering::Relaxed)
+                }
+            };
+            posts.clear();
+        }
+        if !follows.is_empty() {
+            let count = follows.len() as u64;
+            match self.db.insert_follows_batch(follows).await {
+                Ok(()) => metrics.written.fetch_add(count, Ordering::Relaxed),
+                Err(e) => {
+                    error!("Failed to insert {} follows: {}", count, e);
+                    written = false;
+                    metrics.failed.fetch_add(count, Ordering::Relaxed)
+                }
+            };
+            follows.clear();
+        }
+        for key in pending.drain(..) {
+            if written {
+                self.remember(Some(key));
+            }
+        }
+    }
+
+    /// Where a new connection should start: a little before the newest event
+    /// this process has written, or else the cursor saved by the last one
+    async fn resume_cursor(&self, queue: &IngestQueue) -> Result<Option<i64>> {
+        let cursor = match queue.metrics.written_us() {
+            Some(cursor) => Some(cursor),
+            None => self.db.get_jetstream_cursor().await?,
+        };
+        Ok(cursor.map(|cursor| cursor - CURSOR_REWIND_US))
+    }
+
+    /// Reads Jetstream until the ingest writers stop, reconnecting with
+    /// backoff whenever the connection fails or drops. After
+    /// `failover_after` failures in a row it moves on to the next hostname.
+    /// Spawns no tasks, so reconnecting leaves nothing behind; periodic
+    /// maintenance is scheduled once, by `main`.
+    pub async fn start(
+        &self,
+        hostnames: &[String],
+        collections: Vec<String>,
+        failover_after: u32,
+        queue: &IngestQueue,
+    ) -> Result<()> {
+        let backoff = ReconnectBackoff::new(RECONNECT_BASE, RECONNECT_CAP);
+        self.reconnect_with(hostnames, collections, failover_after, queue, backoff)
+            .await
+    }
+
+    /// `start`, waiting between attempts as `backoff` says
+    async fn reconnect_with(
+        &self,
+        hostnames: &[String],
+        collections: Vec<String>,
+        failover_after: u32,
+        queue: &IngestQueue,
+        mut backoff: ReconnectBackoff,
+    ) -> Result<()> {
+        let mut last_saved = std::time::Instant::now();
+        let mut host = 0;
 
         loop {
-            match tokio_tungstenite::connect_async(&ws_url).await {
-                Ok((mut socket, _response)) => {
-                    info!("Connected to Jetstream successfully");
-
-                    while let Some(msg) = socket.next().await {
-                        match msg {
-                            Ok(Message::Text(text)) => {
-                                if let Err(e) = self.handle_message(&text).await {
-                                    error!("Error handling message: {}", e);
-                                }
-                            }
-                            Ok(Message::Close(_)) => {
-                                warn!("Jetstream connection closed");
-                                break;
-                            }
-                            Err(e) => {
-                                error!("WebSocket error: {}", e);
-                                break;
+            let jetstream_hostname = &hostnames[host % hostnames.len()];
+            // Only a connection that delivered events counts as recovered
+            if self
+                .consume(jetstream_hostname, &collections, queue, &mut last_saved)
+                .await?
+            {
+                backoff.reset();
+            }
+
+            let delay = backoff.next_delay();
+            queue
+                .metrics
+                .connection_failures
+                .store(backoff.failures, Ordering::Relaxed);
+            if hostnames.len() > 1 && backoff.failures.is_multiple_of(failover_after.max(1)) {
+                host += 1;
+                warn!(
+                    "Switching to Jetstream at {} after {} failures in a row",
+                    hostnames[host % hostnames.len()],
+                    backoff.failures
+                );
+            }
+            warn!(
+                failures = backoff.failures,
+                "Reconnecting to Jetstream in {:.1}s",
+                delay.as_secs_f64()
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Queues events from one Jetstream connection until it ends, returning
+    /// whether any arrived. Fails only when the ingest writers have stopped.
+    async fn consume(
+        &self,
+        jetstream_hostname: &str,
+        collections: &[String],
+        queue: &IngestQueue,
+        last_saved: &mut std::time::Instant,
+    ) -> Result<bool> {
+        let cursor = match self.resume_cursor(queue).await {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                error!("Failed to read the saved Jetstream cursor: {}", e);
+                return Ok(false);
+            }
+        };
+        let ws_url = subscribe_url(jetstream_hostname, collections, cursor);
+        info!("Connecting to Jetstream at {}", ws_url);
+
+        let mut socket = match tokio_tungstenite::connect_async(&ws_url).await {
+            Ok((socket, _response)) => socket,
+            Err(e) => {
+                error!("Failed to connect to Jetstream: {}", e);
+                return Ok(false);
+            }
+        };
+        info!("Connected to Jetstream successfully");
+
+        let mut received = false;
+        while let Some(msg) = socket.next().await {
+            match msg {
+                Ok(Message::Text(text)) => {
+                    if !received {
+                        received = true;
+                        queue
+                            .metrics
+                            .connection_failures
+                            .store(0, Ordering::Relaxed);
+                    }
+                    match serde_json::from_str::<JetstreamEvent>(&text) {
+                        Ok(event) => queue.push(event).await?,
+                        Err(e) => error!("Error parsing message: {}", e),
+                    }
+                    if last_saved.elapsed() >= CURSOR_SAVE_INTERVAL {
+                        *last_saved = std::time::Instant::now();
+                        if let Some(cursor) = queue.metrics.written_us() {
+                            if let Err(e) = self.db.set_jetstream_cursor(cursor).await {
+                                warn!("Failed to save Jetstream cursor: {}", e);
                             }
-                            _ => {}
                         }
                     }
                 }
+                Ok(Message::Close(_)) => {
+                    warn!("Jetstream connection closed");
+                    break;
+                }
                 Err(e) => {
-                    error!(
-                        "Failed to connect to Jetstream: {}. Reconnecting in 5 seconds...",
-                        e
-                    );
+                    error!("WebSocket error: {}", e);
+                    break;
                 }
+                _ => {}
             }
+        }
+        Ok(received)
+    }
+
+    /// Connects once and handles a single event, for the self-test
+    pub async fn receive_one(&self, jetstream_hostname: &str) -> Result<()> {
+        let (mut socket, _response) = tokio_tungstenite::connect_async(subscribe_url(
+            jetstream_hostname,
+            &SUPPORTED_COLLECTIONS,
+            None,
+        ))
+        .await?;
 
-            tokio::time::sleep(Duration::from_secs(5)).await;
+        while let Some(msg) = socket.next().await {
+            if let Message::Text(text) = msg? {
+                return self.handle_message(&text).await;
+            }
         }
+
+        anyhow::bail!("Jetstream closed the connection before sending an event")
     }
 
     async fn handle_message(&self, message: &str) -> Result<()> {
-        let event: JetstreamEvent = serde_json::from_str(message)?;
+        self.handle_event(serde_json::from_str(message)?).await
+    }
 
+    async fn handle_event(&self, event: JetstreamEvent) -> Result<()> {
         match event {
             JetstreamEvent::Commit { did, commit, .. } => {
                 debug!(
@@ -81,20 +929,107 @@ impl JetstreamEventHandler {
                     "app.bsky.feed.post" => {
                         self.handle_post_event(&did, &commit).await?;
                     }
+                    "app.bsky.feed.repost" => {
+                        self.handle_repost_event(&did, &commit);
+                    }
                     "app.bsky.graph.follow" => {
                         self.handle_follow_event(&did, &commit).await?;
                     }
+                    "app.bsky.graph.block" => {
+                        self.handle_block_event(&did, &commit).await?;
+                    }
+                    "app.bsky.actor.profile" => {
+                        self.handle_profile_event(&did, &commit).await?;
+                    }
                     _ => {}
                 }
             }
-            JetstreamEvent::Account { did, .. } => {
+            JetstreamEvent::Account { did, account, .. } => {
                 debug!("Received account event: did={}", did);
+                self.handle_account_event(&did, &account).await?;
             }
-            JetstreamEvent::Identity { did, .. } => {
+            JetstreamEvent::Identity { did, identity, .. } => {
                 debug!("Received identity event: did={}", did);
+                self.handle_identity_event(&did, &identity).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops the content of deleted accounts, and hides the posts of
+    /// deactivated or taken down ones until they come back
+    async fn handle_account_event(&self, did: &str, account: &serde_json::Value) -> Result<()> {
+        if account["active"].as_bool() == Some(true) {
+            let restored = self.db.reactivate_author(did).await?;
+            if restored > 0 {
+                info!(
+                    "Account {} is active again: restored {} posts",
+                    did, restored
+                );
             }
+            return Ok(());
+        }
+
+        let status = account["status"].as_str().unwrap_or("");
+        match status {
+            "deleted" => {
+                let (posts, follows) = self.db.remove_account_content(did).await?;
+                info!(
+                    "Account {} is deleted: removed {} posts and {} follows",
+                    did, posts, follows
+                );
+            }
+            "deactivated" | "takendown" => {
+                let hidden = self.db.deactivate_author(did).await?;
+                info!("Account {} is {}: hid {} posts", did, status, hidden);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// An identity event means the DID document changed, possibly because the
+    /// account moved to another PDS or rotated its signing key. Drop what we
+    /// cached so the next PDS-direct call or JWT re-resolves.
+    async fn handle_identity_event(&self, did: &str, identity: &serde_json::Value) -> Result<()> {
+        let cached = self.identity.contains(did);
+        if !cached && !self.db.is_tracked_did(did).await? {
+            return Ok(());
+        }
+
+        let previous_handle = self.identity.handle(did).await;
+        self.identity.invalidate(did).await;
+        let handle = identity.get("handle").and_then(|v| v.as_str());
+        if let Some(handle) = handle {
+            self.identity.set_handle(did, handle).await;
+            self.db.set_handle(did, handle).await?;
         }
 
+        info!(
+            "Identity changed for {} ({:?} -> {:?}), invalidated cached DID document and signing key",
+            did, previous_handle, handle
+        );
+        Ok(())
+    }
+
+    /// Keeps the display names of accounts we track. Profiles of the rest
+    /// of the network aren't stored.
+    async fn handle_profile_event(&self, did: &str, commit: &JetstreamCommit) -> Result<()> {
+        let Some(record) = &commit.record else {
+            return Ok(());
+        };
+        if commit.rkey != "self" || !self.db.is_tracked_did(did).await? {
+            return Ok(());
+        }
+        self.db
+            .upsert_profile(
+                did,
+                record["handle"].as_str(),
+                record["displayName"].as_str(),
+            )
+            .await?;
+        debug!("Updated profile of {}", did);
         Ok(())
     }
 
@@ -103,39 +1038,7 @@ impl JetstreamEventHandler {
 
         match commit.operation.as_str() {
             "create" => {
-                if let Some(record) = &commit.record {
-                    // Check if this is a repost by looking for a "subject" field
-                    if record.get("subject").is_some() {
-                        // This is a repost, skip it
-                        return Ok(());
-                    }
-
-                    let text = record
-                        .get("text")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .to_string();
-
-                    let created_at_str = record
-                        .get("createdAt")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("");
-
-                    let created_at = DateTime::parse_from_rfc3339(created_at_str)
-                        .unwrap_or_else(|_| Utc::now().into())
-                        .with_timezone(&Utc);
-
-                    let cid = commit.cid.as_ref().unwrap_or(&String::new()).clone();
-
-                    let post = Post {
-                        uri: uri.clone(),
-                        cid,
-                        author_did: did.to_string(),
-                        text: text.clone(),
-                        created_at,
-                        indexed_at: Utc::now(),
-                    };
-
+                if let Some(post) = post_from_commit(did, commit).filter(|_| self.indexes(did)) {
                     if let Err(e) = self.db.insert_post(&post).await {
                         error!("Failed to insert post: {}", e);
                     } else {
@@ -143,6 +1046,15 @@ impl JetstreamEventHandler {
                     }
                 }
             }
+            "update" => {
+                if let Some(post) = post_from_commit(did, commit) {
+                    match self.db.update_post(&post).await {
+                        Ok(true) => debug!("Updated post: {}", uri),
+                        Ok(false) => debug!("Ignored update of unknown post: {}", uri),
+                        Err(e) => error!("Failed to update post: {}", e),
+                    }
+                }
+            }
             "delete" => {
                 if let Err(e) = self.db.delete_post(&uri).await {
                     error!("Failed to delete post: {}", e);
@@ -150,66 +1062,184 @@ impl JetstreamEventHandler {
                     debug!("Deleted post: {}", uri);
                 }
             }
-            _ => {} // Ignore updates
+            _ => {}
         }
 
         Ok(())
     }
 
+    /// Reposts live in their own collection and never make it into a feed,
+    /// so they're dropped here
+    fn handle_repost_event(&self, did: &str, commit: &JetstreamCommit) {
+        debug!(
+            "Dropped repost {}: at://{}/{}/{}",
+            commit.operation, did, commit.collection, commit.rkey
+        );
+    }
+
     async fn handle_follow_event(&self, did: &str, commit: &JetstreamCommit) -> Result<()> {
         let uri = format!("at://{}/{}/{}", did, commit.collection, commit.rkey);
 
         match commit.operation.as_str() {
             "create" => {
-                if let Some(record) = &commit.record {
-                    let target_did = record
-                        .get("subject")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .to_string();
-
-                    let created_at_str = record
-                        .get("createdAt")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("");
-
-                    let created_at = DateTime::parse_from_rfc3339(created_at_str)
-                        .unwrap_or_else(|_| Utc::now().into())
-                        .with_timezone(&Utc);
-
-                    let follow = Follow {
-                        uri: uri.clone(),
-                        follower_did: did.to_string(),
-                        target_did: target_did.clone(),
-                        created_at,
-                        indexed_at: Utc::now(),
-                    };
-
+                if let Some(follow) = follow_from_commit(did, commit) {
+                    self.note_follow(&follow);
                     if let Err(e) = self.db.insert_follow(&follow).await {
                         error!("Failed to insert follow: {}", e);
                     } else {
-                        debug!("Inserted follow: {} -> {}", did, target_did);
+                        debug!("Inserted follow: {} -> {}", did, follow.target_did);
                     }
                 }
             }
-            "delete" => {
-                if let Err(e) = self.db.delete_follow(&uri).await {
-                    error!("Failed to delete follow: {}", e);
+            "delete" => match self.db.delete_follow(&uri).await {
+                Ok(true) => debug!("Deleted follow: {}", uri),
+                // Follows backfilled from the AppView have made-up URIs, and a
+                // delete doesn't say who was unfollowed, so the user's next
+                // follow sync re-fetches their whole list instead
+                Ok(false) => {
+                    if let Err(e) = self.db.clear_follows_cursor(did).await {
+                        error!("Failed to reset follow sync for {}: {}", did, e);
+                    }
+                }
+                Err(e) => error!("Failed to delete follow: {}", e),
+            },
+            _ => {} // Ignore updates
+        }
+
+        Ok(())
+    }
+
+    /// Keeps the blocks of feed users current, so an unblocked account shows
+    /// up in their feed again. Blocks by anyone else aren't stored.
+    async fn handle_block_event(&self, did: &str, commit: &JetstreamCommit) -> Result<()> {
+        let uri = format!("at://{}/{}/{}", did, commit.collection, commit.rkey);
+
+        match commit.operation.as_str() {
+            "create" => {
+                let Some(block) = block_from_commit(did, commit) else {
+                    return Ok(());
+                };
+                if !self.db.has_follows(did).await? {
+                    return Ok(());
+                }
+                if let Err(e) = self.db.insert_blocks(std::slice::from_ref(&block)).await {
+                    error!("Failed to insert block: {}", e);
                 } else {
-                    debug!("Deleted follow: {}", uri);
+                    debug!("Inserted block: {} -> {}", did, block.target_did);
                 }
             }
-            _ => {} // Ignore updates
+            "delete" => match self.db.delete_block(&uri).await {
+                Ok(true) => debug!("Deleted block: {}", uri),
+                Ok(false) => {}
+                Err(e) => error!("Failed to delete block: {}", e),
+            },
+            _ => {}
         }
 
         Ok(())
     }
 }
 
+fn parse_created_at(record: &serde_json::Value) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(record["createdAt"].as_str()?)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+fn record_created_at(record: &serde_json::Value) -> DateTime<Utc> {
+    parse_created_at(record).unwrap_or_else(Utc::now)
+}
+
+/// The post a create or update commit carries, or `None` if it has no record
+fn post_from_commit(did: &str, commit: &JetstreamCommit) -> Option<Post> {
+    let record = commit.record.as_ref()?;
+    Some(Post {
+        uri: format!("at://{}/{}/{}", did, commit.collection, commit.rkey),
+        cid: commit.cid.clone().unwrap_or_default(),
+        author_did: did.to_string(),
+        text: record["text"].as_str().unwrap_or("").to_string(),
+        reply_parent_uri: record["reply"]["parent"]["uri"]
+            .as_str()
+            .map(|s| s.to_string()),
+        reply_root_uri: record["reply"]["root"]["uri"]
+            .as_str()
+            .map(|s| s.to_string()),
+        quoted_uri: Post::quoted_uri_of(record),
+        is_link_only: Post::is_link_only_record(record),
+        has_media: Post::has_media_record(record),
+        embed_type: Post::embed_type_of(record),
+        hashtags: Post::hashtags_of(record),
+        created_at: record_created_at(record),
+        indexed_at: Utc::now(),
+    })
+}
+
+fn follow_from_commit(did: &str, commit: &JetstreamCommit) -> Option<Follow> {
+    let record = commit.record.as_ref()?;
+    Some(Follow {
+        uri: format!("at://{}/{}/{}", did, commit.collection, commit.rkey),
+        follower_did: did.to_string(),
+        target_did: record["subject"].as_str().unwrap_or("").to_string(),
+        created_at: record_created_at(record),
+        indexed_at: Utc::now(),
+    })
+}
+
+fn block_from_commit(did: &str, commit: &JetstreamCommit) -> Option<Block> {
+    let record = commit.record.as_ref()?;
+    let target_did = record["subject"].as_str().filter(|s| !s.is_empty())?;
+    Some(Block {
+        uri: format!("at://{}/{}/{}", did, commit.collection, commit.rkey),
+        blocker_did: did.to_string(),
+        target_did: target_did.to_string(),
+        created_at: record_created_at(record),
+    })
+}
+
+/// How a writer applies an event: creates are batched, reposts are dropped,
+/// and everything else is handled one event at a time
+enum IngestOp {
+    InsertPost(Post),
+    InsertFollow(Follow),
+    Skip,
+    Handle(JetstreamEvent),
+}
+
+impl From<JetstreamEvent> for IngestOp {
+    fn from(event: JetstreamEvent) -> Self {
+        if let JetstreamEvent::Commit { did, commit, .. } = &event {
+            // Handling these one at a time would flush the batch around each
+            if commit.collection == "app.bsky.feed.repost" {
+                return IngestOp::Skip;
+            }
+            if commit.operation == "create" {
+                match commit.collection.as_str() {
+                    "app.bsky.feed.post" => {
+                        if let Some(post) = post_from_commit(did, commit) {
+                            return IngestOp::InsertPost(post);
+                        }
+                    }
+                    "app.bsky.graph.follow" => {
+                        if let Some(follow) = follow_from_commit(did, commit) {
+                            return IngestOp::InsertFollow(follow);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        IngestOp::Handle(event)
+    }
+}
+
 impl Clone for JetstreamEventHandler {
     fn clone(&self) -> Self {
         Self {
             db: Arc::clone(&self.db),
+            identity: Arc::clone(&self.identity),
+            followed_only: self.followed_only.clone(),
+            seen: Arc::clone(&self.seen),
+            tasks_spawned: Arc::clone(&self.tasks_spawned),
         }
     }
 }
@@ -237,6 +1267,36 @@ enum JetstreamEvent {
     },
 }
 
+impl JetstreamEvent {
+    fn did(&self) -> &str {
+        match self {
+            JetstreamEvent::Commit { did, .. }
+            | JetstreamEvent::Account { did, .. }
+            | JetstreamEvent::Identity { did, .. } => did,
+        }
+    }
+
+    /// Identifies a commit operation across replays: the repo revision and
+    /// the record it touched
+    fn commit_key(&self) -> Option<String> {
+        match self {
+            JetstreamEvent::Commit { did, commit, .. } => Some(format!(
+                "{}/{}/{}@{}",
+                did, commit.collection, commit.rkey, commit.rev
+            )),
+            _ => None,
+        }
+    }
+
+    fn time_us(&self) -> i64 {
+        match self {
+            JetstreamEvent::Commit { time_us, .. }
+            | JetstreamEvent::Account { time_us, .. }
+            | JetstreamEvent::Identity { time_us, .. } => *time_us,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 struct JetstreamCommit {
     rev: String,
@@ -248,3 +1308,1158 @@ struct JetstreamCommit {
     #[serde(skip_serializing_if = "Option::is_none")]
     cid: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::Uri, Json, Router};
+    use sqlx::Row;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct MockNetwork {
+        current_pds: Arc<Mutex<String>>,
+        pds_hits: Arc<Mutex<Vec<String>>>,
+        records: Arc<Mutex<Vec<serde_json::Value>>>,
+    }
+
+    /// Serves a PLC directory whose DID documents point at `/<current_pds>`,
+    /// and records which PDS prefix each listRecords call hit. Every
+    /// listRecords call returns `records`.
+    async fn spawn_mock_network(network: MockNetwork) -> Result<String> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base = format!("http://{}", listener.local_addr()?);
+        let doc_base = base.clone();
+
+        let app = Router::new()
+            .fallback(move |State(network): State<MockNetwork>, uri: Uri| {
+                let doc_base = doc_base.clone();
+                async move {
+                    let path = uri.path().trim_start_matches('/').to_string();
+                    if path.starts_with("did:") {
+                        let did = path.clone();
+                        let pds = network.current_pds.lock().unwrap().clone();
+                        return Json(serde_json::json!({
+                            "id": did,
+                            "service": [{
+                                "id": "#atproto_pds",
+                                "type": "AtprotoPersonalDataServer",
+                                "serviceEndpoint": format!("{}/{}", doc_base, pds),
+                            }]
+                        }));
+                    }
+
+                    let pds = path.split('/').next().unwrap_or_default().to_string();
+                    network.pds_hits.lock().unwrap().push(pds);
+                    let records = network.records.lock().unwrap().clone();
+                    Json(serde_json::json!({ "records": records }))
+                }
+            })
+            .with_state(network);
+
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        Ok(base)
+    }
+
+    #[tokio::test]
+    async fn test_identity_event_evicts_signing_key() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let identity = Arc::new(IdentityCache::default());
+        let handler = JetstreamEventHandler::new(Arc::clone(&db), Arc::clone(&identity));
+
+        // A feed user we only know from their JWTs rotates their key
+        let user_did = "did:plc:ewvi7nxzyoun6qbstvfqxa3j";
+        identity
+            .set_signing_key(user_did, "did:key:zOldKey".to_string())
+            .await;
+        assert!(identity.contains(user_did));
+
+        let event = serde_json::json!({
+            "did": user_did,
+            "time_us": 1,
+            "kind": "identity",
+            "identity": { "did": user_did, "seq": 1 },
+        });
+        handler.handle_message(&event.to_string()).await?;
+
+        assert!(!identity.contains(user_did));
+        assert_eq!(identity.signing_key(user_did).await, None);
+
+        // The next JWT from them resolves the new key
+        let resolved = std::sync::atomic::AtomicUsize::new(0);
+        identity
+            .verify_with_signing_key(
+                user_did,
+                || {
+                    resolved.fetch_add(1, Ordering::SeqCst);
+                    async { Ok("did:key:zNewKey".to_string()) }
+                },
+                |did_key| {
+                    anyhow::ensure!(did_key == "did:key:zNewKey", "Invalid signature");
+                    Ok(())
+                },
+            )
+            .await?;
+        assert_eq!(resolved.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_identity_events_store_handles() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        db.insert_follow(&Follow {
+            uri: "at://did:example:alice/app.bsky.graph.follow/1".to_string(),
+            follower_did: "did:example:alice".to_string(),
+            target_did: "did:example:bob".to_string(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await?;
+        let handler = JetstreamEventHandler::new(Arc::clone(&db), Arc::default());
+        let identity_event = |did: &str, handle: &str| {
+            serde_json::json!({
+                "did": did,
+                "time_us": 1,
+                "kind": "identity",
+                "identity": { "did": did, "handle": handle, "seq": 1 },
+            })
+            .to_string()
+        };
+
+        handler
+            .handle_message(&identity_event("did:example:bob", "bob.example.com"))
+            .await?;
+        assert_eq!(
+            db.describe_did("did:example:bob").await,
+            "@bob.example.com (did:example:bob)"
+        );
+
+        // A new handle replaces the old one; untracked accounts aren't stored
+        handler
+            .handle_message(&identity_event("did:example:bob", "robert.example.com"))
+            .await?;
+        handler
+            .handle_message(&identity_event("did:example:zed", "zed.example.com"))
+            .await?;
+        assert_eq!(
+            db.get_handle("did:example:bob").await?.as_deref(),
+            Some("robert.example.com")
+        );
+        assert_eq!(db.get_handle("did:example:zed").await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_profile_events_store_display_names() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        db.insert_follow(&Follow {
+            uri: "at://did:example:alice/app.bsky.graph.follow/1".to_string(),
+            follower_did: "did:example:alice".to_string(),
+            target_did: "did:example:bob".to_string(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await?;
+        let handler = JetstreamEventHandler::new(Arc::clone(&db), Arc::default());
+        for did in ["did:example:bob", "did:example:zed"] {
+            let event = serde_json::json!({
+                "did": did,
+                "time_us": 1,
+                "kind": "commit",
+                "commit": {
+                    "rev": "1",
+                    "operation": "create",
+                    "collection": "app.bsky.actor.profile",
+                    "rkey": "self",
+                    "cid": "cid",
+                    "record": { "displayName": "Bob" }
+                }
+            });
+            handler.handle_message(&event.to_string()).await?;
+        }
+
+        let profile = db.get_profile("did:example:bob").await?.unwrap();
+        assert_eq!(profile.display_name.as_deref(), Some("Bob"));
+        assert_eq!(profile.handle, None);
+        assert_eq!(db.get_profile("did:example:zed").await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_identity_event_invalidates_pds_endpoint() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        let author_did = "did:plc:migrating";
+        db.insert_follow(&Follow {
+            uri: "at://did:example:alice/app.bsky.graph.follow/1".to_string(),
+            follower_did: "did:example:alice".to_string(),
+            target_did: author_did.to_string(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await?;
+
+        let network = MockNetwork::default();
+        *network.current_pds.lock().unwrap() = "pds-a".to_string();
+        let base = spawn_mock_network(network.clone()).await?;
+
+        let identity = Arc::new(IdentityCache::new(&base));
+        let handler = JetstreamEventHandler::new(Arc::clone(&db), Arc::clone(&identity));
+
+        identity
+            .list_records(author_did, "app.bsky.feed.post", 10)
+            .await?;
+
+        // The account moves, but we keep using the cached endpoint until told otherwise
+        *network.current_pds.lock().unwrap() = "pds-b".to_string();
+        identity
+            .list_records(author_did, "app.bsky.feed.post", 10)
+            .await?;
+        assert_eq!(*network.pds_hits.lock().unwrap(), vec!["pds-a", "pds-a"]);
+
+        let event = serde_json::json!({
+            "did": author_did,
+            "time_us": 1,
+            "kind": "identity",
+            "identity": { "did": author_did, "handle": "moved.example.com", "seq": 1 },
+        });
+        handler.handle_message(&event.to_string()).await?;
+
+        assert!(!identity.contains(author_did));
+        assert_eq!(
+            identity.handle(author_did).await.as_deref(),
+            Some("moved.example.com")
+        );
+
+        identity
+            .list_records(author_did, "app.bsky.feed.post", 10)
+            .await?;
+        assert_eq!(
+            *network.pds_hits.lock().unwrap(),
+            vec!["pds-a", "pds-a", "pds-b"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unfollow_after_backfill() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        let network = MockNetwork::default();
+        *network.current_pds.lock().unwrap() = "pds".to_string();
+        *network.records.lock().unwrap() = vec![serde_json::json!({
+            "uri": "at://did:example:alice/app.bsky.graph.follow/3kreal",
+            "value": { "subject": "did:example:bob", "createdAt": "2024-01-01T00:00:00Z" },
+        })];
+        let base = spawn_mock_network(network).await?;
+        let identity = Arc::new(IdentityCache::new(&base));
+
+        crate::backfill::backfill_follows(
+            Arc::clone(&db),
+            Arc::new(crate::api_budget::ApiBudget::default()),
+            Arc::clone(&identity),
+            "did:example:alice",
+            crate::backfill::BackfillLimits::default(),
+            None,
+        )
+        .await?;
+        assert!(db.has_follows("did:example:alice").await?);
+
+        let handler = JetstreamEventHandler::new(Arc::clone(&db), identity);
+        let event = serde_json::json!({
+            "did": "did:example:alice",
+            "time_us": 1,
+            "kind": "commit",
+            "commit": {
+                "rev": "1",
+                "operation": "delete",
+                "collection": "app.bsky.graph.follow",
+                "rkey": "3kreal",
+            },
+        });
+        handler.handle_message(&event.to_string()).await?;
+        assert!(!db.has_follows("did:example:alice").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_block_events_of_feed_users() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        db.insert_follow(&Follow {
+            uri: "at://did:example:alice/app.bsky.graph.follow/1".to_string(),
+            follower_did: "did:example:alice".to_string(),
+            target_did: "did:example:bob".to_string(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await?;
+
+        let handler = JetstreamEventHandler::new(Arc::clone(&db), Arc::default());
+        let block = |did: &str, operation: &str| {
+            serde_json::json!({
+                "did": did,
+                "time_us": 1,
+                "kind": "commit",
+                "commit": {
+                    "rev": "1",
+                    "operation": operation,
+                    "collection": "app.bsky.graph.block",
+                    "rkey": "3kblock",
+                    "record": { "subject": "did:example:bob", "createdAt": "2024-01-01T00:00:00Z" },
+                },
+            })
+            .to_string()
+        };
+
+        // Blocks by someone we store no follows for are ignored
+        handler
+            .handle_message(&block("did:example:carol", "create"))
+            .await?;
+        assert!(!db.has_blocks("did:example:carol").await?);
+
+        handler
+            .handle_message(&block("did:example:alice", "create"))
+            .await?;
+        assert!(db.has_blocks("did:example:alice").await?);
+
+        // Unblocking lets the account back into the feed
+        handler
+            .handle_message(&block("did:example:alice", "delete"))
+            .await?;
+        assert!(!db.has_blocks("did:example:alice").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unmatched_unfollow_resets_follow_sync() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        db.record_feed_request("did:example:alice").await?;
+        db.set_follows_cursor("did:example:alice", "cursor").await?;
+        // As stored by the AppView fallback, under a URI no event will name
+        db.insert_follow(&Follow {
+            uri: format!(
+                "at://did:example:alice/app.bsky.graph.follow/{}",
+                uuid::Uuid::new_v4()
+            ),
+            follower_did: "did:example:alice".to_string(),
+            target_did: "did:example:bob".to_string(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await?;
+
+        let handler = JetstreamEventHandler::new(Arc::clone(&db), Arc::default());
+        let event = serde_json::json!({
+            "did": "did:example:alice",
+            "time_us": 1,
+            "kind": "commit",
+            "commit": {
+                "rev": "1",
+                "operation": "delete",
+                "collection": "app.bsky.graph.follow",
+                "rkey": "3kreal",
+            },
+        });
+        handler.handle_message(&event.to_string()).await?;
+        assert_eq!(db.get_follows_cursor("did:example:alice").await?, None);
+
+        // The full re-fetch then drops the row
+        db.sync_follows_for_user("did:example:alice", Vec::new())
+            .await?;
+        assert!(!db.has_follows("did:example:alice").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backfill_stops_at_max_follows() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+
+        let network = MockNetwork::default();
+        *network.current_pds.lock().unwrap() = "pds".to_string();
+        *network.records.lock().unwrap() = (0..3)
+            .map(|i| {
+                serde_json::json!({
+                    "uri": format!("at://did:example:alice/app.bsky.graph.follow/{}", i),
+                    "value": { "subject": format!("did:example:{}", i) },
+                })
+            })
+            .collect();
+        let base = spawn_mock_network(network).await?;
+
+        crate::backfill::backfill_follows(
+            Arc::clone(&db),
+            Arc::new(crate::api_budget::ApiBudget::default()),
+            Arc::new(IdentityCache::new(&base)),
+            "did:example:alice",
+            crate::backfill::BackfillLimits {
+                posts_per_user: 1,
+                max_follows: 2,
+                ..Default::default()
+            },
+            None,
+        )
+        .await?;
+        assert_eq!(db.get_stats().await?.follows, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_account_events_remove_content() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let handler =
+            JetstreamEventHandler::new(Arc::clone(&db), Arc::new(IdentityCache::default()));
+
+        for did in [
+            "did:example:gone",
+            "did:example:resting",
+            "did:example:suspended",
+        ] {
+            db.insert_follow(&Follow {
+                uri: format!("at://{}/app.bsky.graph.follow/1", did),
+                follower_did: did.to_string(),
+                target_did: "did:example:bob".to_string(),
+                created_at: Utc::now(),
+                indexed_at: Utc::now(),
+            })
+            .await?;
+            db.insert_post(&Post {
+                uri: format!("at://{}/app.bsky.feed.post/1", did),
+                cid: "cid".to_string(),
+                author_did: did.to_string(),
+                text: "text".to_string(),
+                reply_parent_uri: None,
+                reply_root_uri: None,
+                quoted_uri: None,
+                is_link_only: false,
+                has_media: false,
+                embed_type: None,
+                hashtags: Vec::new(),
+                created_at: Utc::now(),
+                indexed_at: Utc::now(),
+            })
+            .await?;
+        }
+
+        let account_event = |did: &str, account: serde_json::Value| {
+            serde_json::json!({ "did": did, "time_us": 1, "kind": "account", "account": account })
+                .to_string()
+        };
+        handler
+            .handle_message(&account_event(
+                "did:example:gone",
+                serde_json::json!({ "active": false, "status": "deleted" }),
+            ))
+            .await?;
+        // Reactivation and statuses we don't act on leave content alone
+        handler
+            .handle_message(&account_event(
+                "did:example:resting",
+                serde_json::json!({ "active": true }),
+            ))
+            .await?;
+        handler
+            .handle_message(&account_event(
+                "did:example:suspended",
+                serde_json::json!({ "active": false, "status": "suspended" }),
+            ))
+            .await?;
+
+        let count = |table: &'static str, column: &'static str, did: &'static str| {
+            let db = Arc::clone(&db);
+            async move {
+                let count: i64 = sqlx::query(&format!(
+                    "SELECT COUNT(*) as count FROM {} WHERE {} = ?",
+                    table, column
+                ))
+                .bind(did)
+                .fetch_one(&db.pool)
+                .await?
+                .try_get("count")?;
+                Ok::<_, anyhow::Error>(count)
+            }
+        };
+        assert_eq!(count("posts", "author_did", "did:example:gone").await?, 0);
+        assert_eq!(
+            count("follows", "follower_did", "did:example:gone").await?,
+            0
+        );
+        for did in ["did:example:resting", "did:example:suspended"] {
+            assert_eq!(count("posts", "author_did", did).await?, 1);
+            assert_eq!(count("follows", "follower_did", did).await?, 1);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deactivated_accounts_come_back() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let handler =
+            JetstreamEventHandler::new(Arc::clone(&db), Arc::new(IdentityCache::default()));
+        let (alice, bob) = ("did:example:alice", "did:example:bob");
+        db.insert_follow(&Follow {
+            uri: "at://did:example:alice/app.bsky.graph.follow/1".to_string(),
+            follower_did: alice.to_string(),
+            target_did: bob.to_string(),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        })
+        .await?;
+        for rkey in ["kept", "deleted"] {
+            handler.handle_event(post_event(bob, rkey)).await?;
+        }
+        db.delete_post("at://did:example:bob/app.bsky.feed.post/deleted")
+            .await?;
+
+        let account_event = |account: serde_json::Value| {
+            serde_json::json!({ "did": bob, "time_us": 1, "kind": "account", "account": account })
+                .to_string()
+        };
+        let feed = || {
+            let db = Arc::clone(&db);
+            async move {
+                let posts = db
+                    .get_following_posts(alice, 10, None, &crate::types::FeedFilter::default())
+                    .await?;
+                anyhow::Ok(posts.into_iter().map(|p| p.uri).collect::<Vec<_>>())
+            }
+        };
+        assert_eq!(feed().await?.len(), 1);
+
+        handler
+            .handle_message(&account_event(
+                serde_json::json!({ "active": false, "status": "takendown" }),
+            ))
+            .await?;
+        assert!(feed().await?.is_empty());
+
+        // Only the posts the deactivation hid come back
+        handler
+            .handle_message(&account_event(serde_json::json!({ "active": true })))
+            .await?;
+        assert_eq!(
+            feed().await?,
+            ["at://did:example:bob/app.bsky.feed.post/kept"]
+        );
+        assert_eq!(db.reactivate_author(bob).await?, 0);
+
+        Ok(())
+    }
+
+    fn post_event(did: &str, rkey: &str) -> JetstreamEvent {
+        serde_json::from_value(serde_json::json!({
+            "did": did,
+            "time_us": 1,
+            "kind": "commit",
+            "commit": {
+                "rev": "rev",
+                "operation": "create",
+                "collection": "app.bsky.feed.post",
+                "rkey": rkey,
+                "cid": "cid",
+                "record": { "text": "hello", "createdAt": "2024-01-01T00:00:00Z" }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_configured_collections() {
+        let collections = vec![
+            "app.bsky.feed.post".to_string(),
+            "app.bsky.graph.follow".to_string(),
+            "app.bsky.graph.listitem".to_string(),
+        ];
+        assert_eq!(
+            subscribe_url("jetstream.example", &collections, None),
+            "wss://jetstream.example/subscribe?wantedCollections=app.bsky.feed.post\
+             &wantedCollections=app.bsky.graph.follow\
+             &wantedCollections=app.bsky.graph.listitem"
+        );
+
+        // Unknown collections are only warned about
+        assert_eq!(
+            check_collections(&collections),
+            vec!["app.bsky.graph.listitem"]
+        );
+        let defaults = SUPPORTED_COLLECTIONS.map(String::from);
+        assert!(check_collections(&defaults).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_spawn_no_tasks() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let handler =
+            JetstreamEventHandler::new(Arc::clone(&db), Arc::new(IdentityCache::default()));
+        let queue = handler.spawn_writers(8, 8, Duration::from_millis(20));
+        let failures = Arc::clone(&queue.metrics);
+        let spawned = Arc::clone(&handler.tasks_spawned);
+        let before = spawned.load(Ordering::Relaxed);
+        tokio::spawn(async move {
+            let hostnames = ["127.0.0.1:1".to_string()];
+            let backoff = ReconnectBackoff::new(Duration::from_millis(1), Duration::from_millis(5));
+            handler
+                .reconnect_with(&hostnames, vec![], DEFAULT_FAILOVER_AFTER, &queue, backoff)
+                .await
+        });
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while failures.connection_failures.load(Ordering::Relaxed) < 3 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await?;
+        assert_eq!(spawned.load(Ordering::Relaxed), before);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lag_window() {
+        let start = Instant::now();
+        let mut window = LagWindow {
+            started: start,
+            warn_above: Some(Duration::from_secs(10)),
+            ..LagWindow::default()
+        };
+        window.record(2_000_000, start);
+        assert_eq!(window.last, None);
+        window.record(4_000_000, start + LAG_WINDOW);
+        assert_eq!(
+            window.last,
+            Some(IngestLag {
+                average_ms: 3000,
+                max_ms: 4000
+            })
+        );
+        assert_eq!(window.behind_since, None);
+
+        // A window averaging over the threshold starts a stretch of lag,
+        // and one back under it ends the stretch
+        window.record(60_000_000, start + LAG_WINDOW * 2);
+        assert_eq!(window.behind_since, Some(start + LAG_WINDOW));
+        window.record(1_000_000, start + LAG_WINDOW * 3);
+        assert_eq!(window.behind_since, None);
+
+        // A window without events takes the lag of the newest event read
+        window.close(start + LAG_WINDOW * 4, Some(90_000_000));
+        assert_eq!(
+            window.last,
+            Some(IngestLag {
+                average_ms: 90_000,
+                max_ms: 90_000
+            })
+        );
+        assert_eq!(window.behind_since, Some(start + LAG_WINDOW * 3));
+
+        // Before any event, there is nothing to measure
+        let mut idle = LagWindow {
+            started: start,
+            ..LagWindow::default()
+        };
+        idle.close(start + LAG_WINDOW, None);
+        assert_eq!(idle.last, None);
+        assert_eq!(idle.started, start + LAG_WINDOW);
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let mut backoff = ReconnectBackoff::new(RECONNECT_BASE, RECONNECT_CAP);
+        let delays: Vec<Duration> = (0..20).map(|_| backoff.next_delay()).collect();
+        assert_eq!(backoff.failures, 20);
+        assert!(delays
+            .iter()
+            .all(|delay| (RECONNECT_BASE..=RECONNECT_CAP).contains(delay)));
+        // Each wait is at most three times the one before
+        assert!(delays.windows(2).all(|pair| pair[1] <= pair[0] * 3));
+
+        backoff.reset();
+        assert_eq!(backoff.failures, 0);
+        assert!(backoff.next_delay() <= RECONNECT_BASE * 3);
+    }
+
+    #[tokio::test]
+    async fn test_ingest_queue_applies_backpressure() -> Result<()> {
+        let (sender, mut receiver) = mpsc::channel(2);
+        let queue = IngestQueue {
+            senders: vec![sender],
+            metrics: Arc::default(),
+        };
+
+        queue.push(post_event("did:example:bob", "1")).await?;
+        queue.push(post_event("did:example:bob", "2")).await?;
+        assert_eq!(queue.depth(), 2);
+        assert!(queue.format_stats().contains("Depth: 2/2"));
+
+        // A full queue makes the reader wait instead of buffering more
+        let blocked = tokio::time::timeout(
+            Duration::from_millis(50),
+            queue.push(post_event("did:example:bob", "3")),
+        )
+        .await;
+        assert!(blocked.is_err());
+
+        receiver.recv().await;
+        queue.push(post_event("did:example:bob", "3")).await?;
+        assert_eq!(queue.depth(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ingest_writers_store_events() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let handler =
+            JetstreamEventHandler::new(Arc::clone(&db), Arc::new(IdentityCache::default()));
+        let queue = handler.spawn_writers(8, 3, Duration::from_millis(20));
+
+        for i in 0..10 {
+            let did = format!("did:example:{}", i % 3);
+            queue.push(post_event(&did, &i.to_string())).await?;
+        }
+
+        let written = tokio::time::timeout(Duration::from_secs(5), async {
+            while queue.metrics.written.load(Ordering::Relaxed) < 10 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(written.is_ok());
+        assert_eq!(queue.depth(), 0);
+
+        let count: i64 = sqlx::query("SELECT COUNT(*) as count FROM posts")
+            .fetch_one(&db.pool)
+            .await?
+            .try_get("count")?;
+        assert_eq!(count, 10);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_post_deletes_in_a_batch() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let handler =
+            JetstreamEventHandler::new(Arc::clone(&db), Arc::new(IdentityCache::default()));
+        let queue = handler.spawn_writers(8, 8, Duration::from_millis(20));
+
+        // Deletes arrive as commits; one in the same batch as its create
+        // still applies after it
+        queue.push(post_event("did:example:bob", "1")).await?;
+        queue
+            .push(serde_json::from_value(serde_json::json!({
+                "did": "did:example:bob",
+                "time_us": 2,
+                "kind": "commit",
+                "commit": {
+                    "rev": "rev2",
+                    "operation": "delete",
+                    "collection": "app.bsky.feed.post",
+                    "rkey": "1",
+                }
+            }))?)
+            .await?;
+        let written = tokio::time::timeout(Duration::from_secs(5), async {
+            while queue.metrics.written.load(Ordering::Relaxed) < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(written.is_ok());
+
+        let deleted_at: Option<i64> = sqlx::query("SELECT deleted_at FROM posts WHERE uri = ?")
+            .bind("at://did:example:bob/app.bsky.feed.post/1")
+            .fetch_one(&db.pool)
+            .await?
+            .try_get("deleted_at")?;
+        assert!(deleted_at.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replayed_commits_are_skipped() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let handler =
+            JetstreamEventHandler::new(Arc::clone(&db), Arc::new(IdentityCache::default()));
+        let queue = handler.spawn_writers(8, 8, Duration::from_millis(20));
+        let follow = |operation: &str, rev: &str| {
+            serde_json::from_value::<JetstreamEvent>(serde_json::json!({
+                "did": "did:example:alice",
+                "time_us": 1,
+                "kind": "commit",
+                "commit": {
+                    "rev": rev,
+                    "operation": operation,
+                    "collection": "app.bsky.graph.follow",
+                    "rkey": "1",
+                    "record": { "subject": "did:example:bob", "createdAt": "2024-01-01T00:00:00Z" }
+                }
+            }))
+        };
+
+        // A reconnect replays a follow that was since deleted
+        queue.push(follow("create", "rev1")?).await?;
+        queue.push(follow("delete", "rev2")?).await?;
+        queue.push(follow("create", "rev1")?).await?;
+        let written = tokio::time::timeout(Duration::from_secs(5), async {
+            while queue.metrics.written.load(Ordering::Relaxed) < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(written.is_ok());
+        assert!(!db.has_follows("did:example:alice").await?);
+
+        let mut seen = SeenCommits::default();
+        for i in 0..=SEEN_COMMITS {
+            assert!(seen.insert(i.to_string()));
+        }
+        assert!(!seen.insert(SEEN_COMMITS.to_string()));
+        // The oldest was forgotten to make room
+        assert!(seen.insert("0".to_string()));
+        assert_eq!(seen.order.len(), SEEN_COMMITS);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_writes_are_not_remembered() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let handler =
+            JetstreamEventHandler::new(Arc::clone(&db), Arc::new(IdentityCache::default()));
+        let metrics = IngestMetrics::default();
+        let follow = || {
+            serde_json::from_value::<JetstreamEvent>(serde_json::json!({
+                "did": "did:example:alice",
+                "time_us": 1,
+                "kind": "commit",
+                "commit": {
+                    "rev": "rev1",
+                    "operation": "create",
+                    "collection": "app.bsky.graph.follow",
+                    "rkey": "1",
+                    "record": { "subject": "did:example:bob", "createdAt": "2024-01-01T00:00:00Z" }
+                }
+            }))
+        };
+
+        // The write fails, so the replay after a reconnect is applied
+        sqlx::query("ALTER TABLE follows RENAME TO follows_moved")
+            .execute(&db.pool)
+            .await?;
+        handler.write_batch(vec![follow()?], &metrics).await;
+        assert_eq!(metrics.failed.load(Ordering::Relaxed), 1);
+        sqlx::query("ALTER TABLE follows_moved RENAME TO follows")
+            .execute(&db.pool)
+            .await?;
+
+        handler.write_batch(vec![follow()?], &metrics).await;
+        assert_eq!(metrics.written.load(Ordering::Relaxed), 1);
+        assert!(db.has_follows("did:example:alice").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume_cursor() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let handler =
+            JetstreamEventHandler::new(Arc::clone(&db), Arc::new(IdentityCache::default()));
+        let queue = handler.spawn_writers(8, 3, Duration::from_millis(20));
+
+        // A fresh install starts live
+        assert_eq!(handler.resume_cursor(&queue).await?, None);
+
+        // After a restart, a little before the saved cursor
+        db.set_jetstream_cursor(60_000_000).await?;
+        assert_eq!(db.get_jetstream_cursor().await?, Some(60_000_000));
+        assert_eq!(handler.resume_cursor(&queue).await?, Some(55_000_000));
+        assert!(subscribe_url(
+            "jetstream.example",
+            &SUPPORTED_COLLECTIONS,
+            Some(55_000_000)
+        )
+        .ends_with("&cursor=55000000"));
+
+        // After a reconnect, a little before the newest event written
+        let event = |time_us: i64| {
+            serde_json::from_value::<JetstreamEvent>(serde_json::json!({
+                "did": "did:example:bob",
+                "time_us": time_us,
+                "kind": "identity",
+                "identity": { "did": "did:example:bob", "seq": 1 },
+            }))
+        };
+        queue.push(event(90_000_000)?).await?;
+        let written = tokio::time::timeout(Duration::from_secs(5), async {
+            while queue.metrics.written_us().is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(written.is_ok());
+        assert_eq!(handler.resume_cursor(&queue).await?, Some(85_000_000));
+
+        // Events read but still queued don't move it
+        let (sender, _receiver) = mpsc::channel(8);
+        let stalled = IngestQueue {
+            senders: vec![sender; INGEST_WRITERS],
+            metrics: Arc::clone(&queue.metrics),
+        };
+        stalled.push(event(120_000_000)?).await?;
+        assert_eq!(stalled.stats().last_event_us, Some(120_000_000));
+        assert_eq!(handler.resume_cursor(&queue).await?, Some(85_000_000));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_post_updates_edit_stored_post() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let handler =
+            JetstreamEventHandler::new(Arc::clone(&db), Arc::new(IdentityCache::default()));
+        let update = |rkey: &str, record: serde_json::Value| {
+            serde_json::from_value::<JetstreamEvent>(serde_json::json!({
+                "did": "did:example:bob",
+                "time_us": 2,
+                "kind": "commit",
+                "commit": {
+                    "rev": "rev2",
+                    "operation": "update",
+                    "collection": "app.bsky.feed.post",
+                    "rkey": rkey,
+                    "cid": "cid2",
+                    "record": record
+                }
+            }))
+        };
+        let stored = |rkey: &str| {
+            let db = Arc::clone(&db);
+            let uri = format!("at://did:example:bob/app.bsky.feed.post/{}", rkey);
+            async move {
+                let row = sqlx::query(
+                    "SELECT cid, text, has_media, created_at, indexed_at FROM posts WHERE uri = ?",
+                )
+                .bind(uri)
+                .fetch_one(&db.pool)
+                .await?;
+                Ok::<_, anyhow::Error>((
+                    row.try_get::<String, _>("cid")?,
+                    row.try_get::<String, _>("text")?,
+                    row.try_get::<bool, _>("has_media")?,
+                    row.try_get::<i64, _>("created_at")?,
+                    row.try_get::<i64, _>("indexed_at")?,
+                ))
+            }
+        };
+        let original = 1_704_067_200_000_000;
+
+        handler
+            .handle_event(post_event("did:example:bob", "1"))
+            .await?;
+        let indexed_at = stored("1").await?.4;
+        handler
+            .handle_event(update(
+                "1",
+                serde_json::json!({
+                    "text": "hello, edited",
+                    "embed": { "$type": "app.bsky.embed.images", "images": [] }
+                }),
+            )?)
+            .await?;
+        assert_eq!(
+            stored("1").await?,
+            (
+                "cid2".to_string(),
+                "hello, edited".to_string(),
+                true,
+                original,
+                indexed_at
+            )
+        );
+
+        // A new createdAt doesn't move the post either
+        handler
+            .handle_event(update(
+                "1",
+                serde_json::json!({ "text": "again", "createdAt": "2024-01-02T00:00:00Z" }),
+            )?)
+            .await?;
+        assert_eq!(
+            stored("1").await?,
+            (
+                "cid2".to_string(),
+                "again".to_string(),
+                false,
+                original,
+                indexed_at
+            )
+        );
+
+        // An edit of a post we never stored isn't turned into one
+        handler
+            .handle_event(update("2", serde_json::json!({ "text": "unknown" }))?)
+            .await?;
+        assert_eq!(db.get_stats().await?.posts, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_quote_posts_kept_and_reposts_dropped() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        let handler =
+            JetstreamEventHandler::new(Arc::clone(&db), Arc::new(IdentityCache::default()));
+        assert!(
+            subscribe_url("jetstream.example", &SUPPORTED_COLLECTIONS, None)
+                .contains("app.bsky.feed.repost")
+        );
+
+        let commit = |collection: &str, rkey: &str, record: serde_json::Value| {
+            serde_json::from_value::<JetstreamEvent>(serde_json::json!({
+                "did": "did:example:bob",
+                "time_us": 1,
+                "kind": "commit",
+                "commit": {
+                    "rev": "rev",
+                    "operation": "create",
+                    "collection": collection,
+                    "rkey": rkey,
+                    "cid": "cid",
+                    "record": record
+                }
+            }))
+        };
+        let quoted = serde_json::json!({
+            "uri": "at://did:example:carol/app.bsky.feed.post/1",
+            "cid": "cid"
+        });
+
+        let quote = serde_json::json!({
+            "text": "look at this",
+            "createdAt": "2024-01-01T00:00:00Z",
+            "embed": { "$type": "app.bsky.embed.record", "record": quoted },
+            // Not part of the post lexicon, but no reason to drop the post
+            "subject": quoted
+        });
+        assert!(matches!(
+            IngestOp::from(commit("app.bsky.feed.post", "quote", quote.clone())?),
+            IngestOp::InsertPost(_)
+        ));
+        handler
+            .handle_event(commit("app.bsky.feed.post", "quote", quote)?)
+            .await?;
+
+        let repost = serde_json::json!({
+            "$type": "app.bsky.feed.repost",
+            "subject": quoted,
+            "createdAt": "2024-01-01T00:00:00Z"
+        });
+        assert!(matches!(
+            IngestOp::from(commit("app.bsky.feed.repost", "r", repost.clone())?),
+            IngestOp::Skip
+        ));
+        handler
+            .handle_event(commit("app.bsky.feed.repost", "r", repost)?)
+            .await?;
+
+        let uris: Vec<String> = sqlx::query("SELECT uri FROM posts")
+            .fetch_all(&db.pool)
+            .await?
+            .iter()
+            .map(|row| row.try_get("uri"))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(uris, vec!["at://did:example:bob/app.bsky.feed.post/quote"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_index_followed_only() -> Result<()> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        db.record_feed_request("did:example:alice").await?;
+        let follow = |target: &str| Follow {
+            uri: format!("at://did:example:alice/app.bsky.graph.follow/{}", target),
+            follower_did: "did:example:alice".to_string(),
+            target_did: format!("did:example:{}", target),
+            created_at: Utc::now(),
+            indexed_at: Utc::now(),
+        };
+        db.insert_follow(&follow("carol")).await?;
+
+        let authors = Arc::new(FollowedAuthors::default());
+        assert_eq!(authors.reload(&db).await?, 1);
+        let handler = JetstreamEventHandler::new(Arc::clone(&db), Arc::default())
+            .with_followed_only(
+                Arc::clone(&authors),
+                Arc::default(),
+                1,
+                &mut JoinSet::new(),
+                ShutdownCoordinator::new(),
+            );
+
+        // The feed user's own posts are kept too, for self posts in feeds
+        for did in ["did:example:carol", "did:example:dave", "did:example:alice"] {
+            let event = serde_json::json!({
+                "did": did,
+                "time_us": 1,
+                "kind": "commit",
+                "commit": {
+                    "rev": "rev",
+                    "operation": "create",
+                    "collection": "app.bsky.feed.post",
+                    "rkey": "1",
+                    "cid": "cid",
+                    "record": { "text": "hello", "createdAt": "2024-01-01T00:00:00Z" }
+                }
+            });
+            handler.handle_message(&event.to_string()).await?;
+        }
+        let count: i64 = sqlx::query("SELECT COUNT(*) as count FROM posts")
+            .fetch_one(&db.pool)
+            .await?
+            .try_get("count")?;
+        assert_eq!(count, 2);
+
+        // A new follow by a feed user adds the author once; others are ignored
+        assert!(authors.add_follow(&follow("dave")));
+        assert!(!authors.add_follow(&follow("dave")));
+        assert!(authors.contains("did:example:dave"));
+        let mut stranger = follow("erin");
+        stranger.follower_did = "did:example:zed".to_string();
+        assert!(!authors.add_follow(&stranger));
+
+        Ok(())
+    }
+}
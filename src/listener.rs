@@ -0,0 +1,144 @@
+use anyhow::Result;
+use axum::Router;
+use std::fmt;
+use std::future::Future;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+pub const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:3000";
+
+/// Picks the address to serve on. An explicit listen address wins over a port,
+/// which is shorthand for listening on every interface.
+pub fn listen_addr(listen_addr: Option<&str>, port: Option<u16>) -> String {
+    match (listen_addr, port) {
+        (Some(addr), _) => addr.to_string(),
+        (None, Some(port)) => format!("0.0.0.0:{}", port),
+        (None, None) => DEFAULT_LISTEN_ADDR.to_string(),
+    }
+}
+
+fn is_socket_path(addr: &str) -> bool {
+    addr.starts_with('/') || addr.starts_with("./")
+}
+
+/// Where the main HTTP server accepts connections
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, String),
+}
+
+impl Listener {
+    /// Binds a `host:port` address, or a Unix socket for paths starting with
+    /// `/` or `./`
+    pub async fn bind(addr: &str) -> Result<Self> {
+        if !is_socket_path(addr) {
+            return Ok(Self::Tcp(TcpListener::bind(addr).await?));
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            // A socket left behind by a previous run would make bind fail,
+            // but anything else at the path is left alone
+            match std::fs::symlink_metadata(addr) {
+                Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(addr)?,
+                Ok(_) => anyhow::bail!("{} exists and is not a Unix socket", addr),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+            Ok(Self::Unix(UnixListener::bind(addr)?, addr.to_string()))
+        }
+        #[cfg(not(unix))]
+        anyhow::bail!("Unix socket listen addresses are not supported on this platform")
+    }
+
+    /// Serves until `shutdown` resolves, then lets in-flight requests finish
+    pub async fn serve<F>(self, app: Router, shutdown: F) -> Result<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        match self {
+            Self::Tcp(listener) => {
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown)
+                    .await?
+            }
+            #[cfg(unix)]
+            Self::Unix(listener, _) => {
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown)
+                    .await?
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{}", addr),
+                Err(_) => write!(f, "an unknown TCP address"),
+            },
+            #[cfg(unix)]
+            Self::Unix(_, path) => write!(f, "unix:{}", path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_listen_addr() {
+        assert_eq!(listen_addr(None, None), "0.0.0.0:3000");
+        assert_eq!(listen_addr(None, Some(8080)), "0.0.0.0:8080");
+        assert_eq!(
+            listen_addr(Some("127.0.0.1:9000"), Some(8080)),
+            "127.0.0.1:9000"
+        );
+        assert!(is_socket_path("/run/feed.sock"));
+        assert!(is_socket_path("./feed.sock"));
+        assert!(!is_socket_path("localhost:3000"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("feed-{}.sock", uuid::Uuid::new_v4()));
+        let path = path.to_string_lossy().to_string();
+        // Any other file at the path is left alone
+        std::fs::write(&path, b"data")?;
+        assert!(Listener::bind(&path).await.is_err());
+        assert_eq!(std::fs::read(&path)?, b"data");
+        std::fs::remove_file(&path)?;
+
+        // A socket left behind by a previous run is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path)?);
+
+        let listener = Listener::bind(&path).await?;
+        assert_eq!(listener.to_string(), format!("unix:{}", path));
+        let app = Router::new().route("/health", get(|| async { "OK" }));
+        tokio::spawn(listener.serve(app, std::future::pending()));
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await?;
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        let _ = std::fs::remove_file(&path);
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("OK"));
+
+        Ok(())
+    }
+}
@@ -1,31 +1,59 @@
 use anyhow::Result;
 use axum::{
     extract::{Query, State},
-    http::{HeaderMap, StatusCode},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware,
     response::{IntoResponse, Json, Response},
     routing::get,
     Router,
 };
-use clap::Parser;
-use sqlx::Row;
+use clap::{Parser, ValueEnum};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use tower_http::cors::CorsLayer;
-use tracing::{info, warn};
+use tokio::task::JoinSet;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tracing::{error, info, warn, Instrument};
 
+mod admin_http;
 mod admin_socket;
+mod api_budget;
 mod auth;
 mod backfill;
 mod cleanup;
 mod database;
+mod error;
 mod feed_algorithm;
+mod follow_cache;
+mod identity;
 mod jetstream_consumer;
+mod listener;
 mod publish;
+mod request_log;
+mod self_test;
+mod shutdown;
+mod stats;
 mod types;
 
 use crate::{
-    admin_socket::AdminSocket, auth::validate_jwt, database::Database,
-    feed_algorithm::FollowingNoRepostsFeed, jetstream_consumer::JetstreamEventHandler, types::*,
+    admin_socket::AdminSocket,
+    api_budget::ApiBudget,
+    auth::{validate_jwt, ReplayGuard},
+    backfill::{BackfillLimits, BackfillTracker},
+    database::{Database, DatabaseConfig},
+    error::AppError,
+    feed_algorithm::{
+        FeedPageCache, FeedRegistry, FeedSettings, FollowingNoRepostsFeed, PageBoundary,
+        CLEANUP_INTERVAL_SECS, DEFAULT_FEED_LIMIT, MAX_FEED_LIMIT,
+    },
+    follow_cache::FollowCache,
+    identity::IdentityCache,
+    jetstream_consumer::{
+        FollowedAuthors, JetstreamEventHandler, FOLLOWED_AUTHORS_RELOAD_INTERVAL,
+    },
+    request_log::hash_did,
+    shutdown::ShutdownCoordinator,
+    stats::RequestStats,
+    types::*,
 };
 
 #[derive(Parser)]
@@ -35,11 +63,27 @@ struct Args {
     #[command(subcommand)]
     command: Option<Command>,
 
+    /// Log output format
+    #[arg(long, env = "LOG_FORMAT", value_enum, default_value = "text")]
+    log_format: LogFormat,
+
     #[arg(long, env = "DATABASE_URL", default_value = "sqlite:./feed.db")]
     database_url: String,
 
-    #[arg(long, env = "PORT", default_value = "3000")]
-    port: u16,
+    #[command(flatten)]
+    database: DatabaseConfig,
+
+    #[command(flatten)]
+    backfill: BackfillLimits,
+
+    /// Address to serve on: `host:port`, or a Unix socket path starting with
+    /// `/` or `./` [default: 0.0.0.0:3000]
+    #[arg(long, env = "LISTEN_ADDR")]
+    listen_addr: Option<String>,
+
+    /// Shorthand for --listen-addr 0.0.0.0:<port>
+    #[arg(long, env = "PORT")]
+    port: Option<u16>,
 
     #[arg(long, env = "FEEDGEN_HOSTNAME")]
     hostname: Option<String>,
@@ -54,6 +98,80 @@ struct Args {
     )]
     jetstream_hostname: String,
 
+    /// Jetstream hostnames to fail over to, in order, comma separated
+    #[arg(long, env = "JETSTREAM_FALLBACK_HOSTNAMES", value_delimiter = ',')]
+    jetstream_fallback_hostnames: Vec<String>,
+
+    /// Failed Jetstream connections in a row before trying the next hostname
+    #[arg(long, env = "JETSTREAM_FAILOVER_AFTER", default_value_t = jetstream_consumer::DEFAULT_FAILOVER_AFTER)]
+    jetstream_failover_after: u32,
+
+    /// Collections to read from Jetstream, comma separated
+    #[arg(
+        long,
+        env = "JETSTREAM_COLLECTIONS",
+        value_delimiter = ',',
+        default_values = jetstream_consumer::SUPPORTED_COLLECTIONS
+    )]
+    jetstream_collections: Vec<String>,
+
+    /// Hours between removing follows of inactive users and posts by
+    /// authors nobody follows
+    #[arg(long, env = "FOLLOW_CLEANUP_INTERVAL_HOURS", default_value = "24")]
+    follow_cleanup_interval_hours: u64,
+
+    /// Hours between re-syncing each active user's follows from the AppView
+    #[arg(long, env = "FOLLOW_SYNC_INTERVAL_HOURS", default_value = "6")]
+    follow_sync_interval_hours: u64,
+
+    /// Users whose follows are synced back to back before a short pause
+    #[arg(long, env = "FOLLOW_SYNC_BATCH_SIZE", default_value = "10")]
+    follow_sync_batch_size: usize,
+
+    /// Minutes between checkpointing the WAL and vacuuming free pages; 0 disables
+    #[arg(long, env = "DB_MAINTENANCE_INTERVAL_MINS", default_value = "60")]
+    db_maintenance_interval_mins: u64,
+
+    /// Post to show, pinned, to a new user while their follows are backfilled
+    #[arg(long, env = "BACKFILL_PLACEHOLDER_URI")]
+    backfill_placeholder_uri: Option<String>,
+
+    /// Jetstream events that may wait for the database before reading pauses
+    #[arg(long, env = "INGEST_QUEUE_CAPACITY", default_value = "10000")]
+    ingest_queue_capacity: usize,
+
+    /// Most Jetstream events a database writer takes off the queue at once
+    #[arg(long, env = "INGEST_BATCH_SIZE", default_value = "500")]
+    ingest_batch_size: usize,
+
+    /// Longest a database writer waits to fill a batch, in milliseconds
+    #[arg(long, env = "INGEST_FLUSH_MS", default_value = "200")]
+    ingest_flush_ms: u64,
+
+    /// Seconds a post may be dated ahead of when it was indexed, up to a
+    /// day; later dates are replaced with the indexing time
+    #[arg(
+        long,
+        env = "MAX_CLOCK_SKEW_SECS",
+        default_value = "300",
+        value_parser = clap::value_parser!(i64).range(0..=86_400)
+    )]
+    max_clock_skew_secs: i64,
+
+    /// Warn when Jetstream events arrive this many seconds late on average
+    /// for five minutes; 0 disables
+    #[arg(long, env = "INGEST_LAG_WARN_SECS", default_value = "120")]
+    ingest_lag_warn_secs: u64,
+
+    /// Only index posts by accounts feed users follow, rather than every post
+    #[arg(long, env = "INDEX_FOLLOWED_ONLY")]
+    index_followed_only: bool,
+
+    /// Browser origins allowed to call the feed and DID endpoints, comma
+    /// separated, or * for any
+    #[arg(long, env = "CORS_ALLOW_ORIGIN", default_value = "*")]
+    cors_allow_origin: String,
+
     #[arg(
         long,
         env = "ADMIN_SOCKET",
@@ -61,163 +179,669 @@ struct Args {
     )]
     admin_socket: String,
 
+    /// Also serve the admin console on 127.0.0.1 at this port
+    #[arg(long, env = "ADMIN_TCP_PORT")]
+    admin_tcp_port: Option<u16>,
+
+    /// Serve the admin HTTP API on 127.0.0.1 at this port (needs --admin-secret)
+    #[arg(long, env = "ADMIN_HTTP_PORT")]
+    admin_http_port: Option<u16>,
+
+    /// Secret admin clients must send before any command
+    #[arg(long, env = "ADMIN_SECRET")]
+    admin_secret: Option<String>,
+
     #[arg(long, env = "FEED_PUBLISHER_DID")]
     feed_publisher_did: Option<String>,
 
     #[arg(long, env = "FEED_RKEY", default_value = "following-no-reposts")]
     feed_rkey: String,
+
+    /// Record key of the strict feed (no replies, quotes or link-only posts)
+    #[arg(long, env = "STRICT_FEED_RKEY", default_value = "following-strict")]
+    strict_feed_rkey: String,
+
+    /// Record key of the media feed (only posts with images or video)
+    #[arg(long, env = "MEDIA_FEED_RKEY", default_value = "following-media")]
+    media_feed_rkey: String,
+
+    /// Record key of a feed showing only recent posts; not served unless set
+    #[arg(long, env = "RECENT_FEED_RKEY")]
+    recent_feed_rkey: Option<String>,
+
+    /// How many hours back the recent feed goes, from 1 to a year
+    #[arg(
+        long,
+        env = "RECENT_FEED_MAX_AGE_HOURS",
+        default_value = "6",
+        value_parser = clap::value_parser!(i64).range(1..=8_760)
+    )]
+    recent_feed_max_age_hours: i64,
+
+    /// Feed AT-URI to advertise in describeFeedGenerator; repeat for several.
+    /// Defaults to the registered feeds under FEED_PUBLISHER_DID.
+    #[arg(long = "feed-uri", env = "FEED_URIS", value_delimiter = ',')]
+    feed_uris: Vec<String>,
+
+    /// How long to keep posts for users without a retention preference
+    #[arg(
+        long,
+        alias = "retention-hours",
+        env = "DEFAULT_RETENTION_HOURS",
+        default_value_t = feed_algorithm::DEFAULT_RETENTION_HOURS
+    )]
+    default_retention_hours: i64,
+
+    /// Newest posts per author to keep past retention (0 keeps none)
+    #[arg(long, env = "KEEP_POSTS_PER_AUTHOR", default_value_t = 0)]
+    keep_posts_per_author: i64,
+
+    /// Page size when the client doesn't ask for one
+    #[arg(long, env = "FEED_DEFAULT_LIMIT", default_value_t = DEFAULT_FEED_LIMIT)]
+    feed_default_limit: i32,
+
+    /// Largest page size a client can ask for
+    #[arg(long, alias = "max-feed-size", env = "FEED_MAX_LIMIT", default_value_t = MAX_FEED_LIMIT)]
+    feed_max_limit: i32,
+
+    /// Seconds to reuse a generated page for an identical request from the
+    /// same user; 0 turns the cache off
+    #[arg(
+        long,
+        env = "FEED_CACHE_TTL_SECS",
+        default_value_t = feed_algorithm::DEFAULT_PAGE_CACHE_TTL_SECS
+    )]
+    feed_cache_ttl_secs: u64,
+
+    /// Most generated pages kept in memory
+    #[arg(
+        long,
+        env = "FEED_CACHE_CAPACITY",
+        default_value_t = feed_algorithm::DEFAULT_PAGE_CACHE_CAPACITY
+    )]
+    feed_cache_capacity: u64,
+
+    /// Start without checking the database for corruption
+    #[arg(long, env = "SKIP_INTEGRITY_CHECK")]
+    skip_integrity_check: bool,
+
+    /// Show the parent post above replies for context
+    #[arg(long, env = "INCLUDE_REPLY_PARENTS")]
+    include_reply_parents: bool,
+
+    /// Accept each feed request token only once, remembering them until they
+    /// expire
+    #[arg(long, env = "REJECT_REPLAYED_TOKENS")]
+    reject_replayed_tokens: bool,
+
+    /// Show users their own posts in every feed, as if they followed themselves
+    #[arg(long, env = "INCLUDE_SELF_POSTS")]
+    include_self_posts: bool,
+
+    /// Show only the earliest of posts in a page that share the same text
+    #[arg(long, env = "COLLAPSE_DUPLICATE_TEXT")]
+    collapse_duplicate_text: bool,
+
+    /// DID the self-test resolves and backfills
+    #[arg(long, env = "SELF_TEST_DID", default_value = self_test::DEFAULT_TEST_DID)]
+    self_test_did: String,
+
+    /// Time budget for the whole self-test
+    #[arg(long, env = "SELF_TEST_TIMEOUT_SECS", default_value = "60")]
+    self_test_timeout_secs: u64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
 }
 
 #[derive(Parser)]
 enum Command {
     /// Publish the feed to Bluesky
-    Publish,
+    Publish {
+        #[command(subcommand)]
+        action: Option<PublishCommand>,
+    },
+    /// Delete the feed generator record from Bluesky
+    Unpublish(publish::UnpublishArgs),
     /// Run the feed generator server (default)
     Serve,
+    /// Check the live environment end to end against a throwaway database
+    SelfTest,
+    /// Delete old posts, inactive users' follows and unfollowed authors' posts once
+    Cleanup {
+        /// Only count what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Switch a database created before incremental auto-vacuum over to it,
+    /// so maintenance can shrink the file. Rewrites the whole file; run it
+    /// while the server is stopped.
+    Vacuum,
+}
+
+#[derive(clap::Subcommand)]
+enum PublishCommand {
+    /// Create or replace the feed generator record, prompting for every field (default)
+    Create,
+    /// Change an existing feed generator record's display name or description
+    Update(publish::UpdateArgs),
 }
 
 #[derive(Clone)]
 struct AppState {
     db: Arc<Database>,
+    budget: Arc<ApiBudget>,
+    identity: Arc<IdentityCache>,
+    /// Set when each token may only be used once
+    replay_guard: Option<ReplayGuard>,
     service_did: String,
-    feed_uri: Option<String>,
+    /// Host the generator is reached at, served in its did:web document
+    hostname: Option<String>,
+    feed_publisher_did: Option<String>,
+    feed_uris: Vec<String>,
+    feeds: Arc<FeedRegistry>,
+    default_retention_hours: i64,
+    keep_posts_per_author: i64,
+    include_reply_parents: bool,
+    collapse_duplicate_text: bool,
+    backfills: BackfillTracker,
+    backfill_limits: BackfillLimits,
+    backfill_placeholder_uri: Option<String>,
+    feed_default_limit: i32,
+    feed_max_limit: i32,
+    page_cache: Option<Arc<FeedPageCache>>,
+    request_stats: Arc<RequestStats>,
+}
+
+impl AppState {
+    fn feed_settings(&self) -> FeedSettings {
+        FeedSettings {
+            retention_hours: self.default_retention_hours,
+            keep_posts_per_author: self.keep_posts_per_author,
+            include_reply_parents: self.include_reply_parents,
+            collapse_duplicate_text: self.collapse_duplicate_text,
+            default_limit: self.feed_default_limit,
+            max_limit: self.feed_max_limit,
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
     dotenvy::dotenv().ok();
 
     let args = Args::parse();
 
+    match args.log_format {
+        LogFormat::Text => tracing_subscriber::fmt::init(),
+        LogFormat::Json => tracing_subscriber::fmt().json().init(),
+    }
+
     // Handle publish command
-    if matches!(args.command, Some(Command::Publish)) {
-        return publish::publish_feed().await;
+    if let Some(Command::Publish { action }) = &args.command {
+        return match action {
+            Some(PublishCommand::Update(update)) => publish::update_feed(update.clone()).await,
+            Some(PublishCommand::Create) | None => publish::publish_feed().await,
+        };
+    }
+    if let Some(Command::Unpublish(unpublish)) = &args.command {
+        return publish::unpublish_feed(unpublish.clone()).await;
+    }
+
+    if matches!(args.command, Some(Command::SelfTest)) {
+        let report = self_test::run(self_test::SelfTestConfig {
+            jetstream_hostname: args.jetstream_hostname.clone(),
+            service_did: args
+                .service_did
+                .clone()
+                .or_else(|| args.hostname.clone().map(|h| format!("did:web:{}", h)))
+                .unwrap_or_else(|| "did:web:localhost".to_string()),
+            test_did: args.self_test_did.clone(),
+            timeout: tokio::time::Duration::from_secs(args.self_test_timeout_secs),
+        })
+        .await?;
+        print!("{}", report);
+        if !report.passed() {
+            std::process::exit(1);
+        }
+        return Ok(());
     }
 
     // Default to serve mode
-    let service_did = args
-        .service_did
-        .or_else(|| args.hostname.clone().map(|h| format!("did:web:{}", h)))
-        .expect("FEEDGEN_SERVICE_DID or FEEDGEN_HOSTNAME must be set");
+    if args.admin_http_port.is_some() && args.admin_secret.is_none() {
+        anyhow::bail!("--admin-http-port requires --admin-secret");
+    }
+    if args.default_retention_hours < 1 {
+        anyhow::bail!("--default-retention-hours must be at least 1");
+    }
+    if args.keep_posts_per_author < 0 {
+        anyhow::bail!("--keep-posts-per-author can't be negative");
+    }
+    if let Some(Command::Cleanup { dry_run }) = args.command {
+        let db = Arc::new(Database::with_config(&args.database_url, &args.database).await?);
+        db.migrate().await?;
+        return run_cleanup(
+            db,
+            args.default_retention_hours,
+            args.keep_posts_per_author,
+            dry_run,
+        )
+        .await;
+    }
+    if matches!(args.command, Some(Command::Vacuum)) {
+        let db = Database::with_config(&args.database_url, &args.database).await?;
+        db.migrate().await?;
+        info!("Vacuuming the database, which may take a while");
+        if db.enable_incremental_vacuum().await? {
+            info!("Switched the database to incremental auto-vacuum");
+        } else {
+            info!("The database already uses incremental auto-vacuum");
+        }
+        return Ok(());
+    }
+    if args.feed_max_limit < 1 || args.feed_default_limit < 1 {
+        anyhow::bail!("--feed-default-limit and --feed-max-limit must be at least 1");
+    }
+
+    let (service_did, hostname) =
+        service_identity(args.service_did.clone(), args.hostname.clone())?;
 
     // Initialize database
-    let db = Arc::new(Database::new(&args.database_url).await?);
+    let db = Arc::new(Database::with_config(&args.database_url, &args.database).await?);
     db.migrate().await?;
+    if args.skip_integrity_check {
+        warn!("Skipping database integrity check");
+    } else {
+        verify_database_integrity(&db, &args.database_url).await?;
+    }
+    // Feed queries name the accounts a user follows, kept in memory
+    db.set_follow_cache(FollowCache::default());
+    db.set_max_clock_skew(chrono::Duration::seconds(args.max_clock_skew_secs));
 
-    // Construct feed URI if publisher DID is configured
-    let feed_uri = args.feed_publisher_did.map(|did| {
-        format!("at://{}/app.bsky.feed.generator/{}", did, args.feed_rkey)
-    });
+    // Every outbound AppView request draws from this shared budget
+    let budget = Arc::new(ApiBudget::default());
+
+    // Resolved DID documents for PDS-direct calls, invalidated on identity events
+    let identity = Arc::new(IdentityCache::default());
+
+    let with_self = |filter| FeedFilter {
+        include_self: args.include_self_posts,
+        ..filter
+    };
+    let mut feeds = FeedRegistry::default()
+        .register(&args.feed_rkey, with_self(FeedFilter::default()))
+        .register(&args.strict_feed_rkey, with_self(FeedFilter::strict()))
+        .register(&args.media_feed_rkey, with_self(FeedFilter::media()));
+    if let Some(rkey) = &args.recent_feed_rkey {
+        let recent = FeedFilter {
+            max_age_hours: Some(args.recent_feed_max_age_hours),
+            ..FeedFilter::default()
+        };
+        feeds = feeds.register(rkey, with_self(recent));
+    }
 
+    let backfills = BackfillTracker::default();
     let app_state = AppState {
         db: Arc::clone(&db),
+        budget: Arc::clone(&budget),
+        identity: Arc::clone(&identity),
+        replay_guard: args.reject_replayed_tokens.then(ReplayGuard::new),
         service_did: service_did.clone(),
-        feed_uri,
+        hostname,
+        feed_publisher_did: args.feed_publisher_did.clone(),
+        feed_uris: args.feed_uris.clone(),
+        feeds: Arc::new(feeds),
+        default_retention_hours: args.default_retention_hours,
+        keep_posts_per_author: args.keep_posts_per_author,
+        include_reply_parents: args.include_reply_parents,
+        collapse_duplicate_text: args.collapse_duplicate_text,
+        backfills: backfills.clone(),
+        backfill_limits: args.backfill,
+        backfill_placeholder_uri: args.backfill_placeholder_uri.clone(),
+        feed_default_limit: args.feed_default_limit,
+        feed_max_limit: args.feed_max_limit,
+        page_cache: (args.feed_cache_ttl_secs > 0).then(|| {
+            Arc::new(FeedPageCache::new(
+                std::time::Duration::from_secs(args.feed_cache_ttl_secs),
+                args.feed_cache_capacity,
+            ))
+        }),
+        request_stats: Arc::new(RequestStats::default()),
     };
 
-    // Start admin socket
-    let admin_socket = AdminSocket::new(Arc::clone(&db), args.admin_socket.clone());
-    tokio::spawn(async move {
-        if let Err(e) = admin_socket.start().await {
-            warn!("Admin socket error: {}", e);
-        }
-    });
+    // Background tasks share a JoinSet so shutdown can wait for them
+    let shutdown = ShutdownCoordinator::new();
+    let mut background_tasks = JoinSet::new();
 
-    // Start cleanup task - runs every 5 minutes
-    let db_cleanup = Arc::clone(&db);
-    tokio::spawn(async move {
-        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(300)); // Every 5 minutes
-        loop {
-            interval.tick().await;
+    // Jetstream events are written by a small pool of tasks behind a bounded queue
+    let mut event_handler = JetstreamEventHandler::new(Arc::clone(&db), Arc::clone(&identity));
+    if args.index_followed_only {
+        let authors = Arc::new(FollowedAuthors::default());
+        let count = authors.reload(&db).await?;
+        info!(
+            "Indexing only posts by the {} accounts feed users follow",
+            count
+        );
+        authors.spawn_reloads(
+            &mut background_tasks,
+            shutdown.clone(),
+            Arc::clone(&db),
+            FOLLOWED_AUTHORS_RELOAD_INTERVAL,
+        );
+        event_handler = event_handler.with_followed_only(
+            authors,
+            Arc::clone(&budget),
+            args.backfill.posts_per_user,
+            &mut background_tasks,
+            shutdown.clone(),
+        );
+    }
+    let mut ingest_queue = event_handler.spawn_writers(
+        args.ingest_queue_capacity,
+        args.ingest_batch_size,
+        std::time::Duration::from_millis(args.ingest_flush_ms),
+    );
+    if args.ingest_lag_warn_secs > 0 {
+        ingest_queue = ingest_queue
+            .with_lag_warning(std::time::Duration::from_secs(args.ingest_lag_warn_secs));
+    }
+    ingest_queue.spawn_lag_checks(&mut background_tasks, shutdown.clone());
 
-            // Clean up old posts (older than 48 hours)
-            if let Err(e) = db_cleanup.cleanup_old_posts(48).await {
-                warn!("Failed to cleanup old posts: {}", e);
+    // Start admin socket
+    let admin_socket = Arc::new(
+        AdminSocket::new(
+            Arc::clone(&db),
+            Arc::clone(&budget),
+            Arc::clone(&identity),
+            args.admin_socket.clone(),
+        )
+        .with_secret(args.admin_secret.clone())
+        .with_ingest_queue(ingest_queue.clone())
+        .with_service(service_did.clone(), args.jetstream_hostname.clone())
+        .with_feed_settings(app_state.feed_settings())
+        .with_backfill_limits(args.backfill)
+        .with_request_stats(Arc::clone(&app_state.request_stats)),
+    );
+    #[cfg(unix)]
+    {
+        let admin_socket = Arc::clone(&admin_socket);
+        tokio::spawn(async move {
+            if let Err(e) = admin_socket.start().await {
+                warn!("Admin socket error: {}", e);
+            }
+        });
+    }
+    if let Some(port) = args.admin_tcp_port {
+        let admin_socket = Arc::clone(&admin_socket);
+        tokio::spawn(async move {
+            if let Err(e) = admin_socket.start_tcp(port).await {
+                warn!("Admin TCP endpoint error: {}", e);
             }
+        });
+    }
 
-            // Verify follows for active users (accessed feed in last 7 days)
-            // This removes follows that no longer exist in the user's actual follow list
-            if let Err(e) = cleanup::verify_active_user_follows(Arc::clone(&db_cleanup)).await {
-                warn!("Failed to verify active user follows: {}", e);
+    if let Some(port) = args.admin_http_port {
+        let state = admin_http::AdminHttpState {
+            db: Arc::clone(&db),
+            budget: Arc::clone(&budget),
+            identity: Arc::clone(&identity),
+            ingest: Some(ingest_queue.clone()),
+            backfill: args.backfill,
+            secret: args.admin_secret.clone().unwrap_or_default(),
+        };
+        tokio::spawn(async move {
+            if let Err(e) = admin_http::start(state, port).await {
+                warn!("Admin HTTP endpoint error: {}", e);
             }
+        });
+    }
 
-            // Clean up follows for users who haven't accessed the feed
-            // This removes all follow data for users not in the active_users table
-            if let Err(e) = cleanup::cleanup_inactive_user_follows(Arc::clone(&db_cleanup)).await {
-                warn!("Failed to cleanup inactive user follows: {}", e);
+    // Old posts go every 5 minutes, past --default-retention-hours unless
+    // users asked otherwise, keeping --keep-posts-per-author of each author's
+    let db_cleanup = Arc::clone(&db);
+    let default_retention_hours = args.default_retention_hours;
+    let keep_posts_per_author = args.keep_posts_per_author;
+    cleanup::spawn_periodic(
+        &mut background_tasks,
+        shutdown.clone(),
+        "Post cleanup",
+        std::time::Duration::from_secs(CLEANUP_INTERVAL_SECS),
+        move || {
+            let db = Arc::clone(&db_cleanup);
+            async move {
+                cleanup::cleanup_old_posts(
+                    &db,
+                    default_retention_hours,
+                    keep_posts_per_author,
+                    false,
+                )
+                .await
             }
-        }
-    });
+        },
+    );
+
+    // Active users' follow lists are re-checked against the AppView
+    cleanup::spawn_follow_sync(
+        &mut background_tasks,
+        shutdown.clone(),
+        Arc::clone(&db),
+        Arc::clone(&budget),
+        std::time::Duration::from_secs(args.follow_sync_interval_hours * 3600),
+        args.follow_sync_batch_size,
+    );
+
+    // Follows of users who stopped using the feed, then posts by authors
+    // nobody follows any more
+    let follow_cleanup_interval =
+        std::time::Duration::from_secs(args.follow_cleanup_interval_hours.max(1) * 3600);
+    let db_cleanup = Arc::clone(&db);
+    cleanup::spawn_periodic(
+        &mut background_tasks,
+        shutdown.clone(),
+        "Inactive follow cleanup",
+        follow_cleanup_interval,
+        move || cleanup::cleanup_inactive_user_follows(Arc::clone(&db_cleanup), false),
+    );
+    let db_cleanup = Arc::clone(&db);
+    cleanup::spawn_periodic(
+        &mut background_tasks,
+        shutdown.clone(),
+        "Stale author cleanup",
+        follow_cleanup_interval,
+        move || cleanup::cleanup_stale_authors(Arc::clone(&db_cleanup), false),
+    );
+
+    // The WAL is truncated and space freed by cleanup returned, between backfills
+    if args.db_maintenance_interval_mins > 0 {
+        cleanup::spawn_maintenance(
+            &mut background_tasks,
+            shutdown.clone(),
+            Arc::clone(&db),
+            backfills,
+            std::time::Duration::from_secs(args.db_maintenance_interval_mins * 60),
+        );
+    }
 
-    // Start Jetstream consumer with automatic reconnection
-    let event_handler = JetstreamEventHandler::new(Arc::clone(&db));
-    let jetstream_hostname = args.jetstream_hostname.clone();
+    // Start Jetstream consumer, which reconnects on its own
+    let mut jetstream_hostnames = vec![args.jetstream_hostname.clone()];
+    jetstream_hostnames.extend(args.jetstream_fallback_hostnames.iter().cloned());
+    let failover_after = args.jetstream_failover_after;
+    let collections = args.jetstream_collections.clone();
+    jetstream_consumer::check_collections(&collections);
     tokio::spawn(async move {
-        loop {
-            info!("Starting Jetstream consumer...");
-            if let Err(e) = event_handler.start(jetstream_hostname.clone()).await {
-                warn!(
-                    "Jetstream consumer error: {}. Reconnecting in 5 seconds...",
-                    e
-                );
-                tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-            } else {
-                // Consumer stopped without error, wait before restarting
-                warn!("Jetstream consumer stopped unexpectedly. Reconnecting in 5 seconds...");
-                tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-            }
+        info!("Starting Jetstream consumer...");
+        if let Err(e) = event_handler
+            .start(
+                &jetstream_hostnames,
+                collections,
+                failover_after,
+                &ingest_queue,
+            )
+            .await
+        {
+            error!("Jetstream consumer stopped: {}", e);
         }
     });
 
     // Setup web server
-    let app = Router::new()
-        .route("/", get(root))
+    let app = build_router(app_state, cors_layer(&args.cors_allow_origin)?);
+
+    let listen_addr = listener::listen_addr(args.listen_addr.as_deref(), args.port);
+    let listener = listener::Listener::bind(&listen_addr).await?;
+    info!("Feed generator listening on {}", listener);
+
+    let stopping = shutdown.clone();
+    listener
+        .serve(app, async move {
+            shutdown::signal().await;
+            info!("Shutting down");
+            stopping.trigger();
+        })
+        .await?;
+
+    shutdown.trigger();
+    background_tasks.join_all().await;
+    Ok(())
+}
+
+/// `cors` applies to the feed and DID endpoints only. The feed requester
+/// calls them server-side without an Origin, so it only matters to browsers.
+fn build_router(app_state: AppState, cors: CorsLayer) -> Router {
+    let public = Router::new()
         .route("/.well-known/did.json", get(did_document))
         .route(
             "/xrpc/app.bsky.feed.describeFeedGenerator",
             get(describe_feed_generator),
         )
+        .route(
+            "/xrpc/com.atproto.server.describeServer",
+            get(describe_server),
+        )
         .route(
             "/xrpc/app.bsky.feed.getFeedSkeleton",
             get(get_feed_skeleton),
         )
-        .layer(CorsLayer::permissive())
-        .with_state(app_state);
+        .layer(cors);
+    Router::new()
+        .route("/", get(root))
+        .route("/health", get(health))
+        .merge(public)
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&app_state.request_stats),
+            stats::record_latencies,
+        ))
+        .layer(middleware::from_fn(request_log::log_requests))
+        .with_state(app_state)
+}
 
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", args.port)).await?;
-    info!("Feed generator listening on port {}", args.port);
+/// CORS for `--cors-allow-origin`: `*`, or a comma-separated list of origins
+fn cors_layer(allow_origin: &str) -> Result<CorsLayer> {
+    if allow_origin.trim() == "*" {
+        return Ok(CorsLayer::permissive());
+    }
+    let origins = allow_origin
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(HeaderValue::from_str)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow::anyhow!("Invalid CORS origin in {:?}: {}", allow_origin, e))?;
+    Ok(CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET])
+        .allow_headers(Any))
+}
 
-    axum::serve(listener, app).await?;
-    Ok(())
+async fn health() -> &'static str {
+    "OK"
 }
 
 async fn root() -> &'static str {
     "Following No Reposts Feed Generator"
 }
 
-async fn did_document(State(state): State<AppState>) -> Json<DidDocument> {
-    Json(DidDocument {
+/// Works out the generator's DID and hostname from whichever of the two is
+/// configured. A did:web service DID must name the configured hostname, or
+/// the DID document served at /.well-known/did.json would not be its own.
+fn service_identity(
+    service_did: Option<String>,
+    hostname: Option<String>,
+) -> Result<(String, Option<String>)> {
+    let service_did = match (service_did, &hostname) {
+        (Some(did), _) => did,
+        (None, Some(hostname)) => format!("did:web:{}", hostname),
+        (None, None) => anyhow::bail!("FEEDGEN_SERVICE_DID or FEEDGEN_HOSTNAME must be set"),
+    };
+
+    let Some(did_host) = service_did.strip_prefix("did:web:") else {
+        return Ok((service_did, hostname));
+    };
+    match hostname {
+        Some(hostname) if !hostname.eq_ignore_ascii_case(did_host) => anyhow::bail!(
+            "FEEDGEN_SERVICE_DID is {} but FEEDGEN_HOSTNAME is {}; the DID document served \
+             at https://{}/.well-known/did.json would not match it",
+            service_did,
+            hostname,
+            hostname
+        ),
+        Some(hostname) => Ok((service_did, Some(hostname))),
+        None => {
+            let hostname = did_host.to_string();
+            Ok((service_did, Some(hostname)))
+        }
+    }
+}
+
+async fn did_document(State(state): State<AppState>) -> Result<Json<DidDocument>, StatusCode> {
+    state
+        .request_stats
+        .did_document_requests
+        .fetch_add(1, Ordering::Relaxed);
+
+    // Without a hostname there's no endpoint to advertise
+    let hostname = state.hostname.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(DidDocument {
         context: vec!["https://www.w3.org/ns/did/v1".to_string()],
         id: state.service_did.clone(),
         service: vec![ServiceEndpoint {
             id: "#bsky_fg".to_string(),
             service_type: "BskyFeedGenerator".to_string(),
-            service_endpoint: format!(
-                "https://{}",
-                std::env::var("FEEDGEN_HOSTNAME").unwrap_or_default()
-            ),
+            service_endpoint: format!("https://{}", hostname),
         }],
-    })
+    }))
 }
 
 async fn describe_feed_generator(
     State(state): State<AppState>,
 ) -> Json<DescribeFeedGeneratorResponse> {
+    if !state.feed_uris.is_empty() {
+        return Json(DescribeFeedGeneratorResponse {
+            did: state.service_did.clone(),
+            feeds: state
+                .feed_uris
+                .iter()
+                .map(|uri| FeedDescriptor { uri: uri.clone() })
+                .collect(),
+        });
+    }
+
+    // Otherwise feed URIs are only known once a publisher DID is configured
     let feeds = state
-        .feed_uri
+        .feed_publisher_did
         .as_ref()
-        .map(|uri| vec![FeedDescriptor { uri: uri.clone() }])
+        .map(|did| {
+            state
+                .feeds
+                .feeds()
+                .iter()
+                .map(|feed| FeedDescriptor {
+                    uri: format!("at://{}/app.bsky.feed.generator/{}", did, feed.rkey),
+                })
+                .collect()
+        })
         .unwrap_or_default();
 
     Json(DescribeFeedGeneratorResponse {
@@ -226,143 +850,538 @@ async fn describe_feed_generator(
     })
 }
 
+/// For tools that discover services through `describeServer`. This is not a
+/// PDS, so there are no user domains or sign-ups.
+async fn describe_server(State(state): State<AppState>) -> Json<DescribeServerResponse> {
+    Json(DescribeServerResponse {
+        did: state.service_did.clone(),
+        available_user_domains: Vec::new(),
+        invite_code_required: false,
+        links: serde_json::Map::new(),
+    })
+}
+
 async fn get_feed_skeleton(
     headers: HeaderMap,
     Query(params): Query<FeedSkeletonParams>,
     State(state): State<AppState>,
 ) -> Response {
-    info!("Received feed skeleton request for feed: {}", params.feed);
+    // `log_requests` reports the outcome, with these fields on its span
+    tracing::Span::current().record("feed", tracing::field::display(&params.feed));
+    let request_stats = Arc::clone(&state.request_stats);
+    request_stats.feed_requests.fetch_add(1, Ordering::Relaxed);
+
+    let response = serve_feed_skeleton(headers, params, state)
+        .await
+        .unwrap_or_else(IntoResponse::into_response);
+
+    if response.status() == StatusCode::UNAUTHORIZED {
+        request_stats.auth_failures.fetch_add(1, Ordering::Relaxed);
+    }
+    response
+}
+
+async fn serve_feed_skeleton(
+    headers: HeaderMap,
+    params: FeedSkeletonParams,
+    state: AppState,
+) -> Result<Response, AppError> {
+    info!("Received feed skeleton request");
 
     // This feed requires authentication since it's personalized
-    let auth_header = match headers.get("authorization") {
-        Some(h) => h,
-        None => {
-            warn!("Missing Authorization header - this feed requires authentication");
-            return (
-                StatusCode::UNAUTHORIZED,
-                Json(types::ErrorResponse {
-                    error: "AuthenticationRequired".to_string(),
-                    message:
-                        "This feed shows posts from accounts you follow and requires authentication"
-                            .to_string(),
-                }),
-            )
-                .into_response();
-        }
-    };
+    let auth_header = headers.get("authorization").ok_or_else(|| {
+        warn!("Missing Authorization header - this feed requires authentication");
+        AppError::AuthenticationRequired(
+            "This feed shows posts from accounts you follow and requires authentication"
+                .to_string(),
+        )
+    })?;
 
-    let auth_str = match auth_header.to_str() {
-        Ok(s) => s,
-        Err(_) => {
-            warn!("Invalid authorization header format");
-            return (
-                StatusCode::UNAUTHORIZED,
-                Json(types::ErrorResponse {
-                    error: "AuthenticationRequired".to_string(),
-                    message: "Invalid authorization header format".to_string(),
-                }),
-            )
-                .into_response();
-        }
+    let auth_str = auth_header.to_str().map_err(|_| {
+        warn!("Invalid authorization header format");
+        AppError::AuthenticationRequired("Invalid authorization header format".to_string())
+    })?;
+
+    if let Some(limit) = params.limit.filter(|limit| *limit <= 0) {
+        warn!("Rejecting feed request with limit {}", limit);
+        return Err(AppError::InvalidRequest(
+            "limit must be a positive integer".to_string(),
+        ));
+    }
+
+    if let Some(max) = params.max_posts_per_author.filter(|max| *max <= 0) {
+        warn!("Rejecting feed request with max_posts_per_author {}", max);
+        return Err(AppError::InvalidRequest(
+            "max_posts_per_author must be a positive integer".to_string(),
+        ));
+    }
+
+    let Some(feed) = state.feeds.get(&params.feed).cloned() else {
+        warn!("Unknown feed requested: {}", params.feed);
+        return Err(AppError::UnknownFeed(params.feed));
     };
 
     // Remove "Bearer " prefix if present
     let token = auth_str.strip_prefix("Bearer ").unwrap_or(auth_str);
 
     info!("Validating JWT for request");
-    let requester_did = match validate_jwt(token, &state.service_did).await {
+    let requester_did = match validate_jwt(
+        token,
+        &state.service_did,
+        &state.identity,
+        state.replay_guard.as_ref(),
+    )
+    .await
+    {
         Ok(claims) => {
-            info!("Authenticated request from DID: {}", claims.iss);
+            tracing::Span::current().record("requester", hash_did(&claims.iss));
+            info!("Authenticated request");
             claims.iss
         }
         Err(e) => {
+            // The cause can name resolver hosts and URLs, so it stays in the log
             warn!("JWT validation failed: {}", e);
-            return (
-                StatusCode::UNAUTHORIZED,
-                Json(types::ErrorResponse {
-                    error: "AuthenticationRequired".to_string(),
-                    message: format!("JWT validation failed: {}", e),
-                }),
-            )
-                .into_response();
+            return Err(AppError::AuthenticationRequired(
+                "JWT validation failed".to_string(),
+            ));
         }
     };
 
-    // Check if user has any follows, if not, backfill them and their posts
-    let db_for_backfill = Arc::clone(&state.db);
-    let requester_did_clone = requester_did.clone();
-    tokio::spawn(async move {
-        // Check if we have any follows for this user
-        let has_follows =
-            sqlx::query("SELECT COUNT(*) as count FROM follows WHERE follower_did = ?")
-                .bind(&requester_did_clone)
-                .fetch_one(&db_for_backfill.pool)
-                .await
-                .ok()
-                .and_then(|row| row.try_get::<i64, _>("count").ok())
-                .unwrap_or(0);
-
-        if has_follows == 0 {
-            info!(
-                "No follows found for {}, triggering backfill",
-                requester_did_clone
-            );
+    // A user we have no follows for gets backfilled, follows then their posts
+    let has_follows = state.db.has_follows(&requester_did).await.unwrap_or(false);
+    if !has_follows {
+        if let Some(guard) = state.backfills.start(&requester_did) {
+            let db_for_backfill = Arc::clone(&state.db);
+            let budget_for_backfill = Arc::clone(&state.budget);
+            let identity_for_backfill = Arc::clone(&state.identity);
+            let requester_did_clone = requester_did.clone();
+            let requester = hash_did(&requester_did);
+            let limits = state.backfill_limits;
+            tokio::spawn(
+                async move {
+                    // Held until the backfill is over, successful or not
+                    let _guard = guard;
+                    info!("No follows found for {}, triggering backfill", requester);
 
-            // First backfill follows
-            if let Err(e) =
-                backfill::backfill_follows(Arc::clone(&db_for_backfill), &requester_did_clone).await
-            {
-                warn!("Follow backfill failed for {}: {}", requester_did_clone, e);
-                return;
-            }
+                    // First backfill follows
+                    if let Err(e) = backfill::backfill_follows(
+                        Arc::clone(&db_for_backfill),
+                        Arc::clone(&budget_for_backfill),
+                        Arc::clone(&identity_for_backfill),
+                        &requester_did_clone,
+                        limits,
+                        None,
+                    )
+                    .await
+                    {
+                        warn!("Follow backfill failed for {}: {}", requester, e);
+                        return;
+                    }
 
-            // Then backfill recent posts from each follow (10 posts per user)
-            info!("Starting post backfill for {}", requester_did_clone);
-            if let Err(e) = backfill::backfill_posts_for_follows(
-                Arc::clone(&db_for_backfill),
-                &requester_did_clone,
-                10,
-            )
-            .await
-            {
-                warn!("Post backfill failed for {}: {}", requester_did_clone, e);
-            }
+                    // Blocks only hide posts, so a failure doesn't stop the rest
+                    if !db_for_backfill
+                        .has_blocks(&requester_did_clone)
+                        .await
+                        .unwrap_or(true)
+                    {
+                        if let Err(e) = backfill::backfill_blocks(
+                            Arc::clone(&db_for_backfill),
+                            Arc::clone(&identity_for_backfill),
+                            &requester_did_clone,
+                            limits.max_blocks,
+                        )
+                        .await
+                        {
+                            warn!("Block backfill failed for {}: {}", requester, e);
+                        }
+                    }
+
+                    // Then backfill recent posts from each follow
+                    info!("Starting post backfill for {}", requester);
+                    if let Err(e) = backfill::backfill_posts_for_follows(
+                        Arc::clone(&db_for_backfill),
+                        Arc::clone(&budget_for_backfill),
+                        Arc::clone(&identity_for_backfill),
+                        &requester_did_clone,
+                        limits,
+                        None,
+                    )
+                    .await
+                    {
+                        warn!("Post backfill failed for {}: {}", requester, e);
+                    }
+                }
+                .in_current_span(),
+            );
         }
-    });
+    }
+
+    // Until their follows arrive, a user being backfilled sees the placeholder
+    let placeholder = state
+        .backfill_placeholder_uri
+        .clone()
+        .filter(|_| !has_follows && state.backfills.is_running(&requester_did));
 
     // Record that this user accessed the feed
     if let Err(e) = state.db.record_feed_request(&requester_did).await {
-        warn!("Failed to record feed request for {}: {}", requester_did, e);
+        warn!(error = %e, "Failed to record feed request");
     }
 
-    let feed_algorithm = FollowingNoRepostsFeed::new(Arc::clone(&state.db));
+    let filter = FeedFilter {
+        max_posts_per_author: params
+            .max_posts_per_author
+            .map(|max| max.min(types::MAX_POSTS_PER_AUTHOR) as i64),
+        ..feed.filter
+    };
+    let feed_algorithm = FollowingNoRepostsFeed::new(Arc::clone(&state.db))
+        .with_filter(filter)
+        .with_settings(state.feed_settings())
+        .with_backfill_placeholder(placeholder)
+        .with_page_cache(state.page_cache.clone());
 
-    info!(
-        "Generating feed for requester: {}, limit: {:?}, cursor: {:?}",
-        requester_did, params.limit, params.cursor
-    );
+    info!(limit = ?params.limit, cursor = ?params.cursor, "Generating feed");
 
-    match feed_algorithm
+    let page = feed_algorithm
         .generate_feed(Some(requester_did.clone()), params.limit, params.cursor)
         .await
-    {
-        Ok(response) => {
-            info!(
-                "Successfully generated feed with {} posts",
-                response.feed.len()
+        .map_err(|e| e.context("Feed generation failed"))?;
+    if page.cached {
+        state
+            .request_stats
+            .feed_cache_hits
+            .fetch_add(1, Ordering::Relaxed);
+    }
+    info!(
+        posts = page.response.feed.len(),
+        generate_us = page.timings.total().as_micros() as u64,
+        "Generated feed"
+    );
+    if page.boundary == PageBoundary::PastRetention {
+        // Nothing will ever appear behind this cursor again
+        return Ok(([(header::CACHE_CONTROL, "no-store")], Json(page.response)).into_response());
+    }
+    Ok(Json(page.response).into_response())
+}
+
+/// Runs the cleanup passes that don't need the network, in the order the
+/// server schedules them
+async fn run_cleanup(
+    db: Arc<Database>,
+    retention_hours: i64,
+    keep_per_author: i64,
+    dry_run: bool,
+) -> Result<()> {
+    let posts = cleanup::cleanup_old_posts(&db, retention_hours, keep_per_author, dry_run).await?;
+    let follows = cleanup::cleanup_inactive_user_follows(Arc::clone(&db), dry_run).await?;
+    let authors = cleanup::cleanup_stale_authors(db, dry_run).await?;
+
+    println!("{}:", if dry_run { "Would delete" } else { "Deleted" });
+    println!("  Posts older than {}h: {}", retention_hours, posts);
+    println!("  Inactive users' follows: {}", follows);
+    for user in &follows.inactive_users {
+        println!("    {}: {} follows", user.did, user.follows);
+    }
+    println!("  Posts by authors nobody follows: {}", authors);
+    if dry_run {
+        println!("Nothing was deleted. Each pass was counted on its own, so posts by authors only inactive users follow aren't included.");
+    }
+    Ok(())
+}
+
+/// Refuses to serve from a corrupted database, which would otherwise fail
+/// mid-operation
+async fn verify_database_integrity(db: &Database, database_url: &str) -> Result<()> {
+    let problems = match db.check_integrity().await {
+        Ok(problems) => problems,
+        Err(e) => {
+            error!("Integrity check of {} failed: {}", database_url, e);
+            anyhow::bail!(
+                "Could not check database {} for corruption: {}. Restore it from a backup \
+                 or pass --skip-integrity-check to start anyway",
+                database_url,
+                e
             );
-            Json(response).into_response()
         }
-        Err(e) => {
-            tracing::error!("Feed generation error for {}: {:?}", requester_did, e);
+    };
+
+    if !problems.is_empty() {
+        error!(
+            "Database {} failed its integrity check:\n{}",
+            database_url,
+            problems.join("\n")
+        );
+        anyhow::bail!(
+            "Database {} is corrupted ({} problems, first: {}). Restore it from a backup \
+             or pass --skip-integrity-check to start anyway",
+            database_url,
+            problems.len(),
+            problems[0]
+        );
+    }
+
+    info!("Database integrity check passed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn test_state() -> Result<AppState> {
+        let db = Arc::new(Database::new(":memory:").await?);
+        db.migrate().await?;
+        Ok(AppState {
+            db,
+            budget: Arc::new(ApiBudget::default()),
+            identity: Arc::new(IdentityCache::default()),
+            replay_guard: None,
+            service_did: "did:web:feed.example.com".to_string(),
+            hostname: Some("feed.example.com".to_string()),
+            feed_publisher_did: None,
+            feed_uris: Vec::new(),
+            feeds: Arc::new(
+                FeedRegistry::default()
+                    .register("following-no-reposts", FeedFilter::default())
+                    .register("following-strict", FeedFilter::strict()),
+            ),
+            default_retention_hours: feed_algorithm::DEFAULT_RETENTION_HOURS,
+            keep_posts_per_author: 0,
+            include_reply_parents: false,
+            collapse_duplicate_text: false,
+            backfills: BackfillTracker::default(),
+            backfill_limits: BackfillLimits::default(),
+            backfill_placeholder_uri: None,
+            feed_default_limit: DEFAULT_FEED_LIMIT,
+            feed_max_limit: MAX_FEED_LIMIT,
+            page_cache: None,
+            request_stats: Arc::new(RequestStats::default()),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_database_is_rejected() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("noreposts-corrupt-{}.db", uuid::Uuid::new_v4()));
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+
+        let db = Database::new(&url).await?;
+        db.migrate().await?;
+        for i in 0..500 {
+            sqlx::query("INSERT INTO follows VALUES (?, ?, ?, ?, ?)")
+                .bind(format!(
+                    "at://did:example:alice/app.bsky.graph.follow/{}",
+                    i
+                ))
+                .bind("did:example:alice")
+                .bind(format!("did:example:{}", i))
+                .bind(1_704_067_200_000_000_i64)
+                .bind(1_704_067_200_000_000_i64)
+                .execute(&db.pool)
+                .await?;
+        }
+        verify_database_integrity(&db, &url).await?;
+        sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+            .execute(&db.pool)
+            .await?;
+        let (root_page,): (i64,) =
+            sqlx::query_as("SELECT rootpage FROM sqlite_master WHERE name = 'follows'")
+                .fetch_one(&db.pool)
+                .await?;
+        db.pool.close().await;
+
+        // Scribble over the follows table's root page, leaving the schema readable
+        let mut bytes = std::fs::read(&path)?;
+        let start = (root_page as usize - 1) * 4096;
+        bytes[start..start + 4096].fill(0xA5);
+        std::fs::write(&path, bytes)?;
+
+        let db = Database::new(&url).await?;
+        let result = verify_database_integrity(&db, &url).await;
+        db.pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("--skip-integrity-check"), "{}", message);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_describe_feed_generator() -> Result<()> {
+        let mut state = test_state().await?;
+        state.feed_uris = vec![
+            "at://did:plc:pub/app.bsky.feed.generator/one".to_string(),
+            "at://did:plc:pub/app.bsky.feed.generator/two".to_string(),
+        ];
+
+        let Json(response) = describe_feed_generator(State(state.clone())).await;
+        let body = serde_json::to_value(&response)?;
+        assert_eq!(body["did"], state.service_did.as_str());
+        assert_eq!(
+            body["feeds"],
+            serde_json::json!([
+                { "uri": "at://did:plc:pub/app.bsky.feed.generator/one" },
+                { "uri": "at://did:plc:pub/app.bsky.feed.generator/two" },
+            ])
+        );
+
+        // Without explicit URIs, the registered feeds are listed under the publisher
+        let mut state = test_state().await?;
+        state.feed_publisher_did = Some("did:plc:pub".to_string());
+        let Json(response) = describe_feed_generator(State(state)).await;
+        let uris: Vec<String> = response.feeds.into_iter().map(|f| f.uri).collect();
+        assert_eq!(
+            uris,
+            vec![
+                "at://did:plc:pub/app.bsky.feed.generator/following-no-reposts",
+                "at://did:plc:pub/app.bsky.feed.generator/following-strict",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_service_identity() {
+        let some = |s: &str| Some(s.to_string());
+
+        // Either setting implies the other for did:web
+        assert_eq!(
+            service_identity(None, some("feed.example.com")).unwrap(),
+            (
+                "did:web:feed.example.com".to_string(),
+                some("feed.example.com")
+            )
+        );
+        assert_eq!(
+            service_identity(some("did:web:feed.example.com"), None).unwrap(),
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(types::ErrorResponse {
-                    error: "InternalServerError".to_string(),
-                    message: format!("Failed to generate feed: {}", e),
-                }),
+                "did:web:feed.example.com".to_string(),
+                some("feed.example.com")
             )
-                .into_response()
+        );
+        // A did:plc service DID says nothing about the host
+        assert_eq!(
+            service_identity(some("did:plc:abc"), None).unwrap(),
+            ("did:plc:abc".to_string(), None)
+        );
+
+        assert!(service_identity(None, None).is_err());
+        let message = service_identity(some("did:web:feed.example.com"), some("other.example.com"))
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("other.example.com"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_did_document() -> Result<()> {
+        let mut state = test_state().await?;
+        let Json(document) = did_document(State(state.clone())).await.unwrap();
+        let body = serde_json::to_value(&document)?;
+        assert_eq!(body["id"], "did:web:feed.example.com");
+        assert_eq!(
+            body["service"][0]["serviceEndpoint"],
+            "https://feed.example.com"
+        );
+
+        state.hostname = None;
+        assert_eq!(
+            did_document(State(state)).await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_describe_server() -> Result<()> {
+        let state = test_state().await?;
+        let service_did = state.service_did.clone();
+        let app = build_router(state, CorsLayer::permissive());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // No token needed
+        let response =
+            reqwest::get(format!("{}/xrpc/com.atproto.server.describeServer", base)).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await?;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "did": service_did,
+                "availableUserDomains": [],
+                "inviteCodeRequired": false,
+                "links": {},
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_non_positive_limits_are_rejected() -> Result<()> {
+        let app = build_router(test_state().await?, CorsLayer::permissive());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        for limit in ["0", "-5"] {
+            let response = reqwest::Client::new()
+                .get(format!("{}/xrpc/app.bsky.feed.getFeedSkeleton", base))
+                .query(&[
+                    ("feed", "at://did:example:feedgen/app.bsky.feed.generator/x"),
+                    ("limit", limit),
+                ])
+                .bearer_auth("token")
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = response.json().await?;
+            assert_eq!(body["error"], "InvalidRequest");
         }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cors_origins() -> Result<()> {
+        let cors = cors_layer("https://tools.example.com, https://other.example.com")?;
+        let app = build_router(test_state().await?, cors);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let allowed = |path: &'static str, origin: &'static str| {
+            let url = format!("{}{}", base, path);
+            async move {
+                let response = reqwest::Client::new()
+                    .get(url)
+                    .header(header::ORIGIN, origin)
+                    .send()
+                    .await?;
+                Ok::<_, anyhow::Error>(
+                    response
+                        .headers()
+                        .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                        .cloned(),
+                )
+            }
+        };
+        let describe = "/xrpc/app.bsky.feed.describeFeedGenerator";
+        assert_eq!(
+            allowed(describe, "https://tools.example.com").await?,
+            Some(HeaderValue::from_static("https://tools.example.com"))
+        );
+        assert_eq!(allowed(describe, "https://evil.example.com").await?, None);
+        assert_eq!(allowed("/health", "https://tools.example.com").await?, None);
+
+        assert!(cors_layer("*").is_ok());
+        assert!(cors_layer("https://bad\norigin").is_err());
+
+        Ok(())
     }
 }
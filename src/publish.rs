@@ -1,8 +1,54 @@
 use anyhow::{anyhow, Result};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::io::{self, Write};
 
+const FEED_GENERATOR_COLLECTION: &str = "app.bsky.feed.generator";
+
+/// Fields `publish update` can change; `None` keeps the current value
+#[derive(Debug, Clone, clap::Args)]
+pub struct UpdateArgs {
+    /// Record key of the feed generator record to update
+    #[arg(long)]
+    pub rkey: Option<String>,
+
+    #[arg(long, env = "BLUESKY_HANDLE")]
+    pub handle: Option<String>,
+
+    /// App password for the account that owns the record
+    #[arg(long, env = "BLUESKY_APP_PASSWORD", hide_env_values = true)]
+    pub password: Option<String>,
+
+    #[arg(long)]
+    pub display_name: Option<String>,
+
+    /// New description; an empty string removes it
+    #[arg(long)]
+    pub description: Option<String>,
+
+    #[arg(long, default_value = "https://bsky.social")]
+    pub pds_url: String,
+}
+
+/// The feed generator record `unpublish` deletes
+#[derive(Debug, Clone, clap::Args)]
+pub struct UnpublishArgs {
+    /// Record key of the feed generator record to delete
+    #[arg(long, alias = "rkey")]
+    pub record_name: Option<String>,
+
+    #[arg(long, env = "BLUESKY_HANDLE")]
+    pub handle: Option<String>,
+
+    /// App password for the account that owns the record
+    #[arg(long, env = "BLUESKY_APP_PASSWORD", hide_env_values = true)]
+    pub password: Option<String>,
+
+    #[arg(long, default_value = "https://bsky.social")]
+    pub pds_url: String,
+}
+
 #[derive(Debug, Serialize)]
 struct LoginRequest {
     identifier: String,
@@ -38,6 +84,244 @@ struct FeedGeneratorRecord {
     created_at: String,
 }
 
+/// Changes an existing feed generator record's metadata, leaving fields that
+/// weren't given as they are. Prompts for anything missing unless the record
+/// key, handle and password were all passed as flags.
+pub async fn update_feed(mut args: UpdateArgs) -> Result<()> {
+    let interactive = args.rkey.is_none() || args.handle.is_none() || args.password.is_none();
+    if interactive {
+        println!("=== Update Bluesky Feed Generator ===\n");
+        if args.handle.is_none() {
+            args.handle = Some(prompt("Enter your Bluesky handle: ")?);
+        }
+        if args.password.is_none() {
+            args.password = Some(prompt_password(
+                "Enter your Bluesky password (App Password): ",
+            )?);
+        }
+        if args.rkey.is_none() {
+            args.rkey = Some(prompt("Enter the record name of the feed to update: ")?);
+        }
+        if args.display_name.is_none() {
+            args.display_name = Some(prompt_optional("New display name (blank to keep): ")?)
+                .filter(|s| !s.is_empty());
+        }
+        if args.description.is_none() {
+            args.description = Some(prompt_optional("New description (blank to keep): ")?)
+                .filter(|s| !s.is_empty());
+        }
+    }
+
+    if args.display_name.is_none() && args.description.is_none() {
+        return Err(anyhow!(
+            "Nothing to update: pass --display-name and/or --description"
+        ));
+    }
+
+    let client = Client::new();
+    let (handle, password) = (
+        args.handle.unwrap_or_default(),
+        args.password.unwrap_or_default(),
+    );
+    let rkey = args.rkey.unwrap_or_default();
+    let session = login(&client, &args.pds_url, &handle, password).await?;
+    println!("✓ Logged in as {}", session.did);
+
+    update_feed_record(
+        &client,
+        &args.pds_url,
+        &session,
+        &rkey,
+        args.display_name.as_deref(),
+        args.description.as_deref(),
+    )
+    .await?;
+
+    println!(
+        "\n✅ Updated at://{}/{}/{}",
+        session.did, FEED_GENERATOR_COLLECTION, rkey
+    );
+    Ok(())
+}
+
+/// Deletes a feed generator record, prompting for whatever wasn't passed
+/// as a flag
+pub async fn unpublish_feed(mut args: UnpublishArgs) -> Result<()> {
+    if args.handle.is_none() || args.password.is_none() || args.record_name.is_none() {
+        println!("=== Unpublish Bluesky Feed Generator ===\n");
+    }
+    let handle = match args.handle.take() {
+        Some(handle) => handle,
+        None => prompt("Enter your Bluesky handle: ")?,
+    };
+    let password = match args.password.take() {
+        Some(password) => password,
+        None => prompt_password("Enter your Bluesky password (App Password): ")?,
+    };
+    let rkey = match args.record_name.take() {
+        Some(rkey) => rkey,
+        None => prompt("Enter the record name of the feed to delete: ")?,
+    };
+
+    let client = Client::new();
+    let session = login(&client, &args.pds_url, &handle, password).await?;
+    println!("✓ Logged in as {}", session.did);
+
+    let uri = delete_feed_record(&client, &args.pds_url, &session, &rkey).await?;
+    println!("\n✅ Deleted {}", uri);
+    Ok(())
+}
+
+async fn login(
+    client: &Client,
+    pds_url: &str,
+    handle: &str,
+    password: String,
+) -> Result<LoginResponse> {
+    let response = client
+        .post(format!("{}/xrpc/com.atproto.server.createSession", pds_url))
+        .json(&LoginRequest {
+            identifier: handle.to_string(),
+            password,
+        })
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Login failed: {}", response.text().await?));
+    }
+    Ok(response.json().await?)
+}
+
+/// Fetches a feed generator record with its CID, or `None` if it doesn't exist
+async fn get_feed_record(
+    client: &Client,
+    pds_url: &str,
+    repo: &str,
+    rkey: &str,
+) -> Result<Option<(Value, String)>> {
+    let response = client
+        .get(format!("{}/xrpc/com.atproto.repo.getRecord", pds_url))
+        .query(&[
+            ("repo", repo),
+            ("collection", FEED_GENERATOR_COLLECTION),
+            ("rkey", rkey),
+        ])
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or_default();
+        if body["error"] == "RecordNotFound" || status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        return Err(anyhow!("Failed to fetch feed record: {} {}", status, body));
+    }
+
+    let body: Value = response.json().await?;
+    let cid = body["cid"].as_str().unwrap_or_default().to_string();
+    Ok(Some((body["value"].clone(), cid)))
+}
+
+/// Merges the given fields into the stored record and writes it back. The
+/// write is tied to the CID we read, so a concurrent change isn't lost.
+async fn update_feed_record(
+    client: &Client,
+    pds_url: &str,
+    session: &LoginResponse,
+    rkey: &str,
+    display_name: Option<&str>,
+    description: Option<&str>,
+) -> Result<Value> {
+    let Some((mut record, cid)) = get_feed_record(client, pds_url, &session.did, rkey).await?
+    else {
+        return Err(anyhow!(
+            "No feed generator record {} in {}; use `publish create` to create it",
+            rkey,
+            session.did
+        ));
+    };
+
+    if let Some(display_name) = display_name {
+        record["displayName"] = Value::from(display_name);
+    }
+    match description {
+        Some("") => {
+            if let Some(record) = record.as_object_mut() {
+                record.remove("description");
+            }
+        }
+        Some(description) => record["description"] = Value::from(description),
+        None => {}
+    }
+
+    let response = client
+        .post(format!("{}/xrpc/com.atproto.repo.putRecord", pds_url))
+        .bearer_auth(&session.access_jwt)
+        .json(&serde_json::json!({
+            "repo": session.did,
+            "collection": FEED_GENERATOR_COLLECTION,
+            "rkey": rkey,
+            "record": record,
+            "swapRecord": cid,
+        }))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Failed to update feed record: {}",
+            response.text().await?
+        ));
+    }
+    Ok(record)
+}
+
+/// Deletes a feed generator record and checks it is gone, returning its
+/// AT-URI. Fails if there was no such record.
+async fn delete_feed_record(
+    client: &Client,
+    pds_url: &str,
+    session: &LoginResponse,
+    rkey: &str,
+) -> Result<String> {
+    let uri = format!(
+        "at://{}/{}/{}",
+        session.did, FEED_GENERATOR_COLLECTION, rkey
+    );
+    if get_feed_record(client, pds_url, &session.did, rkey)
+        .await?
+        .is_none()
+    {
+        return Err(anyhow!("No feed generator record {}", uri));
+    }
+
+    let response = client
+        .post(format!("{}/xrpc/com.atproto.repo.deleteRecord", pds_url))
+        .bearer_auth(&session.access_jwt)
+        .json(&serde_json::json!({
+            "repo": session.did,
+            "collection": FEED_GENERATOR_COLLECTION,
+            "rkey": rkey,
+        }))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Failed to delete feed record: {}",
+            response.text().await?
+        ));
+    }
+
+    if get_feed_record(client, pds_url, &session.did, rkey)
+        .await?
+        .is_some()
+    {
+        return Err(anyhow!("{} is still there after deleting it", uri));
+    }
+    Ok(uri)
+}
+
 pub async fn publish_feed() -> Result<()> {
     println!("=== Bluesky Feed Generator Publisher ===\n");
 
@@ -62,16 +346,7 @@ pub async fn publish_feed() -> Result<()> {
     let pds_url = "https://bsky.social";
 
     // Login to get session
-    let login_response: LoginResponse = client
-        .post(format!("{}/xrpc/com.atproto.server.createSession", pds_url))
-        .json(&LoginRequest {
-            identifier: handle.clone(),
-            password,
-        })
-        .send()
-        .await?
-        .json()
-        .await?;
+    let login_response = login(&client, pds_url, &handle, password).await?;
 
     println!("✓ Logged in as {}", login_response.did);
 
@@ -152,3 +427,163 @@ fn prompt_password(message: &str) -> Result<String> {
     io::stdin().read_line(&mut password)?;
     Ok(password.trim().to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::Query,
+        http::StatusCode,
+        routing::{get, post},
+        Json, Router,
+    };
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Serves one stored feed record, keeping every putRecord and
+    /// deleteRecord body
+    async fn spawn_pds(
+        puts: Arc<Mutex<Vec<Value>>>,
+        deletes: Arc<Mutex<Vec<Value>>>,
+    ) -> Result<String> {
+        let deleted = Arc::clone(&deletes);
+        let app = Router::new()
+            .route(
+                "/xrpc/com.atproto.server.createSession",
+                post(|| async {
+                    Json(serde_json::json!({
+                        "accessJwt": "jwt",
+                        "did": "did:example:alice",
+                        "handle": "alice.example.com",
+                    }))
+                }),
+            )
+            .route(
+                "/xrpc/com.atproto.repo.getRecord",
+                get(
+                    move |Query(params): Query<HashMap<String, String>>| async move {
+                        if params.get("rkey").map(String::as_str) != Some("following")
+                            || !deleted.lock().unwrap().is_empty()
+                        {
+                            let body = serde_json::json!({ "error": "RecordNotFound" });
+                            return (StatusCode::BAD_REQUEST, Json(body));
+                        }
+                        let body = serde_json::json!({
+                            "uri": "at://did:example:alice/app.bsky.feed.generator/following",
+                            "cid": "bafy-old",
+                            "value": {
+                                "$type": "app.bsky.feed.generator",
+                                "did": "did:web:feed.example.com",
+                                "displayName": "Following",
+                                "description": "Posts from people you follow",
+                                "avatar": { "ref": "blob" },
+                                "createdAt": "2024-01-01T00:00:00Z",
+                            },
+                        });
+                        (StatusCode::OK, Json(body))
+                    },
+                ),
+            )
+            .route(
+                "/xrpc/com.atproto.repo.putRecord",
+                post(move |Json(body): Json<Value>| {
+                    let puts = Arc::clone(&puts);
+                    async move {
+                        puts.lock().unwrap().push(body);
+                        Json(serde_json::json!({ "uri": "at://x", "cid": "bafy-new" }))
+                    }
+                }),
+            )
+            .route(
+                "/xrpc/com.atproto.repo.deleteRecord",
+                post(move |Json(body): Json<Value>| {
+                    let deletes = Arc::clone(&deletes);
+                    async move {
+                        deletes.lock().unwrap().push(body);
+                        Json(serde_json::json!({}))
+                    }
+                }),
+            )
+            .into_make_service();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Ok(format!("http://{}", addr))
+    }
+
+    #[tokio::test]
+    async fn test_update_changes_only_given_fields() -> Result<()> {
+        let puts = Arc::new(Mutex::new(Vec::new()));
+        let pds = spawn_pds(Arc::clone(&puts), Default::default()).await?;
+        let client = Client::new();
+        let session = login(&client, &pds, "alice.example.com", "pw".to_string()).await?;
+
+        update_feed_record(
+            &client,
+            &pds,
+            &session,
+            "following",
+            Some("Following+"),
+            None,
+        )
+        .await?;
+        let put = puts.lock().unwrap().pop().unwrap();
+        assert_eq!(put["rkey"], "following");
+        assert_eq!(put["swapRecord"], "bafy-old");
+        assert_eq!(put["record"]["displayName"], "Following+");
+        assert_eq!(put["record"]["description"], "Posts from people you follow");
+        assert_eq!(put["record"]["avatar"]["ref"], "blob");
+        assert_eq!(put["record"]["createdAt"], "2024-01-01T00:00:00Z");
+
+        // An empty description removes it
+        update_feed_record(&client, &pds, &session, "following", None, Some("")).await?;
+        let put = puts.lock().unwrap().pop().unwrap();
+        assert_eq!(put["record"]["displayName"], "Following");
+        assert!(put["record"].get("description").is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_missing_record_fails() -> Result<()> {
+        let puts = Arc::new(Mutex::new(Vec::new()));
+        let pds = spawn_pds(Arc::clone(&puts), Default::default()).await?;
+        let client = Client::new();
+        let session = login(&client, &pds, "alice.example.com", "pw".to_string()).await?;
+
+        let err = update_feed_record(&client, &pds, &session, "missing", Some("New"), None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("publish create"));
+        assert!(puts.lock().unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_feed_record() -> Result<()> {
+        let deletes = Arc::new(Mutex::new(Vec::new()));
+        let pds = spawn_pds(Default::default(), Arc::clone(&deletes)).await?;
+        let client = Client::new();
+        let session = login(&client, &pds, "alice.example.com", "pw".to_string()).await?;
+
+        let err = delete_feed_record(&client, &pds, &session, "missing")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("No feed generator record"));
+        assert!(deletes.lock().unwrap().is_empty());
+
+        let uri = delete_feed_record(&client, &pds, &session, "following").await?;
+        assert_eq!(
+            uri,
+            "at://did:example:alice/app.bsky.feed.generator/following"
+        );
+        let delete = deletes.lock().unwrap().pop().unwrap();
+        assert_eq!(delete["repo"], "did:example:alice");
+        assert_eq!(delete["collection"], "app.bsky.feed.generator");
+        assert_eq!(delete["rkey"], "following");
+
+        Ok(())
+    }
+}
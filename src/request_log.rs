@@ -0,0 +1,164 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+use std::time::Instant;
+use tracing::{info, Instrument};
+
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Short, stable stand-in for a DID so logs can correlate requests without
+/// recording who made them
+pub fn hash_did(did: &str) -> String {
+    Sha256::digest(did.as_bytes())[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Logs one line per request with its outcome and latency, and tags the
+/// request with an id that downstream logs inherit through the span.
+/// Feed requests fill in the span's `feed` and hashed `requester`. A
+/// caller's own `X-Request-ID` is kept when it is a UUID, so logs can be
+/// matched across services. Headers are otherwise left out of the logs so
+/// credentials never reach them.
+pub async fn log_requests(req: Request, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| uuid::Uuid::parse_str(value).ok())
+        .unwrap_or_else(uuid::Uuid::new_v4);
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        feed = tracing::field::Empty,
+        requester = tracing::field::Empty,
+    );
+    let started = Instant::now();
+
+    let mut response = next.run(req).instrument(span.clone()).await;
+
+    span.in_scope(|| {
+        info!(
+            method = %method,
+            path = %path,
+            status = response.status().as_u16(),
+            latency_ms = started.elapsed().as_millis() as u64,
+            "Request handled"
+        );
+    });
+
+    if let Ok(value) = HeaderValue::from_str(&request_id.to_string()) {
+        response
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER.clone(), value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+    use tracing::field::display;
+
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_id_header() -> anyhow::Result<()> {
+        let app = Router::new()
+            .route("/ok", get(|| async { "ok" }))
+            .route("/denied", get(|| async { StatusCode::UNAUTHORIZED }))
+            .layer(middleware::from_fn(log_requests));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        let client = reqwest::Client::new();
+        let mut ids = Vec::new();
+        for (path, status) in [("/ok", 200), ("/denied", 401)] {
+            let response = client.get(format!("{}{}", base, path)).send().await?;
+            assert_eq!(response.status().as_u16(), status);
+            let id = response.headers()["x-request-id"].to_str()?.to_string();
+            uuid::Uuid::parse_str(&id)?;
+            ids.push(id);
+        }
+        assert_ne!(ids[0], ids[1]);
+
+        // A caller's UUID is kept; anything else is replaced
+        let given = uuid::Uuid::new_v4().to_string();
+        let response = client
+            .get(format!("{}/ok", base))
+            .header("x-request-id", &given)
+            .send()
+            .await?;
+        assert_eq!(response.headers()["x-request-id"], given.as_str());
+        let response = client
+            .get(format!("{}/ok", base))
+            .header("x-request-id", "not-a-uuid")
+            .send()
+            .await?;
+        uuid::Uuid::parse_str(response.headers()["x-request-id"].to_str()?)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handler_logs_carry_request_id() -> anyhow::Result<()> {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route(
+                "/feed",
+                get(|| async {
+                    tracing::Span::current().record("feed", display("at://feed"));
+                    info!("Inside the handler");
+                    "ok"
+                }),
+            )
+            .layer(middleware::from_fn(log_requests));
+        let id = uuid::Uuid::new_v4().to_string();
+        let request = Request::get("/feed")
+            .header("x-request-id", &id)
+            .body(Body::empty())?;
+        app.oneshot(request).await?;
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone())?;
+        let span = format!("request{{request_id={} feed=at://feed}}", id);
+        for message in ["Inside the handler", "Request handled"] {
+            let line = logs.lines().find(|line| line.contains(message)).unwrap();
+            assert!(line.contains(&span), "{}", line);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+use anyhow::{anyhow, Result};
+use sqlx::Row;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    api_budget::ApiBudget,
+    backfill::{self, BackfillLimits, BackfillTracker},
+    database::Database,
+    feed_algorithm::{FeedRegistry, DEFAULT_FEED_LIMIT, DEFAULT_RETENTION_HOURS, MAX_FEED_LIMIT},
+    identity::IdentityCache,
+    jetstream_consumer::JetstreamEventHandler,
+    stats::RequestStats,
+    types::FeedFilter,
+    AppState,
+};
+
+/// bsky.app, which is always resolvable and always has posts
+pub const DEFAULT_TEST_DID: &str = "did:plc:z72i7hdynmk6r22z27h6tvur";
+
+pub struct SelfTestConfig {
+    pub jetstream_hostname: String,
+    pub service_did: String,
+    pub test_did: String,
+    pub timeout: Duration,
+}
+
+/// Outcome of a single self-test step
+#[derive(Debug)]
+pub struct StepResult {
+    pub name: &'static str,
+    pub passed: bool,
+    pub elapsed: Duration,
+    pub detail: String,
+}
+
+#[derive(Debug, Default)]
+pub struct SelfTestReport {
+    pub steps: Vec<StepResult>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        !self.steps.is_empty() && self.steps.iter().all(|step| step.passed)
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Self-test report:")?;
+        for step in &self.steps {
+            writeln!(
+                f,
+                "  [{}] {} ({} ms): {}",
+                if step.passed { "PASS" } else { "FAIL" },
+                step.name,
+                step.elapsed.as_millis(),
+                step.detail
+            )?;
+        }
+        let passed = self.steps.iter().filter(|step| step.passed).count();
+        writeln!(
+            f,
+            "{}/{} steps passed: {}",
+            passed,
+            self.steps.len(),
+            if self.passed() { "PASS" } else { "FAIL" }
+        )
+    }
+}
+
+/// Runs steps one after another, all sharing one global deadline
+pub struct StepRunner {
+    deadline: Instant,
+    report: SelfTestReport,
+}
+
+impl StepRunner {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            deadline: Instant::now() + timeout,
+            report: SelfTestReport::default(),
+        }
+    }
+
+    /// Runs a step that returns a short description of what it checked
+    pub async fn run<F, Fut>(&mut self, name: &'static str, step: F)
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<String>>,
+    {
+        let started = Instant::now();
+        let (passed, detail) = if started >= self.deadline {
+            (false, "skipped: global timeout exceeded".to_string())
+        } else {
+            match tokio::time::timeout(self.deadline - started, step()).await {
+                Ok(Ok(detail)) => (true, detail),
+                Ok(Err(e)) => (false, e.to_string()),
+                Err(_) => (false, "timed out: global timeout exceeded".to_string()),
+            }
+        };
+
+        self.report.steps.push(StepResult {
+            name,
+            passed,
+            elapsed: started.elapsed(),
+            detail,
+        });
+    }
+
+    pub fn finish(self) -> SelfTestReport {
+        self.report
+    }
+}
+
+/// Checks the live environment through the production code paths, using a
+/// throwaway database
+pub async fn run(config: SelfTestConfig) -> Result<SelfTestReport> {
+    let db_path =
+        std::env::temp_dir().join(format!("noreposts-self-test-{}.db", uuid::Uuid::new_v4()));
+    let db = Arc::new(Database::new(&format!("sqlite://{}?mode=rwc", db_path.display())).await?);
+    db.migrate().await?;
+
+    let budget = Arc::new(ApiBudget::default());
+    let identity = Arc::new(IdentityCache::default());
+    let mut runner = StepRunner::new(config.timeout);
+
+    runner
+        .run("jetstream", || async {
+            JetstreamEventHandler::new(Arc::clone(&db), Arc::clone(&identity))
+                .receive_one(&config.jetstream_hostname)
+                .await?;
+            Ok(format!(
+                "received an event from {}",
+                config.jetstream_hostname
+            ))
+        })
+        .await;
+
+    runner
+        .run("resolve-did", || async {
+            let resolved = identity.resolve(&config.test_did).await?;
+            Ok(format!(
+                "{} is hosted on {}",
+                config.test_did, resolved.pds_endpoint
+            ))
+        })
+        .await;
+
+    runner
+        .run("backfill", || async {
+            backfill::backfill_posts(
+                Arc::clone(&db),
+                Arc::clone(&budget),
+                Arc::clone(&identity),
+                &config.test_did,
+                5,
+            )
+            .await?;
+            let count: i64 =
+                sqlx::query("SELECT COUNT(*) as count FROM posts WHERE author_did = ?")
+                    .bind(&config.test_did)
+                    .fetch_one(&db.pool)
+                    .await?
+                    .try_get("count")?;
+            if count == 0 {
+                return Err(anyhow!("no posts stored for {}", config.test_did));
+            }
+            Ok(format!("stored {} posts for {}", count, config.test_did))
+        })
+        .await;
+
+    runner
+        .run("http", || async {
+            let state = AppState {
+                db: Arc::clone(&db),
+                budget: Arc::clone(&budget),
+                identity: Arc::clone(&identity),
+                replay_guard: None,
+                service_did: config.service_did.clone(),
+                hostname: Some(
+                    config
+                        .service_did
+                        .strip_prefix("did:web:")
+                        .unwrap_or("localhost")
+                        .to_string(),
+                ),
+                feed_publisher_did: None,
+                feed_uris: Vec::new(),
+                feeds: Arc::new(
+                    FeedRegistry::default().register("self-test", FeedFilter::default()),
+                ),
+                default_retention_hours: DEFAULT_RETENTION_HOURS,
+                keep_posts_per_author: 0,
+                include_reply_parents: false,
+                collapse_duplicate_text: false,
+                backfills: BackfillTracker::default(),
+                backfill_limits: BackfillLimits::default(),
+                backfill_placeholder_uri: None,
+                feed_default_limit: DEFAULT_FEED_LIMIT,
+                feed_max_limit: MAX_FEED_LIMIT,
+                page_cache: None,
+                request_stats: Arc::new(RequestStats::default()),
+            };
+            check_http(state, &config.service_did).await
+        })
+        .await;
+
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", db_path.display(), suffix));
+    }
+
+    Ok(runner.finish())
+}
+
+/// Boots the router on an ephemeral port and exercises the public endpoints
+async fn check_http(state: AppState, service_did: &str) -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let base = format!("http://{}", listener.local_addr()?);
+    let server = tokio::spawn(async move {
+        axum::serve(
+            listener,
+            crate::build_router(state, tower_http::cors::CorsLayer::permissive()),
+        )
+        .await
+    });
+
+    let result = async {
+        let client = reqwest::Client::new();
+
+        client
+            .get(format!("{}/health", base))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let doc: serde_json::Value = client
+            .get(format!("{}/.well-known/did.json", base))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if doc["id"].as_str() != Some(service_did) {
+            return Err(anyhow!("did.json has id {} instead of {}", doc["id"], service_did));
+        }
+
+        let skeleton = client
+            .get(format!(
+                "{}/xrpc/app.bsky.feed.getFeedSkeleton?feed=at://{}/app.bsky.feed.generator/self-test",
+                base, service_did
+            ))
+            .send()
+            .await?;
+        if skeleton.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Err(anyhow!(
+                "unauthenticated skeleton request returned {} instead of 401",
+                skeleton.status()
+            ));
+        }
+
+        Ok("/health, /.well-known/did.json and unauthenticated getFeedSkeleton behave".to_string())
+    }
+    .await;
+
+    server.abort();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_report_assembly() {
+        let mut runner = StepRunner::new(Duration::from_secs(5));
+        runner.run("ok", || async { Ok("fine".to_string()) }).await;
+        runner
+            .run("broken", || async { Err(anyhow!("connection refused")) })
+            .await;
+
+        let report = runner.finish();
+        assert!(!report.passed());
+        assert!(report.steps[0].passed);
+        assert_eq!(report.steps[1].detail, "connection refused");
+
+        let text = report.to_string();
+        assert!(text.contains("[PASS] ok"));
+        assert!(text.contains("[FAIL] broken"));
+        assert!(text.contains("1/2 steps passed: FAIL"));
+
+        let mut runner = StepRunner::new(Duration::from_secs(5));
+        runner.run("ok", || async { Ok("fine".to_string()) }).await;
+        assert!(runner.finish().passed());
+        assert!(!SelfTestReport::default().passed());
+    }
+
+    #[tokio::test]
+    async fn test_global_timeout() {
+        let started = std::time::Instant::now();
+        let mut runner = StepRunner::new(Duration::from_millis(50));
+        runner
+            .run("slow", || async {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                Ok("never".to_string())
+            })
+            .await;
+        runner
+            .run("after", || async { Ok("never runs".to_string()) })
+            .await;
+
+        let report = runner.finish();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!report.steps[0].passed);
+        assert!(report.steps[0].detail.starts_with("timed out"));
+        assert!(!report.steps[1].passed);
+        assert!(report.steps[1].detail.starts_with("skipped"));
+    }
+}
@@ -0,0 +1,190 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the latency buckets, in milliseconds. Anything slower
+/// than the last is counted in it.
+pub const LATENCY_BUCKETS_MS: [u64; 8] = [1, 5, 10, 50, 100, 500, 1000, 5000];
+
+/// Request latencies counted into fixed buckets
+#[derive(Debug, Default, Clone)]
+pub struct LatencyHistogram {
+    counts: [u64; 8],
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, ms: u64) {
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len() - 1);
+        self.counts[bucket] += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Upper bound of the bucket holding the `p`th percentile (0-100), or 0
+    /// when nothing has been recorded
+    pub fn percentile(&self, p: f64) -> u64 {
+        let total = self.count();
+        if total == 0 {
+            return 0;
+        }
+        let rank = ((p / 100.0 * total as f64).ceil() as u64).clamp(1, total);
+        let mut seen = 0;
+        for (count, bound) in self.counts.iter().zip(LATENCY_BUCKETS_MS) {
+            seen += count;
+            if seen >= rank {
+                return bound;
+            }
+        }
+        LATENCY_BUCKETS_MS[LATENCY_BUCKETS_MS.len() - 1]
+    }
+}
+
+/// Counts and latencies of the HTTP endpoints, for the admin console
+#[derive(Debug, Default)]
+pub struct RequestStats {
+    pub feed_requests: AtomicU64,
+    /// Feed requests answered from the page cache
+    pub feed_cache_hits: AtomicU64,
+    pub did_document_requests: AtomicU64,
+    pub auth_failures: AtomicU64,
+    /// Keyed by the route that matched, not the raw path
+    latency: Mutex<BTreeMap<String, LatencyHistogram>>,
+}
+
+impl RequestStats {
+    pub fn record_latency(&self, route: &str, elapsed: Duration) {
+        self.latency
+            .lock()
+            .unwrap()
+            .entry(route.to_string())
+            .or_default()
+            .record(elapsed.as_millis() as u64);
+    }
+
+    pub fn format_stats(&self) -> String {
+        let mut stats = format!(
+            "Request Statistics:\n  Feed requests: {} ({} from cache)\n  DID document requests: {}\n  Auth failures: {}\n  Latency by endpoint:\n",
+            self.feed_requests.load(Ordering::Relaxed),
+            self.feed_cache_hits.load(Ordering::Relaxed),
+            self.did_document_requests.load(Ordering::Relaxed),
+            self.auth_failures.load(Ordering::Relaxed),
+        );
+        for (route, latency) in self.latency.lock().unwrap().iter() {
+            stats.push_str(&format!(
+                "    {}: {} requests, p50 <= {}ms, p95 <= {}ms, p99 <= {}ms\n",
+                route,
+                latency.count(),
+                latency.percentile(50.0),
+                latency.percentile(95.0),
+                latency.percentile(99.0)
+            ));
+        }
+        stats
+    }
+}
+
+/// Records each request's latency under the route it matched. Added with
+/// `route_layer`, so requests for unknown paths don't each get a histogram.
+pub async fn record_latencies(
+    State(stats): State<Arc<RequestStats>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let started = Instant::now();
+    let response = next.run(req).await;
+    if let Some(route) = route {
+        stats.record_latency(&route, started.elapsed());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentile(50.0), 0);
+
+        // 90 fast requests, 9 at 200ms and one very slow one
+        for _ in 0..90 {
+            histogram.record(3);
+        }
+        for _ in 0..9 {
+            histogram.record(200);
+        }
+        histogram.record(60_000);
+
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.percentile(50.0), 5);
+        assert_eq!(histogram.percentile(90.0), 5);
+        assert_eq!(histogram.percentile(95.0), 500);
+        assert_eq!(histogram.percentile(99.0), 500);
+        assert_eq!(histogram.percentile(100.0), 5000);
+        assert_eq!(histogram.percentile(0.0), 5);
+    }
+
+    #[tokio::test]
+    async fn test_latency_per_route() -> anyhow::Result<()> {
+        use axum::{body::Body, middleware, routing::get, Router};
+        use tower::ServiceExt;
+
+        let stats = Arc::new(RequestStats::default());
+        let app = Router::new()
+            .route("/", get(|| async { "root" }))
+            .route("/health", get(|| async { "ok" }))
+            .route("/posts/{id}", get(|| async { "post" }))
+            .route_layer(middleware::from_fn_with_state(
+                Arc::clone(&stats),
+                record_latencies,
+            ));
+        for path in [
+            "/", "/health", "/health", "/posts/1", "/posts/2", "/missing",
+        ] {
+            let request = Request::get(path).body(Body::empty())?;
+            app.clone().oneshot(request).await?;
+        }
+
+        let count = |route| {
+            let latency = stats.latency.lock().unwrap();
+            latency.get(route).map(LatencyHistogram::count)
+        };
+        assert_eq!(count("/"), Some(1));
+        assert_eq!(count("/health"), Some(2));
+        // Counted under the route, whatever the path parameters
+        assert_eq!(count("/posts/{id}"), Some(2));
+        assert_eq!(count("/missing"), None);
+        assert!(stats
+            .format_stats()
+            .contains("    /health: 2 requests, p50 <= 1ms"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bucket_bounds_are_inclusive() {
+        let mut histogram = LatencyHistogram::default();
+        for ms in [0, 1] {
+            histogram.record(ms);
+        }
+        assert_eq!(histogram.percentile(100.0), 1);
+        histogram.record(2);
+        assert_eq!(histogram.percentile(100.0), 5);
+    }
+}
@@ -1,22 +1,50 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Debug, Deserialize)]
 pub struct FeedSkeletonParams {
     pub feed: String,
     pub limit: Option<i32>,
     pub cursor: Option<String>,
+    /// Most posts to show from any one author, up to `MAX_POSTS_PER_AUTHOR`
+    pub max_posts_per_author: Option<i32>,
 }
 
-#[derive(Debug, Serialize)]
+/// Largest per-author cap a feed request can ask for
+pub const MAX_POSTS_PER_AUTHOR: i32 = 10;
+
+#[derive(Debug, Clone, Serialize)]
 pub struct FeedSkeletonResponse {
     pub cursor: Option<String>,
     pub feed: Vec<SkeletonFeedPost>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct SkeletonFeedPost {
     pub post: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<SkeletonReason>,
+    /// Thread context for a reply, so clients can fetch the thread
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply: Option<SkeletonReplyRef>,
+    /// Opaque data the client sends back with interactions on this item
+    #[serde(rename = "feedContext", skip_serializing_if = "Option::is_none")]
+    pub feed_context: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkeletonReplyRef {
+    pub root: String,
+    pub parent: String,
+}
+
+/// Why a post is in the skeleton out of chronological order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "$type")]
+pub enum SkeletonReason {
+    #[serde(rename = "app.bsky.feed.defs#skeletonReasonPin")]
+    Pin,
 }
 
 #[derive(Debug, Serialize)]
@@ -42,10 +70,230 @@ pub struct Post {
     pub cid: String,
     pub author_did: String,
     pub text: String,
+    pub reply_parent_uri: Option<String>,
+    /// First post of the thread a reply belongs to
+    pub reply_root_uri: Option<String>,
+    pub quoted_uri: Option<String>,
+    pub is_link_only: bool,
+    pub has_media: bool,
+    pub embed_type: Option<String>,
+    /// Lowercased hashtags from the post's tag facets
+    pub hashtags: Vec<String>,
     pub created_at: DateTime<Utc>,
     pub indexed_at: DateTime<Utc>,
 }
 
+impl Post {
+    /// Whether the post claims to be from more than `max_skew` after it was
+    /// indexed. Spam dates posts ahead so they stay on top of feeds.
+    pub fn is_future_dated(&self, max_skew: chrono::Duration) -> bool {
+        self.created_at > self.indexed_at + max_skew
+    }
+
+    /// Thread context of a reply. Posts stored before roots were recorded
+    /// have none.
+    pub fn reply_ref(&self) -> Option<SkeletonReplyRef> {
+        Some(SkeletonReplyRef {
+            root: self.reply_root_uri.clone()?,
+            parent: self.reply_parent_uri.clone()?,
+        })
+    }
+
+    /// DID of the account a quote post quotes
+    pub fn quoted_author_did(&self) -> Option<&str> {
+        self.quoted_uri
+            .as_deref()?
+            .strip_prefix("at://")?
+            .split('/')
+            .next()
+    }
+
+    /// URI of the record a quote post embeds, if any
+    pub fn quoted_uri_of(record: &serde_json::Value) -> Option<String> {
+        let embed = &record["embed"];
+        let uri = match embed["$type"].as_str()? {
+            "app.bsky.embed.record" => embed["record"]["uri"].as_str(),
+            "app.bsky.embed.recordWithMedia" => embed["record"]["record"]["uri"].as_str(),
+            _ => None,
+        }?;
+        Some(uri.to_string())
+    }
+
+    /// Hashtags from a post record's tag facets, lowercased and deduplicated
+    pub fn hashtags_of(record: &serde_json::Value) -> Vec<String> {
+        let mut tags: Vec<String> = Vec::new();
+        for facet in record["facets"].as_array().into_iter().flatten() {
+            for feature in facet["features"].as_array().into_iter().flatten() {
+                if feature["$type"].as_str() != Some("app.bsky.richtext.facet#tag") {
+                    continue;
+                }
+                // Commas separate tags in storage, so they can't be part of one
+                let tag = feature["tag"]
+                    .as_str()
+                    .unwrap_or("")
+                    .trim_start_matches('#')
+                    .to_lowercase()
+                    .replace(',', "");
+                if !tag.is_empty() && !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+        }
+        tags
+    }
+
+    /// The `$type` of a post's embed, if it has one
+    pub fn embed_type_of(record: &serde_json::Value) -> Option<String> {
+        record["embed"]["$type"].as_str().map(|s| s.to_string())
+    }
+
+    /// Whether a post carries images or video. A quote post only counts when
+    /// it attaches media of its own.
+    pub fn has_media_record(record: &serde_json::Value) -> bool {
+        let is_media = |embed: &serde_json::Value| {
+            matches!(
+                embed["$type"].as_str(),
+                Some("app.bsky.embed.images" | "app.bsky.embed.video")
+            )
+        };
+
+        let embed = &record["embed"];
+        match embed["$type"].as_str() {
+            Some("app.bsky.embed.recordWithMedia") => is_media(&embed["media"]),
+            _ => is_media(embed),
+        }
+    }
+
+    /// Whether a post is just a link card: an external embed and no text
+    /// besides the link itself
+    pub fn is_link_only_record(record: &serde_json::Value) -> bool {
+        if record["embed"]["$type"].as_str() != Some("app.bsky.embed.external") {
+            return false;
+        }
+
+        let text = record["text"].as_str().unwrap_or("");
+        let mut remaining = text.as_bytes().to_vec();
+
+        // Link facets cover the (possibly shortened) URL text
+        for facet in record["facets"].as_array().into_iter().flatten() {
+            let is_link = facet["features"].as_array().is_some_and(|features| {
+                features
+                    .iter()
+                    .any(|f| f["$type"].as_str() == Some("app.bsky.richtext.facet#link"))
+            });
+            let start = facet["index"]["byteStart"].as_u64().unwrap_or(0) as usize;
+            let end = facet["index"]["byteEnd"].as_u64().unwrap_or(0) as usize;
+            if is_link && start < end && end <= remaining.len() {
+                remaining[start..end].fill(b' ');
+            }
+        }
+
+        String::from_utf8_lossy(&remaining)
+            .split_whitespace()
+            .all(|word| word.starts_with("http://") || word.starts_with("https://"))
+    }
+}
+
+/// Which kinds of posts a feed lets through. Reposts are never stored, so
+/// every feed leaves them out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FeedFilter {
+    pub replies: bool,
+    pub quotes: bool,
+    pub link_only: bool,
+    /// Most posts shown per author per UTC day, keeping the newest
+    pub author_daily_cap: Option<i64>,
+    /// Most posts shown per author in the whole feed, keeping the newest
+    pub max_posts_per_author: Option<i64>,
+    /// Quote posts of accounts the user doesn't follow
+    pub stranger_quotes: bool,
+    /// Only posts with images or video
+    pub media_only: bool,
+    /// Fewest user-perceived characters a post's text needs; media posts are exempt
+    pub min_post_length: Option<usize>,
+    /// The user's own posts, whether or not they follow themselves
+    pub include_self: bool,
+    /// Only posts created within this many hours, however long they're kept
+    pub max_age_hours: Option<i64>,
+    /// Only posts created within this many hours, or kept by cleanup as one
+    /// of their author's newest `kept_per_author`
+    pub retention_hours: Option<i64>,
+    pub kept_per_author: i64,
+}
+
+impl FeedFilter {
+    /// Only original, standalone posts with something to say
+    pub fn strict() -> Self {
+        Self {
+            replies: false,
+            quotes: false,
+            link_only: false,
+            author_daily_cap: None,
+            max_posts_per_author: None,
+            stranger_quotes: false,
+            media_only: false,
+            min_post_length: None,
+            include_self: false,
+            max_age_hours: None,
+            retention_hours: None,
+            kept_per_author: 0,
+        }
+    }
+
+    /// Only posts with images or video attached
+    pub fn media() -> Self {
+        Self {
+            media_only: true,
+            ..Self::default()
+        }
+    }
+
+    /// This feed's filter as a user with these preferences sees it. A
+    /// per-author cap the request asked for wins over the user's own.
+    pub fn with_preferences(self, preferences: &UserPreferences) -> Self {
+        Self {
+            author_daily_cap: preferences.author_daily_cap,
+            max_posts_per_author: self
+                .max_posts_per_author
+                .or(preferences.max_posts_per_author),
+            stranger_quotes: self.stranger_quotes && !preferences.hide_stranger_quotes,
+            min_post_length: preferences
+                .min_post_length
+                .map(|n| n.max(0) as usize)
+                .or(self.min_post_length),
+            ..self
+        }
+    }
+
+    /// Whether a post's text is long enough, counting graphemes so an emoji
+    /// or a CJK character is one character
+    pub fn allows_length(&self, post: &Post) -> bool {
+        match self.min_post_length {
+            Some(min) if !post.has_media => post.text.trim().graphemes(true).count() >= min,
+            _ => true,
+        }
+    }
+}
+
+impl Default for FeedFilter {
+    fn default() -> Self {
+        Self {
+            replies: true,
+            quotes: true,
+            link_only: true,
+            author_daily_cap: None,
+            max_posts_per_author: None,
+            stranger_quotes: true,
+            media_only: false,
+            min_post_length: None,
+            include_self: false,
+            max_age_hours: None,
+            retention_hours: None,
+            kept_per_author: 0,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Follow {
     pub uri: String,
@@ -55,6 +303,25 @@ pub struct Follow {
     pub indexed_at: DateTime<Utc>,
 }
 
+/// A feed user's block of another account
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub uri: String,
+    pub blocker_did: String,
+    pub target_did: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Per-user feed settings; unset fields fall back to the global defaults
+#[derive(Debug, Clone, Default)]
+pub struct UserPreferences {
+    pub post_retention_hours: Option<i64>,
+    pub author_daily_cap: Option<i64>,
+    pub hide_stranger_quotes: bool,
+    pub min_post_length: Option<i64>,
+    pub max_posts_per_author: Option<i64>,
+}
+
 // JWT Claims
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JwtClaims {
@@ -81,3 +348,14 @@ pub struct DescribeFeedGeneratorResponse {
 pub struct FeedDescriptor {
     pub uri: String,
 }
+
+// com.atproto.server.describeServer response
+#[derive(Debug, Serialize)]
+pub struct DescribeServerResponse {
+    pub did: String,
+    #[serde(rename = "availableUserDomains")]
+    pub available_user_domains: Vec<String>,
+    #[serde(rename = "inviteCodeRequired")]
+    pub invite_code_required: bool,
+    pub links: serde_json::Map<String, serde_json::Value>,
+}